@@ -1,14 +1,13 @@
 use std::fs;
 
 use criterion::{BenchmarkId, Criterion, black_box, criterion_group, criterion_main};
-use tempfile::NamedTempFile;
-
-use crate::{action::ActionRef, parser::WorkflowFile};
+use pin_actions::{action::ActionRef, parser::WorkflowFile, workflow::WorkflowProcessor};
+use tempfile::{NamedTempFile, TempDir};
 
 fn benchmark_action_parsing(c: &mut Criterion) {
     let mut group = c.benchmark_group("action_parsing");
 
-    let test_cases = vec![
+    let test_cases: Vec<&str> = vec![
         "actions/checkout@v4",
         "actions/setup-node@v3",
         "docker/build-push-action@v5",
@@ -16,7 +15,7 @@ fn benchmark_action_parsing(c: &mut Criterion) {
     ];
 
     for case in test_cases {
-        group.bench_with_input(BenchmarkId::from_parameter(case), case, |b, &case| {
+        group.bench_with_input(BenchmarkId::from_parameter(case), case, |b, case| {
             b.iter(|| ActionRef::parse(black_box(case)));
         });
     }
@@ -69,10 +68,59 @@ fn benchmark_large_workflow(c: &mut Criterion) {
     });
 }
 
+fn benchmark_multi_megabyte_workflow(c: &mut Criterion) {
+    // A generated matrix expansion of about 10 MB. Parsing reads it a line at
+    // a time and pinning streams it through a temporary file, so peak memory
+    // stays near the longest line instead of a few copies of the file
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let mut workflow_content = String::from("name: Generated\non: [push]\njobs:\n");
+    for i in 0..2000 {
+        workflow_content.push_str(&format!(
+            "  shard{}:\n    runs-on: ubuntu-latest\n    steps:\n",
+            i
+        ));
+        for j in 0..50 {
+            workflow_content.push_str(&format!(
+                "      - uses: actions/checkout@v4\n        with:\n          ref: shard-{}-{}\n",
+                i, j
+            ));
+        }
+    }
+    let path = workflows_dir.join("generated.yml");
+    fs::write(&path, &workflow_content).unwrap();
+    drop(workflow_content);
+
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        r#"{"actions": {"actions/checkout@v4": "b4ffde65f46336ab88eb53be808477a3936bae11"}}"#,
+    )
+    .unwrap();
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let processor = WorkflowProcessor::new(vec![workflows_dir], true, false, true, 10)
+        .with_lockfile(Some(lockfile))
+        .with_offline(true);
+
+    let mut group = c.benchmark_group("multi_megabyte_workflow");
+    group.sample_size(10);
+    group.bench_function("parse", |b| {
+        b.iter(|| WorkflowFile::parse(black_box(&path)));
+    });
+    group.bench_function("pin_dry_run", |b| {
+        b.iter(|| runtime.block_on(processor.process()).unwrap());
+    });
+    group.finish();
+}
+
 criterion_group!(
     benches,
     benchmark_action_parsing,
     benchmark_workflow_parsing,
-    benchmark_large_workflow
+    benchmark_large_workflow,
+    benchmark_multi_megabyte_workflow
 );
 criterion_main!(benches);
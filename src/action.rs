@@ -2,21 +2,65 @@ use std::fmt;
 
 use serde::{Deserialize, Serialize};
 
+use crate::{resolver::Source, signature::SignatureStatus};
+
+/// Git transport used to reach action repositories during resolution
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    #[default]
+    Https,
+    Ssh,
+}
+
+/// Kind of ref a reference must resolve to, from an explicit "tag:" or
+/// "branch:" prefix
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RefKind {
+    Tag,
+    Branch,
+}
+
+impl fmt::Display for RefKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RefKind::Tag => write!(f, "tag"),
+            RefKind::Branch => write!(f, "branch"),
+        }
+    }
+}
+
+impl RefKind {
+    /// Prefix of the fully qualified ref name, e.g. "refs/tags/"
+    pub fn ref_prefix(&self) -> &'static str {
+        match self {
+            RefKind::Tag => "refs/tags/",
+            RefKind::Branch => "refs/heads/",
+        }
+    }
+}
+
 /// Represents a GitHub Action reference
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ActionRef {
     /// The action repository (e.g., "actions/checkout")
     pub repository: String,
 
-    /// The reference (tag, branch, or SHA)
+    /// The reference (tag, branch, or SHA), without any kind hint
     pub reference: String,
 
     /// Whether this is already a SHA
     pub is_sha: bool,
+
+    /// Restricts resolution to tags or branches ("@tag:stable")
+    #[serde(default)]
+    pub ref_kind: Option<RefKind>,
 }
 
 impl ActionRef {
-    /// Parse an action string like "actions/checkout@v4"
+    /// Parse an action string like "actions/checkout@v4". An empty (or
+    /// whitespace-only) reference is invalid
     pub fn parse(action_str: &str) -> Option<Self> {
         let parts: Vec<&str> = action_str.split('@').collect();
         if parts.len() != 2 {
@@ -24,26 +68,118 @@ impl ActionRef {
         }
 
         let repository = parts[0].trim().to_string();
-        let reference = parts[1].trim().to_string();
+        let reference = parts[1].trim();
+
+        let (ref_kind, reference) = if let Some(tag) = reference.strip_prefix("tag:") {
+            (Some(RefKind::Tag), tag.to_string())
+        } else if let Some(branch) = reference.strip_prefix("branch:") {
+            (Some(RefKind::Branch), branch.to_string())
+        } else {
+            (None, reference.to_string())
+        };
+        if reference.is_empty() {
+            return None;
+        }
 
         // Check if it's already a SHA (40 hex characters)
-        let is_sha = reference.len() == 40 && reference.chars().all(|c| c.is_ascii_hexdigit());
+        let is_sha = ref_kind.is_none()
+            && reference.len() == 40
+            && reference.chars().all(|c| c.is_ascii_hexdigit());
 
         Some(ActionRef {
             repository,
             reference,
             is_sha,
+            ref_kind,
         })
     }
 
-    /// Get the git URL for this action
-    pub fn git_url(&self) -> String {
-        format!("https://github.com/{}.git", self.repository)
+    /// The reference as written, including any "tag:"/"branch:" hint
+    pub fn qualified_reference(&self) -> String {
+        match self.ref_kind {
+            Some(RefKind::Tag) => format!("tag:{}", self.reference),
+            Some(RefKind::Branch) => format!("branch:{}", self.reference),
+            None => self.reference.clone(),
+        }
+    }
+
+    /// The "owner/repo" part of the repository, without any action subpath
+    /// (e.g. "github/codeql-action" for "github/codeql-action/analyze")
+    pub fn base_repository(&self) -> &str {
+        match self.repository.match_indices('/').nth(1) {
+            Some((idx, _)) => &self.repository[..idx],
+            None => &self.repository,
+        }
+    }
+
+    /// Get the git URL of this action's repository on `host`
+    pub fn git_url(&self, protocol: Protocol, host: &str) -> String {
+        match protocol {
+            Protocol::Https => format!("https://{}/{}.git", host, self.base_repository()),
+            Protocol::Ssh => format!("git@{}:{}.git", host, self.base_repository()),
+        }
+    }
+
+    /// Web page of `sha` in this action's repository on `host`
+    pub fn commit_url(&self, host: &str, sha: &str) -> String {
+        format!("https://{}/{}/commit/{}", host, self.base_repository(), sha)
+    }
+
+    /// Whether the reference is a `${{ ... }}` expression, only known when
+    /// the workflow runs
+    pub fn is_expression(&self) -> bool {
+        self.reference.starts_with("${{")
+    }
+
+    /// The tag or branch name, without a trailing [`PEEL_SUFFIX`]
+    pub fn ref_name(&self) -> &str {
+        self.reference
+            .strip_suffix(PEEL_SUFFIX)
+            .unwrap_or(&self.reference)
     }
 
-    /// Format as action@ref
-    pub fn to_string(&self) -> String {
-        format!("{}@{}", self.repository, self.reference)
+    /// The first refspec or pattern character in the reference (`:`, `*`,
+    /// `?`, `[`, `^`, `~` or `\`), which no single tag or branch name can
+    /// contain. A trailing [`PEEL_SUFFIX`] is fine
+    pub fn refspec_char(&self) -> Option<char> {
+        self.ref_name().chars().find(|c| REFSPEC_CHARS.contains(c))
+    }
+
+    /// Whether the reference looks like an abbreviated commit SHA: 7 to 39
+    /// hex characters, not all digits (so numeric tags aren't mistaken)
+    pub fn is_short_sha(&self) -> bool {
+        self.ref_kind.is_none()
+            && (7..40).contains(&self.reference.len())
+            && self.reference.chars().all(|c| c.is_ascii_hexdigit())
+            && !self.reference.chars().all(|c| c.is_ascii_digit())
+    }
+
+    /// Whether the reference looks like a version tag ("v4", "1.2.3"), as
+    /// opposed to a name that could be a branch such as "main"
+    pub fn is_version_like(&self) -> bool {
+        let version = self.reference.strip_prefix('v').unwrap_or(&self.reference);
+        version.starts_with(|c: char| c.is_ascii_digit())
+    }
+
+    /// The caret range a version tag allows, "^4.0.0" for "v4" or "^4.1.2"
+    /// for "v4.1.2", for automation that updates pins within it. None for
+    /// branches and refs that aren't plain numeric versions
+    pub fn caret_constraint(&self) -> Option<String> {
+        if self.ref_kind == Some(RefKind::Branch) || self.is_sha {
+            return None;
+        }
+        let version = self.reference.strip_prefix('v').unwrap_or(&self.reference);
+        let parts: Vec<&str> = version.split('.').collect();
+        if parts.len() > 3
+            || parts
+                .iter()
+                .any(|part| part.is_empty() || !part.chars().all(|c| c.is_ascii_digit()))
+        {
+            return None;
+        }
+
+        let part = |idx: usize| parts.get(idx).copied().unwrap_or("0");
+        Some(format!("^{}.{}.{}", part(0), part(1), part(2)))
     }
 
     /// Check if this is a local action (starts with ./)
@@ -54,34 +190,143 @@ impl ActionRef {
 
 impl fmt::Display for ActionRef {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}@{}", self.repository, self.reference)
+        write!(f, "{}@{}", self.repository, self.qualified_reference())
     }
 }
 
+/// Suffix asking for the commit a tag marks rather than the tag object, as
+/// in `v4^{}`
+pub const PEEL_SUFFIX: &str = "^{}";
+
+/// Characters of refspecs and ref patterns, which can't appear in a ref name
+const REFSPEC_CHARS: [char; 7] = [':', '*', '?', '[', '^', '~', '\\'];
+
 /// Represents a pinned action with SHA
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PinnedAction {
     pub action: ActionRef,
     pub sha: String,
     pub original_ref: String,
+    /// Date the pin was made (YYYY-MM-DD), recorded in the comment if set
+    pub pinned_on: Option<String>,
+    /// The ref is a branch (e.g. the default `main`), so the pin freezes a
+    /// moving target; noted as "(branch)" in the comment
+    pub on_branch: bool,
+    /// Signature of the tag pinned, when checked
+    #[serde(default)]
+    pub signature: Option<SignatureStatus>,
+    /// Where the SHA came from, when not from resolving the ref (a config
+    /// override)
+    #[serde(default)]
+    pub source: Option<Source>,
+    /// Version range recorded in the comment as "constraint:^4.0.0"
+    #[serde(default)]
+    pub constraint: Option<String>,
+    /// Leave the version comment out, pinning to the bare SHA
+    #[serde(default)]
+    pub no_comment: bool,
+    /// The use had no ref and was taken to mean --default-ref, which the
+    /// comment notes
+    #[serde(default)]
+    pub default_ref: bool,
 }
 
 impl PinnedAction {
     pub fn new(action: ActionRef, sha: String) -> Self {
-        let original_ref = action.reference.clone();
+        let original_ref = action.qualified_reference();
         PinnedAction {
             action,
             sha,
             original_ref,
+            pinned_on: None,
+            on_branch: false,
+            signature: None,
+            source: None,
+            constraint: None,
+            no_comment: false,
+            default_ref: false,
         }
     }
 
-    /// Format as "action@sha # original_ref"
+    /// Note in the version comment that the ref is a branch
+    pub fn with_branch(mut self, on_branch: bool) -> Self {
+        self.on_branch = on_branch;
+        self
+    }
+
+    /// Record the outcome of checking the tag's signature
+    pub fn with_signature(mut self, signature: Option<SignatureStatus>) -> Self {
+        self.signature = signature;
+        self
+    }
+
+    /// Record where the SHA came from
+    pub fn with_source(mut self, source: Option<Source>) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Record a version range in the comment after the ref
+    pub fn with_constraint(mut self, constraint: Option<String>) -> Self {
+        self.constraint = constraint;
+        self
+    }
+
+    /// Record the pin date in the version comment
+    pub fn with_pinned_on(mut self, date: String) -> Self {
+        self.pinned_on = Some(date);
+        self
+    }
+
+    /// Note in the version comment that the ref was assumed for a use
+    /// written without one
+    pub fn with_default_ref(mut self, default_ref: bool) -> Self {
+        self.default_ref = default_ref;
+        self
+    }
+
+    /// Pin without a version comment
+    pub fn with_no_comment(mut self, no_comment: bool) -> Self {
+        self.no_comment = no_comment;
+        self
+    }
+
+    /// Format as "action@sha # original_ref", optionally followed by
+    /// "(pinned YYYY-MM-DD)", or as "action@sha" alone without a comment
     pub fn format_uses_line(&self) -> String {
-        format!(
-            "{}@{} # {}",
-            self.action.repository, self.sha, self.original_ref
-        )
+        match self.version_comment() {
+            Some(comment) => format!("{} # {}", self.pinned_ref(), comment),
+            None => self.pinned_ref(),
+        }
+    }
+
+    /// The comment to write after the pin, unless it is left out
+    pub fn version_comment(&self) -> Option<String> {
+        (!self.no_comment).then(|| self.comment())
+    }
+
+    /// The pinned reference alone, "action@sha"
+    pub fn pinned_ref(&self) -> String {
+        format!("{}@{}", self.action.repository, self.sha)
+    }
+
+    /// The version comment text, "original_ref" plus the optional
+    /// constraint, branch and default ref notes and pin date
+    pub fn comment(&self) -> String {
+        let mut comment = self.original_ref.clone();
+        if let Some(constraint) = &self.constraint {
+            comment.push_str(&format!(" constraint:{}", constraint));
+        }
+        if self.on_branch {
+            comment.push_str(" (branch)");
+        }
+        if self.default_ref {
+            comment.push_str(" (default ref)");
+        }
+        if let Some(date) = &self.pinned_on {
+            comment.push_str(&format!(" (pinned {})", date));
+        }
+        comment
     }
 }
 
@@ -106,6 +351,78 @@ mod tests {
         assert!(action.is_sha);
     }
 
+    #[test]
+    fn test_parse_action_ref_empty_reference() {
+        for action in [
+            "owner/repo@",
+            "owner/repo@   ",
+            "owner/repo@tag:",
+            "owner/repo@branch:",
+        ] {
+            assert!(ActionRef::parse(action).is_none(), "{:?}", action);
+        }
+    }
+
+    #[test]
+    fn test_caret_constraint() {
+        let constraint = |action: &str| ActionRef::parse(action).unwrap().caret_constraint();
+
+        assert_eq!(
+            constraint("actions/checkout@v4"),
+            Some("^4.0.0".to_string())
+        );
+        assert_eq!(
+            constraint("actions/checkout@v4.1"),
+            Some("^4.1.0".to_string())
+        );
+        assert_eq!(
+            constraint("actions/checkout@4.1.2"),
+            Some("^4.1.2".to_string())
+        );
+        assert_eq!(
+            constraint("actions/checkout@tag:v0.3"),
+            Some("^0.3.0".to_string())
+        );
+        assert_eq!(constraint("actions/checkout@main"), None);
+        assert_eq!(constraint("actions/checkout@branch:v4"), None);
+        assert_eq!(constraint("actions/checkout@v4.1.0-rc.1"), None);
+        assert_eq!(constraint("actions/checkout@v1.2.3.4"), None);
+
+        let pinned = PinnedAction::new(
+            ActionRef::parse("actions/checkout@v4").unwrap(),
+            "a".repeat(40),
+        )
+        .with_constraint(Some("^4.0.0".to_string()))
+        .with_pinned_on("2024-05-01".to_string());
+        assert_eq!(pinned.comment(), "v4 constraint:^4.0.0 (pinned 2024-05-01)");
+    }
+
+    #[test]
+    fn test_branch_comment() {
+        let action = ActionRef::parse("actions/checkout@master").unwrap();
+        let pinned = PinnedAction::new(action, "a".repeat(40)).with_branch(true);
+        assert_eq!(pinned.comment(), "master (branch)");
+
+        let pinned = pinned.with_pinned_on("2024-05-01".to_string());
+        assert_eq!(pinned.comment(), "master (branch) (pinned 2024-05-01)");
+        assert_eq!(
+            crate::parser::parse_version_comment(&pinned.comment()),
+            Some("master".to_string())
+        );
+    }
+
+    #[test]
+    fn test_is_version_like() {
+        for reference in ["v4", "v4.2.1", "1.2", "2024.01"] {
+            let action = ActionRef::parse(&format!("actions/checkout@{}", reference)).unwrap();
+            assert!(action.is_version_like(), "{}", reference);
+        }
+        for reference in ["main", "master", "release", "vnext"] {
+            let action = ActionRef::parse(&format!("actions/checkout@{}", reference)).unwrap();
+            assert!(!action.is_version_like(), "{}", reference);
+        }
+    }
+
     #[test]
     fn test_is_local() {
         let action = ActionRef::parse("./local-action@v1").unwrap();
@@ -115,10 +432,129 @@ mod tests {
         assert!(!action.is_local());
     }
 
+    #[test]
+    fn test_base_repository() {
+        let action = ActionRef::parse("actions/checkout@v4").unwrap();
+        assert_eq!(action.base_repository(), "actions/checkout");
+
+        let action = ActionRef::parse("github/codeql-action/init/sub@v3").unwrap();
+        assert_eq!(action.base_repository(), "github/codeql-action");
+    }
+
+    #[test]
+    fn test_parse_ref_kind_hint() {
+        let action = ActionRef::parse("actions/checkout@tag:stable").unwrap();
+        assert_eq!(action.reference, "stable");
+        assert_eq!(action.ref_kind, Some(RefKind::Tag));
+        assert_eq!(action.to_string(), "actions/checkout@tag:stable");
+
+        let action = ActionRef::parse("actions/checkout@branch:stable").unwrap();
+        assert_eq!(action.reference, "stable");
+        assert_eq!(action.ref_kind, Some(RefKind::Branch));
+
+        let pinned = PinnedAction::new(action, "abc123".to_string());
+        assert_eq!(
+            pinned.format_uses_line(),
+            "actions/checkout@abc123 # branch:stable"
+        );
+
+        let action = ActionRef::parse("actions/checkout@stable").unwrap();
+        assert_eq!(action.ref_kind, None);
+    }
+
+    #[test]
+    fn test_is_short_sha() {
+        assert!(
+            ActionRef::parse("actions/checkout@b4ffde6")
+                .unwrap()
+                .is_short_sha()
+        );
+        assert!(
+            !ActionRef::parse("actions/checkout@v4")
+                .unwrap()
+                .is_short_sha()
+        );
+        assert!(
+            !ActionRef::parse("actions/checkout@20240101")
+                .unwrap()
+                .is_short_sha()
+        );
+        assert!(
+            !ActionRef::parse("actions/checkout@cafe")
+                .unwrap()
+                .is_short_sha()
+        );
+        assert!(
+            !ActionRef::parse("actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11")
+                .unwrap()
+                .is_short_sha()
+        );
+    }
+
+    #[test]
+    fn test_refspec_references() {
+        let peeled = ActionRef::parse("actions/checkout@v4^{}").unwrap();
+        assert_eq!(peeled.ref_name(), "v4");
+        assert_eq!(peeled.refspec_char(), None);
+        assert_eq!(peeled.to_string(), "actions/checkout@v4^{}");
+
+        let refspec_char = |action: &str| ActionRef::parse(action).unwrap().refspec_char();
+        assert_eq!(refspec_char("actions/checkout@tag:v4"), None);
+        assert_eq!(refspec_char("actions/checkout@v*"), Some('*'));
+        assert_eq!(refspec_char("actions/checkout@refs/tags/v4:v4"), Some(':'));
+        assert_eq!(refspec_char("actions/checkout@v4^"), Some('^'));
+        assert_eq!(refspec_char("actions/checkout@v4~1"), Some('~'));
+    }
+
+    #[test]
+    fn test_sha_boundary_lengths() {
+        let sha = "b4ffde65f46336ab88eb53be808477a3936bae11";
+        let classify = |reference: &str| {
+            let action = ActionRef::parse(&format!("actions/checkout@{}", reference)).unwrap();
+            (action.is_sha, action.is_short_sha())
+        };
+
+        assert_eq!(classify(&sha[..6]), (false, false));
+        assert_eq!(classify(&sha[..7]), (false, true));
+        assert_eq!(classify(&sha[..39]), (false, true));
+        assert_eq!(classify(sha), (true, false));
+        assert_eq!(classify(&format!("{}0", sha)), (false, false));
+        assert_eq!(classify("B4FFDE65f463"), (false, true));
+        assert_eq!(classify(&sha.to_ascii_uppercase()), (true, false));
+    }
+
     #[test]
     fn test_git_url() {
         let action = ActionRef::parse("actions/checkout@v4").unwrap();
-        assert_eq!(action.git_url(), "https://github.com/actions/checkout.git");
+        assert_eq!(
+            action.git_url(Protocol::Https, "github.com"),
+            "https://github.com/actions/checkout.git"
+        );
+
+        let action = ActionRef::parse("github/codeql-action/analyze@v3").unwrap();
+        assert_eq!(
+            action.git_url(Protocol::Ssh, "ghes.example.com"),
+            "git@ghes.example.com:github/codeql-action.git"
+        );
+    }
+
+    #[test]
+    fn test_commit_url() {
+        let sha = "b4ffde65f46336ab88eb53be808477a3936bae11";
+        let action = ActionRef::parse("actions/checkout@v4").unwrap();
+        assert_eq!(
+            action.commit_url("github.com", sha),
+            format!("https://github.com/actions/checkout/commit/{}", sha)
+        );
+
+        let action = ActionRef::parse("github/codeql-action/analyze@v3").unwrap();
+        assert_eq!(
+            action.commit_url("ghes.example.com", sha),
+            format!(
+                "https://ghes.example.com/github/codeql-action/commit/{}",
+                sha
+            )
+        );
     }
 
     #[test]
@@ -127,4 +563,23 @@ mod tests {
         let pinned = PinnedAction::new(action, "abc123".to_string());
         assert_eq!(pinned.format_uses_line(), "actions/checkout@abc123 # v4");
     }
+
+    #[test]
+    fn test_pinned_action_format_with_date() {
+        let action = ActionRef::parse("actions/checkout@v4").unwrap();
+        let pinned = PinnedAction::new(action, "abc123".to_string())
+            .with_pinned_on("2024-05-01".to_string());
+        assert_eq!(
+            pinned.format_uses_line(),
+            "actions/checkout@abc123 # v4 (pinned 2024-05-01)"
+        );
+    }
+
+    #[test]
+    fn test_pinned_action_format_without_comment() {
+        let action = ActionRef::parse("actions/checkout@v4").unwrap();
+        let pinned = PinnedAction::new(action, "abc123".to_string()).with_no_comment(true);
+        assert_eq!(pinned.format_uses_line(), "actions/checkout@abc123");
+        assert_eq!(pinned.version_comment(), None);
+    }
 }
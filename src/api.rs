@@ -0,0 +1,427 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use anyhow::{Context, Result};
+use futures::future::BoxFuture;
+use reqwest::{
+    StatusCode,
+    header::{self, HeaderMap},
+};
+use serde_json::Value;
+use tracing::debug;
+
+use crate::{
+    action::{ActionRef, RefKind},
+    git::GitResolver,
+    http,
+    resolver::{Resolution, Resolver, Source},
+};
+
+/// Tags asked for per page when listing them
+pub const TAGS_PER_PAGE: usize = 100;
+
+/// Resolver looking each ref up through the GitHub REST API, which is much
+/// lighter than listing every ref of the repository over git. Mirrored
+/// repositories, abbreviated SHAs and anything the API can't answer go
+/// through git
+#[derive(Clone)]
+pub struct ApiResolver {
+    client: reqwest::Client,
+    api_url: String,
+    token: Option<String>,
+    fallback: GitResolver,
+    /// Lookups sent through git after their request failed
+    retries: Arc<AtomicUsize>,
+}
+
+impl ApiResolver {
+    pub fn new(api_url: String, token: Option<String>) -> Self {
+        Self {
+            client: http::default_client(),
+            api_url,
+            token,
+            fallback: GitResolver::new(),
+            retries: Arc::default(),
+        }
+    }
+
+    /// Send requests through `client`, e.g. one from [`HttpOptions::client`]
+    ///
+    /// [`HttpOptions::client`]: crate::http::HttpOptions::client
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Use the given git resolver for refs the API can't answer
+    pub fn with_fallback(mut self, fallback: GitResolver) -> Self {
+        self.fallback = fallback;
+        self
+    }
+
+    /// Resolve from the shared cache (where --warm-cache entries are
+    /// primed), then the API, caching what it answers alongside git's
+    /// resolutions so it is saved with them
+    async fn resolve_ref(&self, action: &ActionRef) -> Result<Resolution> {
+        if let Some(resolution) = self.fallback.cached(action) {
+            return Ok(resolution);
+        }
+        if self.fallback.mirror_path(action).is_some() || action.is_short_sha() {
+            return self.fallback.resolve_ref(action).await;
+        }
+
+        match self.lookup(action).await {
+            Ok(Some(resolution)) => {
+                self.fallback.remember(action, &resolution);
+                Ok(resolution)
+            },
+            // git also matches refs partially, which the API can't
+            Ok(None) => {
+                debug!("No exact ref for {} via the API, asking git", action);
+                self.fallback.resolve_ref(action).await
+            },
+            Err(e) => {
+                debug!(
+                    "API lookup of {} failed, falling back to git: {:#}",
+                    action, e
+                );
+                self.retries.fetch_add(1, Ordering::SeqCst);
+                self.fallback.resolve_ref(action).await
+            },
+        }
+    }
+
+    /// Look the ref up as a tag, then as a branch (or only as the hinted
+    /// kind). None when neither exists
+    async fn lookup(&self, action: &ActionRef) -> Result<Option<Resolution>> {
+        let repository = action.base_repository();
+
+        for kind in [RefKind::Tag, RefKind::Branch] {
+            if action.ref_kind.is_some_and(|hint| hint != kind) {
+                continue;
+            }
+            let path = format!(
+                "repos/{}/git/{}{}",
+                repository,
+                kind.ref_prefix().replacen("refs/", "ref/", 1),
+                action.ref_name()
+            );
+            let Some(reference) = self.get(&path).await? else {
+                continue;
+            };
+
+            let object = &reference["object"];
+            let sha = object["sha"]
+                .as_str()
+                .with_context(|| format!("Reference {} has no object SHA", action))?;
+            // An annotated tag points at a tag object, not the commit
+            let sha = if object["type"].as_str() == Some("tag") {
+                self.peel_tag(repository, action.ref_name()).await?
+            } else {
+                sha.to_string()
+            };
+            return Ok(Some(Resolution::new(sha, Some(kind), Source::Api)));
+        }
+
+        Ok(None)
+    }
+
+    /// The commit an annotated tag points at, from the tag listing (which
+    /// gives peeled commits). Pages are fetched only until the tag turns up
+    async fn peel_tag(&self, repository: &str, tag: &str) -> Result<String> {
+        let mut url = Some(format!(
+            "{}/repos/{}/tags?per_page={}",
+            self.api_url, repository, TAGS_PER_PAGE
+        ));
+
+        while let Some(page) = url {
+            let response = self
+                .request(&page)
+                .send()
+                .await
+                .with_context(|| format!("Failed to reach {}", page))?
+                .error_for_status()
+                .with_context(|| format!("Request to {} was rejected", page))?;
+            url = next_page(response.headers());
+
+            let tags: Value = response
+                .json()
+                .await
+                .with_context(|| format!("Invalid response from {}", page))?;
+            if let Some(sha) = find_tag(&tags, tag) {
+                return Ok(sha);
+            }
+        }
+
+        anyhow::bail!("Tag {} not in the tag listing of {}", tag, repository)
+    }
+
+    fn request(&self, url: &str) -> reqwest::RequestBuilder {
+        let request = self
+            .client
+            .get(url)
+            .header(header::ACCEPT, "application/vnd.github+json");
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    /// GET an API path, returning None on 404
+    async fn get(&self, path: &str) -> Result<Option<Value>> {
+        let url = format!("{}/{}", self.api_url, path);
+        let response = self
+            .request(&url)
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach {}", url))?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let body = response
+            .error_for_status()
+            .with_context(|| format!("Request to {} was rejected", url))?
+            .json()
+            .await
+            .with_context(|| format!("Invalid response from {}", url))?;
+        Ok(Some(body))
+    }
+}
+
+impl Resolver for ApiResolver {
+    fn resolve<'a>(&'a self, action: &'a ActionRef) -> BoxFuture<'a, Result<Resolution>> {
+        Box::pin(self.resolve_ref(action))
+    }
+
+    fn prime(&self, entries: HashMap<String, String>) {
+        self.fallback.prime(entries);
+    }
+
+    fn snapshot(&self) -> HashMap<String, String> {
+        self.fallback.snapshot()
+    }
+
+    fn default_branches(&self) -> BTreeMap<String, String> {
+        self.fallback.default_branches()
+    }
+
+    fn retries(&self) -> usize {
+        self.retries.load(Ordering::SeqCst)
+    }
+}
+
+/// The URL of the next page from a `Link` header, if there is one
+pub fn next_page(headers: &HeaderMap) -> Option<String> {
+    let link = headers.get(header::LINK)?.to_str().ok()?;
+    link.split(',').find_map(|part| {
+        let (url, params) = part.split_once(';')?;
+        params
+            .split(';')
+            .any(|param| param.trim() == r#"rel="next""#)
+            .then(|| {
+                url.trim()
+                    .trim_start_matches('<')
+                    .trim_end_matches('>')
+                    .to_string()
+            })
+    })
+}
+
+/// The commit SHA of the tag named `name` in a tag listing page
+pub fn find_tag(tags: &Value, name: &str) -> Option<String> {
+    tags.as_array()?
+        .iter()
+        .find(|tag| tag["name"].as_str() == Some(name))
+        .and_then(|tag| tag["commit"]["sha"].as_str())
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMMIT_SHA: &str = "b4ffde65f46336ab88eb53be808477a3936bae11";
+    const TAG_SHA: &str = "9c8f5b2e0d1a3f4e5b6c7d8e9f0a1b2c3d4e5f60";
+
+    #[test]
+    fn test_next_page() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::LINK,
+            r#"<https://api.github.com/repositories/1/tags?page=2>; rel="next", <https://api.github.com/repositories/1/tags?page=5>; rel="last""#
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(
+            next_page(&headers).as_deref(),
+            Some("https://api.github.com/repositories/1/tags?page=2")
+        );
+
+        headers.insert(
+            header::LINK,
+            r#"<https://api.github.com/repositories/1/tags?page=1>; rel="prev""#
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(next_page(&headers), None);
+        assert_eq!(next_page(&HeaderMap::new()), None);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_tags_and_branches() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/repos/actions/checkout/git/ref/tags/v4")
+            .with_body(format!(
+                r#"{{"ref": "refs/tags/v4", "object": {{"type": "commit", "sha": "{}"}}}}"#,
+                COMMIT_SHA
+            ))
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/repos/actions/checkout/git/ref/tags/main")
+            .with_status(404)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/repos/actions/checkout/git/ref/heads/main")
+            .with_body(format!(
+                r#"{{"ref": "refs/heads/main", "object": {{"type": "commit", "sha": "{}"}}}}"#,
+                COMMIT_SHA
+            ))
+            .create_async()
+            .await;
+
+        let resolver = ApiResolver::new(server.url(), None);
+        for (action, kind) in [
+            ("actions/checkout@v4", RefKind::Tag),
+            ("actions/checkout@main", RefKind::Branch),
+        ] {
+            let resolution = resolver
+                .resolve(&ActionRef::parse(action).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(
+                resolution,
+                Resolution::new(COMMIT_SHA.to_string(), Some(kind), Source::Api)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_annotated_tag_pages_listing() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/repos/actions/cache/git/ref/tags/v4")
+            .with_body(format!(
+                r#"{{"ref": "refs/tags/v4", "object": {{"type": "tag", "sha": "{}"}}}}"#,
+                TAG_SHA
+            ))
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/repos/actions/cache/tags?per_page=100")
+            .with_header(
+                "link",
+                &format!(
+                    r#"<{}/repos/actions/cache/tags?per_page=100&page=2>; rel="next""#,
+                    server.url()
+                ),
+            )
+            .with_body(r#"[{"name": "v5", "commit": {"sha": "1111111111111111111111111111111111111111"}}]"#)
+            .create_async()
+            .await;
+        let second = server
+            .mock("GET", "/repos/actions/cache/tags?per_page=100&page=2")
+            .with_body(format!(
+                r#"[{{"name": "v4", "commit": {{"sha": "{}"}}}}]"#,
+                COMMIT_SHA
+            ))
+            .create_async()
+            .await;
+
+        let resolution = ApiResolver::new(server.url(), None)
+            .resolve(&ActionRef::parse("actions/cache@v4").unwrap())
+            .await
+            .unwrap();
+        assert_eq!(resolution.sha, COMMIT_SHA);
+        assert_eq!(resolution.ref_type, Some(RefKind::Tag));
+        second.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_api_failure_falls_back_to_git() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/repos/actions/checkout/git/ref/tags/v4")
+            .with_status(403)
+            .create_async()
+            .await;
+
+        // An offline fallback without a mirror can only say it tried
+        let resolver = ApiResolver::new(server.url(), None)
+            .with_fallback(GitResolver::new().with_offline(true));
+        let error = resolver
+            .resolve(&ActionRef::parse("actions/checkout@v4").unwrap())
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("offline mode"));
+        assert_eq!(resolver.retries(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_api_shares_the_cache() {
+        let mut server = mockito::Server::new_async().await;
+        let lookup = server
+            .mock("GET", "/repos/actions/checkout/git/ref/tags/v4")
+            .with_body(format!(
+                r#"{{"ref": "refs/tags/v4", "object": {{"type": "commit", "sha": "{}"}}}}"#,
+                COMMIT_SHA
+            ))
+            .expect(1)
+            .create_async()
+            .await;
+        let cached = server
+            .mock("GET", "/repos/actions/cache/git/ref/tags/v4")
+            .expect(0)
+            .create_async()
+            .await;
+
+        // Primed entries, as --warm-cache loads them, are answered first
+        let resolver = ApiResolver::new(server.url(), None);
+        resolver.prime(HashMap::from([(
+            "actions/cache@v4".to_string(),
+            TAG_SHA.to_string(),
+        )]));
+        let cache = ActionRef::parse("actions/cache@v4").unwrap();
+        let resolution = resolver.resolve(&cache).await.unwrap();
+        assert_eq!(resolution.sha, TAG_SHA);
+        assert_eq!(resolution.source, Source::Cache);
+
+        // API answers are cached once and saved with the rest
+        let checkout = ActionRef::parse("actions/checkout@v4").unwrap();
+        assert_eq!(
+            resolver.resolve(&checkout).await.unwrap().source,
+            Source::Api
+        );
+        assert_eq!(
+            resolver.resolve(&checkout).await.unwrap().source,
+            Source::Cache
+        );
+        assert_eq!(
+            resolver.snapshot(),
+            HashMap::from([
+                ("actions/cache@v4".to_string(), TAG_SHA.to_string()),
+                ("actions/checkout@v4".to_string(), COMMIT_SHA.to_string()),
+            ])
+        );
+        lookup.assert_async().await;
+        cached.assert_async().await;
+    }
+}
@@ -0,0 +1,445 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use chrono::{Datelike, NaiveDate, Timelike};
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+
+/// Archive container formats, chosen by file extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Format of a `.tar`, `.tar.gz`/`.tgz` or `.zip` path
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Ok(ArchiveFormat::TarGz)
+        } else if name.ends_with(".tar") {
+            Ok(ArchiveFormat::Tar)
+        } else if name.ends_with(".zip") {
+            Ok(ArchiveFormat::Zip)
+        } else {
+            anyhow::bail!(
+                "Unsupported archive {} (expected .tar, .tar.gz, .tgz or .zip)",
+                path.display()
+            )
+        }
+    }
+}
+
+/// What an archive entry is
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum EntryKind {
+    #[default]
+    File,
+    Directory,
+    /// A symbolic link to the path given
+    Symlink(String),
+    /// A tar hard link to the entry named
+    HardLink(String),
+    /// Anything else tar records, such as a device or a FIFO, which only a
+    /// tar archive can hold
+    Other,
+}
+
+/// A tar header as it was read, compared by its bytes
+#[derive(Debug, Clone)]
+pub struct TarHeader(Box<tar::Header>);
+
+impl PartialEq for TarHeader {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_bytes() == other.0.as_bytes()
+    }
+}
+
+impl Eq for TarHeader {}
+
+/// An entry stored in an archive
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    pub name: String,
+    /// A file's content; empty for other kinds of entry
+    pub data: Vec<u8>,
+    /// Unix permission bits, when the archive records them
+    pub mode: Option<u32>,
+    pub kind: EntryKind,
+    /// Last modified, in seconds since the Unix epoch, when the archive
+    /// records it
+    pub mtime: Option<u64>,
+    /// The header of an entry read from a tar archive, so a tar archive is
+    /// written back with each entry's owners and other fields as they were
+    pub header: Option<TarHeader>,
+}
+
+/// The entries of an archive, held in memory
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Archive {
+    pub entries: Vec<ArchiveEntry>,
+}
+
+impl Archive {
+    /// Read every entry of an archive into memory
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let format = ArchiveFormat::from_path(path)?;
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open archive: {}", path.display()))?;
+        let reader = BufReader::new(file);
+
+        let entries = match format {
+            ArchiveFormat::Tar => read_tar(reader),
+            ArchiveFormat::TarGz => read_tar(GzDecoder::new(reader)),
+            ArchiveFormat::Zip => read_zip(reader),
+        }
+        .with_context(|| format!("Failed to read archive: {}", path.display()))?;
+
+        Ok(Self { entries })
+    }
+
+    /// Write the archive to `path`, in the format its extension names
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let format = ArchiveFormat::from_path(path)?;
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create archive: {}", path.display()))?;
+        let writer = BufWriter::new(file);
+
+        let written = match format {
+            ArchiveFormat::Tar => write_tar(&self.entries, writer).and_then(|mut w| Ok(w.flush()?)),
+            ArchiveFormat::TarGz => write_tar(
+                &self.entries,
+                GzEncoder::new(writer, Compression::default()),
+            )
+            .and_then(|gz| Ok(gz.finish()?.flush()?)),
+            ArchiveFormat::Zip => write_zip(&self.entries, writer),
+        };
+        written.with_context(|| format!("Failed to write archive: {}", path.display()))
+    }
+
+    /// Entries that are workflow files: YAML directly under a
+    /// `.github/workflows` directory, at the root or below a top-level
+    /// folder as in GitHub's source downloads. Links are left alone
+    pub fn workflow_entries(&self) -> impl Iterator<Item = &ArchiveEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.kind == EntryKind::File && is_workflow_path(&entry.name))
+    }
+
+    /// Replace the contents of the named entry
+    pub fn replace(&mut self, name: &str, data: Vec<u8>) {
+        if let Some(entry) = self.entries.iter_mut().find(|entry| entry.name == name) {
+            entry.data = data;
+        }
+    }
+}
+
+/// Whether an archive path names a workflow file
+fn is_workflow_path(name: &str) -> bool {
+    let Some((dir, file)) = name.rsplit_once('/') else {
+        return false;
+    };
+    (dir == ".github/workflows" || dir.ends_with("/.github/workflows"))
+        && (file.ends_with(".yml") || file.ends_with(".yaml"))
+}
+
+/// Seconds since the Unix epoch of a zip timestamp, which has no time zone
+/// and is taken as UTC
+fn zip_to_unix(time: zip::DateTime) -> Option<u64> {
+    let date = NaiveDate::from_ymd_opt(time.year().into(), time.month().into(), time.day().into())?;
+    let time = date.and_hms_opt(
+        time.hour().into(),
+        time.minute().into(),
+        time.second().into(),
+    )?;
+    time.and_utc().timestamp().try_into().ok()
+}
+
+/// A zip timestamp for seconds since the Unix epoch, if zip can hold it
+fn unix_to_zip(mtime: u64) -> Option<zip::DateTime> {
+    let time = chrono::DateTime::from_timestamp(mtime.try_into().ok()?, 0)?.naive_utc();
+    zip::DateTime::from_date_and_time(
+        time.year().try_into().ok()?,
+        time.month() as u8,
+        time.day() as u8,
+        time.hour() as u8,
+        time.minute() as u8,
+        time.second() as u8,
+    )
+    .ok()
+}
+
+fn read_tar<R: Read>(reader: R) -> Result<Vec<ArchiveEntry>> {
+    let mut archive = tar::Archive::new(reader);
+    let mut entries = Vec::new();
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let header = entry.header().clone();
+        let link = || -> Result<String> {
+            Ok(entry
+                .link_name()?
+                .map(|target| target.to_string_lossy().to_string())
+                .unwrap_or_default())
+        };
+        let kind = match header.entry_type() {
+            tar::EntryType::Regular | tar::EntryType::Continuous => EntryKind::File,
+            tar::EntryType::Directory => EntryKind::Directory,
+            tar::EntryType::Symlink => EntryKind::Symlink(link()?),
+            tar::EntryType::Link => EntryKind::HardLink(link()?),
+            _ => EntryKind::Other,
+        };
+
+        let name = entry.path()?.to_string_lossy().to_string();
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        entries.push(ArchiveEntry {
+            name,
+            data,
+            mode: header.mode().ok(),
+            kind,
+            mtime: header.mtime().ok(),
+            header: Some(TarHeader(Box::new(header))),
+        });
+    }
+
+    Ok(entries)
+}
+
+fn read_zip<R: Read + std::io::Seek>(reader: R) -> Result<Vec<ArchiveEntry>> {
+    let mut archive = zip::ZipArchive::new(reader)?;
+    let mut entries = Vec::new();
+
+    for idx in 0..archive.len() {
+        let mut file = archive.by_index(idx)?;
+        let name = file.name().to_string();
+        // Zip records the file type alongside the permission bits
+        let mode = file.unix_mode().map(|mode| mode & 0o7777);
+        let mtime = file.last_modified().and_then(zip_to_unix);
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        // A symlink's target is stored as its content
+        let kind = if file.is_dir() {
+            EntryKind::Directory
+        } else if file.is_symlink() {
+            EntryKind::Symlink(String::from_utf8_lossy(&std::mem::take(&mut data)).to_string())
+        } else {
+            EntryKind::File
+        };
+        entries.push(ArchiveEntry {
+            name,
+            data,
+            mode,
+            kind,
+            mtime,
+            header: None,
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Write entries as a tar stream, returning the underlying writer. Entries
+/// read from a tar archive keep their header, with the size brought up to
+/// date
+fn write_tar<W: Write>(entries: &[ArchiveEntry], writer: W) -> Result<W> {
+    let mut builder = tar::Builder::new(writer);
+    for entry in entries {
+        let mut header = match &entry.header {
+            Some(TarHeader(header)) => (**header).clone(),
+            None => {
+                let mut header = tar::Header::new_gnu();
+                let (entry_type, mode) = match entry.kind {
+                    EntryKind::File | EntryKind::Other => (tar::EntryType::Regular, 0o644),
+                    EntryKind::Directory => (tar::EntryType::Directory, 0o755),
+                    EntryKind::Symlink(_) => (tar::EntryType::Symlink, 0o777),
+                    EntryKind::HardLink(_) => (tar::EntryType::Link, 0o644),
+                };
+                header.set_entry_type(entry_type);
+                header.set_mode(entry.mode.unwrap_or(mode));
+                header.set_mtime(entry.mtime.unwrap_or_default());
+                header
+            },
+        };
+        header.set_size(entry.data.len() as u64);
+        match &entry.kind {
+            EntryKind::Symlink(target) | EntryKind::HardLink(target) => {
+                builder.append_link(&mut header, &entry.name, target)?
+            },
+            _ => builder.append_data(&mut header, &entry.name, entry.data.as_slice())?,
+        }
+    }
+    Ok(builder.into_inner()?)
+}
+
+/// Write entries as a zip archive. Hard links and the other entries only
+/// tar can hold are left out
+fn write_zip<W: Write + std::io::Seek>(entries: &[ArchiveEntry], writer: W) -> Result<()> {
+    let mut zip = zip::ZipWriter::new(writer);
+    for entry in entries {
+        let mut options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated);
+        if let Some(mode) = entry.mode {
+            options = options.unix_permissions(mode);
+        }
+        if let Some(time) = entry.mtime.and_then(unix_to_zip) {
+            options = options.last_modified_time(time);
+        }
+        match &entry.kind {
+            EntryKind::File => {
+                zip.start_file(entry.name.as_str(), options)?;
+                zip.write_all(&entry.data)?;
+            },
+            EntryKind::Directory => zip.add_directory(entry.name.as_str(), options)?,
+            EntryKind::Symlink(target) => zip.add_symlink(entry.name.as_str(), target, options)?,
+            EntryKind::HardLink(_) | EntryKind::Other => {},
+        }
+    }
+    zip.finish()?.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn sample() -> Archive {
+        Archive {
+            entries: vec![
+                ArchiveEntry {
+                    name: "repo-main/".to_string(),
+                    mode: Some(0o755),
+                    kind: EntryKind::Directory,
+                    mtime: Some(1_700_000_000),
+                    ..Default::default()
+                },
+                ArchiveEntry {
+                    name: "repo-main/.github/workflows/ci.yml".to_string(),
+                    data: b"steps:\n  - uses: actions/checkout@v4\n".to_vec(),
+                    mode: Some(0o644),
+                    mtime: Some(1_700_000_000),
+                    ..Default::default()
+                },
+                ArchiveEntry {
+                    name: "repo-main/README.md".to_string(),
+                    data: b"# repo\n".to_vec(),
+                    mode: Some(0o600),
+                    mtime: Some(1_600_000_000),
+                    ..Default::default()
+                },
+                ArchiveEntry {
+                    name: "repo-main/.github/workflows/linked.yml".to_string(),
+                    mode: Some(0o777),
+                    kind: EntryKind::Symlink("ci.yml".to_string()),
+                    mtime: Some(1_700_000_000),
+                    ..Default::default()
+                },
+            ],
+        }
+    }
+
+    /// The entries of the archive at `path`, without the tar headers read
+    fn read_back(path: &Path) -> Archive {
+        let mut archive = Archive::read(path).unwrap();
+        for entry in &mut archive.entries {
+            entry.header = None;
+        }
+        archive
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(
+            ArchiveFormat::from_path(Path::new("repo.tar.gz")).unwrap(),
+            ArchiveFormat::TarGz
+        );
+        assert_eq!(
+            ArchiveFormat::from_path(Path::new("repo.tgz")).unwrap(),
+            ArchiveFormat::TarGz
+        );
+        assert_eq!(
+            ArchiveFormat::from_path(Path::new("repo.ZIP")).unwrap(),
+            ArchiveFormat::Zip
+        );
+        assert!(ArchiveFormat::from_path(Path::new("repo.rar")).is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let temp = TempDir::new().unwrap();
+        for name in ["out.tar", "out.tar.gz", "out.zip"] {
+            let path = temp.path().join(name);
+            sample().write(&path).unwrap();
+            assert_eq!(read_back(&path), sample(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_tar_entries_keep_their_headers() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("in.tar");
+        let mut builder = tar::Builder::new(File::create(&path).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_size(3);
+        header.set_mode(0o640);
+        header.set_mtime(1_650_000_000);
+        header.set_uid(1000);
+        header.set_username("builder").unwrap();
+        builder
+            .append_data(&mut header, "repo/notes.txt", &b"hi\n"[..])
+            .unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Link);
+        header.set_size(0);
+        header.set_mtime(1_650_000_000);
+        builder
+            .append_link(&mut header, "repo/also-notes.txt", "repo/notes.txt")
+            .unwrap();
+        builder.into_inner().unwrap().flush().unwrap();
+
+        let archive = Archive::read(&path).unwrap();
+        assert_eq!(
+            archive.entries[1].kind,
+            EntryKind::HardLink("repo/notes.txt".to_string())
+        );
+        let out = temp.path().join("out.tar");
+        archive.write(&out).unwrap();
+
+        let copied = Archive::read(&out).unwrap();
+        assert_eq!(copied, archive);
+        let header = &copied.entries[0].header.as_ref().unwrap().0;
+        assert_eq!(header.uid().unwrap(), 1000);
+        assert_eq!(header.username().unwrap(), Some("builder"));
+    }
+
+    #[test]
+    fn test_workflow_entries() {
+        assert!(is_workflow_path(".github/workflows/ci.yml"));
+        assert!(is_workflow_path("repo-main/.github/workflows/ci.yaml"));
+        assert!(!is_workflow_path(".github/workflows/nested/ci.yml"));
+        assert!(!is_workflow_path(".github/workflows/README.md"));
+        assert!(!is_workflow_path("ci.yml"));
+
+        // The symlink is named like a workflow, but isn't one to rewrite
+        let archive = sample();
+        let names: Vec<&str> = archive
+            .workflow_entries()
+            .map(|entry| entry.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["repo-main/.github/workflows/ci.yml"]);
+    }
+}
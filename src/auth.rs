@@ -0,0 +1,198 @@
+use std::{fmt, process::Command};
+
+use tracing::debug;
+
+/// Where the GitHub API token came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthSource {
+    /// Passed explicitly with --token
+    Flag,
+    /// The GITHUB_TOKEN environment variable
+    GithubToken,
+    /// The GH_TOKEN environment variable
+    GhToken,
+    /// `gh auth token` from an authenticated GitHub CLI
+    GhCli,
+    /// No credentials
+    Anonymous,
+}
+
+impl fmt::Display for AuthSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let source = match self {
+            AuthSource::Flag => "--token",
+            AuthSource::GithubToken => "GITHUB_TOKEN",
+            AuthSource::GhToken => "GH_TOKEN",
+            AuthSource::GhCli => "gh CLI",
+            AuthSource::Anonymous => "anonymous",
+        };
+        write!(f, "{}", source)
+    }
+}
+
+/// Credentials for the GitHub API
+#[derive(Clone, PartialEq, Eq)]
+pub struct Auth {
+    pub token: Option<String>,
+    pub source: AuthSource,
+}
+
+// Never print the token itself
+impl fmt::Debug for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Auth")
+            .field("token", &self.token.as_ref().map(|_| "<redacted>"))
+            .field("source", &self.source)
+            .finish()
+    }
+}
+
+impl Auth {
+    pub fn anonymous() -> Self {
+        Self {
+            token: None,
+            source: AuthSource::Anonymous,
+        }
+    }
+
+    /// Discover credentials: an explicit token, then GITHUB_TOKEN/GH_TOKEN,
+    /// then `gh auth token`, then anonymous
+    pub fn discover(explicit: Option<String>, no_auth: bool) -> Self {
+        Self::discover_with(explicit, no_auth, |name| std::env::var(name).ok(), "gh")
+    }
+
+    fn discover_with(
+        explicit: Option<String>,
+        no_auth: bool,
+        env: impl Fn(&str) -> Option<String>,
+        gh_program: &str,
+    ) -> Self {
+        let auth = if no_auth {
+            Self::anonymous()
+        } else if let Some(token) = non_empty(explicit) {
+            Self::with_token(token, AuthSource::Flag)
+        } else if let Some(token) = non_empty(env("GITHUB_TOKEN")) {
+            Self::with_token(token, AuthSource::GithubToken)
+        } else if let Some(token) = non_empty(env("GH_TOKEN")) {
+            Self::with_token(token, AuthSource::GhToken)
+        } else if let Some(token) = gh_auth_token(gh_program) {
+            Self::with_token(token, AuthSource::GhCli)
+        } else {
+            Self::anonymous()
+        };
+
+        debug!("Using GitHub credentials from {}", auth.source);
+        auth
+    }
+
+    fn with_token(token: String, source: AuthSource) -> Self {
+        Self {
+            token: Some(token),
+            source,
+        }
+    }
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// Ask an authenticated GitHub CLI for its token, if the binary exists
+fn gh_auth_token(program: &str) -> Option<String> {
+    let output = Command::new(program)
+        .args(["auth", "token"])
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        debug!(
+            "`{} auth token` failed, not using gh CLI credentials",
+            program
+        );
+        return None;
+    }
+
+    non_empty(String::from_utf8(output.stdout).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn test_explicit_token_wins() {
+        let auth = Auth::discover_with(
+            Some("flag-token".to_string()),
+            false,
+            env(&[("GITHUB_TOKEN", "env-token")]),
+            "/nonexistent/gh",
+        );
+        assert_eq!(auth.token.as_deref(), Some("flag-token"));
+        assert_eq!(auth.source, AuthSource::Flag);
+    }
+
+    #[test]
+    fn test_env_token_order() {
+        let auth = Auth::discover_with(
+            None,
+            false,
+            env(&[("GITHUB_TOKEN", "github"), ("GH_TOKEN", "gh")]),
+            "/nonexistent/gh",
+        );
+        assert_eq!(auth.source, AuthSource::GithubToken);
+
+        let auth = Auth::discover_with(None, false, env(&[("GH_TOKEN", "gh")]), "/nonexistent/gh");
+        assert_eq!(auth.token.as_deref(), Some("gh"));
+        assert_eq!(auth.source, AuthSource::GhToken);
+    }
+
+    #[test]
+    fn test_no_auth_forces_anonymous() {
+        let auth = Auth::discover_with(
+            Some("flag-token".to_string()),
+            true,
+            env(&[("GITHUB_TOKEN", "env-token")]),
+            "/nonexistent/gh",
+        );
+        assert_eq!(auth, Auth::anonymous());
+    }
+
+    #[test]
+    fn test_missing_gh_is_anonymous() {
+        let auth = Auth::discover_with(None, false, env(&[]), "/nonexistent/gh");
+        assert_eq!(auth, Auth::anonymous());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_gh_cli_token() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = tempfile::TempDir::new().unwrap();
+        let gh = temp.path().join("gh");
+        std::fs::write(&gh, "#!/bin/sh\necho gho_fake_token\n").unwrap();
+        std::fs::set_permissions(&gh, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let auth = Auth::discover_with(None, false, env(&[]), gh.to_str().unwrap());
+        assert_eq!(auth.token.as_deref(), Some("gho_fake_token"));
+        assert_eq!(auth.source, AuthSource::GhCli);
+    }
+
+    #[test]
+    fn test_debug_redacts_token() {
+        let auth = Auth::with_token("secret".to_string(), AuthSource::Flag);
+        assert!(!format!("{:?}", auth).contains("secret"));
+    }
+}
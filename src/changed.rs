@@ -0,0 +1,199 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use git2::{Delta, DiffOptions, Repository};
+use tracing::debug;
+
+/// Base compared against when `--changed-only` is given without one: the
+/// remote's default branch, as recorded by clone or `git remote set-head`
+pub const DEFAULT_BASE: &str = "origin/HEAD";
+
+/// Branches tried, in order, when the remote's HEAD was never recorded
+const FALLBACK_BASES: [&str; 2] = ["origin/main", "origin/master"];
+
+/// Files added or modified in the working tree of the repository holding
+/// `dir`, relative to where HEAD forked from `base`, as canonical paths.
+/// Untracked files count as added; deleted files are left out
+pub fn changed_paths(dir: &Path, base: &str) -> Result<HashSet<PathBuf>> {
+    let repo = Repository::discover(dir).with_context(|| {
+        format!(
+            "--changed-only needs a git repository, but {} is not in one",
+            dir.display()
+        )
+    })?;
+    let workdir = repo
+        .workdir()
+        .context("--changed-only needs a working tree, not a bare repository")?;
+    let workdir = fs::canonicalize(workdir)?;
+
+    let base_commit = base_commit(&repo, base)?;
+    let head = repo
+        .head()
+        .and_then(|head| head.peel_to_commit())
+        .context("Repository has no HEAD commit")?;
+    let fork_point = repo
+        .merge_base(base_commit.id(), head.id())
+        .with_context(|| format!("HEAD has no common history with '{}'", base))?;
+    let tree = repo.find_commit(fork_point)?.tree()?;
+
+    let mut options = DiffOptions::new();
+    options.include_untracked(true).recurse_untracked_dirs(true);
+    let diff = repo.diff_tree_to_workdir_with_index(Some(&tree), Some(&mut options))?;
+
+    let changed: HashSet<PathBuf> = diff
+        .deltas()
+        .filter(|delta| delta.status() != Delta::Deleted)
+        .filter_map(|delta| delta.new_file().path())
+        .map(|path| workdir.join(path))
+        .collect();
+    debug!(
+        "{} file(s) changed since {} ({})",
+        changed.len(),
+        base,
+        &fork_point.to_string()[..8]
+    );
+
+    Ok(changed)
+}
+
+/// The commit `base` names. The default base falls back to origin/main or
+/// origin/master for a remote added by hand, whose HEAD git never recorded
+fn base_commit<'r>(repo: &'r Repository, base: &str) -> Result<git2::Commit<'r>> {
+    let fallbacks: &[&str] = if base == DEFAULT_BASE {
+        &FALLBACK_BASES
+    } else {
+        &[]
+    };
+    std::iter::once(base)
+        .chain(fallbacks.iter().copied())
+        .find_map(|name| {
+            let object = repo.revparse_single(name).ok()?;
+            let commit = object.peel_to_commit().ok()?;
+            debug!("Comparing against {} for --changed-only", name);
+            Some(commit)
+        })
+        .with_context(|| {
+            if base == DEFAULT_BASE {
+                "The remote's default branch is unknown; run `git remote set-head origin --auto` or pass one with --changed-only=<base>".to_string()
+            } else {
+                format!(
+                    "Base ref '{}' not found; pass one with --changed-only=<base>",
+                    base
+                )
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use git2::Signature;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// Stage everything in the working tree and commit it on HEAD
+    fn commit_all(repo: &Repository, message: &str) -> git2::Oid {
+        let mut index = repo.index().unwrap();
+        index
+            .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        index.update_all(["*"], None).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("test", "test@example.com").unwrap();
+        let parents = repo.head().ok().map(|head| head.peel_to_commit().unwrap());
+        let parents: Vec<&git2::Commit> = parents.iter().collect();
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &parents,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_changed_paths() {
+        let temp = TempDir::new().unwrap();
+        let root = fs::canonicalize(temp.path()).unwrap();
+        let repo = Repository::init(&root).unwrap();
+        for name in [
+            "modified.yml",
+            "deleted.yml",
+            "untouched.yml",
+            "committed.yml",
+        ] {
+            fs::write(root.join(name), "steps: []\n").unwrap();
+        }
+        fs::remove_file(root.join("committed.yml")).unwrap();
+        let base = commit_all(&repo, "base");
+        repo.reference("refs/remotes/origin/main", base, false, "test")
+            .unwrap();
+
+        // Committed on the branch, then edited, deleted and added in the
+        // working tree
+        fs::write(root.join("committed.yml"), "steps: []\n").unwrap();
+        commit_all(&repo, "branch");
+        fs::write(root.join("modified.yml"), "steps: [x]\n").unwrap();
+        fs::remove_file(root.join("deleted.yml")).unwrap();
+        fs::write(root.join("added.yml"), "steps: []\n").unwrap();
+
+        let changed = changed_paths(&root, DEFAULT_BASE).unwrap();
+        let expected: HashSet<PathBuf> = ["modified.yml", "committed.yml", "added.yml"]
+            .iter()
+            .map(|name| root.join(name))
+            .collect();
+        assert_eq!(changed, expected);
+
+        let error = changed_paths(&root, "origin/develop").unwrap_err();
+        assert!(error.to_string().contains("origin/develop"));
+    }
+
+    #[test]
+    fn test_changed_paths_from_remote_head() {
+        let temp = TempDir::new().unwrap();
+        let root = fs::canonicalize(temp.path()).unwrap();
+        let repo = Repository::init(&root).unwrap();
+        fs::write(root.join("base.yml"), "steps: []\n").unwrap();
+        let develop = commit_all(&repo, "base");
+        fs::write(root.join("main.yml"), "steps: []\n").unwrap();
+        let main = commit_all(&repo, "main");
+        repo.reference("refs/remotes/origin/main", main, false, "test")
+            .unwrap();
+
+        // Without origin/HEAD the default falls back to origin/main
+        fs::write(root.join("added.yml"), "steps: []\n").unwrap();
+        let changed = changed_paths(&root, DEFAULT_BASE).unwrap();
+        assert_eq!(changed, HashSet::from([root.join("added.yml")]));
+
+        // The remote's HEAD names its default branch
+        repo.reference("refs/remotes/origin/develop", develop, false, "test")
+            .unwrap();
+        repo.reference_symbolic(
+            "refs/remotes/origin/HEAD",
+            "refs/remotes/origin/develop",
+            false,
+            "test",
+        )
+        .unwrap();
+        let changed = changed_paths(&root, DEFAULT_BASE).unwrap();
+        let expected: HashSet<PathBuf> = ["main.yml", "added.yml"]
+            .iter()
+            .map(|name| root.join(name))
+            .collect();
+        assert_eq!(changed, expected);
+    }
+
+    #[test]
+    fn test_changed_paths_outside_repository() {
+        let temp = TempDir::new().unwrap();
+        let error = changed_paths(temp.path(), DEFAULT_BASE).unwrap_err();
+        assert!(error.to_string().contains("needs a git repository"));
+    }
+}
@@ -0,0 +1,151 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    policy::PolicyReason,
+    workflow::{ArchivedAction, PolicyViolation, RunStatus, SkippedFile, WorkflowProcessor},
+};
+
+/// Results from scanning for unpinned actions without resolving anything
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UnpinnedResults {
+    #[serde(default)]
+    pub status: RunStatus,
+    pub files_processed: usize,
+    pub files_skipped: usize,
+    /// Files skipped for a reason worth fixing, such as invalid UTF-8
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped_files: Vec<SkippedFile>,
+    #[serde(default)]
+    pub files_unchanged: usize,
+    pub actions_found: usize,
+    pub already_pinned: usize,
+    pub unpinned_actions: Vec<UnpinnedAction>,
+    /// Uses whose ref is a `${{ ... }}` expression, only known when the
+    /// workflow runs: there's nothing to pin, so they aren't unpinned
+    #[serde(default)]
+    pub dynamic_refs: usize,
+    /// Actions whose repository is archived, with --check-health
+    #[serde(default)]
+    pub archived_actions: Vec<ArchivedAction>,
+    /// Uses of blocked actions
+    #[serde(default)]
+    pub policy_violations: Vec<PolicyViolation>,
+    /// Every use classified against the config's `[policy]`, when it has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy_checks: Option<Vec<PolicyCheck>>,
+}
+
+impl UnpinnedResults {
+    /// Uses counting against the run: with a `[policy]` only those
+    /// violating it, otherwise every unpinned one
+    pub fn failing_uses(&self) -> usize {
+        match &self.policy_checks {
+            Some(checks) => checks.iter().filter(|check| !check.compliant).count(),
+            None => self.unpinned_actions.len(),
+        }
+    }
+
+    /// The check's overall status; it never changes anything
+    pub fn run_status(&self) -> RunStatus {
+        if self.failing_uses() > 0 || !self.policy_violations.is_empty() {
+            RunStatus::Unresolved
+        } else {
+            RunStatus::Ok
+        }
+    }
+}
+
+/// How one action use fares against the config's `[policy]`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyCheck {
+    pub file: String,
+    pub line: usize,
+    /// The action as used, "owner/repo@ref"
+    pub action: String,
+    pub compliant: bool,
+    pub reason: PolicyReason,
+}
+
+/// An action use that is not pinned to a SHA
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnpinnedAction {
+    pub file: String,
+    pub line: usize,
+    /// 1-based byte column of the "owner/repo@ref" reference
+    #[serde(default)]
+    pub column: usize,
+    pub action: String,
+    /// None for ref-less uses, which follow the default branch
+    pub reference: Option<String>,
+}
+
+impl WorkflowProcessor {
+    /// Count and list unpinned actions without contacting any remote
+    pub async fn report_unpinned(&self) -> Result<UnpinnedResults> {
+        let mut collected = self.collect_actions().await?;
+
+        let mut results = UnpinnedResults {
+            files_processed: collected.files_processed,
+            files_skipped: collected.files_skipped,
+            skipped_files: std::mem::take(&mut collected.skipped_files),
+            files_unchanged: collected.files_unchanged,
+            policy_violations: std::mem::take(&mut collected.policy_violations),
+            ..Default::default()
+        };
+
+        let mut unhealthy = self.check_health(&collected.workflows).await;
+        unhealthy.extend(self.check_archived(&collected.workflows).await);
+        results.archived_actions = self.archived_actions(&collected.workflows, &unhealthy);
+
+        if let Some(policy) = &self.policy {
+            results.policy_checks = Some(
+                collected
+                    .workflows
+                    .iter()
+                    .flat_map(|workflow| {
+                        workflow.actions.iter().map(|uses| {
+                            let reason = match policy.evaluate(uses) {
+                                PolicyReason::TrustedOwner
+                                    if self.strict_sha && uses.action.is_short_sha() =>
+                                {
+                                    PolicyReason::Unpinned
+                                },
+                                reason => reason,
+                            };
+                            PolicyCheck {
+                                file: workflow.path.clone(),
+                                line: uses.line_number,
+                                action: uses.action.to_string(),
+                                compliant: !reason.is_violation(),
+                                reason,
+                            }
+                        })
+                    })
+                    .collect(),
+            );
+        }
+
+        for workflow in &collected.workflows {
+            results.actions_found += workflow.actions.len();
+            results.already_pinned += workflow.pinned_count();
+            let (dynamic, unpinned): (Vec<_>, Vec<_>) = workflow
+                .unpinned_actions()
+                .into_iter()
+                .partition(|uses| uses.action.is_expression());
+            results.dynamic_refs += dynamic.len();
+            results
+                .unpinned_actions
+                .extend(unpinned.into_iter().map(|uses| UnpinnedAction {
+                    file: workflow.path.clone(),
+                    line: uses.line_number,
+                    column: uses.column(),
+                    action: uses.action.repository.clone(),
+                    reference: (!uses.unversioned).then(|| uses.action.qualified_reference()),
+                }));
+        }
+
+        results.status = results.run_status();
+        Ok(results)
+    }
+}
@@ -0,0 +1,204 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use git2::{Commit, Oid, Repository, Signature};
+use tracing::debug;
+
+use crate::{pr, workflow::ProcessResults};
+
+/// Subject line used by --commit when no message is given
+pub const DEFAULT_MESSAGE: &str = "chore: pin GitHub Actions to commit SHAs";
+
+/// `subject` followed by one line per action and image the run pinned
+pub fn commit_message(results: &ProcessResults, subject: &str) -> String {
+    format!("{}\n\n{}\n", subject, pr::pinned_lines(results).join("\n"))
+}
+
+/// Stage exactly the files a run modified and commit them on the current
+/// branch. Other staged changes would be swept into the commit, so they are
+/// refused unless `force`. Returns None when nothing was modified
+pub fn commit_changes(results: &ProcessResults, message: &str, force: bool) -> Result<Option<Oid>> {
+    let files: Vec<PathBuf> = results.modified_files.iter().map(PathBuf::from).collect();
+    let Some(first) = files.first() else {
+        return Ok(None);
+    };
+
+    let repo = Repository::discover(first.parent().unwrap_or(Path::new(".")))
+        .with_context(|| format!("{} is not in a git repository", first.display()))?;
+    let signature = repo.signature().context(
+        "No git identity to commit as; set one with `git config user.name \"Your Name\"` and `git config user.email you@example.com`",
+    )?;
+
+    commit_files(&repo, &files, message, &signature, force).map(Some)
+}
+
+/// Stage `files` and commit them on HEAD as `signature`, refusing other
+/// staged changes unless `force`
+pub fn commit_files(
+    repo: &Repository,
+    files: &[PathBuf],
+    message: &str,
+    signature: &Signature,
+    force: bool,
+) -> Result<Oid> {
+    let workdir = repo
+        .workdir()
+        .context("Cannot commit in a bare repository")?;
+    let workdir = fs::canonicalize(workdir)?;
+    let paths = files
+        .iter()
+        .map(|file| pr::relative_to(&workdir, file))
+        .collect::<Result<Vec<_>>>()?;
+
+    // An unborn branch has no HEAD commit yet
+    let head = repo
+        .head()
+        .ok()
+        .map(|head| head.peel_to_commit())
+        .transpose()?;
+    let mut index = repo.index()?;
+
+    if !force {
+        refuse_other_staged(repo, head.as_ref(), &paths)?;
+    }
+
+    for path in &paths {
+        index.add_path(path)?;
+    }
+    index.write()?;
+    let tree = repo.find_tree(index.write_tree()?)?;
+
+    let parents: Vec<&Commit> = head.iter().collect();
+    let oid = repo.commit(Some("HEAD"), signature, signature, message, &tree, &parents)?;
+
+    debug!("Committed {} with {} file(s)", oid, paths.len());
+    Ok(oid)
+}
+
+/// Fail when the index differs from `head` in anything but `paths`
+fn refuse_other_staged(repo: &Repository, head: Option<&Commit>, paths: &[PathBuf]) -> Result<()> {
+    let head_tree = head.map(Commit::tree).transpose()?;
+    let index = repo.index()?;
+    let staged = repo.diff_tree_to_index(head_tree.as_ref(), Some(&index), None)?;
+    let ours: BTreeSet<&Path> = paths.iter().map(PathBuf::as_path).collect();
+    let unrelated: Vec<String> = staged
+        .deltas()
+        .filter_map(|delta| delta.new_file().path().or(delta.old_file().path()))
+        .filter(|path| !ours.contains(path))
+        .map(|path| path.display().to_string())
+        .collect();
+    if !unrelated.is_empty() {
+        anyhow::bail!(
+            "Refusing to commit: other changes are staged ({}); unstage them or pass --force-commit",
+            unrelated.join(", ")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::workflow::PinnedActionResult;
+
+    const SHA: &str = "b4ffde65f46336ab88eb53be808477a3936bae11";
+
+    /// A repository with an identity and one commit holding an unpinned
+    /// workflow, which is then pinned in the working tree
+    fn pinned_repo(temp: &TempDir) -> (Repository, ProcessResults) {
+        let repo = Repository::init(temp.path()).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "test").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+
+        let workflows = temp.path().join(".github/workflows");
+        fs::create_dir_all(&workflows).unwrap();
+        let workflow = workflows.join("ci.yml");
+        fs::write(&workflow, "steps:\n  - uses: actions/checkout@v4\n").unwrap();
+
+        let mut index = repo.index().unwrap();
+        index
+            .add_path(Path::new(".github/workflows/ci.yml"))
+            .unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("test", "test@example.com").unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "init", &tree, &[])
+            .unwrap();
+        drop(tree);
+
+        fs::write(
+            &workflow,
+            format!("steps:\n  - uses: actions/checkout@{} # v4\n", SHA),
+        )
+        .unwrap();
+        let file = workflow.to_string_lossy().to_string();
+        let results = ProcessResults {
+            pinned_actions: vec![PinnedActionResult {
+                file: file.clone(),
+                line: 3,
+                column: 11,
+                action: "actions/checkout".to_string(),
+                old_ref: "v4".to_string(),
+                sha: SHA.to_string(),
+                previous_sha: None,
+                applied: true,
+                was_unversioned: false,
+                on_branch: false,
+                signature: None,
+                link: String::new(),
+                source: None,
+            }],
+            modified_files: vec![file],
+            ..Default::default()
+        };
+
+        (repo, results)
+    }
+
+    #[test]
+    fn test_commit_changes() {
+        let temp = TempDir::new().unwrap();
+        let (repo, results) = pinned_repo(&temp);
+
+        let message = commit_message(&results, DEFAULT_MESSAGE);
+        let oid = commit_changes(&results, &message, false).unwrap().unwrap();
+
+        let commit = repo.find_commit(oid).unwrap();
+        assert_eq!(commit.summary(), Some(DEFAULT_MESSAGE));
+        assert!(
+            commit
+                .message()
+                .unwrap()
+                .contains(&format!("- `actions/checkout@v4` → `{}`", SHA))
+        );
+        assert_eq!(repo.head().unwrap().target(), Some(oid));
+        assert!(repo.statuses(None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_commit_refuses_unrelated_staged_changes() {
+        let temp = TempDir::new().unwrap();
+        let (repo, results) = pinned_repo(&temp);
+
+        fs::write(temp.path().join("README.md"), "wip\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("README.md")).unwrap();
+        index.write().unwrap();
+
+        let error = commit_changes(&results, DEFAULT_MESSAGE, false).unwrap_err();
+        assert!(error.to_string().contains("README.md"));
+
+        let oid = commit_changes(&results, DEFAULT_MESSAGE, true)
+            .unwrap()
+            .unwrap();
+        let tree = repo.find_commit(oid).unwrap().tree().unwrap();
+        assert!(tree.get_path(Path::new("README.md")).is_ok());
+    }
+}
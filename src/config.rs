@@ -0,0 +1,160 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::{action::ActionRef, policy::Policy};
+
+/// Default config file name, looked up in the current directory
+pub const DEFAULT_CONFIG_FILE: &str = ".pin-actions.toml";
+
+/// Settings loaded from a `.pin-actions.toml` file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Glob patterns (relative to the workflows directory) for files to skip
+    pub ignore_files: Vec<String>,
+    /// `with:` inputs whose `owner/repo@ref` values are pinned too
+    pub pin_inputs: Vec<String>,
+    /// Globs over "owner/repo" for actions that must not be used at all
+    pub blocked_actions: Vec<String>,
+    /// SHAs mandated for "owner/repo" or "owner/repo@ref", used in place of
+    /// whatever the ref resolves to
+    pub overrides: BTreeMap<String, String>,
+    /// Which actions must be pinned and whether branches are allowed
+    pub policy: Option<Policy>,
+}
+
+impl Config {
+    /// Load a config file from the given path
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+
+        let config: Self = toml::from_str(&content)
+            .with_context(|| format!("Invalid config file: {}", path.display()))?;
+        config
+            .validate()
+            .with_context(|| format!("Invalid config file: {}", path.display()))?;
+        Ok(config)
+    }
+
+    /// Check every override names an action and a full commit SHA
+    fn validate(&self) -> Result<()> {
+        for (action, sha) in &self.overrides {
+            let (repository, valid) = match action.split_once('@') {
+                Some(_) => match ActionRef::parse(action) {
+                    Some(parsed) => (parsed.repository, true),
+                    None => (String::new(), false),
+                },
+                None => (action.clone(), true),
+            };
+            if !valid || !repository.contains('/') {
+                anyhow::bail!("Invalid override '{}': expected owner/repo[@ref]", action);
+            }
+            if sha.len() != 40 || !sha.chars().all(|c| c.is_ascii_hexdigit()) {
+                anyhow::bail!("Invalid SHA '{}' for override {}", sha, action);
+            }
+        }
+        Ok(())
+    }
+
+    /// Load the explicitly given config file, or the default one if present
+    pub fn discover(explicit: Option<&Path>) -> Result<Self> {
+        if let Some(path) = explicit {
+            return Self::load(path);
+        }
+
+        let default_path = PathBuf::from(DEFAULT_CONFIG_FILE);
+        if default_path.is_file() {
+            debug!("Using config file {}", default_path.display());
+            return Self::load(default_path);
+        }
+
+        Ok(Self::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::RequirePin;
+
+    #[test]
+    fn test_load_config() {
+        let temp = tempfile::NamedTempFile::new().unwrap();
+        fs::write(temp.path(), "ignore_files = [\"release-*.yml\"]\n").unwrap();
+
+        let config = Config::load(temp.path()).unwrap();
+        assert_eq!(config.ignore_files, vec!["release-*.yml"]);
+    }
+
+    #[test]
+    fn test_load_overrides() {
+        let temp = tempfile::NamedTempFile::new().unwrap();
+        fs::write(
+            temp.path(),
+            "[overrides]\n\"tj-actions/changed-files\" = \"b4ffde65f46336ab88eb53be808477a3936bae11\"\n\"actions/checkout@v4\" = \"11bd71901bbe5b1630ceea73d27597364c9af683\"\n",
+        )
+        .unwrap();
+
+        let config = Config::load(temp.path()).unwrap();
+        assert_eq!(config.overrides.len(), 2);
+        assert_eq!(
+            config.overrides["actions/checkout@v4"],
+            "11bd71901bbe5b1630ceea73d27597364c9af683"
+        );
+
+        fs::write(
+            temp.path(),
+            "[overrides]\n\"actions/checkout\" = \"b4ffde65\"\n",
+        )
+        .unwrap();
+        let err = format!("{:#}", Config::load(temp.path()).unwrap_err());
+        assert!(err.contains("Invalid SHA 'b4ffde65' for override actions/checkout"));
+
+        fs::write(
+            temp.path(),
+            "[overrides]\n\"checkout@\" = \"b4ffde65f46336ab88eb53be808477a3936bae11\"\n",
+        )
+        .unwrap();
+        assert!(Config::load(temp.path()).is_err());
+    }
+
+    #[test]
+    fn test_load_policy() {
+        let temp = tempfile::NamedTempFile::new().unwrap();
+        fs::write(
+            temp.path(),
+            "[policy]\ntrusted_owners = [\"my-org\"]\nrequire_pin = \"third-party\"\n",
+        )
+        .unwrap();
+
+        let policy = Config::load(temp.path()).unwrap().policy.unwrap();
+        assert_eq!(policy.trusted_owners, vec!["my-org"]);
+        assert_eq!(policy.require_pin, RequirePin::ThirdParty);
+        assert!(policy.allow_branches);
+
+        fs::write(temp.path(), "[policy]\nrequire_pin = \"some\"\n").unwrap();
+        assert!(Config::load(temp.path()).is_err());
+    }
+
+    #[test]
+    fn test_unknown_key_rejected() {
+        let temp = tempfile::NamedTempFile::new().unwrap();
+        fs::write(temp.path(), "unknown = true\n").unwrap();
+
+        assert!(Config::load(temp.path()).is_err());
+    }
+
+    #[test]
+    fn test_discover_missing_explicit_file() {
+        assert!(Config::discover(Some(Path::new("/nonexistent/config.toml"))).is_err());
+    }
+}
@@ -0,0 +1,511 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    action::ActionRef,
+    error::ErrorDetail,
+    parser::WorkflowFile,
+    strict_sha::StrictShaResults,
+    workflow::{
+        CollectedActions, FilePatch, MovedRef, PinnedActionResult, WorkflowProcessor, write_error,
+    },
+};
+
+/// A pinned action whose version comment now resolves to a different SHA
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DriftedAction {
+    pub file: String,
+    pub line: usize,
+    /// 1-based byte column of the "owner/repo@ref" reference
+    #[serde(default)]
+    pub column: usize,
+    pub action: String,
+    pub comment_ref: String,
+    pub pinned_sha: String,
+    pub current_sha: String,
+    /// Most specific tag at the current SHA (e.g. "v4.2.1" for "v4"), when
+    /// checked for with --outdated
+    #[serde(default)]
+    pub newer_tag: Option<String>,
+}
+
+/// A pin whose version comment --sync-comments couldn't resolve
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnresolvedComment {
+    pub file: String,
+    pub line: usize,
+    pub action: String,
+    pub comment_ref: String,
+    pub pinned_sha: String,
+    pub message: String,
+}
+
+/// Results from checking pinned actions for drift
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DriftResults {
+    pub files_processed: usize,
+    pub pins_checked: usize,
+    /// SHA pins without a version comment, which can't be checked
+    #[serde(default)]
+    pub uncommented_pins: usize,
+    pub errors: usize,
+    #[serde(default)]
+    pub errors_detail: Vec<ErrorDetail>,
+    pub drifted_actions: Vec<DriftedAction>,
+    /// Pins rewritten to their current SHA (or that would be, under dry-run)
+    #[serde(default)]
+    pub updated: Vec<PinnedActionResult>,
+    /// Refs resolving somewhere other than when first seen
+    #[serde(default)]
+    pub moved_refs: Vec<MovedRef>,
+    /// Pins left alone by --sync-comments because their comment didn't
+    /// resolve; reported rather than counted as errors
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unresolved_comments: Vec<UnresolvedComment>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub patches: Vec<FilePatch>,
+}
+
+impl WorkflowProcessor {
+    /// Resolve the version comment of every pinned action and report pins
+    /// whose ref now points at a different SHA, without modifying anything
+    pub async fn report_drift(&self) -> Result<DriftResults> {
+        let collected = self.collect_actions().await?;
+        let (_, results) = self.check_drift(collected, false, false).await?;
+        Ok(results)
+    }
+
+    /// Report pins whose version comment now resolves to a different SHA,
+    /// naming the most specific tag at the current SHA. With `update`, the
+    /// outdated pins are rewritten to the current SHA
+    pub async fn report_outdated(&self, update: bool) -> Result<DriftResults> {
+        let collected = self.collect_actions().await?;
+        let (workflows, mut results) = self.check_drift(collected, true, update).await?;
+        if update && !results.drifted_actions.is_empty() {
+            self.update_pins(&workflows, &mut results);
+        }
+        Ok(results)
+    }
+
+    /// The outdated pins and abbreviated SHAs `audit` reports, from a
+    /// single pass over the files
+    pub async fn audit(&self) -> Result<(DriftResults, StrictShaResults)> {
+        let mut collected = self.collect_actions().await?;
+        // Files that failed to parse are reported once, with the outdated pins
+        let parse_errors = std::mem::take(&mut collected.errors);
+        let short_shas = self.strict_sha_in(&collected, false).await?;
+        collected.errors = parse_errors;
+        let (_, outdated) = self.check_drift(collected, true, false).await?;
+        Ok((outdated, short_shas))
+    }
+
+    /// Treat version comments as the source of truth: rewrite every pin whose
+    /// comment resolves to a different SHA to that SHA, e.g. after someone
+    /// edits `# v3` to `# v4` by hand. Comments that don't resolve are
+    /// reported as unresolved comments instead of errors
+    pub async fn sync_comments(&self) -> Result<DriftResults> {
+        let collected = self.collect_actions().await?;
+        let (workflows, mut results) = self.check_drift(collected, false, true).await?;
+
+        for workflow in &workflows {
+            for uses in workflow.actions.iter().filter(|u| u.action.is_sha) {
+                let Some(comment_ref) = &uses.comment_ref else {
+                    continue;
+                };
+                let Some(current) =
+                    ActionRef::parse(&format!("{}@{}", uses.action.repository, comment_ref))
+                else {
+                    continue;
+                };
+                let target = current.to_string();
+                let Some(error) = results.errors_detail.iter().find(|e| e.target == target) else {
+                    continue;
+                };
+                warn!(
+                    "Leaving {}:{} alone: {}@{} didn't resolve",
+                    workflow.path,
+                    uses.line_number,
+                    current.repository,
+                    current.qualified_reference()
+                );
+                results.unresolved_comments.push(UnresolvedComment {
+                    file: workflow.path.clone(),
+                    line: uses.line_number,
+                    action: current.repository.clone(),
+                    comment_ref: current.qualified_reference(),
+                    pinned_sha: uses.action.reference.clone(),
+                    message: error.message.clone(),
+                });
+            }
+        }
+        let unresolved: HashSet<String> = results
+            .unresolved_comments
+            .iter()
+            .map(|unresolved| format!("{}@{}", unresolved.action, unresolved.comment_ref))
+            .collect();
+        results
+            .errors_detail
+            .retain(|error| !unresolved.contains(&error.target));
+        results.errors = results.errors_detail.len();
+
+        if !results.drifted_actions.is_empty() {
+            self.update_pins(&workflows, &mut results);
+        }
+        Ok(results)
+    }
+
+    /// Rewrite drifted pins to their current SHA
+    fn update_pins(&self, workflows: &[WorkflowFile], results: &mut DriftResults) {
+        let mut pinned_map = HashMap::new();
+        for drifted in &results.drifted_actions {
+            let Some(current) =
+                ActionRef::parse(&format!("{}@{}", drifted.action, drifted.comment_ref))
+            else {
+                continue;
+            };
+            pinned_map.insert(
+                format!("{}@{}", drifted.action, drifted.pinned_sha),
+                self.pin(current, drifted.current_sha.clone()),
+            );
+        }
+
+        for workflow in workflows {
+            // Only the drifted lines are rewritten: another line pinned to the
+            // same SHA may carry a comment that still matches it
+            let lines: HashSet<usize> = results
+                .drifted_actions
+                .iter()
+                .filter(|drifted| drifted.file == workflow.path)
+                .map(|drifted| drifted.line)
+                .collect();
+            if lines.is_empty() {
+                continue;
+            }
+            let outdated = WorkflowFile {
+                path: workflow.path.clone(),
+                content: workflow.content.clone(),
+                actions: workflow
+                    .actions
+                    .iter()
+                    .filter(|uses| lines.contains(&uses.line_number))
+                    .cloned()
+                    .collect(),
+                images: Vec::new(),
+                lossy: workflow.lossy,
+                fingerprint: workflow.fingerprint,
+            };
+
+            if let Err(e) = self.rewrite_workflow(&outdated, &pinned_map, &mut results.updated) {
+                error!("Failed to rewrite {}: {}", workflow.path, e);
+                results.errors += 1;
+                results.errors_detail.push(write_error(workflow, &e));
+            }
+        }
+        if let Some(error) = self.save_archive() {
+            results.errors += 1;
+            results.errors_detail.push(error);
+        }
+        results.patches = self.take_patches();
+    }
+
+    /// Pair each commented pin in `collected` with its comment's ref resolved
+    /// now, returning the parsed files and the pins that no longer match.
+    /// Refs that moved since first seen aren't offered for updating; the
+    /// known refs store is only written when `update` is set
+    async fn check_drift(
+        &self,
+        collected: CollectedActions,
+        newer_tags: bool,
+        update: bool,
+    ) -> Result<(Vec<WorkflowFile>, DriftResults)> {
+        let CollectedActions {
+            workflows: parsed_workflows,
+            errors: mut errors_detail,
+            ..
+        } = collected;
+
+        // Pair each commented pin with the action its comment refers to
+        let mut pins = Vec::new();
+        let mut uncommented_pins = 0;
+        for workflow in &parsed_workflows {
+            for uses in workflow.actions.iter().filter(|u| u.action.is_sha) {
+                let Some(comment_ref) = &uses.comment_ref else {
+                    uncommented_pins += usize::from(!uses.ignored);
+                    continue;
+                };
+                if let Some(current) =
+                    ActionRef::parse(&format!("{}@{}", uses.action.repository, comment_ref))
+                {
+                    pins.push((workflow, uses, current));
+                }
+            }
+        }
+
+        let mut unique: HashMap<String, ActionRef> = HashMap::new();
+        for (_, _, current) in &pins {
+            unique
+                .entry(current.to_string())
+                .or_insert_with(|| current.clone());
+        }
+
+        info!("Checking {} pinned action(s) for drift", pins.len());
+        if uncommented_pins > 0 {
+            warn!(
+                "{} pin(s) have no version comment (e.g. pinned with --no-comment), so they \
+                 can't be checked or refreshed automatically",
+                uncommented_pins
+            );
+        }
+
+        // The lockfile holds what was pinned, so it would answer with the
+        // very SHAs being checked and hide any drift; it only stands in for
+        // the network offline. Drift checks are read-only, so nothing new is
+        // saved to it
+        let mut lockfile = if self.offline {
+            self.load_lockfile()?
+        } else {
+            None
+        };
+        let (mut shas, errors) = self
+            .resolve_actions(unique.into_values().collect(), lockfile.as_mut())
+            .await;
+        self.stop_on_error(&errors, None)?;
+        errors_detail.extend(errors);
+        let moved_refs = self.check_known_refs(&mut shas, update).await?;
+
+        let mut drifted_actions = Vec::new();
+        for (workflow, uses, current) in &pins {
+            let Some(current_sha) = shas.get(&current.to_string()) else {
+                continue;
+            };
+
+            if !current_sha.eq_ignore_ascii_case(&uses.action.reference) {
+                warn!(
+                    "Drift in {}:{}: {}@{} now points at {}",
+                    workflow.path,
+                    uses.line_number,
+                    current.repository,
+                    current.qualified_reference(),
+                    current_sha
+                );
+                drifted_actions.push(DriftedAction {
+                    file: workflow.path.clone(),
+                    line: uses.line_number,
+                    column: uses.column(),
+                    action: current.repository.clone(),
+                    comment_ref: current.qualified_reference(),
+                    pinned_sha: uses.action.reference.clone(),
+                    current_sha: current_sha.clone(),
+                    newer_tag: None,
+                });
+            }
+        }
+
+        if newer_tags && !self.offline {
+            let git = self.git_resolver();
+            let mut found: HashMap<String, Option<String>> = HashMap::new();
+
+            for drifted in &mut drifted_actions {
+                let key = format!(
+                    "{}@{}@{}",
+                    drifted.action, drifted.comment_ref, drifted.current_sha
+                );
+                if !found.contains_key(&key) {
+                    let current =
+                        ActionRef::parse(&format!("{}@{}", drifted.action, drifted.comment_ref));
+                    let tag = match current {
+                        Some(current) => git
+                            .newer_tag(&current, &drifted.current_sha)
+                            .await
+                            .unwrap_or_else(|e| {
+                                debug!("Failed to list tags of {}: {}", drifted.action, e);
+                                None
+                            }),
+                        None => None,
+                    };
+                    found.insert(key.clone(), tag);
+                }
+                drifted.newer_tag = found[&key].clone();
+            }
+        }
+
+        let results = DriftResults {
+            files_processed: parsed_workflows.len(),
+            pins_checked: pins.len(),
+            uncommented_pins,
+            errors: errors_detail.len(),
+            errors_detail,
+            drifted_actions,
+            moved_refs,
+            ..Default::default()
+        };
+        Ok((parsed_workflows, results))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{lockfile::Lockfile, workflow::BlockedActions};
+
+    #[tokio::test]
+    async fn test_report_drift() {
+        let temp = TempDir::new().unwrap();
+        let old_sha = "b4ffde65f46336ab88eb53be808477a3936bae11";
+        let new_sha = "11bd71901bbe5b1630ceea73d27597364c9af683";
+        let content = format!(
+            "steps:\n  - uses: actions/checkout@{} # v4\n  - uses: actions/cache@{} # v3\n  - uses: actions/setup-node@{}\n",
+            old_sha, old_sha, old_sha
+        );
+        let path = temp.path().join("ci.yml");
+        fs::write(&path, &content).unwrap();
+
+        let lockfile_path = temp.path().join("pin-actions.lock");
+        let mut lockfile = Lockfile::default();
+        lockfile.insert("actions/checkout@v4".to_string(), new_sha.to_string());
+        lockfile.insert("actions/cache@v3".to_string(), old_sha.to_string());
+        lockfile.save(&lockfile_path).unwrap();
+
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 10)
+                .with_lockfile(Some(lockfile_path))
+                .with_offline(true);
+
+        let results = processor.report_drift().await.unwrap();
+
+        assert_eq!(results.pins_checked, 2);
+        assert_eq!(results.errors, 0);
+        assert_eq!(results.drifted_actions.len(), 1);
+
+        let drifted = &results.drifted_actions[0];
+        assert_eq!(drifted.action, "actions/checkout");
+        assert_eq!(drifted.line, 2);
+        assert_eq!(drifted.comment_ref, "v4");
+        assert_eq!(drifted.pinned_sha, old_sha);
+        assert_eq!(drifted.current_sha, new_sha);
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn test_update_records_previous_sha() {
+        let temp = TempDir::new().unwrap();
+        let old_sha = "b4ffde65f46336ab88eb53be808477a3936bae11";
+        let new_sha = "11bd71901bbe5b1630ceea73d27597364c9af683";
+        let path = temp.path().join("ci.yml");
+        fs::write(
+            &path,
+            format!(
+                "steps:\n  - uses: actions/checkout@{} # v4\n  - uses: actions/cache@{} # v3\n",
+                old_sha, old_sha
+            ),
+        )
+        .unwrap();
+
+        let lockfile_path = temp.path().join("pin-actions.lock");
+        let mut lockfile = Lockfile::default();
+        lockfile.insert("actions/checkout@v4".to_string(), new_sha.to_string());
+        lockfile.insert("actions/cache@v3".to_string(), old_sha.to_string());
+        lockfile.save(&lockfile_path).unwrap();
+
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 10)
+                .with_lockfile(Some(lockfile_path))
+                .with_offline(true);
+        let results = processor.report_outdated(true).await.unwrap();
+
+        assert_eq!(results.updated.len(), 1);
+        let updated = &results.updated[0];
+        assert_eq!(updated.action, "actions/checkout");
+        assert_eq!(updated.previous_sha.as_deref(), Some(old_sha));
+        assert_eq!(updated.sha, new_sha);
+        assert!(updated.applied);
+        assert!(
+            fs::read_to_string(&path)
+                .unwrap()
+                .contains(&format!("actions/checkout@{} # v4", new_sha))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_rewrites_only_drifted_lines() {
+        let temp = TempDir::new().unwrap();
+        let old_sha = "b4ffde65f46336ab88eb53be808477a3936bae11";
+        let new_sha = "11bd71901bbe5b1630ceea73d27597364c9af683";
+        let path = temp.path().join("ci.yml");
+        // Both lines are pinned to the same SHA, but only v4 has moved
+        let current_line = format!("  - uses: actions/checkout@{} # v4.1.1\n", old_sha);
+        fs::write(
+            &path,
+            format!(
+                "steps:\n  - uses: actions/checkout@{} # v4\n{}",
+                old_sha, current_line
+            ),
+        )
+        .unwrap();
+
+        let lockfile_path = temp.path().join("pin-actions.lock");
+        let mut lockfile = Lockfile::default();
+        lockfile.insert("actions/checkout@v4".to_string(), new_sha.to_string());
+        lockfile.insert("actions/checkout@v4.1.1".to_string(), old_sha.to_string());
+        lockfile.save(&lockfile_path).unwrap();
+
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 10)
+                .with_lockfile(Some(lockfile_path))
+                .with_offline(true);
+        let results = processor.report_outdated(true).await.unwrap();
+
+        assert_eq!(results.drifted_actions.len(), 1);
+        assert_eq!(results.updated.len(), 1);
+        assert_eq!(results.updated[0].line, 2);
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.contains(&format!("actions/checkout@{} # v4\n", new_sha)));
+        assert!(content.ends_with(&current_line));
+    }
+
+    #[tokio::test]
+    async fn test_update_leaves_blocked_actions_alone() {
+        let temp = TempDir::new().unwrap();
+        let old_sha = "b4ffde65f46336ab88eb53be808477a3936bae11";
+        let new_sha = "11bd71901bbe5b1630ceea73d27597364c9af683";
+        let path = temp.path().join("ci.yml");
+        let blocked_line = format!("  - uses: evil/tool@{} # v1\n", old_sha);
+        fs::write(
+            &path,
+            format!(
+                "steps:\n  - uses: actions/checkout@{} # v4\n{}",
+                old_sha, blocked_line
+            ),
+        )
+        .unwrap();
+
+        let lockfile_path = temp.path().join("pin-actions.lock");
+        let mut lockfile = Lockfile::default();
+        lockfile.insert("actions/checkout@v4".to_string(), new_sha.to_string());
+        lockfile.insert("evil/tool@v1".to_string(), new_sha.to_string());
+        lockfile.save(&lockfile_path).unwrap();
+
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 10)
+                .with_lockfile(Some(lockfile_path))
+                .with_offline(true)
+                .with_blocked_actions(BlockedActions::new(vec!["evil/*".to_string()]).unwrap());
+        let results = processor.report_outdated(true).await.unwrap();
+
+        // The blocked pin isn't even checked, let alone moved
+        assert_eq!(results.pins_checked, 1);
+        assert_eq!(results.updated.len(), 1);
+        assert_eq!(results.updated[0].action, "actions/checkout");
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.contains(&format!("actions/checkout@{} # v4", new_sha)));
+        assert!(content.ends_with(&blocked_line));
+    }
+}
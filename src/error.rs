@@ -0,0 +1,156 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Stable classification of a failure, for automation to branch on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum ErrorCode {
+    /// The remote rejected our credentials, or we had none to offer
+    #[serde(rename = "E_AUTH")]
+    Auth,
+    /// The repository has no such tag, branch or commit
+    #[serde(rename = "E_REF_NOT_FOUND")]
+    RefNotFound,
+    /// The remote couldn't be reached or answered with an error
+    #[serde(rename = "E_NETWORK")]
+    Network,
+    /// The ref isn't in the lockfile and `--offline` forbids looking it up
+    #[serde(rename = "E_OFFLINE")]
+    Offline,
+    /// A workflow file couldn't be read or parsed
+    #[serde(rename = "E_PARSE")]
+    Parse,
+    /// A rewritten workflow file (or its backup) couldn't be written
+    #[serde(rename = "E_WRITE")]
+    Write,
+    /// A tag is unsigned or its signature couldn't be verified, with
+    /// `--require-signed`
+    #[serde(rename = "E_SIGNATURE")]
+    Signature,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::Auth => "E_AUTH",
+            ErrorCode::RefNotFound => "E_REF_NOT_FOUND",
+            ErrorCode::Network => "E_NETWORK",
+            ErrorCode::Offline => "E_OFFLINE",
+            ErrorCode::Parse => "E_PARSE",
+            ErrorCode::Write => "E_WRITE",
+            ErrorCode::Signature => "E_SIGNATURE",
+        }
+    }
+
+    /// Classify a failure to resolve an action's ref. Anything that isn't
+    /// recognisably an auth or missing-ref failure is treated as a network
+    /// error, since retrying is the safest default
+    pub fn classify_resolve(error: &anyhow::Error) -> Self {
+        for cause in error.chain() {
+            if cause.downcast_ref::<RefNotFound>().is_some() {
+                return ErrorCode::RefNotFound;
+            }
+
+            if let Some(error) = cause.downcast_ref::<git2::Error>() {
+                if error.code() == git2::ErrorCode::Auth
+                    || error.code() == git2::ErrorCode::Certificate
+                    || error.class() == git2::ErrorClass::Ssh
+                {
+                    return ErrorCode::Auth;
+                }
+            }
+
+            if let Some(error) = cause.downcast_ref::<reqwest::Error>() {
+                if let Some(status) = error.status() {
+                    if status == reqwest::StatusCode::UNAUTHORIZED
+                        || status == reqwest::StatusCode::FORBIDDEN
+                    {
+                        return ErrorCode::Auth;
+                    }
+                }
+            }
+        }
+
+        ErrorCode::Network
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A repository was reached but has no matching ref
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct RefNotFound(pub String);
+
+/// A file isn't valid UTF-8; the offset counts bytes from its start
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("invalid UTF-8 at byte {0}")]
+pub struct InvalidUtf8(pub usize);
+
+/// A classified failure recorded in results
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorDetail {
+    pub code: ErrorCode,
+    /// The action ("owner/repo@ref") or file the error concerns
+    pub target: String,
+    pub message: String,
+}
+
+impl ErrorDetail {
+    pub fn new(code: ErrorCode, target: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            target: target.into(),
+            message: message.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+
+    use super::*;
+
+    #[test]
+    fn test_classify_ref_not_found() {
+        let error = anyhow::Error::new(RefNotFound("no tag 'v9'".to_string()))
+            .context("Failed to resolve actions/checkout@v9");
+        assert_eq!(ErrorCode::classify_resolve(&error), ErrorCode::RefNotFound);
+    }
+
+    #[test]
+    fn test_classify_auth() {
+        let error: anyhow::Result<()> = Err(git2::Error::new(
+            git2::ErrorCode::Auth,
+            git2::ErrorClass::Http,
+            "authentication required",
+        ))
+        .context("Failed to connect");
+        assert_eq!(
+            ErrorCode::classify_resolve(&error.unwrap_err()),
+            ErrorCode::Auth
+        );
+    }
+
+    #[test]
+    fn test_classify_network() {
+        let error = anyhow::Error::new(git2::Error::new(
+            git2::ErrorCode::GenericError,
+            git2::ErrorClass::Net,
+            "failed to connect to 127.0.0.1",
+        ));
+        assert_eq!(ErrorCode::classify_resolve(&error), ErrorCode::Network);
+    }
+
+    #[test]
+    fn test_error_code_serialization() {
+        let detail = ErrorDetail::new(ErrorCode::RefNotFound, "actions/checkout@v9", "missing");
+        let json = serde_json::to_string(&detail).unwrap();
+        assert!(json.contains("\"code\":\"E_REF_NOT_FOUND\""));
+    }
+}
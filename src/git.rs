@@ -1,64 +1,684 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
 };
 
 use anyhow::{Context, Result};
-use git2::Repository;
+use futures::future::BoxFuture;
+use git2::{
+    Cred, CredentialType, ErrorClass, ErrorCode, FetchOptions, RemoteCallbacks, Repository,
+};
+use tempfile::TempDir;
 use tokio::task;
 use tracing::debug;
 
-use crate::action::ActionRef;
+use crate::{
+    action::{ActionRef, PEEL_SUFFIX, Protocol, RefKind},
+    error::RefNotFound,
+    http::HttpOptions,
+    lock,
+    resolver::{Resolution, Resolver, Source},
+};
+
+/// Default host action repositories are fetched from
+pub const DEFAULT_GIT_HOST: &str = "github.com";
+
+/// Refspec no remote has, for downloads that should only list refs
+const NO_REFS: &str = "refs/pin-actions/none:refs/pin-actions/none";
+
+/// Where action repositories live and how to connect to them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitRemote {
+    pub protocol: Protocol,
+    pub host: String,
+    /// Private key for SSH; the ssh-agent is used when unset
+    pub ssh_key: Option<PathBuf>,
+    /// Extra headers sent over HTTPS
+    pub http: HttpOptions,
+}
+
+impl Default for GitRemote {
+    fn default() -> Self {
+        Self {
+            protocol: Protocol::Https,
+            host: DEFAULT_GIT_HOST.to_string(),
+            ssh_key: None,
+            http: HttpOptions::default(),
+        }
+    }
+}
 
 /// Git resolver for fetching SHAs from remote repositories
 #[derive(Clone)]
 pub struct GitResolver {
-    cache: Arc<Mutex<HashMap<String, String>>>,
+    cache: Arc<Mutex<HashMap<String, Resolution>>>,
+    /// Default branch of each repository listed so far, by "owner/repo",
+    /// taken from the same ref advertisement as the lookups
+    default_branches: Arc<Mutex<BTreeMap<String, String>>>,
+    remote: GitRemote,
+    /// Parent of the scratch repository; the system temp dir when unset
+    scratch_parent: Option<PathBuf>,
+    /// Bare repository used to talk to remotes, created on first use and
+    /// removed when the last clone of the resolver is dropped
+    scratch: Arc<Mutex<Option<TempDir>>>,
+    /// Directory of local clones, laid out as `owner/repo` or
+    /// `owner/repo.git`, consulted before the network
+    mirror_dir: Option<PathBuf>,
+    /// Never fall back to the network when the mirror can't answer
+    offline: bool,
+    /// Consider prerelease and build-metadata tags as newer tags
+    allow_prerelease: bool,
 }
 
 impl GitResolver {
     pub fn new() -> Self {
         Self {
             cache: Arc::new(Mutex::new(HashMap::new())),
+            default_branches: Arc::default(),
+            remote: GitRemote::default(),
+            scratch_parent: None,
+            scratch: Arc::new(Mutex::new(None)),
+            mirror_dir: None,
+            offline: false,
+            allow_prerelease: false,
+        }
+    }
+
+    /// Read refs from local clones under `dir` where available
+    pub fn with_mirror_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.mirror_dir = dir;
+        self
+    }
+
+    /// Resolve from the mirror only, failing refs it can't answer rather
+    /// than asking the remote
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Let `newer_tag` pick prerelease tags such as "v4.1.0-rc.1"
+    pub fn with_allow_prerelease(mut self, allow_prerelease: bool) -> Self {
+        self.allow_prerelease = allow_prerelease;
+        self
+    }
+
+    /// The local clone of an action's repository, if the mirror has one
+    pub fn mirror_path(&self, action: &ActionRef) -> Option<PathBuf> {
+        let dir = self.mirror_dir.as_ref()?;
+        let repository = action.base_repository();
+        [
+            dir.join(repository),
+            dir.join(format!("{}.git", repository)),
+        ]
+        .into_iter()
+        .find(|path| path.is_dir())
+    }
+
+    /// Create the scratch repository under `dir` instead of the system temp
+    /// dir (which honours TMPDIR)
+    pub fn with_scratch_parent(mut self, dir: Option<PathBuf>) -> Self {
+        self.scratch_parent = dir;
+        self
+    }
+
+    /// Path of the scratch repository, creating it on first use
+    fn scratch_repo(&self) -> Result<PathBuf> {
+        let mut scratch = self.scratch.lock().unwrap();
+        if let Some(dir) = scratch.as_ref() {
+            return Ok(dir.path().to_path_buf());
         }
+
+        let parent = self
+            .scratch_parent
+            .clone()
+            .unwrap_or_else(std::env::temp_dir);
+        let dir = tempfile::Builder::new()
+            .prefix("pin-actions-git-")
+            .tempdir_in(&parent)
+            .with_context(|| {
+                format!(
+                    "Failed to create git scratch directory in {}",
+                    parent.display()
+                )
+            })?;
+        Repository::init_bare(dir.path()).context("Failed to initialise git scratch repository")?;
+        debug!("Using git scratch directory {}", dir.path().display());
+
+        let path = dir.path().to_path_buf();
+        *scratch = Some(dir);
+        Ok(path)
+    }
+
+    /// Connect to action repositories through the given remote
+    pub fn with_remote(mut self, remote: GitRemote) -> Self {
+        self.remote = remote;
+        self
+    }
+
+    /// Seed the cache with SHAs keyed by "owner/repo@ref"; primed entries
+    /// are answered without touching the mirror or the network
+    pub fn prime(&self, entries: HashMap<String, String>) {
+        self.cache.lock().unwrap().extend(
+            entries
+                .into_iter()
+                .map(|(key, sha)| (key, Resolution::new(sha, None, Source::Cache))),
+        );
+    }
+
+    /// Everything cached so far, primed or resolved, keyed by
+    /// "owner/repo@ref"
+    pub fn snapshot(&self) -> HashMap<String, String> {
+        self.cache
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(key, resolution)| (key.clone(), resolution.sha.clone()))
+            .collect()
     }
 
     /// Resolve a reference to its SHA using git ls-remote
     pub async fn resolve_sha(&self, action: &ActionRef) -> Result<String> {
-        let key = action.to_string();
+        Ok(self.resolve_ref(action).await?.sha)
+    }
 
+    /// Resolve a reference from the cache, the mirror or the remote, in that
+    /// order
+    pub async fn resolve_ref(&self, action: &ActionRef) -> Result<Resolution> {
         // Check cache first
-        {
-            let cache = self.cache.lock().unwrap();
-            if let Some(sha) = cache.get(&key) {
-                debug!("Cache hit for {}", key);
-                return Ok(sha.clone());
-            }
+        if let Some(resolution) = self.cached(action) {
+            return Ok(resolution);
         }
 
-        // Resolve via git
-        let git_url = action.git_url();
+        let resolution = match self.resolve_mirrored(action).await {
+            Some(Ok(resolution)) => resolution,
+            // A stale mirror may lack a new tag; the remote has the final say
+            Some(Err(e)) if !self.offline => {
+                debug!(
+                    "Mirror couldn't resolve {}, asking the remote: {}",
+                    action, e
+                );
+                self.resolve_remote(action).await?
+            },
+            Some(Err(e)) => return Err(e),
+            None if self.offline => anyhow::bail!(
+                "{} is not in the mirror directory (offline mode)",
+                action.base_repository()
+            ),
+            None => self.resolve_remote(action).await?,
+        };
+
+        self.remember(action, &resolution);
+        Ok(resolution)
+    }
+
+    /// The cached resolution of `action`, primed or resolved earlier
+    pub fn cached(&self, action: &ActionRef) -> Option<Resolution> {
+        let key = action.to_string();
+        let resolution = self.cache.lock().unwrap().get(&key)?.clone();
+        debug!("Cache hit for {}", key);
+        Some(Resolution {
+            source: Source::Cache,
+            ..resolution
+        })
+    }
+
+    /// Cache a resolution of `action` made elsewhere, e.g. by another
+    /// backend using this resolver as its fallback, so it is answered from
+    /// the cache next time and included in [`snapshot`](Self::snapshot)
+    pub fn remember(&self, action: &ActionRef, resolution: &Resolution) {
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(action.to_string(), resolution.clone());
+    }
+
+    /// Resolve via git ls-remote against the action's repository
+    async fn resolve_remote(&self, action: &ActionRef) -> Result<Resolution> {
+        let git_url = action.git_url(self.remote.protocol, &self.remote.host);
         let reference = action.reference.clone();
+        let ref_kind = action.ref_kind;
+        let remote = self.remote.clone();
+        let scratch = self.scratch_repo()?;
 
         debug!("Resolving {} from {}", reference, git_url);
 
-        let sha = task::spawn_blocking(move || Self::git_ls_remote(&git_url, &reference))
+        let (resolution, branch) = task::spawn_blocking(move || {
+            Self::git_ls_remote(&scratch, &remote, &git_url, &reference, ref_kind)
+        })
+        .await
+        .context("Failed to spawn git ls-remote task")??;
+        if let Some(branch) = branch {
+            self.learn_default_branch(action, branch);
+        }
+        Ok(resolution)
+    }
+
+    /// The default branch of each repository listed so far, by
+    /// "owner/repo"
+    pub fn default_branches(&self) -> BTreeMap<String, String> {
+        self.default_branches.lock().unwrap().clone()
+    }
+
+    fn learn_default_branch(&self, action: &ActionRef, branch: String) {
+        self.default_branches
+            .lock()
+            .unwrap()
+            .insert(action.base_repository().to_string(), branch);
+    }
+
+    /// Resolve from the action's local clone, or None if it isn't mirrored
+    async fn resolve_mirrored(&self, action: &ActionRef) -> Option<Result<Resolution>> {
+        let path = self.mirror_path(action)?;
+        // Local lookups always peel to the commit
+        let reference = action.ref_name().to_string();
+        let ref_kind = action.ref_kind;
+
+        debug!("Resolving {} from mirror {}", reference, path.display());
+
+        let result =
+            task::spawn_blocking(move || Self::git_resolve_local(&path, &reference, ref_kind))
+                .await
+                .context("Failed to spawn mirror lookup task");
+        Some(result.and_then(|resolution| resolution))
+    }
+
+    /// Resolve a ref in a local clone, peeling tags to the commit they mark.
+    /// Besides its own branches, a non-bare clone's remote-tracking branches
+    /// count, since a clone only checks out the default branch
+    fn git_resolve_local(
+        path: &Path,
+        reference: &str,
+        ref_kind: Option<RefKind>,
+    ) -> Result<Resolution> {
+        let repo = Repository::open(path)
+            .with_context(|| format!("Failed to open mirror {}", path.display()))?;
+
+        let tag = format!("refs/tags/{}", reference);
+        let branches = [
+            format!("refs/heads/{}", reference),
+            format!("refs/remotes/origin/{}", reference),
+        ];
+        let candidates: Vec<&str> = match ref_kind {
+            Some(RefKind::Tag) => vec![&tag],
+            Some(RefKind::Branch) => branches.iter().map(String::as_str).collect(),
+            None => std::iter::once(tag.as_str())
+                .chain(branches.iter().map(String::as_str))
+                .chain(std::iter::once(reference))
+                .collect(),
+        };
+
+        for name in candidates {
+            if let Ok(found) = repo.find_reference(name) {
+                let commit = found
+                    .peel_to_commit()
+                    .with_context(|| format!("'{}' in {} is not a commit", name, path.display()))?;
+                return Ok(Resolution::new(
+                    commit.id().to_string(),
+                    kind_of_ref(found.name().unwrap_or(name)),
+                    Source::Mirror,
+                ));
+            }
+        }
+
+        // An abbreviated SHA can be expanded against the local objects
+        let is_short_sha = ref_kind.is_none()
+            && reference.len() >= 7
+            && reference.chars().all(|c| c.is_ascii_hexdigit());
+        if is_short_sha {
+            if let Ok(commit) = repo
+                .revparse_single(reference)
+                .and_then(|object| object.peel_to_commit())
+            {
+                return Ok(Resolution::new(
+                    commit.id().to_string(),
+                    None,
+                    Source::Mirror,
+                ));
+            }
+        }
+
+        let what = ref_kind.map_or("reference".to_string(), |kind| kind.to_string());
+        Err(RefNotFound(format!(
+            "No {} '{}' in mirror {}",
+            what,
+            reference,
+            path.display()
+        ))
+        .into())
+    }
+
+    /// The commit a tag or branch named exactly like the action's reference
+    /// points at, for a 40-hex reference that may be a ref name rather than
+    /// a commit. None when no ref has that name. Tags win over branches
+    pub async fn ref_named(&self, action: &ActionRef) -> Result<Option<Resolution>> {
+        let name = action.reference.clone();
+        match self.mirror_path(action) {
+            Some(path) => task::spawn_blocking(move || {
+                for kind in [RefKind::Tag, RefKind::Branch] {
+                    match Self::git_resolve_local(&path, &name, Some(kind)) {
+                        Ok(resolution) => return Ok(Some(resolution)),
+                        Err(e) if e.downcast_ref::<RefNotFound>().is_some() => continue,
+                        Err(e) => return Err(e),
+                    }
+                }
+                Ok(None)
+            })
             .await
-            .context("Failed to spawn git ls-remote task")??;
+            .context("Failed to spawn mirror lookup task")?,
+            None if self.offline => anyhow::bail!(
+                "{} is not in the mirror directory (offline mode)",
+                action.base_repository()
+            ),
+            None => {
+                let git_url = action.git_url(self.remote.protocol, &self.remote.host);
+                let remote = self.remote.clone();
+                let scratch = self.scratch_repo()?;
+                task::spawn_blocking(move || {
+                    Self::git_remote_ref_named(&scratch, &remote, &git_url, &name)
+                })
+                .await
+                .context("Failed to spawn git ls-remote task")?
+            },
+        }
+    }
+
+    /// Look for a tag or branch called `name` in the remote's ref
+    /// advertisement, taking an annotated tag's peeled "^{}" entry
+    fn git_remote_ref_named(
+        scratch: &Path,
+        git_remote: &GitRemote,
+        url: &str,
+        name: &str,
+    ) -> Result<Option<Resolution>> {
+        let repo = Repository::open_bare(scratch)?;
+        let remote = Self::connect(&repo, git_remote, url)?;
+        let heads = remote.list()?;
+        let oid_of = |ref_name: &str| {
+            heads
+                .iter()
+                .find(|h| h.name() == ref_name)
+                .map(|h| h.oid().to_string())
+        };
+
+        let tag = format!("refs/tags/{}", name);
+        let found = oid_of(&format!("{}^{{}}", tag))
+            .or_else(|| oid_of(&tag))
+            .map(|oid| (oid, RefKind::Tag))
+            .or_else(|| oid_of(&format!("refs/heads/{}", name)).map(|oid| (oid, RefKind::Branch)));
+        Ok(found.map(|(oid, kind)| Resolution::new(oid, Some(kind), Source::Remote)))
+    }
+
+    /// Where `action` is resolved from: its mirror clone, or the remote URL
+    pub fn source(&self, action: &ActionRef) -> String {
+        match self.mirror_path(action) {
+            Some(path) => path.display().to_string(),
+            None => action.git_url(self.remote.protocol, &self.remote.host),
+        }
+    }
+
+    /// Up to `limit` tag and branch names in the action's repository, those
+    /// closest to its ref first, to suggest what a failing ref meant
+    pub async fn available_refs(&self, action: &ActionRef, limit: usize) -> Result<Vec<String>> {
+        let names = match self.mirror_path(action) {
+            Some(path) => task::spawn_blocking(move || Self::git_local_refs(&path))
+                .await
+                .context("Failed to spawn mirror lookup task")??,
+            None if self.offline => anyhow::bail!(
+                "{} is not in the mirror directory (offline mode)",
+                action.base_repository()
+            ),
+            None => {
+                let git_url = action.git_url(self.remote.protocol, &self.remote.host);
+                let remote = self.remote.clone();
+                let scratch = self.scratch_repo()?;
+                task::spawn_blocking(move || Self::git_remote_refs(&scratch, &remote, &git_url))
+                    .await
+                    .context("Failed to spawn git ls-remote task")??
+            },
+        };
 
-        // Cache the result
+        Ok(closest_refs(names, &action.reference, limit))
+    }
+
+    fn git_local_refs(path: &Path) -> Result<Vec<String>> {
+        let repo = Repository::open(path)
+            .with_context(|| format!("Failed to open mirror {}", path.display()))?;
+        let names = repo
+            .references()?
+            .filter_map(|reference| reference.ok()?.name().and_then(short_ref_name))
+            .collect();
+        Ok(names)
+    }
+
+    fn git_remote_refs(scratch: &Path, git_remote: &GitRemote, url: &str) -> Result<Vec<String>> {
+        let repo = Repository::open_bare(scratch)?;
+        let remote = Self::connect(&repo, git_remote, url)?;
+        let names = remote
+            .list()?
+            .iter()
+            .filter_map(|head| short_ref_name(head.name()))
+            .collect();
+        Ok(names)
+    }
+
+    /// Find the name of the branch the repository's HEAD points at. A
+    /// repository already listed while resolving is answered from that
+    pub async fn default_branch(&self, action: &ActionRef) -> Result<String> {
+        if let Some(branch) = self
+            .default_branches
+            .lock()
+            .unwrap()
+            .get(action.base_repository())
         {
-            let mut cache = self.cache.lock().unwrap();
-            cache.insert(key, sha.clone());
+            return Ok(branch.clone());
+        }
+        if let Some(path) = self.mirror_path(action) {
+            let repo = Repository::open(&path)
+                .with_context(|| format!("Failed to open mirror {}", path.display()))?;
+            let head = repo.find_reference("HEAD")?;
+            if let Some(branch) = head.symbolic_target() {
+                return Ok(branch.trim_start_matches("refs/heads/").to_string());
+            }
+        }
+        if self.offline {
+            anyhow::bail!(
+                "Default branch of {} is unknown (offline mode)",
+                action.base_repository()
+            );
         }
 
-        Ok(sha)
+        let git_url = action.git_url(self.remote.protocol, &self.remote.host);
+        let remote = self.remote.clone();
+        let scratch = self.scratch_repo()?;
+
+        debug!("Looking up default branch of {}", git_url);
+
+        let branch =
+            task::spawn_blocking(move || Self::git_default_branch(&scratch, &remote, &git_url))
+                .await
+                .context("Failed to spawn git ls-remote task")??;
+        self.learn_default_branch(action, branch.clone());
+        Ok(branch)
+    }
+
+    fn git_default_branch(scratch: &Path, git_remote: &GitRemote, url: &str) -> Result<String> {
+        let repo = Repository::open_bare(scratch)?;
+        let remote = Self::connect(&repo, git_remote, url)?;
+        let remote_heads = remote.list()?;
+
+        anyhow::ensure!(
+            remote_heads.iter().any(|h| h.name() == "HEAD"),
+            "Repository '{}' has no HEAD",
+            url
+        );
+        head_branch(remote_heads)
+            .with_context(|| format!("Could not determine the default branch of '{}'", url))
+    }
+
+    /// Find the most specific tag in the same release line as `action`'s
+    /// ref that points at `sha`, e.g. "v4.2.1" for "v4". Prereleases are
+    /// skipped unless allowed
+    pub async fn newer_tag(&self, action: &ActionRef, sha: &str) -> Result<Option<String>> {
+        let git_url = action.git_url(self.remote.protocol, &self.remote.host);
+        let remote = self.remote.clone();
+        let scratch = self.scratch_repo()?;
+        let sha = sha.to_string();
+
+        let (tags, _) =
+            task::spawn_blocking(move || Self::git_refs_at(&scratch, &remote, &git_url, &sha))
+                .await
+                .context("Failed to spawn git ls-remote task")??;
+
+        Ok(most_specific_tag(
+            &tags,
+            &action.reference,
+            self.allow_prerelease,
+        ))
+    }
+
+    /// Name the commit `sha` of the action's repository for a version
+    /// comment: the most specific tag pointing at it, otherwise a branch
+    /// whose tip it is (or, in a mirror, that contains it). None when
+    /// nothing names it
+    pub async fn name_commit(&self, action: &ActionRef, sha: &str) -> Result<Option<CommitName>> {
+        let sha = sha.to_string();
+        let (tags, branches) = match self.mirror_path(action) {
+            Some(path) => task::spawn_blocking(move || Self::git_local_refs_at(&path, &sha))
+                .await
+                .context("Failed to spawn mirror lookup task")??,
+            None if self.offline => anyhow::bail!(
+                "{} is not in the mirror directory (offline mode)",
+                action.base_repository()
+            ),
+            None => {
+                let git_url = action.git_url(self.remote.protocol, &self.remote.host);
+                let remote = self.remote.clone();
+                let scratch = self.scratch_repo()?;
+                task::spawn_blocking(move || Self::git_refs_at(&scratch, &remote, &git_url, &sha))
+                    .await
+                    .context("Failed to spawn git ls-remote task")??
+            },
+        };
+
+        Ok(best_tag(&tags, self.allow_prerelease)
+            .map(CommitName::Tag)
+            .or_else(|| branches.into_iter().min().map(CommitName::Branch)))
+    }
+
+    /// Tags and branches whose tip is `sha`
+    fn git_refs_at(
+        scratch: &Path,
+        git_remote: &GitRemote,
+        url: &str,
+        sha: &str,
+    ) -> Result<(Vec<String>, Vec<String>)> {
+        let repo = Repository::open_bare(scratch)?;
+        let remote = Self::connect(&repo, git_remote, url)?;
+        let heads: Vec<&str> = remote
+            .list()?
+            .iter()
+            .filter(|h| h.oid().to_string().eq_ignore_ascii_case(sha))
+            .map(|h| h.name())
+            .collect();
+
+        // Annotated tags are listed twice; the peeled "^{}" entry carries
+        // the commit
+        let mut tags: Vec<String> = heads
+            .iter()
+            .filter_map(|name| name.strip_prefix("refs/tags/"))
+            .map(|name| name.trim_end_matches("^{}").to_string())
+            .collect();
+        tags.dedup();
+        let branches = heads
+            .iter()
+            .filter_map(|name| name.strip_prefix("refs/heads/"))
+            .map(str::to_string)
+            .collect();
+        Ok((tags, branches))
+    }
+
+    /// Tags pointing at `sha` in a local clone, and branches containing it
+    fn git_local_refs_at(path: &Path, sha: &str) -> Result<(Vec<String>, Vec<String>)> {
+        let repo = Repository::open(path)
+            .with_context(|| format!("Failed to open mirror {}", path.display()))?;
+        let target = git2::Oid::from_str(sha)?;
+
+        let mut tags = Vec::new();
+        let mut branches = Vec::new();
+        for reference in repo.references()? {
+            let reference = reference?;
+            let (Some(name), Ok(commit)) = (reference.name(), reference.peel_to_commit()) else {
+                continue;
+            };
+            let Some(short) = short_ref_name(name) else {
+                continue;
+            };
+
+            if name.starts_with("refs/tags/") {
+                if commit.id() == target {
+                    tags.push(short);
+                }
+            } else if commit.id() == target || repo.graph_descendant_of(commit.id(), target)? {
+                branches.push(short);
+            }
+        }
+
+        Ok((tags, branches))
     }
 
     /// Execute git ls-remote to get SHA
-    fn git_ls_remote(url: &str, reference: &str) -> Result<String> {
-        let repo = Repository::init_bare("/tmp/pin-actions-git")?;
-        let mut remote = repo.remote_anonymous(url)?;
+    fn git_ls_remote(
+        scratch: &Path,
+        git_remote: &GitRemote,
+        url: &str,
+        reference: &str,
+        ref_kind: Option<RefKind>,
+    ) -> Result<(Resolution, Option<String>)> {
+        let repo = Repository::open_bare(scratch)?;
+        let remote = Self::connect(&repo, git_remote, url)?;
+        let remote_heads = remote.list()?;
+        let resolution = Self::find_in_heads(remote_heads, url, reference, ref_kind)?;
+        Ok((resolution, head_branch(remote_heads)))
+    }
+
+    /// Resolve a ref against a remote's ref advertisement
+    fn find_in_heads(
+        remote_heads: &[git2::RemoteHead<'_>],
+        url: &str,
+        reference: &str,
+        ref_kind: Option<RefKind>,
+    ) -> Result<Resolution> {
+        let resolved =
+            |oid: git2::Oid, kind| Ok(Resolution::new(oid.to_string(), kind, Source::Remote));
+
+        // "v4^{}" asks for the commit an annotated tag marks, advertised as
+        // the tag's peeled entry; other refs are commits already
+        let (reference, peeled) = match reference.strip_suffix(PEEL_SUFFIX) {
+            Some(name) => (name, true),
+            None => (reference, false),
+        };
+        let find = |ref_name: &str| {
+            let peeled_name = format!("{}{}", ref_name, PEEL_SUFFIX);
+            peeled
+                .then(|| remote_heads.iter().find(|h| h.name() == peeled_name))
+                .flatten()
+                .or_else(|| remote_heads.iter().find(|h| h.name() == ref_name))
+        };
+
+        // A hinted ref only ever matches that exact kind
+        if let Some(kind) = ref_kind {
+            let ref_name = format!("{}{}", kind.ref_prefix(), reference);
+            return match find(&ref_name) {
+                Some(remote_head) => resolved(remote_head.oid(), Some(kind)),
+                None => Err(RefNotFound(format!(
+                    "No {} '{}' in repository '{}'",
+                    kind, reference, url
+                ))
+                .into()),
+            };
+        }
 
         // Try to fetch the reference
         let refs_to_fetch = vec![
@@ -67,51 +687,329 @@ impl GitResolver {
             reference.to_string(),
         ];
 
-        remote.connect(git2::Direction::Fetch)?;
-        let remote_heads = remote.list()?;
-
         for ref_name in refs_to_fetch {
-            if let Some(remote_head) = remote_heads.iter().find(|h| h.name() == ref_name) {
-                let oid = remote_head.oid();
-                return Ok(oid.to_string());
+            if let Some(remote_head) = find(&ref_name) {
+                return resolved(remote_head.oid(), kind_of_ref(&ref_name));
+            }
+        }
+
+        // An abbreviated SHA can be expanded when it is the tip of some ref
+        let is_short_sha = reference.len() >= 7 && reference.chars().all(|c| c.is_ascii_hexdigit());
+        if is_short_sha {
+            let reference = reference.to_ascii_lowercase();
+            if let Some(remote_head) = remote_heads
+                .iter()
+                .find(|h| h.oid().to_string().starts_with(&reference))
+            {
+                return resolved(remote_head.oid(), None);
             }
         }
 
         // If no exact match, try partial match
-        for remote_head in remote_heads {
-            if remote_head.name().ends_with(&reference) {
-                let oid = remote_head.oid();
-                return Ok(oid.to_string());
+        let names: Vec<&str> = remote_heads.iter().map(|h| h.name()).collect();
+        if let Some(name) = partial_match(&names, reference) {
+            if let Some(remote_head) = find(name) {
+                debug!("Partial match for '{}': {}", reference, name);
+                return resolved(remote_head.oid(), kind_of_ref(name));
             }
         }
 
-        anyhow::bail!(
+        Err(RefNotFound(format!(
             "Could not resolve reference '{}' in repository '{}'",
-            reference,
-            url
-        )
+            reference, url
+        ))
+        .into())
     }
 
-    /// Batch resolve multiple actions concurrently
-    pub async fn batch_resolve(
-        &self,
-        actions: Vec<ActionRef>,
-        concurrency: usize,
-    ) -> Vec<(ActionRef, Result<String>)> {
-        use futures::stream::{self, StreamExt};
-
-        stream::iter(actions)
-            .map(|action| {
-                let resolver = self.clone();
-                async move {
-                    let result = resolver.resolve_sha(&action).await;
-                    (action, result)
-                }
-            })
-            .buffer_unordered(concurrency)
-            .collect()
-            .await
+    /// Connect an anonymous remote for listing refs
+    fn connect<'r>(
+        repo: &'r Repository,
+        git_remote: &GitRemote,
+        url: &str,
+    ) -> Result<git2::Remote<'r>> {
+        let mut remote = repo.remote_anonymous(url)?;
+        // The scratch repository's config is the user's global and system
+        // config, where credential helpers are set up
+        let config = repo.config().ok();
+        if git_remote.http.headers.is_empty() {
+            remote
+                .connect_auth(
+                    git2::Direction::Fetch,
+                    Some(Self::callbacks(git_remote, config)),
+                    None,
+                )
+                .map_err(|e| Self::connect_error(git_remote, url, e))?;
+            return Ok(remote);
+        }
+
+        // connect_auth can't send custom headers, but a download can. The
+        // refspec matches nothing, so it only lists the refs and leaves the
+        // remote connected
+        let mut options = FetchOptions::new();
+        options.remote_callbacks(Self::callbacks(git_remote, config));
+        git_remote.http.apply_to_fetch(&mut options);
+        remote
+            .download(&[NO_REFS], Some(&mut options))
+            .map_err(|e| Self::connect_error(git_remote, url, e))?;
+        Ok(remote)
+    }
+
+    /// Credential callbacks offering the configured SSH key or the
+    /// ssh-agent, and over HTTPS whatever git's `credential.helper` returns
+    /// for the host
+    fn callbacks(git_remote: &GitRemote, config: Option<git2::Config>) -> RemoteCallbacks<'_> {
+        let mut callbacks = RemoteCallbacks::new();
+        let mut attempted = false;
+
+        callbacks.credentials(move |url, username, allowed| {
+            // libgit2 keeps asking after rejected credentials; give up after
+            // one try
+            if attempted {
+                return Err(git2::Error::from_str("credentials were rejected"));
+            }
+            attempted = true;
+
+            if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+                let config = config.as_ref().ok_or_else(|| {
+                    git2::Error::from_str("no git config to find a credential helper in")
+                })?;
+                return Cred::credential_helper(config, url, username);
+            }
+            if !allowed.contains(CredentialType::SSH_KEY) {
+                return Err(git2::Error::from_str("no usable SSH credentials"));
+            }
+
+            let username = username.unwrap_or("git");
+            match &git_remote.ssh_key {
+                Some(key) => Cred::ssh_key(username, None, key, None),
+                None => Cred::ssh_key_from_agent(username),
+            }
+        });
+
+        callbacks
+    }
+
+    /// Turn SSH connection failures into errors that say how to fix them
+    fn connect_error(git_remote: &GitRemote, url: &str, error: git2::Error) -> anyhow::Error {
+        let host = &git_remote.host;
+
+        let hint = if error.code() == ErrorCode::Certificate
+            || (error.class() == ErrorClass::Ssh && error.message().contains("host key"))
+        {
+            format!(
+                "host key verification failed for {}; add it to ~/.ssh/known_hosts \
+                 (e.g. `ssh-keyscan {} >> ~/.ssh/known_hosts`)",
+                host, host
+            )
+        } else if error.code() == ErrorCode::Auth && git_remote.protocol == Protocol::Https {
+            format!(
+                "HTTPS authentication to {} failed; configure a git credential.helper for it \
+                 or pass an Authorization --header",
+                host
+            )
+        } else if error.code() == ErrorCode::Auth || error.class() == ErrorClass::Ssh {
+            match &git_remote.ssh_key {
+                Some(key) => format!(
+                    "SSH authentication to {} failed with key {}",
+                    host,
+                    key.display()
+                ),
+                None => format!(
+                    "SSH authentication to {} failed; load a key into ssh-agent or pass --ssh-key",
+                    host
+                ),
+            }
+        } else {
+            return anyhow::Error::new(error).context(format!("Failed to connect to {}", url));
+        };
+
+        anyhow::Error::new(error).context(hint)
+    }
+}
+
+impl Resolver for GitResolver {
+    fn resolve<'a>(&'a self, action: &'a ActionRef) -> BoxFuture<'a, Result<Resolution>> {
+        Box::pin(self.resolve_ref(action))
+    }
+
+    fn default_branches(&self) -> BTreeMap<String, String> {
+        GitResolver::default_branches(self)
+    }
+
+    fn prime(&self, entries: HashMap<String, String>) {
+        GitResolver::prime(self, entries);
+    }
+
+    fn snapshot(&self) -> HashMap<String, String> {
+        GitResolver::snapshot(self)
+    }
+}
+
+/// The branch a remote's HEAD points at, from its ref advertisement.
+/// Servers that don't advertise the symref still list the branch with the
+/// same commit
+fn head_branch(remote_heads: &[git2::RemoteHead<'_>]) -> Option<String> {
+    let head = remote_heads.iter().find(|h| h.name() == "HEAD")?;
+    let branch = head.symref_target().map(str::to_string).or_else(|| {
+        remote_heads
+            .iter()
+            .find(|h| h.name().starts_with("refs/heads/") && h.oid() == head.oid())
+            .map(|h| h.name().to_string())
+    })?;
+    Some(branch.trim_start_matches("refs/heads/").to_string())
+}
+
+/// The kind of ref a full ref name is: tags under refs/tags, branches under
+/// refs/heads or (in clones) refs/remotes
+fn kind_of_ref(name: &str) -> Option<RefKind> {
+    if name.starts_with("refs/tags/") {
+        Some(RefKind::Tag)
+    } else if name.starts_with("refs/heads/") || name.starts_with("refs/remotes/") {
+        Some(RefKind::Branch)
+    } else {
+        None
+    }
+}
+
+/// Pick the tag with the most version components among those sharing the
+/// release line (the first dot-separated component) of `reference`,
+/// leaving out prereleases unless `allow_prerelease`
+pub fn most_specific_tag(
+    tags: &[String],
+    reference: &str,
+    allow_prerelease: bool,
+) -> Option<String> {
+    let line = reference.split('.').next().unwrap_or(reference);
+
+    tags.iter()
+        .filter(|tag| *tag != reference)
+        .filter(|tag| allow_prerelease || !is_prerelease(tag))
+        .filter(|tag| *tag == line || tag.starts_with(&format!("{}.", line)))
+        .max_by_key(|tag| (tag.matches('.').count(), tag.len()))
+        .cloned()
+}
+
+/// What names a pinned commit in its version comment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommitName {
+    Tag(String),
+    Branch(String),
+}
+
+/// The most specific tag among `tags`, e.g. "v4.1.7" over "v4" and "v4.1",
+/// preferring releases over prereleases unless `allow_prerelease`
+pub fn best_tag(tags: &[String], allow_prerelease: bool) -> Option<String> {
+    tags.iter()
+        .max_by_key(|tag| {
+            (
+                allow_prerelease || !is_prerelease(tag),
+                tag.matches('.').count(),
+                tag.len(),
+                std::cmp::Reverse(tag.as_str()),
+            )
+        })
+        .cloned()
+}
+
+/// The tag or branch name of a full ref name, or None for other refs
+fn short_ref_name(name: &str) -> Option<String> {
+    let short = name
+        .strip_prefix("refs/tags/")
+        .map(|tag| tag.trim_end_matches("^{}"))
+        .or_else(|| name.strip_prefix("refs/heads/"))
+        .or_else(|| name.strip_prefix("refs/remotes/origin/"))?;
+    (short != "HEAD").then(|| short.to_string())
+}
+
+/// Dedup `names` and order them by how long a prefix they share with
+/// `reference`, then by name, keeping the first `limit`
+pub fn closest_refs(mut names: Vec<String>, reference: &str, limit: usize) -> Vec<String> {
+    let shared = |name: &str| {
+        name.chars()
+            .zip(reference.chars())
+            .take_while(|(a, b)| a == b)
+            .count()
+    };
+
+    names.sort();
+    names.dedup();
+    names.sort_by_key(|name| std::cmp::Reverse(shared(name)));
+    names.truncate(limit);
+    names
+}
+
+/// The ref a partial `reference` stands for, among full ref names ending
+/// with it. Ties are broken the same way on every run: tags before branches
+/// before other refs, then the shortest name (the least added to
+/// `reference`), then the name itself in byte order
+pub fn partial_match<'a>(names: &[&'a str], reference: &str) -> Option<&'a str> {
+    let rank = |name: &str| {
+        if name.starts_with("refs/tags/") {
+            0
+        } else if name.starts_with("refs/heads/") {
+            1
+        } else {
+            2
+        }
+    };
+
+    names
+        .iter()
+        .copied()
+        .filter(|name| name.ends_with(reference))
+        .min_by(|a, b| {
+            rank(a)
+                .cmp(&rank(b))
+                .then(a.len().cmp(&b.len()))
+                .then(a.cmp(b))
+        })
+}
+
+/// Read a resolver cache written by [`save_cache`], returning an empty one if
+/// the file doesn't exist yet
+pub fn load_cache<P: AsRef<Path>>(path: P) -> Result<HashMap<String, String>> {
+    let path = path.as_ref();
+    let Some(content) = lock::read_locked(path)? else {
+        return Ok(HashMap::new());
+    };
+    let entries: HashMap<String, String> = serde_json::from_str(&content)
+        .with_context(|| format!("Invalid resolver cache: {}", path.display()))?;
+
+    if let Some((key, sha)) = entries
+        .iter()
+        .find(|(_, sha)| sha.len() != 40 || !sha.chars().all(|c| c.is_ascii_hexdigit()))
+    {
+        anyhow::bail!(
+            "Invalid SHA '{}' for {} in resolver cache {}",
+            sha,
+            key,
+            path.display()
+        );
     }
+
+    Ok(entries)
+}
+
+/// Write a resolver cache as sorted JSON, keeping entries another run saved
+/// in the meantime (ours win on conflict)
+pub fn save_cache<P: AsRef<Path>>(path: P, entries: &HashMap<String, String>) -> Result<()> {
+    let path = path.as_ref();
+    lock::update_locked(path, |current| {
+        let mut merged: BTreeMap<String, String> = match current {
+            Some(current) => serde_json::from_str(current)
+                .with_context(|| format!("Invalid resolver cache: {}", path.display()))?,
+            None => BTreeMap::new(),
+        };
+        merged.extend(entries.clone());
+        Ok(serde_json::to_string_pretty(&merged)? + "\n")
+    })
+    .with_context(|| format!("Failed to write resolver cache: {}", path.display()))
+}
+
+/// Whether a version tag carries a semver prerelease ("v4.0.0-rc.1") or
+/// build metadata ("v4.0.0+build.5") suffix
+pub fn is_prerelease(tag: &str) -> bool {
+    tag.contains(['-', '+'])
 }
 
 impl Default for GitResolver {
@@ -124,6 +1022,89 @@ impl Default for GitResolver {
 mod tests {
     use super::*;
 
+    /// A bare repository with one commit on `main`, tagged `v1.0.0`
+    /// (annotated) and `v1` (lightweight); returns the commit SHA
+    fn seed_mirror(path: &Path) -> String {
+        let repo = Repository::init_bare(path).unwrap();
+        let sig = git2::Signature::now("test", "test@example.com").unwrap();
+        let tree = repo
+            .find_tree(repo.treebuilder(None).unwrap().write().unwrap())
+            .unwrap();
+        let commit = repo
+            .commit(Some("refs/heads/main"), &sig, &sig, "init", &tree, &[])
+            .unwrap();
+        repo.set_head("refs/heads/main").unwrap();
+
+        let object = repo.find_object(commit, None).unwrap();
+        repo.tag("v1.0.0", &object, &sig, "release", false).unwrap();
+        repo.tag_lightweight("v1", &object, false).unwrap();
+        commit.to_string()
+    }
+
+    #[tokio::test]
+    async fn test_resolve_from_mirror_offline() {
+        let temp = TempDir::new().unwrap();
+        let sha = seed_mirror(&temp.path().join("owner/tool.git"));
+
+        let resolver = GitResolver::new()
+            .with_mirror_dir(Some(temp.path().to_path_buf()))
+            .with_offline(true);
+
+        // Annotated tags resolve to the commit they mark, not the tag object
+        for reference in ["v1.0.0", "v1", "tag:v1", "main", "branch:main", &sha[..10]] {
+            let action = ActionRef::parse(&format!("owner/tool@{}", reference)).unwrap();
+            assert_eq!(
+                resolver.resolve_sha(&action).await.unwrap(),
+                sha,
+                "{}",
+                reference
+            );
+        }
+
+        let action = ActionRef::parse("owner/tool/sub@v1").unwrap();
+        assert_eq!(resolver.resolve_sha(&action).await.unwrap(), sha);
+        assert_eq!(resolver.default_branch(&action).await.unwrap(), "main");
+
+        let missing = ActionRef::parse("owner/tool@v9").unwrap();
+        let error = resolver.resolve_sha(&missing).await.unwrap_err();
+        assert!(error.downcast_ref::<RefNotFound>().is_some());
+
+        let unmirrored = ActionRef::parse("owner/other@v1").unwrap();
+        let error = resolver.resolve_sha(&unmirrored).await.unwrap_err();
+        assert!(error.to_string().contains("offline mode"));
+    }
+
+    #[tokio::test]
+    async fn test_ref_named_like_sha() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("owner/tool.git");
+        let sha = seed_mirror(&path);
+
+        // A tag whose name is itself a 40-hex string, as a vendored hash
+        let vendored = "0123456789abcdef0123456789abcdef01234567";
+        let repo = Repository::open_bare(&path).unwrap();
+        let sig = git2::Signature::now("test", "test@example.com").unwrap();
+        let object = repo
+            .find_object(git2::Oid::from_str(&sha).unwrap(), None)
+            .unwrap();
+        repo.tag(vendored, &object, &sig, "vendored", false)
+            .unwrap();
+
+        let resolver = GitResolver::new()
+            .with_mirror_dir(Some(temp.path().to_path_buf()))
+            .with_offline(true);
+
+        let action = ActionRef::parse(&format!("owner/tool@{}", vendored)).unwrap();
+        assert!(action.is_sha);
+        let found = resolver.ref_named(&action).await.unwrap().unwrap();
+        assert_eq!(found.sha, sha);
+        assert_eq!(found.ref_type, Some(RefKind::Tag));
+
+        // A real commit SHA names no ref
+        let action = ActionRef::parse(&format!("owner/tool@{}", sha)).unwrap();
+        assert_eq!(resolver.ref_named(&action).await.unwrap(), None);
+    }
+
     #[tokio::test]
     #[ignore] // Requires network access
     async fn test_resolve_sha() {
@@ -149,4 +1130,403 @@ mod tests {
 
         assert_eq!(sha1, sha2);
     }
+
+    #[test]
+    fn test_prime_snapshot_round_trip() {
+        let entries = HashMap::from([
+            ("actions/checkout@v4".to_string(), "a".repeat(40)),
+            ("actions/cache@v4".to_string(), "b".repeat(40)),
+        ]);
+        let resolver = GitResolver::new();
+        resolver.prime(entries.clone());
+        assert_eq!(resolver.snapshot(), entries);
+
+        // Clones share the cache, and a fresh resolver can be warmed from it
+        let warmed = GitResolver::new();
+        warmed.prime(resolver.clone().snapshot());
+        assert_eq!(warmed.snapshot(), entries);
+
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join("cache.json");
+        assert!(load_cache(&path).unwrap().is_empty());
+        save_cache(&path, &warmed.snapshot()).unwrap();
+        assert_eq!(load_cache(&path).unwrap(), entries);
+    }
+
+    #[tokio::test]
+    async fn test_primed_entries_are_hits() {
+        // Offline without a mirror, anything not primed fails to resolve
+        let resolver = GitResolver::new().with_offline(true);
+        let sha = "b4ffde65f46336ab88eb53be808477a3936bae11".to_string();
+        resolver.prime(HashMap::from([(
+            "actions/checkout@v4".to_string(),
+            sha.clone(),
+        )]));
+
+        let primed = ActionRef::parse("actions/checkout@v4").unwrap();
+        assert_eq!(resolver.resolve_sha(&primed).await.unwrap(), sha);
+
+        let other = ActionRef::parse("actions/cache@v4").unwrap();
+        assert!(resolver.resolve_sha(&other).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_available_refs() {
+        let temp = TempDir::new().unwrap();
+        seed_mirror(&temp.path().join("owner/tool.git"));
+
+        let resolver = GitResolver::new()
+            .with_mirror_dir(Some(temp.path().to_path_buf()))
+            .with_offline(true);
+
+        let action = ActionRef::parse("owner/tool@v1.0.9").unwrap();
+        assert_eq!(
+            resolver.available_refs(&action, 10).await.unwrap(),
+            ["v1.0.0", "v1", "main"]
+        );
+        assert_eq!(
+            resolver.available_refs(&action, 1).await.unwrap(),
+            ["v1.0.0"]
+        );
+        assert!(resolver.source(&action).ends_with("tool.git"));
+    }
+
+    #[test]
+    fn test_connect_with_headers_lists_refs() {
+        let temp = TempDir::new().unwrap();
+        let mirror = temp.path().join("tool.git");
+        let sha = seed_mirror(&mirror);
+        let scratch = Repository::init_bare(temp.path().join("scratch")).unwrap();
+
+        let remote = GitRemote {
+            http: HttpOptions {
+                user_agent: None,
+                headers: vec!["X-Proxy-Auth: secret".to_string()],
+            },
+            ..GitRemote::default()
+        };
+        let url = format!("file://{}", mirror.display());
+        let connected = GitResolver::connect(&scratch, &remote, &url).unwrap();
+
+        let refs = connected.list().unwrap();
+        assert!(
+            refs.iter()
+                .any(|head| head.name() == "refs/tags/v1" && head.oid().to_string() == sha)
+        );
+        assert!(scratch.references().unwrap().next().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_name_commit() {
+        let temp = TempDir::new().unwrap();
+        let sha = seed_mirror(&temp.path().join("owner/tool.git"));
+
+        let resolver = GitResolver::new()
+            .with_mirror_dir(Some(temp.path().to_path_buf()))
+            .with_offline(true);
+        let action = ActionRef::parse(&format!("owner/tool@{}", sha)).unwrap();
+        assert_eq!(
+            resolver.name_commit(&action, &sha).await.unwrap(),
+            Some(CommitName::Tag("v1.0.0".to_string()))
+        );
+
+        // An untagged commit is named after the branch containing it
+        let repo = Repository::open(temp.path().join("owner/tool.git")).unwrap();
+        let sig = git2::Signature::now("test", "test@example.com").unwrap();
+        let parent = repo
+            .find_commit(git2::Oid::from_str(&sha).unwrap())
+            .unwrap();
+        let tree = parent.tree().unwrap();
+        let child = repo
+            .commit(
+                Some("refs/heads/main"),
+                &sig,
+                &sig,
+                "next",
+                &tree,
+                &[&parent],
+            )
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            resolver.name_commit(&action, &child).await.unwrap(),
+            Some(CommitName::Branch("main".to_string()))
+        );
+
+        let orphan = repo
+            .commit(None, &sig, &sig, "orphan", &tree, &[])
+            .unwrap()
+            .to_string();
+        assert_eq!(resolver.name_commit(&action, &orphan).await.unwrap(), None);
+    }
+
+    #[test]
+    fn test_partial_match() {
+        let names = [
+            "refs/pull/12/release-v2",
+            "refs/heads/release-v2",
+            "refs/tags/hotfix/release-v2",
+            "refs/tags/b-release-v2",
+            "refs/tags/a-release-v2",
+            "refs/tags/v2",
+        ];
+
+        // Shortest tag first, then byte order between equally long ones
+        assert_eq!(
+            partial_match(&names, "release-v2"),
+            Some("refs/tags/a-release-v2")
+        );
+        let mut reversed = names;
+        reversed.reverse();
+        assert_eq!(
+            partial_match(&reversed, "release-v2"),
+            Some("refs/tags/a-release-v2")
+        );
+
+        // Branches win over other refs when no tag matches
+        assert_eq!(
+            partial_match(&names[..2], "release-v2"),
+            Some("refs/heads/release-v2")
+        );
+        assert_eq!(partial_match(&names, "v3"), None);
+    }
+
+    #[test]
+    fn test_best_tag() {
+        let tags = |names: &[&str]| names.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            best_tag(&tags(&["v4", "v4.1.7", "v4.1"]), false),
+            Some("v4.1.7".to_string())
+        );
+        assert_eq!(
+            best_tag(&tags(&["v4", "v4.2.0-rc.1"]), false),
+            Some("v4".to_string())
+        );
+        assert_eq!(
+            best_tag(&tags(&["v4", "v4.2.0-rc.1"]), true),
+            Some("v4.2.0-rc.1".to_string())
+        );
+        assert_eq!(best_tag(&[], false), None);
+    }
+
+    #[test]
+    fn test_most_specific_tag() {
+        let tags: Vec<String> = ["v4", "v4.2", "v4.2.1", "v5.0.0", "latest"]
+            .iter()
+            .map(|t| t.to_string())
+            .collect();
+
+        assert_eq!(
+            most_specific_tag(&tags, "v4", false),
+            Some("v4.2.1".to_string())
+        );
+        assert_eq!(
+            most_specific_tag(&tags, "v4.1.0", false),
+            Some("v4.2.1".to_string())
+        );
+        assert_eq!(most_specific_tag(&tags, "v3", false), None);
+        assert_eq!(most_specific_tag(&tags, "main", false), None);
+    }
+
+    #[test]
+    fn test_most_specific_tag_skips_prereleases() {
+        let tags: Vec<String> = ["v4", "v4.2.1", "v4.3.0-rc.1", "v4.2.1+build.7"]
+            .iter()
+            .map(|t| t.to_string())
+            .collect();
+
+        assert_eq!(
+            most_specific_tag(&tags, "v4", false),
+            Some("v4.2.1".to_string())
+        );
+
+        let only_prereleases = vec!["v5".to_string(), "v5.0.0-beta.2".to_string()];
+        assert_eq!(most_specific_tag(&only_prereleases, "v5", false), None);
+        assert_eq!(
+            most_specific_tag(&only_prereleases, "v5", true),
+            Some("v5.0.0-beta.2".to_string())
+        );
+    }
+
+    #[test]
+    fn test_host_key_error_is_actionable() {
+        let remote = GitRemote {
+            protocol: Protocol::Ssh,
+            host: "ghes.example.com".to_string(),
+            ssh_key: None,
+            http: HttpOptions::default(),
+        };
+        let error = git2::Error::new(ErrorCode::Certificate, ErrorClass::Ssh, "invalid host key");
+
+        let message = format!(
+            "{:#}",
+            GitResolver::connect_error(&remote, "git@ghes.example.com:a/b.git", error)
+        );
+        assert!(message.contains("ssh-keyscan ghes.example.com"));
+    }
+
+    /// A smart HTTP ref advertisement listing `refs` as (oid, name)
+    fn advertisement(refs: &[(&str, &str)]) -> String {
+        let pkt_line = |line: &str| format!("{:04x}{}", line.len() + 4, line);
+        let mut body = format!("{}0000", pkt_line("# service=git-upload-pack\n"));
+        for (i, (oid, name)) in refs.iter().enumerate() {
+            let capabilities = if i == 0 { "\0multi_ack" } else { "" };
+            body.push_str(&pkt_line(&format!("{} {}{}\n", oid, name, capabilities)));
+        }
+        body.push_str("0000");
+        body
+    }
+
+    #[test]
+    fn test_ls_remote_peeled_tag() {
+        let tag_object = "9c8f5b2e0d1a3f4e5b6c7d8e9f0a1b2c3d4e5f60";
+        let commit = "b4ffde65f46336ab88eb53be808477a3936bae11";
+        let lightweight = "11bd71901bbe5b1630ceea73d27597364c9af683";
+
+        let mut server = mockito::Server::new();
+        server
+            .mock(
+                "GET",
+                "/actions/checkout.git/info/refs?service=git-upload-pack",
+            )
+            .with_header(
+                "content-type",
+                "application/x-git-upload-pack-advertisement",
+            )
+            .with_body(advertisement(&[
+                (tag_object, "refs/tags/v4"),
+                (commit, "refs/tags/v4^{}"),
+                (lightweight, "refs/tags/v5"),
+            ]))
+            .create();
+
+        let dir = tempfile::TempDir::new().unwrap();
+        Repository::init_bare(dir.path()).unwrap();
+        let url = format!("{}/actions/checkout.git", server.url());
+        let ls_remote = |reference: &str, kind| {
+            GitResolver::git_ls_remote(dir.path(), &GitRemote::default(), &url, reference, kind)
+                .unwrap()
+                .0
+        };
+
+        // The peeled entry carries the commit an annotated tag marks
+        assert_eq!(ls_remote("v4^{}", None).sha, commit);
+        assert_eq!(ls_remote("v4^{}", Some(RefKind::Tag)).sha, commit);
+        assert_eq!(ls_remote("v4^{}", None).ref_type, Some(RefKind::Tag));
+        // A lightweight tag is a commit already
+        assert_eq!(ls_remote("v5^{}", None).sha, lightweight);
+    }
+
+    #[test]
+    fn test_ls_remote_reports_default_branch() {
+        let head = "b4ffde65f46336ab88eb53be808477a3936bae11";
+        let tag = "11bd71901bbe5b1630ceea73d27597364c9af683";
+
+        let mut server = mockito::Server::new();
+        server
+            .mock(
+                "GET",
+                "/actions/checkout.git/info/refs?service=git-upload-pack",
+            )
+            .with_header(
+                "content-type",
+                "application/x-git-upload-pack-advertisement",
+            )
+            .with_body(advertisement(&[
+                (head, "HEAD"),
+                (tag, "refs/heads/feature"),
+                (head, "refs/heads/trunk"),
+                (tag, "refs/tags/v4"),
+            ]))
+            .create();
+
+        let dir = tempfile::TempDir::new().unwrap();
+        Repository::init_bare(dir.path()).unwrap();
+        let url = format!("{}/actions/checkout.git", server.url());
+
+        // The listing that answers the ref names the default branch too
+        let (resolution, branch) =
+            GitResolver::git_ls_remote(dir.path(), &GitRemote::default(), &url, "v4", None)
+                .unwrap();
+        assert_eq!(resolution.sha, tag);
+        assert_eq!(branch.as_deref(), Some("trunk"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_https_credentials_from_helper() {
+        use std::os::unix::fs::PermissionsExt;
+
+        use mockito::Matcher;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let helper = dir.path().join("git-credential-stub");
+        std::fs::write(
+            &helper,
+            "#!/bin/sh\necho username=pin\necho password=s3cret\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&helper, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let scratch = dir.path().join("scratch");
+        let repo = Repository::init_bare(&scratch).unwrap();
+        repo.config()
+            .unwrap()
+            .set_str("credential.helper", helper.to_str().unwrap())
+            .unwrap();
+
+        let sha = "b4ffde65f46336ab88eb53be808477a3936bae11";
+
+        let mut server = mockito::Server::new();
+        let path = "/private/action.git/info/refs?service=git-upload-pack";
+        server
+            .mock("GET", path)
+            .match_header("authorization", Matcher::Missing)
+            .with_status(401)
+            .with_header("www-authenticate", "Basic realm=\"git\"")
+            .create();
+        let authorized = server
+            .mock("GET", path)
+            // base64 of "pin:s3cret"
+            .match_header("authorization", "Basic cGluOnMzY3JldA==")
+            .with_header(
+                "content-type",
+                "application/x-git-upload-pack-advertisement",
+            )
+            .with_body(advertisement(&[(sha, "refs/tags/v4")]))
+            .create();
+
+        let (resolution, _) = GitResolver::git_ls_remote(
+            &scratch,
+            &GitRemote::default(),
+            &format!("{}/private/action.git", server.url()),
+            "v4",
+            None,
+        )
+        .unwrap();
+        assert_eq!(resolution.sha, sha);
+        assert_eq!(resolution.ref_type, Some(RefKind::Tag));
+        authorized.assert();
+    }
+
+    #[tokio::test]
+    async fn test_scratch_dir_removed_after_use() {
+        let parent = tempfile::TempDir::new().unwrap();
+        let resolver = GitResolver::new()
+            .with_remote(GitRemote {
+                // Nothing listens here, so resolution fails without network
+                host: "127.0.0.1:1".to_string(),
+                ..GitRemote::default()
+            })
+            .with_scratch_parent(Some(parent.path().to_path_buf()));
+
+        let action = ActionRef::parse("actions/checkout@v4").unwrap();
+        let results = resolver.resolve_all(vec![action], 2).await;
+        assert!(results[0].1.is_err());
+        assert_eq!(std::fs::read_dir(parent.path()).unwrap().count(), 1);
+
+        drop(resolver);
+        assert_eq!(std::fs::read_dir(parent.path()).unwrap().count(), 0);
+    }
 }
@@ -0,0 +1,490 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use anyhow::{Context, Result};
+use futures::future::BoxFuture;
+use serde_json::Value;
+use tracing::debug;
+
+use crate::{
+    action::{ActionRef, RefKind},
+    git::{self, GitResolver},
+    http,
+    resolver::{Resolution, Resolver, Source},
+};
+
+/// Default GitHub GraphQL endpoint
+pub const DEFAULT_GRAPHQL_URL: &str = "https://api.github.com/graphql";
+
+/// The GraphQL endpoint serving the git host `host`: api.github.com for
+/// github.com, and `/api/graphql` on the host itself for GitHub Enterprise
+/// Server
+pub fn graphql_url(host: &str) -> String {
+    if host.eq_ignore_ascii_case(git::DEFAULT_GIT_HOST) {
+        DEFAULT_GRAPHQL_URL.to_string()
+    } else {
+        format!("https://{}/api/graphql", host)
+    }
+}
+
+/// Maximum number of actions looked up in a single query, keeping each
+/// request well below GitHub's node and complexity limits
+pub const MAX_ACTIONS_PER_QUERY: usize = 50;
+
+/// Resolver that batches many ref lookups into a few GraphQL queries,
+/// falling back to git ls-remote for refs GraphQL can't answer
+#[derive(Clone)]
+pub struct GraphQlResolver {
+    client: reqwest::Client,
+    endpoint: String,
+    token: Option<String>,
+    fallback: GitResolver,
+    /// Whether each repository queried is archived, by "owner/repo"
+    archived: Arc<Mutex<BTreeMap<String, bool>>>,
+    /// Default branch of each repository queried, by "owner/repo"
+    default_branches: Arc<Mutex<BTreeMap<String, String>>>,
+    /// Lookups sent through git after their query failed
+    retries: Arc<AtomicUsize>,
+}
+
+impl GraphQlResolver {
+    /// Create a resolver; without a token every lookup goes through git since
+    /// GitHub's GraphQL API requires authentication
+    pub fn new(endpoint: String, token: Option<String>) -> Self {
+        Self {
+            client: http::default_client(),
+            endpoint,
+            token,
+            fallback: GitResolver::new(),
+            archived: Arc::default(),
+            default_branches: Arc::default(),
+            retries: Arc::default(),
+        }
+    }
+
+    /// Send requests through `client`, e.g. one from [`HttpOptions::client`]
+    ///
+    /// [`HttpOptions::client`]: crate::http::HttpOptions::client
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Use the given git resolver for refs GraphQL can't answer
+    pub fn with_fallback(mut self, fallback: GitResolver) -> Self {
+        self.fallback = fallback;
+        self
+    }
+
+    /// Resolve actions in batched queries, running up to `concurrency` of
+    /// them at once. Mirrored repositories are read locally and anything
+    /// GraphQL can't answer goes through git
+    async fn resolve_batched(
+        &self,
+        actions: Vec<ActionRef>,
+        concurrency: usize,
+    ) -> Vec<(ActionRef, Result<Resolution>)> {
+        use futures::stream::{self, StreamExt};
+
+        let (mut unanswered, remote): (Vec<ActionRef>, Vec<ActionRef>) = actions
+            .into_iter()
+            .partition(|action| self.fallback.mirror_path(action).is_some());
+        let chunks: Vec<Vec<ActionRef>> = remote
+            .chunks(MAX_ACTIONS_PER_QUERY)
+            .map(|chunk| chunk.to_vec())
+            .collect();
+
+        let answered: Vec<(ActionRef, Option<Resolution>)> = stream::iter(chunks)
+            .map(|chunk| async move {
+                match self.query(&chunk).await {
+                    Ok(shas) => chunk.into_iter().zip(shas).collect::<Vec<_>>(),
+                    Err(e) => {
+                        debug!("GraphQL query failed, falling back to git: {}", e);
+                        self.retries.fetch_add(chunk.len(), Ordering::SeqCst);
+                        chunk.into_iter().map(|action| (action, None)).collect()
+                    },
+                }
+            })
+            .buffer_unordered(concurrency.max(1))
+            .flat_map(stream::iter)
+            .collect()
+            .await;
+
+        let mut results = Vec::new();
+        for (action, resolution) in answered {
+            match resolution {
+                Some(resolution) => results.push((action, Ok(resolution))),
+                None => unanswered.push(action),
+            }
+        }
+
+        if !unanswered.is_empty() {
+            debug!(
+                "Resolving {} action(s) GraphQL couldn't answer via git",
+                unanswered.len()
+            );
+            results.extend(self.fallback.resolve_all(unanswered, concurrency).await);
+        }
+
+        results
+    }
+
+    /// Whether each repository looked up while resolving is archived. It
+    /// comes back with the refs, so knowing costs no extra requests
+    pub fn archived(&self) -> BTreeMap<String, bool> {
+        self.archived.lock().unwrap().clone()
+    }
+
+    /// Run one batched query, returning the resolution of each action in
+    /// order (None when GraphQL has no answer)
+    async fn query(&self, actions: &[ActionRef]) -> Result<Vec<Option<Resolution>>> {
+        let Some(token) = &self.token else {
+            anyhow::bail!("GraphQL API requires authentication");
+        };
+
+        let body = serde_json::json!({ "query": build_query(actions) });
+
+        let response: Value = self
+            .client
+            .post(&self.endpoint)
+            .bearer_auth(token)
+            .json(&body)
+            .send()
+            .await
+            .context("Failed to send GraphQL request")?
+            .error_for_status()
+            .context("GraphQL request was rejected")?
+            .json()
+            .await
+            .context("Failed to decode GraphQL response")?;
+
+        self.archived
+            .lock()
+            .unwrap()
+            .extend(parse_archived(actions, &response));
+        self.default_branches
+            .lock()
+            .unwrap()
+            .extend(parse_default_branches(actions, &response));
+        Ok(parse_response(actions.len(), &response))
+    }
+}
+
+impl Resolver for GraphQlResolver {
+    fn resolve<'a>(&'a self, action: &'a ActionRef) -> BoxFuture<'a, Result<Resolution>> {
+        Box::pin(async move {
+            let (_, result) = self
+                .resolve_batched(vec![action.clone()], 1)
+                .await
+                .pop()
+                .context("GraphQL resolver returned no result")?;
+            result
+        })
+    }
+
+    fn resolve_all<'a>(
+        &'a self,
+        actions: Vec<ActionRef>,
+        concurrency: usize,
+    ) -> BoxFuture<'a, Vec<(ActionRef, Result<Resolution>)>> {
+        Box::pin(self.resolve_batched(actions, concurrency))
+    }
+
+    fn prime(&self, entries: HashMap<String, String>) {
+        self.fallback.prime(entries);
+    }
+
+    fn snapshot(&self) -> HashMap<String, String> {
+        self.fallback.snapshot()
+    }
+
+    fn archived(&self) -> BTreeMap<String, bool> {
+        GraphQlResolver::archived(self)
+    }
+
+    /// Repositories answered by a query, plus any git listed instead
+    fn default_branches(&self) -> BTreeMap<String, String> {
+        let mut branches = self.fallback.default_branches();
+        branches.extend(self.default_branches.lock().unwrap().clone());
+        branches
+    }
+
+    fn batches(&self) -> bool {
+        true
+    }
+
+    fn retries(&self) -> usize {
+        self.retries.load(Ordering::SeqCst)
+    }
+}
+
+/// Build a query looking up each action's ref as both a tag and a branch
+/// (or only the hinted kind), aliased `a0`, `a1`, ... in input order. The
+/// repository's archived flag and default branch come along for free
+pub fn build_query(actions: &[ActionRef]) -> String {
+    let mut query = String::from("query {\n");
+
+    for (idx, action) in actions.iter().enumerate() {
+        let (owner, name) = action
+            .base_repository()
+            .split_once('/')
+            .unwrap_or((action.repository.as_str(), ""));
+
+        query.push_str(&format!(
+            "  a{}: repository(owner: {}, name: {}) {{\n    isArchived\n",
+            idx,
+            graphql_string(owner),
+            graphql_string(name)
+        ));
+        query.push_str("    defaultBranchRef { name }\n");
+        for (alias, kind) in [("tag", RefKind::Tag), ("branch", RefKind::Branch)] {
+            if action.ref_kind.is_some_and(|hint| hint != kind) {
+                continue;
+            }
+            query.push_str(&format!(
+                "    {}: ref(qualifiedName: {}) {{ target {{ oid ... on Tag {{ target {{ oid }} }} }} }}\n",
+                alias,
+                graphql_string(&format!("{}{}", kind.ref_prefix(), action.ref_name()))
+            ));
+        }
+        query.push_str("  }\n");
+    }
+
+    query.push('}');
+    query
+}
+
+/// Map a query response back to resolutions, preferring tags over branches
+/// and unwrapping annotated tags to the commit they point at
+pub fn parse_response(count: usize, response: &Value) -> Vec<Option<Resolution>> {
+    let data = &response["data"];
+
+    (0..count)
+        .map(|idx| {
+            let repository = &data[format!("a{}", idx)];
+            [("tag", RefKind::Tag), ("branch", RefKind::Branch)]
+                .into_iter()
+                .find_map(|(alias, kind)| {
+                    let sha = commit_oid(&repository[alias]["target"])?;
+                    Some(Resolution::new(sha, Some(kind), Source::Graphql))
+                })
+        })
+        .collect()
+}
+
+/// Whether the repository of each of `actions` is archived, for those a
+/// query response found
+pub fn parse_archived(actions: &[ActionRef], response: &Value) -> BTreeMap<String, bool> {
+    let data = &response["data"];
+
+    actions
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, action)| {
+            let archived = data[format!("a{}", idx)]["isArchived"].as_bool()?;
+            Some((action.base_repository().to_string(), archived))
+        })
+        .collect()
+}
+
+/// The default branch of the repository of each of `actions`, for those a
+/// query response found
+pub fn parse_default_branches(actions: &[ActionRef], response: &Value) -> BTreeMap<String, String> {
+    let data = &response["data"];
+
+    actions
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, action)| {
+            let branch = data[format!("a{}", idx)]["defaultBranchRef"]["name"].as_str()?;
+            Some((action.base_repository().to_string(), branch.to_string()))
+        })
+        .collect()
+}
+
+/// Extract the commit oid from a ref target, peeling an annotated tag
+fn commit_oid(target: &Value) -> Option<String> {
+    let oid = target
+        .get("target")
+        .and_then(|inner| inner.get("oid"))
+        .or_else(|| target.get("oid"))?;
+
+    oid.as_str().map(str::to_string)
+}
+
+/// Quote a value as a GraphQL string literal (JSON string syntax is valid
+/// GraphQL)
+fn graphql_string(value: &str) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_build_query() {
+        let actions = vec![
+            ActionRef::parse("actions/checkout@v4").unwrap(),
+            ActionRef::parse("github/codeql-action/analyze@main").unwrap(),
+        ];
+
+        let query = build_query(&actions);
+
+        assert!(query.contains(r#"a0: repository(owner: "actions", name: "checkout")"#));
+        assert!(query.contains(r#"tag: ref(qualifiedName: "refs/tags/v4")"#));
+        assert!(query.contains(r#"branch: ref(qualifiedName: "refs/heads/v4")"#));
+        assert!(query.contains(r#"a1: repository(owner: "github", name: "codeql-action")"#));
+        assert!(query.contains(r#"tag: ref(qualifiedName: "refs/tags/main")"#));
+        assert!(query.contains("... on Tag { target { oid } }"));
+        assert!(query.contains("defaultBranchRef { name }"));
+    }
+
+    #[test]
+    fn test_build_query_escapes_values() {
+        let action = ActionRef {
+            repository: "evil/repo".to_string(),
+            reference: "v1\") { x }".to_string(),
+            is_sha: false,
+            ref_kind: None,
+        };
+
+        let query = build_query(&[action]);
+        assert!(query.contains(r#""refs/tags/v1\") { x }""#));
+    }
+
+    #[test]
+    fn test_build_query_with_hint() {
+        let actions = vec![
+            ActionRef::parse("actions/checkout@tag:stable").unwrap(),
+            ActionRef::parse("actions/cache@branch:stable").unwrap(),
+        ];
+
+        let query = build_query(&actions);
+        let (first, second) = query.split_once("a1:").unwrap();
+
+        assert!(first.contains(r#"tag: ref(qualifiedName: "refs/tags/stable")"#));
+        assert!(!first.contains("refs/heads/"));
+        assert!(second.contains(r#"branch: ref(qualifiedName: "refs/heads/stable")"#));
+        assert!(!second.contains("refs/tags/"));
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let response = json!({
+            "data": {
+                "a0": {
+                    "tag": { "target": { "oid": "1111111111111111111111111111111111111111" } },
+                    "branch": null
+                },
+                "a1": {
+                    "tag": {
+                        "target": {
+                            "oid": "tagobject00000000000000000000000000000000",
+                            "target": { "oid": "2222222222222222222222222222222222222222" }
+                        }
+                    },
+                    "branch": null
+                },
+                "a2": {
+                    "tag": null,
+                    "branch": { "target": { "oid": "3333333333333333333333333333333333333333" } }
+                },
+                "a3": { "tag": null, "branch": null },
+                "a4": null
+            }
+        });
+
+        let resolutions = parse_response(5, &response);
+        let found: Vec<Option<(&str, Option<RefKind>)>> = resolutions
+            .iter()
+            .map(|r| r.as_ref().map(|r| (r.sha.as_str(), r.ref_type)))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                Some((
+                    "1111111111111111111111111111111111111111",
+                    Some(RefKind::Tag)
+                )),
+                Some((
+                    "2222222222222222222222222222222222222222",
+                    Some(RefKind::Tag)
+                )),
+                Some((
+                    "3333333333333333333333333333333333333333",
+                    Some(RefKind::Branch)
+                )),
+                None,
+                None,
+            ]
+        );
+        assert!(
+            resolutions
+                .iter()
+                .flatten()
+                .all(|r| r.source == Source::Graphql)
+        );
+    }
+
+    #[test]
+    fn test_parse_archived() {
+        let actions = vec![
+            ActionRef::parse("actions/checkout@v4").unwrap(),
+            ActionRef::parse("old/tool/sub@v1").unwrap(),
+            ActionRef::parse("gone/repo@v1").unwrap(),
+        ];
+        let response = json!({
+            "data": {
+                "a0": { "isArchived": false },
+                "a1": { "isArchived": true },
+                "a2": null
+            }
+        });
+
+        assert_eq!(
+            parse_archived(&actions, &response),
+            BTreeMap::from([
+                ("actions/checkout".to_string(), false),
+                ("old/tool".to_string(), true),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_default_branches() {
+        let actions = vec![
+            ActionRef::parse("actions/checkout@v4").unwrap(),
+            ActionRef::parse("github/codeql-action/analyze@main").unwrap(),
+            ActionRef::parse("empty/repo@v1").unwrap(),
+        ];
+        let response = json!({
+            "data": {
+                "a0": { "defaultBranchRef": { "name": "main" } },
+                "a1": { "defaultBranchRef": { "name": "develop" } },
+                "a2": { "defaultBranchRef": null }
+            }
+        });
+
+        assert_eq!(
+            parse_default_branches(&actions, &response),
+            BTreeMap::from([
+                ("actions/checkout".to_string(), "main".to_string()),
+                ("github/codeql-action".to_string(), "develop".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_error_response() {
+        let response = json!({ "errors": [{ "message": "Bad credentials" }] });
+        assert_eq!(parse_response(2, &response), vec![None, None]);
+    }
+}
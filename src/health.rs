@@ -0,0 +1,157 @@
+use anyhow::{Context, Result};
+use reqwest::{StatusCode, header};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{error::RefNotFound, http};
+
+/// An action repository that is archived or has moved, as reported by the
+/// GitHub API
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RepoHealth {
+    /// "owner/repo" as used in the workflows
+    pub repository: String,
+    pub archived: bool,
+    /// Where a renamed or transferred repository lives now
+    #[serde(default)]
+    pub moved_to: Option<String>,
+}
+
+impl RepoHealth {
+    /// Whether there is anything worth warning about
+    pub fn is_unhealthy(&self) -> bool {
+        self.archived || self.moved_to.is_some()
+    }
+}
+
+/// Looks up action repositories through the GitHub REST API
+#[derive(Clone)]
+pub struct HealthChecker {
+    client: reqwest::Client,
+    api_url: String,
+    token: Option<String>,
+}
+
+impl HealthChecker {
+    pub fn new(api_url: String, token: Option<String>) -> Self {
+        Self {
+            client: http::default_client(),
+            api_url,
+            token,
+        }
+    }
+
+    /// Send requests through `client`, e.g. one from [`HttpOptions::client`]
+    ///
+    /// [`HttpOptions::client`]: crate::http::HttpOptions::client
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Fetch a repository's metadata. GitHub answers a renamed or transferred
+    /// repository with a 301 to its new location; the redirect is followed
+    /// and the new name reported as `moved_to`
+    pub async fn check(&self, repository: &str) -> Result<RepoHealth> {
+        let url = format!("{}/repos/{}", self.api_url, repository);
+        let mut request = self
+            .client
+            .get(&url)
+            .header(header::ACCEPT, "application/vnd.github+json");
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach {}", url))?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(RefNotFound(format!("Repository {} not found", repository)).into());
+        }
+
+        let body: Value = response
+            .error_for_status()
+            .with_context(|| format!("Failed to look up {}", repository))?
+            .json()
+            .await
+            .with_context(|| format!("Invalid repository response for {}", repository))?;
+
+        Ok(parse_repository(repository, &body))
+    }
+}
+
+/// Read the archived flag and current name out of a repository response
+pub fn parse_repository(repository: &str, body: &Value) -> RepoHealth {
+    let moved_to = body["full_name"]
+        .as_str()
+        .filter(|name| !name.eq_ignore_ascii_case(repository))
+        .map(str::to_string);
+
+    RepoHealth {
+        repository: repository.to_string(),
+        archived: body["archived"].as_bool().unwrap_or(false),
+        moved_to,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_repository() {
+        let body = serde_json::json!({"full_name": "Actions/Checkout", "archived": false});
+        let health = parse_repository("actions/checkout", &body);
+        assert!(!health.is_unhealthy());
+
+        let body = serde_json::json!({"full_name": "new-owner/tool", "archived": true});
+        let health = parse_repository("old-owner/tool", &body);
+        assert!(health.archived);
+        assert_eq!(health.moved_to.as_deref(), Some("new-owner/tool"));
+    }
+
+    #[tokio::test]
+    async fn test_check_archived_repository() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/repos/actions/create-release")
+            .match_header("authorization", "Bearer secret")
+            .with_body(r#"{"full_name": "actions/create-release", "archived": true}"#)
+            .create_async()
+            .await;
+
+        let health = HealthChecker::new(server.url(), Some("secret".to_string()))
+            .check("actions/create-release")
+            .await
+            .unwrap();
+
+        assert!(health.archived);
+        assert_eq!(health.moved_to, None);
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_check_moved_repository() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/repos/old-owner/tool")
+            .with_status(301)
+            .with_header("location", &format!("{}/repositories/42", server.url()))
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/repositories/42")
+            .with_body(r#"{"full_name": "new-owner/tool", "archived": false}"#)
+            .create_async()
+            .await;
+
+        let health = HealthChecker::new(server.url(), None)
+            .check("old-owner/tool")
+            .await
+            .unwrap();
+
+        assert!(!health.archived);
+        assert_eq!(health.moved_to.as_deref(), Some("new-owner/tool"));
+    }
+}
@@ -0,0 +1,149 @@
+use anyhow::{Context, Result};
+use git2::FetchOptions;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+
+/// User agent sent when none is configured
+pub const DEFAULT_USER_AGENT: &str = concat!("pin-actions/", env!("CARGO_PKG_VERSION"));
+
+/// Headers libgit2 sets itself and refuses as custom headers
+const RESERVED_HEADERS: &[&str] = &[
+    "user-agent",
+    "host",
+    "accept",
+    "content-type",
+    "transfer-encoding",
+    "content-length",
+];
+
+/// User agent and extra headers for corporate proxies and gateways. The API
+/// clients of the resolvers send both; git fetches send the headers, but
+/// keep libgit2's own user agent since git2 can't change it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HttpOptions {
+    pub user_agent: Option<String>,
+    /// "Name: Value" headers, as checked by [`parse_header`]
+    pub headers: Vec<String>,
+}
+
+impl HttpOptions {
+    /// The configured user agent, or [`DEFAULT_USER_AGENT`]
+    pub fn user_agent(&self) -> &str {
+        self.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT)
+    }
+
+    /// Add the headers to a git fetch over HTTP(S); SSH ignores them
+    pub fn apply_to_fetch(&self, options: &mut FetchOptions<'_>) {
+        let headers: Vec<&str> = self.headers.iter().map(String::as_str).collect();
+        options.custom_headers(&headers);
+    }
+
+    /// The headers as a map for reqwest
+    pub fn header_map(&self) -> Result<HeaderMap> {
+        let mut map = HeaderMap::new();
+        for header in &self.headers {
+            let (name, value) = split_header(header)?;
+            map.append(HeaderName::try_from(name)?, HeaderValue::try_from(value)?);
+        }
+        Ok(map)
+    }
+
+    /// A reqwest client sending the user agent and headers on every request
+    pub fn client(&self) -> Result<reqwest::Client> {
+        reqwest::Client::builder()
+            .user_agent(self.user_agent())
+            .default_headers(self.header_map()?)
+            .build()
+            .context("Failed to build HTTP client")
+    }
+}
+
+/// Client sending [`DEFAULT_USER_AGENT`], for API clients that haven't been
+/// given one built from [`HttpOptions`]
+pub fn default_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .user_agent(DEFAULT_USER_AGENT)
+        .build()
+        .expect("Failed to build HTTP client")
+}
+
+/// Check a `--header` value and normalize it to "Name: Value"
+pub fn parse_header(header: &str) -> Result<String> {
+    let (name, value) = split_header(header)?;
+    if RESERVED_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+        anyhow::bail!(
+            "The {} header can't be overridden{}",
+            name,
+            if name.eq_ignore_ascii_case("user-agent") {
+                "; use --user-agent"
+            } else {
+                ""
+            }
+        );
+    }
+    HeaderName::try_from(name).with_context(|| format!("Invalid header name '{}'", name))?;
+    HeaderValue::try_from(value).with_context(|| format!("Invalid value for header {}", name))?;
+
+    Ok(format!("{}: {}", name, value))
+}
+
+fn split_header(header: &str) -> Result<(&str, &str)> {
+    let (name, value) = header
+        .split_once(':')
+        .with_context(|| format!("Expected 'Name: Value', got '{}'", header))?;
+    Ok((name.trim(), value.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use mockito::Matcher;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_header() {
+        assert_eq!(
+            parse_header("X-Proxy-Auth:  secret ").unwrap(),
+            "X-Proxy-Auth: secret"
+        );
+        assert!(parse_header("no colon").is_err());
+        assert!(parse_header("Bad Name: x").is_err());
+        assert!(
+            parse_header("User-Agent: curl")
+                .unwrap_err()
+                .to_string()
+                .contains("--user-agent")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_sends_headers() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/")
+            .match_header("user-agent", "corp-bot/1.0")
+            .match_header("x-proxy-auth", "secret")
+            .match_header("x-team", Matcher::Any)
+            .with_status(204)
+            .create_async()
+            .await;
+
+        let http = HttpOptions {
+            user_agent: Some("corp-bot/1.0".to_string()),
+            headers: vec![
+                "X-Proxy-Auth: secret".to_string(),
+                "X-Team: platform".to_string(),
+            ],
+        };
+        let response = http
+            .client()
+            .unwrap()
+            .get(server.url())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 204);
+        mock.assert_async().await;
+
+        assert_eq!(HttpOptions::default().user_agent(), DEFAULT_USER_AGENT);
+    }
+}
@@ -0,0 +1,553 @@
+use std::{collections::HashMap, fmt, fs, path::PathBuf};
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use lazy_static::lazy_static;
+use regex::Regex;
+use reqwest::{StatusCode, header};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::debug;
+
+use crate::{error::RefNotFound, http};
+
+/// Docker Hub's canonical registry name
+pub const DOCKER_HUB: &str = "docker.io";
+
+/// Host serving Docker Hub's registry API
+const DOCKER_HUB_API: &str = "registry-1.docker.io";
+
+/// Key Docker uses for Docker Hub credentials in config.json
+const DOCKER_HUB_CONFIG_KEY: &str = "https://index.docker.io/v1/";
+
+/// Manifest media types we accept. Offering the index and manifest list
+/// types means a multi-arch image resolves to the digest of its list, which
+/// works on every platform, rather than to one platform's manifest
+const MANIFEST_TYPES: &str = "application/vnd.oci.image.index.v1+json, \
+                              application/vnd.docker.distribution.manifest.list.v2+json, \
+                              application/vnd.oci.image.manifest.v1+json, \
+                              application/vnd.docker.distribution.manifest.v2+json";
+
+lazy_static! {
+    /// Regex to match a `key="value"` parameter of a WWW-Authenticate challenge
+    static ref CHALLENGE_PARAM_REGEX: Regex = Regex::new(r#"(\w+)="([^"]*)""#).unwrap();
+}
+
+/// Whether a string is a `sha256:` manifest digest
+pub fn is_digest(digest: &str) -> bool {
+    digest.strip_prefix("sha256:").is_some_and(|hex| {
+        hex.len() == 64
+            && hex
+                .chars()
+                .all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase())
+    })
+}
+
+/// Where in a workflow a container image is used
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageKind {
+    /// A job's `container:`
+    #[default]
+    Container,
+    /// A service container under a job's `services:`
+    Service,
+}
+
+impl ImageKind {
+    /// Human-readable plural label for summaries
+    pub fn label(&self) -> &'static str {
+        match self {
+            ImageKind::Container => "Job containers",
+            ImageKind::Service => "Services",
+        }
+    }
+}
+
+/// A container image reference such as "node:20", "ghcr.io/owner/app:1.2"
+/// or "redis@sha256:..."
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ImageRef {
+    /// The image name as written, without tag or digest
+    pub name: String,
+    /// Registry host, "docker.io" when none is given
+    pub registry: String,
+    /// Repository path on the registry, e.g. "library/node"
+    pub repository: String,
+    pub tag: Option<String>,
+    pub digest: Option<String>,
+}
+
+impl ImageRef {
+    /// Parse an image reference, returning None for expressions and other
+    /// values that aren't a literal image
+    pub fn parse(image: &str) -> Option<Self> {
+        if image.is_empty() || image.contains("${{") || image.contains(char::is_whitespace) {
+            return None;
+        }
+
+        let (rest, digest) = match image.split_once('@') {
+            Some((rest, digest)) => (rest, Some(digest.to_string())),
+            None => (image, None),
+        };
+
+        // A colon before the last slash belongs to a registry port
+        let (name, tag) = match rest.rsplit_once(':') {
+            Some((name, tag)) if !tag.contains('/') => (name, Some(tag.to_string())),
+            _ => (rest, None),
+        };
+        if name.is_empty() || tag.as_deref() == Some("") {
+            return None;
+        }
+
+        let (registry, repository) = match name.split_once('/') {
+            Some((host, path)) if host.contains(['.', ':']) || host == "localhost" => {
+                (host, path.to_string())
+            },
+            _ => (DOCKER_HUB, name.to_string()),
+        };
+        let registry = match registry {
+            "index.docker.io" => DOCKER_HUB,
+            registry => registry,
+        };
+
+        // Official Docker Hub images live under library/
+        let repository = if registry == DOCKER_HUB && !repository.contains('/') {
+            format!("library/{}", repository)
+        } else {
+            repository
+        };
+
+        let valid = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || "._-/".contains(c);
+        if !repository.chars().all(valid) {
+            return None;
+        }
+
+        Some(Self {
+            name: name.to_string(),
+            registry: registry.to_string(),
+            repository,
+            tag,
+            digest,
+        })
+    }
+
+    /// Check if the image is already pinned to a digest
+    pub fn is_pinned(&self) -> bool {
+        self.digest.is_some()
+    }
+
+    /// The tag to resolve, "latest" when none is given
+    pub fn tag_or_latest(&self) -> &str {
+        self.tag.as_deref().unwrap_or("latest")
+    }
+
+    /// The reference pinned to a digest, keeping the tag for readability
+    pub fn pinned(&self, digest: &str) -> String {
+        match &self.tag {
+            Some(tag) => format!("{}:{}@{}", self.name, tag, digest),
+            None => format!("{}@{}", self.name, digest),
+        }
+    }
+}
+
+impl fmt::Display for ImageRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(tag) = &self.tag {
+            write!(f, ":{}", tag)?;
+        }
+        if let Some(digest) = &self.digest {
+            write!(f, "@{}", digest)?;
+        }
+        Ok(())
+    }
+}
+
+/// Registry credentials, keyed by registry host
+#[derive(Clone, Default)]
+pub struct RegistryCredentials {
+    auths: HashMap<String, (String, String)>,
+}
+
+// Never print the passwords themselves
+impl fmt::Debug for RegistryCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RegistryCredentials")
+            .field("registries", &self.auths.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl RegistryCredentials {
+    /// Load credentials from Docker's config.json (in `$DOCKER_CONFIG`, or
+    /// `~/.docker`), returning none if it is missing or unreadable
+    pub fn from_docker_config() -> Self {
+        let dir = std::env::var_os("DOCKER_CONFIG")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".docker")));
+        let Some(path) = dir.map(|dir| dir.join("config.json")) else {
+            return Self::default();
+        };
+
+        let Ok(content) = fs::read_to_string(&path) else {
+            return Self::default();
+        };
+
+        Self::parse_docker_config(&content).unwrap_or_else(|e| {
+            debug!("Ignoring {}: {}", path.display(), e);
+            Self::default()
+        })
+    }
+
+    /// Parse the `auths` section of a Docker config.json
+    pub fn parse_docker_config(content: &str) -> Result<Self> {
+        let config: Value = serde_json::from_str(content)?;
+        let mut auths = HashMap::new();
+
+        let entries = config["auths"].as_object().into_iter().flatten();
+        for (registry, entry) in entries {
+            let Some(auth) = entry["auth"].as_str() else {
+                continue;
+            };
+            let decoded = base64::engine::general_purpose::STANDARD
+                .decode(auth)
+                .with_context(|| format!("Invalid auth for {}", registry))?;
+            let decoded = String::from_utf8(decoded)?;
+            if let Some((username, password)) = decoded.split_once(':') {
+                auths.insert(
+                    config_registry(registry),
+                    (username.to_string(), password.to_string()),
+                );
+            }
+        }
+
+        Ok(Self { auths })
+    }
+
+    /// Use a GitHub token for ghcr.io, unless Docker already has credentials
+    /// for it
+    pub fn with_github_token(mut self, token: Option<&str>) -> Self {
+        if let Some(token) = token {
+            self.auths
+                .entry("ghcr.io".to_string())
+                .or_insert_with(|| ("x-access-token".to_string(), token.to_string()));
+        }
+        self
+    }
+
+    /// Username and password for a registry host
+    pub fn get(&self, registry: &str) -> Option<&(String, String)> {
+        self.auths.get(registry)
+    }
+}
+
+/// The registry host a Docker config.json `auths` key refers to
+fn config_registry(key: &str) -> String {
+    if key == DOCKER_HUB_CONFIG_KEY {
+        return DOCKER_HUB.to_string();
+    }
+
+    let host = key
+        .trim_start_matches("https://")
+        .trim_start_matches("http://");
+    let host = host.split('/').next().unwrap_or(host);
+    match host {
+        "index.docker.io" | "registry-1.docker.io" => DOCKER_HUB.to_string(),
+        host => host.to_string(),
+    }
+}
+
+/// Resolves image tags to manifest digests through the registry HTTP API
+#[derive(Clone)]
+pub struct RegistryClient {
+    client: reqwest::Client,
+    credentials: RegistryCredentials,
+}
+
+impl RegistryClient {
+    pub fn new(credentials: RegistryCredentials) -> Self {
+        Self {
+            client: http::default_client(),
+            credentials,
+        }
+    }
+
+    /// Send requests through `client`, e.g. one from [`HttpOptions::client`]
+    ///
+    /// [`HttpOptions::client`]: crate::http::HttpOptions::client
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Resolve an image's tag to the digest of its manifest (or manifest
+    /// list, for multi-arch images)
+    pub async fn resolve(&self, image: &ImageRef) -> Result<String> {
+        let url = format!(
+            "{}/v2/{}/manifests/{}",
+            api_base(&image.registry),
+            image.repository,
+            image.tag_or_latest()
+        );
+
+        let mut response = self.head_manifest(&url, None).await?;
+
+        // Registries answer anonymous requests with a challenge naming where
+        // to get a token, even for public images
+        if response.status() == StatusCode::UNAUTHORIZED {
+            let challenge = response
+                .headers()
+                .get(header::WWW_AUTHENTICATE)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string);
+            if let Some(challenge) = challenge {
+                let authorization = self.authorize(&challenge, &image.registry).await?;
+                response = self.head_manifest(&url, Some(&authorization)).await?;
+            }
+        }
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(RefNotFound(format!(
+                "no tag '{}' for image {}",
+                image.tag_or_latest(),
+                image.name
+            ))
+            .into());
+        }
+
+        let response = response.error_for_status()?;
+        let digest = response
+            .headers()
+            .get("docker-content-digest")
+            .and_then(|value| value.to_str().ok())
+            .context("Registry response has no Docker-Content-Digest")?;
+
+        if !is_digest(digest) {
+            anyhow::bail!("Unexpected digest '{}' for {}", digest, image);
+        }
+
+        debug!("Resolved {} → {}", image, digest);
+        Ok(digest.to_string())
+    }
+
+    async fn head_manifest(
+        &self,
+        url: &str,
+        authorization: Option<&str>,
+    ) -> Result<reqwest::Response> {
+        let mut request = self.client.head(url).header(header::ACCEPT, MANIFEST_TYPES);
+        if let Some(authorization) = authorization {
+            request = request.header(header::AUTHORIZATION, authorization);
+        }
+
+        request
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach registry: {}", url))
+    }
+
+    /// Answer a WWW-Authenticate challenge, returning the Authorization
+    /// header to retry with
+    async fn authorize(&self, challenge: &str, registry: &str) -> Result<String> {
+        let credentials = self.credentials.get(registry);
+
+        let Some(params) = challenge.strip_prefix("Bearer ") else {
+            let (username, password) =
+                credentials.with_context(|| format!("No credentials for {}", registry))?;
+            let basic = base64::engine::general_purpose::STANDARD
+                .encode(format!("{}:{}", username, password));
+            return Ok(format!("Basic {}", basic));
+        };
+
+        let params: HashMap<&str, &str> = CHALLENGE_PARAM_REGEX
+            .captures_iter(params)
+            .filter_map(|c| Some((c.get(1)?.as_str(), c.get(2)?.as_str())))
+            .collect();
+        let realm = params
+            .get("realm")
+            .with_context(|| format!("Malformed challenge from {}: {}", registry, challenge))?;
+
+        let query: Vec<(&str, &str)> = ["service", "scope"]
+            .into_iter()
+            .filter_map(|key| Some((key, *params.get(key)?)))
+            .collect();
+        let mut request = self.client.get(*realm).query(&query);
+        if let Some((username, password)) = credentials {
+            request = request.basic_auth(username, Some(password));
+        }
+
+        let body: Value = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach token service: {}", realm))?
+            .error_for_status()?
+            .json()
+            .await?;
+        let token = body["token"]
+            .as_str()
+            .or_else(|| body["access_token"].as_str())
+            .with_context(|| format!("No token from {}", realm))?;
+
+        Ok(format!("Bearer {}", token))
+    }
+}
+
+/// Base URL of a registry's API. Local registries are plain HTTP, as Docker
+/// itself assumes
+fn api_base(registry: &str) -> String {
+    let host = match registry {
+        DOCKER_HUB => DOCKER_HUB_API,
+        registry => registry,
+    };
+
+    if host.starts_with("localhost") || host.starts_with("127.0.0.1") {
+        format!("http://{}", host)
+    } else {
+        format!("https://{}", host)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGEST: &str = "sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+    #[test]
+    fn test_parse_image() {
+        let image = ImageRef::parse("node:20").unwrap();
+        assert_eq!(image.registry, DOCKER_HUB);
+        assert_eq!(image.repository, "library/node");
+        assert_eq!(image.tag.as_deref(), Some("20"));
+        assert!(!image.is_pinned());
+
+        let image = ImageRef::parse("ghcr.io/owner/app").unwrap();
+        assert_eq!(image.registry, "ghcr.io");
+        assert_eq!(image.repository, "owner/app");
+        assert_eq!(image.tag_or_latest(), "latest");
+
+        let image = ImageRef::parse("localhost:5000/team/app:1.0").unwrap();
+        assert_eq!(image.registry, "localhost:5000");
+        assert_eq!(image.repository, "team/app");
+        assert_eq!(image.tag.as_deref(), Some("1.0"));
+
+        let image = ImageRef::parse(&format!("redis:7@{}", DIGEST)).unwrap();
+        assert!(image.is_pinned());
+        assert_eq!(image.to_string(), format!("redis:7@{}", DIGEST));
+
+        assert!(ImageRef::parse("${{ matrix.image }}").is_none());
+        assert!(ImageRef::parse("Node:20").is_none());
+        assert!(ImageRef::parse("node:").is_none());
+    }
+
+    #[test]
+    fn test_pinned_image() {
+        let image = ImageRef::parse("node:20").unwrap();
+        assert_eq!(image.pinned(DIGEST), format!("node:20@{}", DIGEST));
+
+        let image = ImageRef::parse("ghcr.io/owner/app").unwrap();
+        assert_eq!(
+            image.pinned(DIGEST),
+            format!("ghcr.io/owner/app@{}", DIGEST)
+        );
+
+        assert!(is_digest(DIGEST));
+        assert!(!is_digest("sha256:abc"));
+    }
+
+    #[test]
+    fn test_docker_config_credentials() {
+        let config = r#"{"auths": {
+            "https://index.docker.io/v1/": {"auth": "dXNlcjpodWI="},
+            "ghcr.io": {"auth": "b3duZXI6c2VjcmV0"}
+        }}"#;
+        let credentials = RegistryCredentials::parse_docker_config(config)
+            .unwrap()
+            .with_github_token(Some("ghp_token"));
+
+        assert_eq!(
+            credentials.get(DOCKER_HUB),
+            Some(&("user".to_string(), "hub".to_string()))
+        );
+        // Docker's own credentials win over the GitHub token
+        assert_eq!(
+            credentials.get("ghcr.io"),
+            Some(&("owner".to_string(), "secret".to_string()))
+        );
+        assert!(!format!("{:?}", credentials).contains("secret"));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_with_token_challenge() {
+        let mut server = mockito::Server::new_async().await;
+        let registry = server.host_with_port();
+
+        let challenge = server
+            .mock("HEAD", "/v2/team/app/manifests/1.0")
+            .match_header("authorization", mockito::Matcher::Missing)
+            .with_status(401)
+            .with_header(
+                "www-authenticate",
+                &format!(
+                    r#"Bearer realm="{}/token",service="registry",scope="repository:team/app:pull""#,
+                    server.url()
+                ),
+            )
+            .create_async()
+            .await;
+        let token = server
+            .mock("GET", "/token")
+            .match_query(mockito::Matcher::UrlEncoded(
+                "scope".into(),
+                "repository:team/app:pull".into(),
+            ))
+            .with_body(r#"{"token": "abc"}"#)
+            .create_async()
+            .await;
+        let manifest = server
+            .mock("HEAD", "/v2/team/app/manifests/1.0")
+            .match_header("authorization", "Bearer abc")
+            .match_header(
+                "accept",
+                mockito::Matcher::Regex("manifest.list.v2".to_string()),
+            )
+            .with_header("docker-content-digest", DIGEST)
+            .create_async()
+            .await;
+
+        let image = ImageRef::parse(&format!("{}/team/app:1.0", registry)).unwrap();
+        let digest = RegistryClient::new(RegistryCredentials::default())
+            .resolve(&image)
+            .await
+            .unwrap();
+
+        assert_eq!(digest, DIGEST);
+        challenge.assert_async().await;
+        token.assert_async().await;
+        manifest.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_resolve_missing_tag() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("HEAD", "/v2/team/app/manifests/9.9")
+            .with_status(404)
+            .create_async()
+            .await;
+
+        let image = ImageRef::parse(&format!("{}/team/app:9.9", server.host_with_port())).unwrap();
+        let error = RegistryClient::new(RegistryCredentials::default())
+            .resolve(&image)
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            crate::error::ErrorCode::classify_resolve(&error),
+            crate::error::ErrorCode::RefNotFound
+        );
+    }
+}
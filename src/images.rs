@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use tracing::{debug, info, warn};
+
+use crate::{
+    error::{ErrorCode, ErrorDetail},
+    image::{ImageRef, RegistryClient, RegistryCredentials},
+    lockfile::Lockfile,
+    workflow::WorkflowProcessor,
+};
+
+impl WorkflowProcessor {
+    /// Resolve container images to manifest digests keyed by the image as
+    /// written, answering from the lockfile first like actions
+    pub(crate) async fn resolve_images(
+        &self,
+        images: Vec<ImageRef>,
+        mut lockfile: Option<&mut Lockfile>,
+    ) -> (HashMap<String, String>, Vec<ErrorDetail>) {
+        use futures::stream::{self, StreamExt};
+
+        let mut digests = HashMap::new();
+        let mut errors = Vec::new();
+
+        let mut to_resolve = Vec::new();
+        for image in images {
+            let key = image.to_string();
+            match lockfile
+                .as_deref()
+                .and_then(|lockfile| lockfile.get_image(&key))
+            {
+                Some(digest) => {
+                    debug!("Lockfile hit for {} → {}", key, digest);
+                    digests.insert(key, digest.clone());
+                },
+                None if self.offline => {
+                    warn!(
+                        "Failed to resolve {}: not in the lockfile (offline mode)",
+                        key
+                    );
+                    errors.push(self.fail(
+                        ErrorCode::Offline,
+                        key,
+                        "not in the lockfile (offline mode)".to_string(),
+                    ));
+                },
+                None => to_resolve.push(image),
+            }
+        }
+
+        if to_resolve.is_empty() {
+            return (digests, errors);
+        }
+
+        info!("Resolving {} container image(s)", to_resolve.len());
+        let credentials =
+            RegistryCredentials::from_docker_config().with_github_token(self.auth.token.as_deref());
+        let client = RegistryClient::new(credentials).with_client(self.http_client.clone());
+
+        let results: Vec<(ImageRef, Result<String>)> = stream::iter(to_resolve)
+            .map(|image| {
+                let client = &client;
+                async move {
+                    let digest = client.resolve(&image).await;
+                    (image, digest)
+                }
+            })
+            .buffer_unordered(self.concurrency)
+            .collect()
+            .await;
+
+        for (image, result) in results {
+            let key = image.to_string();
+            match result {
+                Ok(digest) => {
+                    if let Some(lockfile) = lockfile.as_deref_mut() {
+                        lockfile.insert_image(key.clone(), digest.clone());
+                    }
+                    digests.insert(key, digest);
+                },
+                Err(e) => {
+                    warn!("Failed to resolve {}: {}", key, e);
+                    errors.push(self.fail(
+                        ErrorCode::classify_resolve(&e),
+                        key,
+                        format!("{:#}", e),
+                    ));
+                },
+            }
+        }
+
+        (digests, errors)
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    action::ActionRef,
+    error::ErrorDetail,
+    parser::WorkflowFile,
+    workflow::{CollectedActions, WorkflowProcessor},
+};
+
+/// One distinct action and every place it is used
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActionInventory {
+    pub action: String,
+    /// Distinct refs the action is used at, in order of appearance
+    pub refs: Vec<String>,
+    pub usages: Vec<ActionUsage>,
+}
+
+/// A single use of an action
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActionUsage {
+    pub file: String,
+    pub line: usize,
+    /// Column of the action on its line, counting from 1, telling apart
+    /// several uses on one line
+    #[serde(default)]
+    pub column: usize,
+    /// None for ref-less uses, which follow the default branch
+    pub reference: Option<String>,
+    /// Version recorded in the trailing comment, if any
+    pub comment_ref: Option<String>,
+    pub pinned: bool,
+    /// Commit the use points at: the pin itself, or the ref resolved now.
+    /// Only filled in by [`WorkflowProcessor::inventory`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha: Option<String>,
+}
+
+/// Every action used, as catalogued by --inventory
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct InventoryResults {
+    pub files_processed: usize,
+    pub actions: Vec<ActionInventory>,
+    pub errors: usize,
+    #[serde(default)]
+    pub errors_detail: Vec<ErrorDetail>,
+}
+
+/// Group the uses of parsed workflows by action, in order of appearance
+fn inventory_of(workflows: &[WorkflowFile]) -> Vec<ActionInventory> {
+    let mut inventory: BTreeMap<String, ActionInventory> = BTreeMap::new();
+    for workflow in workflows {
+        for uses in &workflow.actions {
+            let entry = inventory
+                .entry(uses.action.repository.clone())
+                .or_insert_with(|| ActionInventory {
+                    action: uses.action.repository.clone(),
+                    refs: Vec::new(),
+                    usages: Vec::new(),
+                });
+
+            let reference = (!uses.unversioned).then(|| uses.action.qualified_reference());
+            if let Some(reference) = &reference {
+                if !entry.refs.contains(reference) {
+                    entry.refs.push(reference.clone());
+                }
+            }
+
+            entry.usages.push(ActionUsage {
+                file: workflow.path.clone(),
+                line: uses.line_number,
+                column: uses.column(),
+                reference,
+                comment_ref: uses.comment_ref.clone(),
+                pinned: uses.action.is_sha,
+                sha: None,
+            });
+        }
+    }
+
+    inventory.into_values().collect()
+}
+
+impl WorkflowProcessor {
+    /// Inventory of every distinct action and where it is used
+    pub async fn list_actions(&self) -> Result<Vec<ActionInventory>> {
+        let collected = self.collect_actions().await?;
+        Ok(inventory_of(&collected.workflows))
+    }
+
+    /// Inventory of every distinct action and where it is used, with the
+    /// commit each use points at. Pins are taken as they are; with
+    /// `resolve`, other refs are resolved without writing anything
+    pub async fn inventory(&self, resolve: bool) -> Result<InventoryResults> {
+        let CollectedActions {
+            files_processed,
+            workflows: mut parsed_workflows,
+            errors: mut errors_detail,
+            ..
+        } = self.collect_actions().await?;
+        let mut actions = inventory_of(&parsed_workflows);
+
+        let mut shas: HashMap<(String, usize, usize), String> = HashMap::new();
+        for workflow in &parsed_workflows {
+            for uses in workflow.actions.iter().filter(|u| u.action.is_sha) {
+                shas.insert(
+                    (workflow.path.clone(), uses.line_number, uses.start + 1),
+                    uses.action.reference.to_lowercase(),
+                );
+            }
+        }
+
+        if resolve {
+            errors_detail.extend(self.resolve_unversioned(&mut parsed_workflows).await);
+
+            let mut unique: HashMap<String, ActionRef> = HashMap::new();
+            for uses in parsed_workflows
+                .iter()
+                .flat_map(|w| &w.actions)
+                .filter(|u| !u.action.is_sha)
+            {
+                unique
+                    .entry(uses.action.to_string())
+                    .or_insert_with(|| uses.action.clone());
+            }
+
+            info!("Resolving {} action ref(s) for the inventory", unique.len());
+            // The inventory is read-only, so new resolutions aren't saved
+            let mut lockfile = self.load_lockfile()?;
+            let (resolved, errors) = self
+                .resolve_actions(unique.into_values().collect(), lockfile.as_mut())
+                .await;
+            self.stop_on_error(&errors, None)?;
+            errors_detail.extend(errors);
+
+            for workflow in &parsed_workflows {
+                for uses in workflow.actions.iter().filter(|u| !u.action.is_sha) {
+                    if let Some(sha) = resolved.get(&uses.action.to_string()) {
+                        shas.insert(
+                            (workflow.path.clone(), uses.line_number, uses.start + 1),
+                            sha.clone(),
+                        );
+                    }
+                }
+            }
+        }
+
+        for usage in actions.iter_mut().flat_map(|entry| &mut entry.usages) {
+            usage.sha = shas.remove(&(usage.file.clone(), usage.line, usage.column));
+        }
+
+        Ok(InventoryResults {
+            files_processed,
+            actions,
+            errors: errors_detail.len(),
+            errors_detail,
+        })
+    }
+}
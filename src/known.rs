@@ -0,0 +1,111 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::lock;
+
+/// Conventional location of the store, committed alongside the workflows
+pub const DEFAULT_KNOWN_REFS: &str = ".github/pin-actions-known.json";
+
+/// The SHA an "owner/repo@ref" resolved to when first seen
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KnownRef {
+    pub sha: String,
+    /// Date the resolution was recorded (YYYY-MM-DD)
+    pub first_seen: String,
+}
+
+/// Trust-on-first-use record of ref resolutions. Unlike the lockfile, which
+/// answers lookups, this only ever raises the alarm when a ref that was seen
+/// before now resolves somewhere else
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KnownRefs {
+    pub actions: BTreeMap<String, KnownRef>,
+}
+
+impl KnownRefs {
+    /// Load the store, returning an empty one if the file doesn't exist yet
+    /// or is empty (so `touch` is enough to opt in)
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content = lock::read_locked(path)
+            .with_context(|| format!("Failed to read known refs: {}", path.display()))?;
+        match content {
+            Some(content) => Self::parse(&content, path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    fn parse(content: &str, path: &Path) -> Result<Self> {
+        if content.trim().is_empty() {
+            return Ok(Self::default());
+        }
+        serde_json::from_str(content)
+            .with_context(|| format!("Invalid known refs: {}", path.display()))
+    }
+
+    /// Write the store as pretty JSON, keeping refs another run recorded
+    /// since this one was loaded
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        lock::update_locked(path, |current| {
+            let mut merged = match current {
+                Some(current) => Self::parse(current, path)?,
+                None => Self::default(),
+            };
+            merged.actions.extend(self.actions.clone());
+            Ok(serde_json::to_string_pretty(&merged)? + "\n")
+        })
+        .with_context(|| format!("Failed to write known refs: {}", path.display()))
+    }
+
+    pub fn get(&self, action: &str) -> Option<&KnownRef> {
+        self.actions.get(action)
+    }
+
+    /// Record (or, when accepting a change, replace) the SHA for a key
+    pub fn insert(&mut self, action: String, sha: String, date: String) {
+        self.actions.insert(
+            action,
+            KnownRef {
+                sha,
+                first_seen: date,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    const SHA: &str = "b4ffde65f46336ab88eb53be808477a3936bae11";
+
+    #[test]
+    fn test_known_refs_round_trip() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join("known.json");
+
+        assert_eq!(KnownRefs::load(&path).unwrap(), KnownRefs::default());
+        fs::write(&path, "").unwrap();
+        assert_eq!(KnownRefs::load(&path).unwrap(), KnownRefs::default());
+
+        let mut known = KnownRefs::default();
+        known.insert(
+            "actions/checkout@v4".to_string(),
+            SHA.to_string(),
+            "2024-05-01".to_string(),
+        );
+        known.save(&path).unwrap();
+
+        let loaded = KnownRefs::load(&path).unwrap();
+        assert_eq!(loaded.get("actions/checkout@v4").unwrap().sha, SHA);
+        assert_eq!(
+            loaded.get("actions/checkout@v4").unwrap().first_seen,
+            "2024-05-01"
+        );
+    }
+}
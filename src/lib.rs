@@ -0,0 +1,34 @@
+//! Pin GitHub Actions to commit SHAs for improved security
+
+pub mod action;
+pub mod api;
+pub mod archive;
+pub mod auth;
+pub mod changed;
+pub mod check;
+pub mod commit;
+pub mod config;
+pub mod drift;
+pub mod error;
+pub mod git;
+pub mod graphql;
+pub mod health;
+pub mod http;
+pub mod image;
+pub mod images;
+pub mod inventory;
+pub mod known;
+pub mod lock;
+pub mod lockfile;
+pub mod logging;
+pub mod parser;
+pub mod policy;
+pub mod pr;
+pub mod rdjson;
+pub mod report;
+pub mod resolver;
+pub mod signature;
+pub mod strict_sha;
+pub mod unpin;
+pub mod unresolvable;
+pub mod workflow;
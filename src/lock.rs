@@ -0,0 +1,152 @@
+use std::{
+    fs::{File, OpenOptions, TryLockError},
+    io::{ErrorKind, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use tracing::{debug, info};
+
+/// What to do when another instance holds a lock we need
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LockMode {
+    /// Give up straight away with an error naming the locked path
+    #[default]
+    Fail,
+    /// Block until the other instance releases it
+    Wait,
+}
+
+/// An advisory exclusive lock on a workflows directory (or archive), held
+/// for the duration of a run and released when dropped
+#[derive(Debug)]
+pub struct RunLock {
+    _file: File,
+}
+
+impl RunLock {
+    /// Take an exclusive lock on `path`, a directory or a file
+    pub fn acquire<P: AsRef<Path>>(path: P, mode: LockMode) -> Result<Self> {
+        let path = path.as_ref();
+        let file =
+            File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+
+        match file.try_lock() {
+            Ok(()) => {},
+            Err(TryLockError::WouldBlock) if mode == LockMode::Wait => {
+                info!("Waiting for another run to release {}", path.display());
+                file.lock()
+                    .with_context(|| format!("Failed to lock {}", path.display()))?;
+            },
+            Err(TryLockError::WouldBlock) => anyhow::bail!(
+                "Another pin-actions run holds the lock on {}; rerun with --lock-mode wait to wait for it",
+                path.display()
+            ),
+            Err(TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("Failed to lock {}", path.display()));
+            },
+        }
+
+        debug!("Locked {}", path.display());
+        Ok(Self { _file: file })
+    }
+}
+
+/// Read a file while holding a shared lock, so a concurrent writer is never
+/// observed half-way. Returns None if the file doesn't exist
+pub fn read_locked<P: AsRef<Path>>(path: P) -> Result<Option<String>> {
+    let path = path.as_ref();
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+
+    file.lock_shared()
+        .with_context(|| format!("Failed to lock {}", path.display()))?;
+    let mut content = String::new();
+    file.read_to_string(&mut content)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(Some(content))
+}
+
+/// Rewrite a file while holding an exclusive lock. `update` receives the
+/// current content (None if the file is new) so changes made by another
+/// instance since we read it can be merged rather than overwritten
+pub fn update_locked<P, F>(path: P, update: F) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: FnOnce(Option<&str>) -> Result<String>,
+{
+    let path = path.as_ref();
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    file.lock()
+        .with_context(|| format!("Failed to lock {}", path.display()))?;
+
+    let mut current = String::new();
+    file.read_to_string(&mut current)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let content = update((!current.is_empty()).then_some(current.as_str()))?;
+
+    file.set_len(0)
+        .and_then(|_| file.seek(SeekFrom::Start(0)))
+        .and_then(|_| file.write_all(content.as_bytes()))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, thread};
+
+    use super::*;
+
+    #[test]
+    fn test_concurrent_updates_are_not_lost() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join("store.json");
+
+        let writers: Vec<_> = (0..8)
+            .map(|n| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    update_locked(&path, |current| {
+                        let mut map: BTreeMap<String, usize> = current
+                            .map(serde_json::from_str)
+                            .transpose()?
+                            .unwrap_or_default();
+                        map.insert(format!("writer-{}", n), n);
+                        Ok(serde_json::to_string_pretty(&map)?)
+                    })
+                    .unwrap();
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let content = read_locked(&path).unwrap().unwrap();
+        let map: BTreeMap<String, usize> = serde_json::from_str(&content).unwrap();
+        assert_eq!(map.len(), 8);
+    }
+
+    #[test]
+    fn test_run_lock_contention() {
+        let temp = tempfile::TempDir::new().unwrap();
+
+        let held = RunLock::acquire(temp.path(), LockMode::Fail).unwrap();
+        let error = RunLock::acquire(temp.path(), LockMode::Fail).unwrap_err();
+        assert!(error.to_string().contains("holds the lock"));
+
+        drop(held);
+        assert!(RunLock::acquire(temp.path(), LockMode::Fail).is_ok());
+    }
+}
@@ -0,0 +1,167 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{image, lock};
+
+/// Persistent record of resolved action SHAs, keyed by "owner/repo@ref",
+/// and container image digests, keyed by the image as written
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lockfile {
+    pub actions: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub images: BTreeMap<String, String>,
+}
+
+impl Lockfile {
+    /// Load a lockfile, returning an empty one if the file doesn't exist yet
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content = lock::read_locked(path)
+            .with_context(|| format!("Failed to read lockfile: {}", path.display()))?;
+        match content {
+            Some(content) => Self::parse(&content, path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Parse and validate lockfile content read from `path`
+    fn parse(content: &str, path: &Path) -> Result<Self> {
+        let lockfile: Self = serde_json::from_str(content)
+            .with_context(|| format!("Invalid lockfile: {}", path.display()))?;
+
+        for (action, sha) in &lockfile.actions {
+            if sha.len() != 40 || !sha.chars().all(|c| c.is_ascii_hexdigit()) {
+                anyhow::bail!(
+                    "Invalid SHA '{}' for {} in lockfile {}",
+                    sha,
+                    action,
+                    path.display()
+                );
+            }
+        }
+
+        for (image, digest) in &lockfile.images {
+            if !image::is_digest(digest) {
+                anyhow::bail!(
+                    "Invalid digest '{}' for {} in lockfile {}",
+                    digest,
+                    image,
+                    path.display()
+                );
+            }
+        }
+
+        Ok(lockfile)
+    }
+
+    /// Write the lockfile as pretty JSON. Entries another run saved since
+    /// this one was loaded are kept, with ours winning on conflict
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        lock::update_locked(path, |current| {
+            let mut merged = match current {
+                Some(current) => Self::parse(current, path)?,
+                None => Self::default(),
+            };
+            merged.actions.extend(self.actions.clone());
+            merged.images.extend(self.images.clone());
+            Ok(serde_json::to_string_pretty(&merged)? + "\n")
+        })
+        .with_context(|| format!("Failed to write lockfile: {}", path.display()))
+    }
+
+    /// Look up the SHA recorded for an "owner/repo@ref" key
+    pub fn get(&self, action: &str) -> Option<&String> {
+        self.actions.get(action)
+    }
+
+    /// Record the SHA for an "owner/repo@ref" key
+    pub fn insert(&mut self, action: String, sha: String) {
+        self.actions.insert(action, sha);
+    }
+
+    /// Look up the digest recorded for an image such as "node:20"
+    pub fn get_image(&self, image: &str) -> Option<&String> {
+        self.images.get(image)
+    }
+
+    /// Record the digest for an image
+    pub fn insert_image(&mut self, image: String, digest: String) {
+        self.images.insert(image, digest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    const SHA: &str = "b4ffde65f46336ab88eb53be808477a3936bae11";
+
+    #[test]
+    fn test_lockfile_round_trip() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join("pin-actions.lock");
+
+        assert_eq!(Lockfile::load(&path).unwrap(), Lockfile::default());
+
+        let mut lockfile = Lockfile::default();
+        lockfile.insert("actions/checkout@v4".to_string(), SHA.to_string());
+        lockfile.save(&path).unwrap();
+
+        let loaded = Lockfile::load(&path).unwrap();
+        assert_eq!(loaded.get("actions/checkout@v4"), Some(&SHA.to_string()));
+    }
+
+    #[test]
+    fn test_lockfile_rejects_invalid_sha() {
+        let temp = tempfile::NamedTempFile::new().unwrap();
+        fs::write(
+            temp.path(),
+            r#"{"actions": {"actions/checkout@v4": "abc123"}}"#,
+        )
+        .unwrap();
+
+        assert!(Lockfile::load(temp.path()).is_err());
+    }
+
+    #[test]
+    fn test_lockfile_images() {
+        let temp = tempfile::NamedTempFile::new().unwrap();
+        fs::write(
+            temp.path(),
+            r#"{"actions": {}, "images": {"node:20": "sha256:abc"}}"#,
+        )
+        .unwrap();
+        assert!(Lockfile::load(temp.path()).is_err());
+
+        // Lockfiles without images still load, and don't gain the key
+        let path = temp.path().with_extension("new");
+        let mut lockfile = Lockfile::default();
+        lockfile.insert("actions/checkout@v4".to_string(), SHA.to_string());
+        lockfile.save(&path).unwrap();
+        assert!(!fs::read_to_string(&path).unwrap().contains("images"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_lockfile_save_keeps_concurrent_entries() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join("pin-actions.lock");
+
+        // Two runs load the same (empty) lockfile and each resolve something
+        let mut first = Lockfile::load(&path).unwrap();
+        let mut second = Lockfile::load(&path).unwrap();
+        first.insert("actions/checkout@v4".to_string(), SHA.to_string());
+        second.insert("actions/cache@v4".to_string(), SHA.to_string());
+        first.save(&path).unwrap();
+        second.save(&path).unwrap();
+
+        let loaded = Lockfile::load(&path).unwrap();
+        assert!(loaded.get("actions/checkout@v4").is_some());
+        assert!(loaded.get("actions/cache@v4").is_some());
+    }
+}
@@ -1,60 +1,743 @@
-use std::path::PathBuf;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::Parser;
 use colored::Colorize;
-use tracing::{info, warn};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-
-mod action;
-mod git;
-mod parser;
-mod workflow;
-
-use workflow::WorkflowProcessor;
+use pin_actions::{
+    action::Protocol,
+    api::ApiResolver,
+    archive::Archive,
+    auth::Auth,
+    changed,
+    check::{self, PolicyCheck},
+    commit,
+    config::{Config, DEFAULT_CONFIG_FILE},
+    drift,
+    error::{ErrorCode, ErrorDetail},
+    git::{DEFAULT_GIT_HOST, GitRemote, GitResolver},
+    graphql::{self, GraphQlResolver},
+    health::RepoHealth,
+    http::{self, HttpOptions},
+    image::ImageKind,
+    inventory::ActionInventory,
+    known::DEFAULT_KNOWN_REFS,
+    lock::{LockMode, RunLock},
+    logging::{LogFormat, Redacting},
+    policy::Policy,
+    pr, rdjson,
+    report::{self, Report},
+    resolver::{Backpressure, FixedResolver, Resolver},
+    strict_sha::{self, ShaRefCollision},
+    unpin, unresolvable,
+    workflow::{
+        self, ArchivedAction, BlockedActions, EventSink, IgnorePatterns, Metrics, MovedRef,
+        PlannedChange, PolicyViolation, ProcessEvent, RemainingAction, RemainingReason,
+        ReplaceComment, ResolverKind, SkippedFile, TargetKind, WorkflowProcessor,
+    },
+};
+use tracing::{debug, info, warn};
+use tracing_subscriber::{
+    EnvFilter, Layer,
+    filter::Targets,
+    fmt::{self, writer::BoxMakeWriter},
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+};
 
 /// Pin GitHub Actions to specific commit SHAs for improved security
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Path to the workflows directory (defaults to .github/workflows)
-    #[arg(short, long, default_value = ".github/workflows")]
-    workflows_dir: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Path to a workflows directory (repeatable, defaults to
+    /// .github/workflows, or from a subdirectory the repository's)
+    #[arg(short, long, value_name = "PATH", global = true)]
+    workflows_dir: Vec<PathBuf>,
+
+    /// Scan a whole repository: workflows, composite actions (action.yml)
+    /// and workflow templates
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with = "workflows_dir",
+        global = true
+    )]
+    repo: Option<PathBuf>,
+
+    /// Options for pinning when no subcommand is given
+    #[command(flatten)]
+    pin: PinArgs,
 
     /// Perform a dry run without modifying files
-    #[arg(short = 'n', long)]
+    #[arg(short = 'n', long, global = true)]
     dry_run: bool,
 
+    /// Only report which actions are unpinned, without resolving anything
+    /// or contacting any remote
+    #[arg(long, conflicts_with_all = ["strict_sha", "report_drift", "outdated"])]
+    no_resolve: bool,
+
+    /// Check that every pin is a full 40-character SHA, reporting
+    /// abbreviated ones; with `check`, they count as unpinned even when the
+    /// `[policy]` trusts their owner
+    #[arg(long, global = true)]
+    strict_sha: bool,
+
+    /// With --strict-sha, resolve abbreviated SHAs and rewrite them to full
+    /// length
+    #[arg(long, requires = "strict_sha")]
+    expand_short_shas: bool,
+
+    /// Resolve every unpinned action without writing anything and report
+    /// only the ones that fail, with the URL tried and the error kind
+    #[arg(
+        long,
+        conflicts_with_all = ["no_resolve", "strict_sha", "report_drift", "outdated"]
+    )]
+    only_unresolvable: bool,
+
+    /// Catalogue every action used, its ref, whether it is pinned and the
+    /// commit it points at, without modifying files; with --no-resolve,
+    /// only pins have a commit
+    #[arg(
+        long,
+        conflicts_with_all = ["strict_sha", "only_unresolvable", "report_drift", "outdated"]
+    )]
+    inventory: bool,
+
+    /// With --only-unresolvable, list up to N tags and branches the
+    /// repository does have for each ref that doesn't exist
+    #[arg(long, value_name = "N", requires = "only_unresolvable")]
+    candidate_refs: Option<usize>,
+
+    /// Report pins whose version comment now resolves to a different SHA,
+    /// with the newer specific tag where one can be found
+    #[arg(long, conflicts_with_all = ["strict_sha", "report_drift"])]
+    outdated: bool,
+
+    /// Never suggest prerelease or build-metadata tags (e.g. v4.1.0-rc.1)
+    /// as newer tags; the default
+    #[arg(long, overrides_with = "allow_prerelease", global = true)]
+    no_prerelease: bool,
+
+    /// Let --outdated suggest prerelease tags as newer tags
+    #[arg(long, overrides_with = "no_prerelease", global = true)]
+    allow_prerelease: bool,
+
+    /// Rewrite the pins reported by --outdated to their current SHA
+    #[arg(long, requires = "outdated")]
+    update: bool,
+
+    /// Rewrite pins to the SHA their version comment resolves to, e.g. after
+    /// editing `# v3` to `# v4` by hand; comments that don't resolve are
+    /// reported and left alone. Comments are looked up afresh rather than
+    /// taken from the lockfile, except with --offline
+    #[arg(
+        long,
+        conflicts_with_all = ["report_drift", "outdated", "strict_sha", "only_unresolvable", "no_resolve"]
+    )]
+    sync_comments: bool,
+
+    /// When to exit non-zero: never, on errors, or also whenever unpinned
+    /// actions were found
+    #[arg(long, value_enum, default_value_t = FailOn::Errors, global = true)]
+    fail_on: FailOn,
+
+    /// Confirm every full-length SHA pin is a commit rather than a tag or
+    /// branch named like one, reporting clashes and repinning them to the
+    /// ref's commit when pinning
+    #[arg(long, global = true)]
+    verify_shas: bool,
+
+    /// Stop at the first action that fails to resolve, cancelling the
+    /// remaining lookups, and exit non-zero naming it
+    #[arg(long, global = true)]
+    fail_fast: bool,
+
+    /// Report pinned actions whose version comment now resolves to a
+    /// different SHA, without modifying files. Refs are looked up afresh
+    /// rather than taken from the lockfile, except with --offline
+    #[arg(long, conflicts_with = "strict_sha")]
+    report_drift: bool,
+
     /// Create backup files before modifying
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     backup: bool,
 
-    /// Number of concurrent requests for resolving SHAs
-    #[arg(short = 'j', long, default_value = "10")]
+    /// Backups kept per file, newest as `.bak` and older ones as `.bak.1`,
+    /// `.bak.2`, ...; each run shifts them along and drops the oldest
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u32).range(1..),
+        requires = "backup",
+        global = true
+    )]
+    backup_keep: u32,
+
+    /// Number of concurrent requests for resolving SHAs (0 or `auto` to
+    /// choose from the available parallelism)
+    #[arg(short = 'j', long, default_value = "10", value_parser = parse_jobs, global = true)]
     jobs: usize,
 
+    /// Record how resolution lookups queue behind --jobs (the most in
+    /// flight and queue waits) and report it, to help tune --jobs; not
+    /// available with --resolver graphql, which batches lookups
+    #[arg(long, global = true)]
+    timings: bool,
+
+    /// Number of workflow files parsed at once, separate from --jobs since
+    /// parsing is CPU-bound (`auto` for the available parallelism)
+    #[arg(long, value_name = "N", default_value = "auto", value_parser = parse_io_workers, global = true)]
+    io_workers: usize,
+
+    /// Backend used to resolve refs to commit SHAs
+    #[arg(long, value_enum, default_value_t = ResolverKind::Git, global = true)]
+    resolver: ResolverKind,
+
+    /// JSON map of "owner/repo@ref" to full commit SHA answering every
+    /// lookup with --resolver fixed; actions not in it fail
+    #[arg(long, value_name = "FILE", global = true)]
+    resolution_map: Option<PathBuf>,
+
+    /// GitHub token for API-backed resolvers (falls back to GITHUB_TOKEN,
+    /// GH_TOKEN, then `gh auth token`)
+    #[arg(long, value_name = "TOKEN", global = true)]
+    token: Option<String>,
+
+    /// Never send credentials to the GitHub API
+    #[arg(long, conflicts_with = "token", global = true)]
+    no_auth: bool,
+
+    /// Git transport used to resolve refs
+    #[arg(long, value_enum, default_value_t = Protocol::Https, global = true)]
+    protocol: Protocol,
+
+    /// Host serving action repositories (e.g. a GitHub Enterprise Server),
+    /// whose API at https://HOST/api/v3 is used in place of api.github.com
+    #[arg(long, value_name = "HOST", default_value = DEFAULT_GIT_HOST, global = true)]
+    git_host: String,
+
+    /// Private key for SSH resolution and --create-pr pushes (defaults to
+    /// the ssh-agent)
+    #[arg(long, value_name = "PATH", global = true)]
+    ssh_key: Option<PathBuf>,
+
+    /// Extra header for HTTPS requests to git hosts and APIs, e.g. for a
+    /// proxy; repeatable
+    #[arg(
+        long = "header",
+        value_name = "NAME: VALUE",
+        value_parser = |s: &str| http::parse_header(s).map_err(|e| e.to_string()),
+        global = true
+    )]
+    headers: Vec<String>,
+
+    /// User agent for GitHub and registry API requests; git fetches keep
+    /// libgit2's own
+    #[arg(long, value_name = "UA", global = true)]
+    user_agent: Option<String>,
+
+    /// Where to create git's scratch repository (defaults to TMPDIR); it is
+    /// removed when the run finishes
+    #[arg(long, value_name = "DIR", global = true)]
+    git_tmp_dir: Option<PathBuf>,
+
     /// Verbose output
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     verbose: bool,
 
+    /// List a link to each pinned commit on the git host in text output
+    #[arg(long, global = true)]
+    print_links: bool,
+
+    /// Don't preview the lines a verbose dry run would change
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Also write logs, including every resolution outcome at debug level,
+    /// to this file (truncated on each run)
+    #[arg(long, value_name = "PATH", global = true)]
+    log_file: Option<PathBuf>,
+
+    /// Format of --log-file records: text or json (one object per line)
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        default_value = "text",
+        requires = "log_file",
+        global = true
+    )]
+    log_format: LogFormat,
+
+    /// Leave actions written without an @ref (the default branch) untouched
+    #[arg(long, global = true)]
+    skip_unversioned: bool,
+
+    /// Pin actions written without an @ref at this ref instead of their
+    /// default branch, noting "(default ref)" in the pin comment
+    #[arg(
+        long,
+        value_name = "REF",
+        global = true,
+        conflicts_with = "skip_unversioned"
+    )]
+    default_ref: Option<String>,
+
     /// Skip actions that are already pinned
     #[arg(long, default_value = "true")]
     skip_pinned: bool,
 
-    /// Output format (text, json)
-    #[arg(short, long, default_value = "text")]
+    /// Output format: text or json (jsonl for `pin`; csv and markdown for
+    /// `list`, --inventory and --summary-only; rdjson for `check`, `audit`
+    /// and `pin --dry-run`)
+    #[arg(short, long, default_value = "text", global = true)]
     format: OutputFormat,
+
+    /// Write the --format document to this file (`-` for stdout, the
+    /// default), replacing it atomically and creating missing directories;
+    /// the text summary still prints to the terminal
+    #[arg(short, long, value_name = "PATH", global = true)]
+    output: Option<PathBuf>,
+
+    /// Report only the totals: documents keep their counters (plus
+    /// `schema_version`) but drop per-item lists, and text output skips the
+    /// line logged for each pin
+    #[arg(long, global = true)]
+    summary_only: bool,
+
+    /// Also pin `owner/repo@ref` values of these `with:` inputs
+    /// (comma-separated, defaults to "action")
+    #[arg(
+        long,
+        value_name = "KEYS",
+        value_delimiter = ',',
+        num_args = 0..=1,
+        default_missing_value = "action",
+        global = true
+    )]
+    pin_inputs: Option<Vec<String>>,
+
+    /// Skip workflow files matching a glob, relative to the workflows directory
+    /// (repeatable)
+    #[arg(long = "ignore-files", value_name = "GLOB", global = true)]
+    ignore_files: Vec<String>,
+
+    /// Skip workflow files excluded by .gitignore, global excludes, or
+    /// .git/info/exclude
+    #[arg(long, global = true)]
+    respect_gitignore: bool,
+
+    /// Append the pin date to version comments, e.g. "# v4 (pinned 2024-05-01)"
+    #[arg(long, global = true)]
+    comment_date: bool,
+
+    /// Record the semver range a version tag allows in its pin comment, e.g.
+    /// "# v4 constraint:^4.0.0", for update tools to stay within
+    #[arg(long, global = true)]
+    record_constraint: bool,
+
+    /// Pin to the bare SHA without appending a version comment, keeping any
+    /// comment already on the line; such pins can't be checked for drift or
+    /// updated later
+    #[arg(long, global = true, conflicts_with_all = ["comment_date", "record_constraint"])]
+    no_comment: bool,
+
+    /// Write a comment above each rewritten uses line naming the tool,
+    /// resolver and date, e.g. "# pinned by pin-actions v1.2.0 via ls-remote
+    /// on 2024-05-01"; one left by an earlier run is refreshed, not repeated
+    #[arg(long, global = true)]
+    provenance_comment: bool,
+
+    /// When re-pinning a SHA pin that already has a version comment (as
+    /// `update` does): rewrite the comment always, never (only the SHA
+    /// changes), or if-stale, when it names a different tag than the new pin
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ReplaceComment::Always, global = true)]
+    replace_comment: ReplaceComment,
+
+    /// Include each changed file's original and rewritten content in the
+    /// JSON output (requires --format json)
+    #[arg(long, global = true)]
+    emit_patches: bool,
+
+    /// Hold an exclusive lock on the workflows directories (or repository,
+    /// or archive) for the whole run, so concurrent runs can't interleave
+    #[arg(long, global = true)]
+    lock: bool,
+
+    /// What to do when another run holds a lock: fail straight away or
+    /// wait for it to be released
+    #[arg(
+        long,
+        value_enum,
+        value_name = "MODE",
+        default_value = "fail",
+        global = true
+    )]
+    lock_mode: LockMode,
+
+    /// Before writing each file, rewrite the result again in memory and
+    /// fail the file if that second pass would change anything
+    #[arg(long, global = true)]
+    assert_idempotent: bool,
+
+    /// Process workflows that aren't valid UTF-8 (skipped otherwise), with
+    /// the invalid bytes replaced when reading; lines holding them are never
+    /// rewritten
+    #[arg(long, global = true)]
+    lossy_utf8: bool,
+
+    /// Lockfile recording resolved SHAs; entries found there skip resolution
+    #[arg(long, value_name = "PATH", global = true)]
+    lockfile: Option<PathBuf>,
+
+    /// Trust-on-first-use store recording the SHA each ref first resolved
+    /// to; used automatically when .github/pin-actions-known.json exists.
+    /// Refs the lockfile answers are still looked up, except with --offline
+    #[arg(long, value_name = "PATH", global = true)]
+    known_refs: Option<PathBuf>,
+
+    /// Exit with status 1 when a ref resolves to a different SHA than when
+    /// first seen
+    #[arg(long, global = true)]
+    fail_on_tag_moved: bool,
+
+    /// Exit with status 1 when a used action's repository is archived.
+    /// Checked through the GitHub API, answered by the resolution itself
+    /// with --resolver graphql
+    #[arg(long, global = true)]
+    fail_on_archived: bool,
+
+    /// Trust refs that moved since first seen: pin them to their new SHA
+    /// and update the known refs store
+    #[arg(long, global = true)]
+    accept_changes: bool,
+
+    /// Resolve only from the lockfile (and --mirror-dir), failing on any
+    /// entry missing from them
+    #[arg(long, global = true)]
+    offline: bool,
+
+    /// Directory of local clones of action repositories, laid out as
+    /// owner/repo or owner/repo.git, read before asking the network
+    #[arg(long, value_name = "DIR", global = true)]
+    mirror_dir: Option<PathBuf>,
+
+    /// Resolver cache to prime from before resolving and to save new
+    /// resolutions to afterwards (JSON keyed by "owner/repo@ref")
+    #[arg(long, value_name = "FILE", global = true)]
+    warm_cache: Option<PathBuf>,
+
+    /// Only process files added or modified since HEAD forked from BASE
+    /// (default the remote's HEAD, origin/HEAD, else origin/main or
+    /// origin/master), including uncommitted and untracked ones
+    #[arg(
+        long,
+        value_name = "BASE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = changed::DEFAULT_BASE,
+        global = true
+    )]
+    changed_only: Option<String>,
+
+    /// Only touch actions in this job, given by its key or `name:`;
+    /// repeatable
+    #[arg(long = "job", value_name = "NAME", global = true)]
+    only_jobs: Vec<String>,
+
+    /// Only touch actions in this step, given by its `name:` or `id:`;
+    /// repeatable
+    #[arg(long = "step", value_name = "NAME", global = true)]
+    only_steps: Vec<String>,
+
+    /// Print the effective settings, after merging the config file and
+    /// flags, as TOML (default) or JSON and exit
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        default_missing_value = "toml"
+    )]
+    print_config: Option<ConfigFormat>,
+
+    /// Path to a config file (defaults to .pin-actions.toml if present)
+    #[arg(long, value_name = "PATH", global = true)]
+    config: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum ConfigFormat {
+    Toml,
+    Json,
+}
+
+/// The settings a run would use, as printed by --print-config
+#[derive(Debug, serde::Serialize)]
+struct EffectiveConfig<'a> {
+    config_file: Option<PathBuf>,
+    workflows_dirs: &'a [PathBuf],
+    repo: Option<&'a PathBuf>,
+    archive: Option<&'a PathBuf>,
+    out: Option<&'a PathBuf>,
+    dry_run: bool,
+    backup: bool,
+    backup_keep: u32,
+    jobs: usize,
+    timings: bool,
+    io_workers: usize,
+    resolver: ResolverKind,
+    resolution_map: Option<&'a PathBuf>,
+    auth_source: String,
+    protocol: Protocol,
+    git_host: &'a str,
+    ssh_key: Option<&'a PathBuf>,
+    user_agent: &'a str,
+    /// Header names only; values may hold credentials
+    headers: Vec<&'a str>,
+    git_tmp_dir: Option<&'a PathBuf>,
+    skip_unversioned: bool,
+    default_ref: Option<&'a str>,
+    pin_inputs: &'a [String],
+    ignore_files: &'a [String],
+    blocked_actions: &'a [String],
+    respect_gitignore: bool,
+    comment_date: bool,
+    record_constraint: bool,
+    no_comment: bool,
+    provenance_comment: bool,
+    replace_comment: ReplaceComment,
+    emit_patches: bool,
+    assert_idempotent: bool,
+    lossy_utf8: bool,
+    lock: bool,
+    lock_mode: LockMode,
+    pin_images: bool,
+    annotate: bool,
+    create_pr: bool,
+    commit: Option<&'a str>,
+    force_commit: bool,
+    check_health: bool,
+    fail_on_archived: bool,
+    verify_signatures: bool,
+    require_signed: bool,
+    require_signed_tags: bool,
+    lockfile: Option<&'a PathBuf>,
+    known_refs: Option<&'a PathBuf>,
+    accept_changes: bool,
+    offline: bool,
+    mirror_dir: Option<&'a PathBuf>,
+    allow_prerelease: bool,
+    warm_cache: Option<&'a PathBuf>,
+    changed_only: Option<&'a str>,
+    only_jobs: &'a [String],
+    only_steps: &'a [String],
+    log_file: Option<&'a PathBuf>,
+    log_format: LogFormat,
+    format: &'a OutputFormat,
+    output: Option<&'a PathBuf>,
+    summary_only: bool,
+    fail_on: FailOn,
+    fail_fast: bool,
+    verify_shas: bool,
+    overrides: &'a BTreeMap<String, String>,
+    policy: Option<&'a Policy>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+enum FailOn {
+    Never,
+    Errors,
+    Unpinned,
+}
+
+impl FailOn {
+    /// Whether a run with these counts should exit non-zero
+    fn fails(self, errors: usize, unpinned: usize) -> bool {
+        match self {
+            FailOn::Never => false,
+            FailOn::Errors => errors > 0,
+            FailOn::Unpinned => errors > 0 || unpinned > 0,
+        }
+    }
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Pin every action to a commit SHA (the default without a subcommand)
+    #[command(
+        after_help = "Exit codes:\n  0  success\n  1  errors occurred (or unpinned actions remain with --fail-on unpinned,\n     or a tag moved with --fail-on-tag-moved)"
+    )]
+    Pin(PinArgs),
+
+    /// Report unpinned actions without network access or modifying files
+    #[command(
+        after_help = "Exit codes:\n  0  every action is pinned (or complies with the config's [policy])\n  1  unpinned actions, or uses violating the [policy], were found (unless\n     --fail-on never)"
+    )]
+    Check,
+
+    /// Rewrite pins whose version comment now resolves to a different SHA
+    #[command(
+        after_help = "Exit codes:\n  0  success\n  1  a pin could not be checked or rewritten"
+    )]
+    Update,
+
+    /// List every action used, its refs, and whether each use is pinned,
+    /// without network access or modifying files
+    #[command(after_help = "Exit codes:\n  0  success\n  1  the workflows could not be read")]
+    List(ListArgs),
+
+    /// Report outdated pins and pins using abbreviated SHAs, without
+    /// modifying files
+    #[command(
+        after_help = "Exit codes:\n  0  no findings\n  1  outdated or abbreviated pins (or, with --verify-shas, tags or branches\n     named like SHAs) were found, or errors occurred (unless --fail-on never)"
+    )]
+    Audit,
+
+    /// Rewrite SHA pins back to the ref named in their version comment
+    #[command(
+        after_help = "Exit codes:\n  0  success\n  1  a workflow could not be read or written"
+    )]
+    Unpin,
+}
+
+#[derive(clap::Args, Debug)]
+struct PinArgs {
+    /// Read workflows straight from a .tar, .tar.gz/.tgz or .zip archive,
+    /// pinning in memory without touching the archive
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["workflows_dir", "repo", "backup"]
+    )]
+    archive: Option<PathBuf>,
+
+    /// With --archive, write a copy of the archive with the pinned
+    /// workflows here (format follows the extension). Every other entry,
+    /// directories and links included, is copied as it was
+    #[arg(long, value_name = "PATH", requires = "archive")]
+    out: Option<PathBuf>,
+
+    /// List pinned actions in the text summary, grouped by file or by
+    /// repository with a subtotal per group
+    #[arg(long, value_enum, value_name = "BY")]
+    group_by: Option<GroupBy>,
+
+    /// Also pin job container images (`container: node:20`) to their
+    /// manifest digests, using credentials from Docker's config.json
+    #[arg(long)]
+    pin_images: bool,
+
+    /// Append a version comment such as `# v4.1.7` to SHA pins that have
+    /// none, naming the most specific tag at the SHA (or the branch holding
+    /// it); the SHA itself is left alone
+    #[arg(long)]
+    annotate: bool,
+
+    /// After writing, commit the changes to a branch and, with a GitHub
+    /// token, force-push it and open a pull request (or update the one a
+    /// previous run opened from the same branch)
+    #[arg(long, alias = "open-pr", conflicts_with = "archive")]
+    create_pr: bool,
+
+    /// Branch the pull request merges into, and that its branch starts from
+    /// (origin's copy when fetched; defaults to the current branch)
+    #[arg(long, value_name = "BRANCH", requires = "create_pr")]
+    pr_base: Option<String>,
+
+    /// Name of the pull request branch; {date} is replaced with today's date
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        default_value = pr::DEFAULT_BRANCH_TEMPLATE,
+        requires = "create_pr"
+    )]
+    pr_branch: String,
+
+    /// Label to add to the pull request (repeatable)
+    #[arg(long = "pr-label", value_name = "LABEL", requires = "create_pr")]
+    pr_labels: Vec<String>,
+
+    /// Open the pull request as a draft
+    #[arg(long, requires = "create_pr")]
+    pr_draft: bool,
+
+    /// After writing, stage exactly the modified files and commit them on
+    /// the current branch, with MESSAGE as the subject (defaults to "chore:
+    /// pin GitHub Actions to commit SHAs")
+    #[arg(long, value_name = "MESSAGE", conflicts_with_all = ["archive", "create_pr"])]
+    commit: Option<Option<String>>,
+
+    /// With --commit, commit even when unrelated changes are already
+    /// staged, which then become part of the commit
+    #[arg(long, requires = "commit")]
+    force_commit: bool,
+
+    /// Warn about actions whose repository is archived or was renamed or
+    /// transferred, looked up through the GitHub API
+    #[arg(long)]
+    check_health: bool,
+
+    /// Check whether each tag pinned carries a GPG or SSH signature that
+    /// GitHub reports as verified, recording it per pinned action
+    #[arg(long)]
+    verify_signatures: bool,
+
+    /// Treat unsigned or unverified tags as errors and leave them unpinned
+    /// (implies --verify-signatures)
+    #[arg(long)]
+    require_signed: bool,
+
+    /// Pin only refs that resolve to a signed, verified tag; branches and
+    /// refs with no tag of that name are errors too (implies
+    /// --require-signed)
+    #[arg(long)]
+    require_signed_tags: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct ListArgs {
+    /// Only show uses that are not pinned to a SHA
+    #[arg(long)]
+    unpinned_only: bool,
 }
 
-#[derive(Debug, Clone, clap::ValueEnum)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
 enum OutputFormat {
     Text,
     Json,
+    /// One JSON object per action as it is processed, then a summary object
+    Jsonl,
+    /// Comma-separated values (`list`, --inventory and --summary-only)
+    Csv,
+    /// A Markdown table (`list`, --inventory and --summary-only)
+    Markdown,
+    /// reviewdog diagnostics (`check`, `audit` and `pin --dry-run`)
+    Rdjson,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum GroupBy {
+    File,
+    Repository,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
+    // Where the --format document goes, following --output
+    let report = Arc::new(Report::new(args.output.as_deref()));
 
     // Setup logging
     let log_level = if args.verbose {
@@ -63,94 +746,1927 @@ async fn main() -> Result<()> {
         tracing::Level::INFO
     };
 
+    // Keep stdout clean for machine-readable formats
+    let log_writer = if args.format == OutputFormat::Text {
+        BoxMakeWriter::new(std::io::stdout)
+    } else {
+        BoxMakeWriter::new(std::io::stderr)
+    };
+
+    // The log file always records our debug events, whatever the console shows
+    let file_layer = match &args.log_file {
+        Some(path) => {
+            let file = std::fs::File::create(path)
+                .with_context(|| format!("Failed to create log file {}", path.display()))?;
+            let layer = fmt::layer()
+                .with_ansi(false)
+                .with_writer(Redacting(std::sync::Mutex::new(file)));
+            let layer = match args.log_format {
+                LogFormat::Text => layer.boxed(),
+                LogFormat::Json => layer.json().boxed(),
+            };
+            let targets = Targets::new()
+                .with_target("pin_actions", tracing::Level::DEBUG)
+                .with_default(tracing::Level::INFO);
+            Some(layer.with_filter(targets))
+        },
+        None => None,
+    };
+
     tracing_subscriber::registry()
         .with(
-            tracing_subscriber::fmt::layer()
+            fmt::layer()
                 .with_target(false)
                 .without_time()
-                .with_level(true),
+                .with_level(true)
+                .with_writer(Redacting(log_writer))
+                .with_filter(EnvFilter::from_default_env().add_directive(log_level.into())),
         )
-        .with(tracing_subscriber::EnvFilter::from_default_env().add_directive(log_level.into()))
+        .with(file_layer)
         .init();
 
-    // Validate workflows directory exists
-    if !args.workflows_dir.exists() {
+    // Bare `pin-actions` is `pin-actions pin`
+    let pin_args = match &args.command {
+        Some(Command::Pin(pin)) => pin,
+        _ => &args.pin,
+    };
+
+    let lists = matches!(args.command, Some(Command::List(_)))
+        || (args.command.is_none() && args.inventory);
+    // Other results have per-item lists of many shapes; only their counters
+    // make a table
+    if matches!(args.format, OutputFormat::Csv | OutputFormat::Markdown)
+        && !lists
+        && !args.summary_only
+    {
+        anyhow::bail!(
+            "--format csv and markdown are only supported by `list`, --inventory and --summary-only"
+        );
+    }
+    if args.format == OutputFormat::Jsonl && !streams_events(&args) {
+        anyhow::bail!(
+            "--format jsonl is only supported by `pin`, which streams an object per action; \
+             use --format json for other commands"
+        );
+    }
+    if args.format == OutputFormat::Rdjson && !reports_diagnostics(&args) {
+        anyhow::bail!(
+            "--format rdjson is only supported by `check` (or --no-resolve), `audit` and \
+             `pin --dry-run`, since it suggests fixes rather than making them"
+        );
+    }
+    if args.strict_sha && !matches!(args.command, None | Some(Command::Check)) {
+        anyhow::bail!("--strict-sha only applies to `check` or without a subcommand");
+    }
+    if args.format == OutputFormat::Rdjson && args.summary_only {
+        anyhow::bail!("--format rdjson lists each finding, so can't be used with --summary-only");
+    }
+    if pin_args.commit.is_some() && args.dry_run {
+        anyhow::bail!("--commit needs changes written, so can't be used with --dry-run");
+    }
+    if pin_args.create_pr && args.dry_run {
+        anyhow::bail!("--create-pr needs changes written, so can't be used with --dry-run");
+    }
+    if args.changed_only.is_some() && pin_args.archive.is_some() {
+        anyhow::bail!("--changed-only needs a git working tree, so can't be used with --archive");
+    }
+    if args.offline && args.lockfile.is_none() && args.mirror_dir.is_none() {
+        anyhow::bail!("--offline needs --lockfile or --mirror-dir to resolve from");
+    }
+    if args.resolution_map.is_some() && args.resolver != ResolverKind::Fixed {
+        anyhow::bail!("--resolution-map only applies to --resolver fixed");
+    }
+    if args.timings && args.resolver == ResolverKind::Graphql {
         anyhow::bail!(
-            "Workflows directory not found: {}",
-            args.workflows_dir.display()
+            "--timings measures lookups one at a time, but --resolver graphql batches them; \
+             tune --jobs with another resolver"
         );
     }
+    if pin_args.check_health && args.offline {
+        anyhow::bail!("--check-health needs the GitHub API, so can't be used with --offline");
+    }
+    if args.fail_on_archived && args.offline {
+        anyhow::bail!("--fail-on-archived needs the GitHub API, so can't be used with --offline");
+    }
+    let require_signed = pin_args.require_signed || pin_args.require_signed_tags;
+    let verify_signatures = pin_args.verify_signatures || require_signed;
+    if verify_signatures && args.offline {
+        anyhow::bail!("--verify-signatures needs the GitHub API, so can't be used with --offline");
+    }
+    if report.path().is_some() && args.format == OutputFormat::Text {
+        anyhow::bail!("--output writes the --format document, so needs a --format other than text");
+    }
+    if args.emit_patches && args.format != OutputFormat::Json {
+        anyhow::bail!("--emit-patches requires --format json");
+    }
+
+    // In repository mode the workflows directory is discovered under the
+    // root, and archives carry their own
+    let workflows_dirs = match (&args.repo, &pin_args.archive) {
+        (Some(repo), _) => {
+            if !repo.is_dir() {
+                anyhow::bail!("Repository directory not found: {}", repo.display());
+            }
+            Vec::new()
+        },
+        (None, Some(_)) => Vec::new(),
+        (None, None) if args.workflows_dir.is_empty() => vec![default_workflows_dir()],
+        (None, None) => args.workflows_dir.clone(),
+    };
+    let archive = pin_args.archive.as_ref().map(Archive::read).transpose()?;
+
+    // Validate workflows directories exist
+    for workflows_dir in &workflows_dirs {
+        // Starter workflows can stand in for a missing workflows directory
+        if !workflows_dir.exists() && workflow::templates_dir_for(workflows_dir).is_some() {
+            continue;
+        }
+
+        if !workflows_dir.exists() {
+            anyhow::bail!("Workflows directory not found: {}", workflows_dir.display());
+        }
+
+        if !workflows_dir.is_dir() {
+            anyhow::bail!("Not a directory: {}", workflows_dir.display());
+        }
+    }
+
+    let config = Config::discover(args.config.as_deref())?;
+
+    let known_refs = args.known_refs.clone().or_else(|| {
+        let default = args
+            .repo
+            .as_deref()
+            .unwrap_or(Path::new("."))
+            .join(DEFAULT_KNOWN_REFS);
+        default.exists().then_some(default)
+    });
+
+    let jobs = workflow::effective_jobs(args.jobs);
+    let io_workers = workflow::effective_io_workers(args.io_workers);
+    debug!(
+        "Using {} concurrent job(s) and {} I/O worker(s)",
+        jobs, io_workers
+    );
+
+    let mut ignore_files = config.ignore_files.clone();
+    ignore_files.extend(args.ignore_files.iter().cloned());
+
+    let blocked_actions = BlockedActions::new(config.blocked_actions.clone())?;
+
+    let mut input_keys = config.pin_inputs.clone();
+    input_keys.extend(args.pin_inputs.iter().flatten().cloned());
+
+    // Only API-backed resolvers need credentials, and GHCR for images and
+    // the pull request and repository APIs
+    let needs_api = pin_args.pin_images
+        || pin_args.create_pr
+        || pin_args.check_health
+        || args.fail_on_archived
+        || verify_signatures;
+    let auth = match args.resolver {
+        ResolverKind::Git | ResolverKind::Fixed if !needs_api => Auth::anonymous(),
+        _ => Auth::discover(args.token.clone(), args.no_auth),
+    };
+
+    let http_options = HttpOptions {
+        user_agent: args.user_agent.clone(),
+        headers: args.headers.clone(),
+    };
+
+    if let Some(format) = args.print_config {
+        let effective = EffectiveConfig {
+            config_file: config_file(args.config.as_deref()),
+            workflows_dirs: &workflows_dirs,
+            repo: args.repo.as_ref(),
+            archive: pin_args.archive.as_ref(),
+            out: pin_args.out.as_ref(),
+            dry_run: args.dry_run,
+            backup: args.backup,
+            backup_keep: args.backup_keep,
+            jobs,
+            timings: args.timings,
+            io_workers,
+            resolver: args.resolver,
+            resolution_map: args.resolution_map.as_ref(),
+            auth_source: auth.source.to_string(),
+            protocol: args.protocol,
+            git_host: &args.git_host,
+            ssh_key: args.ssh_key.as_ref(),
+            user_agent: http_options.user_agent(),
+            headers: args
+                .headers
+                .iter()
+                .filter_map(|header| header.split_once(':').map(|(name, _)| name))
+                .collect(),
+            git_tmp_dir: args.git_tmp_dir.as_ref(),
+            skip_unversioned: args.skip_unversioned,
+            default_ref: args.default_ref.as_deref(),
+            pin_inputs: &input_keys,
+            ignore_files: &ignore_files,
+            blocked_actions: &config.blocked_actions,
+            respect_gitignore: args.respect_gitignore,
+            comment_date: args.comment_date,
+            record_constraint: args.record_constraint,
+            no_comment: args.no_comment,
+            provenance_comment: args.provenance_comment,
+            replace_comment: args.replace_comment,
+            emit_patches: args.emit_patches,
+            assert_idempotent: args.assert_idempotent,
+            lossy_utf8: args.lossy_utf8,
+            lock: args.lock,
+            lock_mode: args.lock_mode,
+            pin_images: pin_args.pin_images,
+            annotate: pin_args.annotate,
+            create_pr: pin_args.create_pr,
+            commit: pin_args
+                .commit
+                .as_ref()
+                .map(|message| message.as_deref().unwrap_or(commit::DEFAULT_MESSAGE)),
+            force_commit: pin_args.force_commit,
+            check_health: pin_args.check_health,
+            fail_on_archived: args.fail_on_archived,
+            verify_signatures,
+            require_signed,
+            require_signed_tags: pin_args.require_signed_tags,
+            lockfile: args.lockfile.as_ref(),
+            known_refs: known_refs.as_ref(),
+            accept_changes: args.accept_changes,
+            offline: args.offline,
+            mirror_dir: args.mirror_dir.as_ref(),
+            allow_prerelease: args.allow_prerelease,
+            warm_cache: args.warm_cache.as_ref(),
+            changed_only: args.changed_only.as_deref(),
+            only_jobs: &args.only_jobs,
+            only_steps: &args.only_steps,
+            log_file: args.log_file.as_ref(),
+            log_format: args.log_format,
+            format: &args.format,
+            output: args.output.as_ref(),
+            summary_only: args.summary_only,
+            fail_on: args.fail_on,
+            fail_fast: args.fail_fast,
+            verify_shas: args.verify_shas,
+            overrides: &config.overrides,
+            policy: config.policy.as_ref(),
+        };
+
+        match format {
+            ConfigFormat::Toml => print!("{}", toml::to_string(&effective)?),
+            ConfigFormat::Json => println!("{}", serde_json::to_string_pretty(&effective)?),
+        }
 
-    if !args.workflows_dir.is_dir() {
-        anyhow::bail!("Not a directory: {}", args.workflows_dir.display());
+        return Ok(());
     }
 
+    let git_remote = GitRemote {
+        protocol: args.protocol,
+        host: args.git_host.clone(),
+        ssh_key: args.ssh_key.clone(),
+        http: http_options.clone(),
+    };
+    let http_client = http_options.client()?;
+
+    // Tokens only ever go to the API of the host the actions live on
+    let api_url = pr::api_url(&args.git_host);
+
+    // Mirrored repositories are read locally whichever backend is picked
+    let git = GitResolver::new()
+        .with_remote(git_remote.clone())
+        .with_scratch_parent(args.git_tmp_dir.clone())
+        .with_mirror_dir(args.mirror_dir.clone())
+        .with_offline(args.offline)
+        .with_allow_prerelease(args.allow_prerelease);
+    let resolver: Arc<dyn Resolver> = match args.resolver {
+        ResolverKind::Git => Arc::new(git),
+        ResolverKind::Graphql => Arc::new(
+            GraphQlResolver::new(graphql::graphql_url(&args.git_host), auth.token.clone())
+                .with_client(http_client.clone())
+                .with_fallback(git),
+        ),
+        ResolverKind::Api => Arc::new(
+            ApiResolver::new(api_url.clone(), auth.token.clone())
+                .with_client(http_client.clone())
+                .with_fallback(git),
+        ),
+        ResolverKind::Fixed => match &args.resolution_map {
+            Some(path) => Arc::new(FixedResolver::load(path)?),
+            None => anyhow::bail!("--resolver fixed requires --resolution-map"),
+        },
+    };
+
+    // Pin comments made in this run share one date
+    let today = chrono::Local::now().format("%Y-%m-%d").to_string();
+
     // Create processor
     let processor = WorkflowProcessor::new(
-        args.workflows_dir.clone(),
+        workflows_dirs.clone(),
         args.dry_run,
         args.backup,
         args.skip_pinned,
-        args.jobs,
-    );
+        jobs,
+    )
+    .with_io_workers(io_workers)
+    .with_backup_keep(args.backup_keep as usize)
+    .with_ignore_files(IgnorePatterns::new(ignore_files)?)
+    .with_blocked_actions(blocked_actions)
+    .with_repo_root(args.repo.clone())
+    .with_archive(archive, pin_args.out.clone())
+    .with_respect_gitignore(args.respect_gitignore)
+    .with_resolver(resolver)
+    .with_auth(auth.clone())
+    .with_git_remote(git_remote)
+    .with_http_client(http_client)
+    .with_git_tmp_dir(args.git_tmp_dir.clone())
+    .with_skip_unversioned(args.skip_unversioned)
+    .with_default_ref(args.default_ref.clone())
+    .with_input_keys(input_keys)
+    .with_overrides(config.overrides.clone())
+    .with_policy(config.policy.clone())
+    .with_event_sink(
+        (args.format == OutputFormat::Jsonl && !args.summary_only)
+            .then(|| jsonl_sink(report.clone())),
+    )
+    .with_lockfile(args.lockfile.clone())
+    .with_known_refs(known_refs)
+    .with_accept_changes(args.accept_changes)
+    .with_offline(args.offline)
+    .with_mirror_dir(args.mirror_dir.clone())
+    .with_warm_cache(args.warm_cache.clone())
+    .with_changed_only(args.changed_only.clone())
+    .with_jobs(args.only_jobs.clone())
+    .with_steps(args.only_steps.clone())
+    .with_allow_prerelease(args.allow_prerelease)
+    .with_emit_patches(args.emit_patches)
+    .with_assert_idempotent(args.assert_idempotent)
+    .with_lossy_utf8(args.lossy_utf8)
+    .with_pin_images(pin_args.pin_images)
+    .with_annotate(pin_args.annotate)
+    .with_verify_signatures(verify_signatures.then(|| api_url.clone()))
+    .with_require_signed(require_signed)
+    .with_require_signed_tags(pin_args.require_signed_tags)
+    .with_check_archived(args.fail_on_archived.then(|| api_url.clone()))
+    .with_check_health(pin_args.check_health.then(|| api_url.clone()))
+    .with_comment_date(args.comment_date.then(|| today.clone()))
+    .with_record_constraint(args.record_constraint)
+    .with_no_comment(args.no_comment)
+    .with_provenance(
+        args.provenance_comment
+            .then(|| workflow::provenance_comment(args.resolver, &today)),
+    )
+    .with_replace_comment(args.replace_comment)
+    .with_summary_only(args.summary_only)
+    .with_fail_fast(args.fail_fast)
+    .with_timings(args.timings)
+    .with_verify_shas(args.verify_shas)
+    .with_strict_sha(args.strict_sha);
+
+    // Held until the run ends; the OS releases them if we exit early
+    let _locks = if args.lock {
+        let mut targets = match (&args.repo, &pin_args.archive) {
+            (Some(repo), _) => vec![repo.clone()],
+            (None, Some(archive)) => vec![archive.clone()],
+            (None, None) => workflows_dirs
+                .iter()
+                .filter(|dir| dir.exists())
+                .cloned()
+                .collect(),
+        };
+        // A consistent order keeps two runs over the same directories from
+        // each holding one lock while waiting for the other
+        targets.sort();
+        targets.dedup();
+        targets
+            .iter()
+            .map(|target| RunLock::acquire(target, args.lock_mode))
+            .collect::<Result<Vec<_>>>()?
+    } else {
+        Vec::new()
+    };
 
     // Process workflows
+    let scanned_dirs = match (&args.repo, &pin_args.archive) {
+        (Some(repo), _) => format!("repository {}", repo.display()),
+        (None, Some(archive)) => format!("archive {}", archive.display()),
+        (None, None) => workflows_dirs
+            .iter()
+            .map(|dir| dir.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", "),
+    };
     info!(
         "{}",
-        format!("🔍 Scanning workflows in {}", args.workflows_dir.display()).cyan()
+        format!("🔍 Scanning workflows in {}", scanned_dirs).cyan()
     );
 
+    match &args.command {
+        Some(Command::Pin(pin)) => run_pin(&args, &report, pin, &processor, &auth).await,
+        // A check fails on unpinned actions unless told never to fail
+        Some(Command::Check) => {
+            let fail_on = match args.fail_on {
+                FailOn::Never => FailOn::Never,
+                _ => FailOn::Unpinned,
+            };
+            run_check(&args, &report, &processor, fail_on).await
+        },
+        Some(Command::Update) => run_drift(&args, &report, &processor, true, true).await,
+        Some(Command::List(list)) => run_list(&args, &report, list, &processor).await,
+        Some(Command::Audit) => run_audit(&args, &report, &processor).await,
+        Some(Command::Unpin) => run_unpin(&args, &report, &processor).await,
+        None if args.strict_sha => run_strict_sha(&args, &report, &processor).await,
+        None if args.sync_comments => run_sync_comments(&args, &report, &processor).await,
+        None if args.only_unresolvable => run_unresolvable(&args, &report, &processor).await,
+        None if args.inventory => run_inventory(&args, &report, &processor).await,
+        None if args.no_resolve => run_check(&args, &report, &processor, args.fail_on).await,
+        None if args.report_drift || args.outdated => {
+            run_drift(&args, &report, &processor, args.outdated, args.update).await
+        },
+        None => run_pin(&args, &report, &args.pin, &processor, &auth).await,
+    }
+}
+
+async fn run_pin(
+    args: &Args,
+    report: &Report,
+    pin: &PinArgs,
+    processor: &WorkflowProcessor,
+    auth: &Auth,
+) -> Result<()> {
     let results = processor.process().await?;
 
+    if pin.create_pr {
+        let options = pr::PrOptions {
+            base: pin.pr_base.clone(),
+            branch_template: pin.pr_branch.clone(),
+            labels: pin.pr_labels.clone(),
+            draft: pin.pr_draft,
+            ssh_key: args.ssh_key.clone(),
+        };
+        let date = chrono::Local::now().format("%Y-%m-%d").to_string();
+        let url = pr::open_pr(
+            &results,
+            auth.token.as_deref(),
+            &pr::api_url(&args.git_host),
+            &options,
+            &date,
+        )
+        .await?;
+        if let Some(url) = url {
+            info!("🔀 Opened pull request: {}", url);
+        }
+    }
+
+    if let Some(subject) = &pin.commit {
+        let subject = subject.as_deref().unwrap_or(commit::DEFAULT_MESSAGE);
+        if results.errors > 0 {
+            warn!("Not committing because the run had errors");
+        } else {
+            let message = commit::commit_message(&results, subject);
+            match commit::commit_changes(&results, &message, pin.force_commit)? {
+                Some(oid) => info!(
+                    "📝 Committed {} file(s) as {}",
+                    results.modified_files.len(),
+                    &oid.to_string()[..8]
+                ),
+                None => info!("No changes to commit"),
+            }
+        }
+    }
+
+    // Pins made in an archive without --out are only ever a preview
+    let preview = args.dry_run || (pin.archive.is_some() && pin.out.is_none());
+
     // Display results
+    if shows_text(args) && args.summary_only {
+        display_text_results(&results, preview, None, true);
+        display_backpressure(results.backpressure.as_ref());
+        if args.verbose {
+            display_metrics(&results.metrics);
+        }
+    } else if shows_text(args) {
+        // The summary lists each planned edit; verbose runs also show the
+        // lines themselves
+        if args.verbose && !args.quiet {
+            display_planned_changes(&results.planned_changes);
+        }
+        if args.print_links {
+            display_pin_links(&results.pinned_actions);
+        }
+        display_text_results(&results, preview, pin.group_by, false);
+        display_policy_violations(&results.policy_violations);
+        display_remaining_unpinned(&results.remaining_unpinned);
+        display_backpressure(results.backpressure.as_ref());
+        if args.verbose {
+            display_metrics(&results.metrics);
+        }
+    }
     match args.format {
-        OutputFormat::Text => display_text_results(&results, args.dry_run),
-        OutputFormat::Json => display_json_results(&results)?,
+        OutputFormat::Text => {},
+        OutputFormat::Jsonl => display_jsonl_summary(args, report, &results)?,
+        OutputFormat::Rdjson => display_json_results(args, report, &rdjson::from_pin(&results))?,
+        _ => display_json_results(args, report, &results)?,
     }
 
-    if results.errors > 0 {
-        warn!("⚠️  Completed with {} errors", results.errors);
+    report_error_codes(&results.errors_detail);
+    report_moved_refs(&results.moved_refs);
+    report_unhealthy_actions(&results.unhealthy_actions);
+    report_archived_actions(&results.archived_actions);
+    // What is still on a ref once the run's pins are in, not what it found
+    if args
+        .fail_on
+        .fails(results.errors, results.actions_unresolved())
+        || blocked_fails(args.fail_on, &results.policy_violations)
+        || tag_moved_fails(args, &results.moved_refs)
+        || archived_fails(args, &results.archived_actions)
+    {
         std::process::exit(1);
     }
 
     Ok(())
 }
 
-fn display_text_results(results: &workflow::ProcessResults, dry_run: bool) {
-    println!();
-    println!("{}", "📊 Summary".bold().cyan());
-    println!("{}", "─".repeat(50).cyan());
-    println!("  Files processed:  {}", results.files_processed);
-    println!("  Actions found:    {}", results.actions_found);
-    println!(
-        "  Actions pinned:   {}",
-        results.actions_pinned.to_string().green()
-    );
-    println!("  Already pinned:   {}", results.already_pinned);
-    println!(
-        "  Errors:           {}",
-        if results.errors > 0 {
-            results.errors.to_string().red()
-        } else {
-            results.errors.to_string().green()
-        }
-    );
-    println!("{}", "─".repeat(50).cyan());
+async fn run_check(
+    args: &Args,
+    report: &Report,
+    processor: &WorkflowProcessor,
+    fail_on: FailOn,
+) -> Result<()> {
+    let results = processor.report_unpinned().await?;
+    display_findings(
+        args,
+        report,
+        &results,
+        |results| {
+            display_unpinned_results(results);
+            if let Some(checks) = &results.policy_checks {
+                display_policy_checks(checks);
+            }
+            display_policy_violations(&results.policy_violations);
+        },
+        rdjson::from_unpinned,
+    )?;
+    display_processor_metrics(args, processor);
 
-    if dry_run {
-        println!("\n{}", "ℹ️  Dry run mode - no files were modified".yellow());
-    } else if results.actions_pinned > 0 {
-        println!(
-            "\n{}",
-            "✅ All unpinned actions have been pinned to commit SHAs".green()
-        );
+    report_archived_actions(&results.archived_actions);
+    // With a [policy], only uses violating it count against the run
+    if fail_on.fails(0, results.failing_uses())
+        || blocked_fails(fail_on, &results.policy_violations)
+        || archived_fails(args, &results.archived_actions)
+    {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+async fn run_drift(
+    args: &Args,
+    report: &Report,
+    processor: &WorkflowProcessor,
+    outdated: bool,
+    update: bool,
+) -> Result<()> {
+    let results = if outdated {
+        processor.report_outdated(update).await?
     } else {
-        println!("\n{}", "✨ No actions needed pinning".green());
+        processor.report_drift().await?
+    };
+    display_results(args, report, &results, display_drift_results)?;
+    display_processor_metrics(args, processor);
+
+    report_error_codes(&results.errors_detail);
+    report_moved_refs(&results.moved_refs);
+    if args.fail_on.fails(results.errors, 0) || tag_moved_fails(args, &results.moved_refs) {
+        std::process::exit(1);
     }
+
+    Ok(())
 }
 
-fn display_json_results(results: &workflow::ProcessResults) -> Result<()> {
-    let json = serde_json::to_string_pretty(&results)?;
-    println!("{}", json);
+async fn run_sync_comments(
+    args: &Args,
+    report: &Report,
+    processor: &WorkflowProcessor,
+) -> Result<()> {
+    let results = processor.sync_comments().await?;
+    display_results(args, report, &results, display_drift_results)?;
+    display_processor_metrics(args, processor);
+
+    report_error_codes(&results.errors_detail);
+    report_moved_refs(&results.moved_refs);
+    if args.fail_on.fails(results.errors, 0) || tag_moved_fails(args, &results.moved_refs) {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+async fn run_list(
+    args: &Args,
+    report: &Report,
+    list: &ListArgs,
+    processor: &WorkflowProcessor,
+) -> Result<()> {
+    let mut inventory = processor.list_actions().await?;
+    if list.unpinned_only {
+        for entry in &mut inventory {
+            entry.usages.retain(|usage| !usage.pinned);
+        }
+        inventory.retain(|entry| !entry.usages.is_empty());
+    }
+
+    if shows_text(args) {
+        display_list_text(&inventory);
+    }
+    display_inventory(args, report, &inventory, &inventory, false)?;
+    display_processor_metrics(args, processor);
+    Ok(())
+}
+
+async fn run_inventory(args: &Args, report: &Report, processor: &WorkflowProcessor) -> Result<()> {
+    let results = processor.inventory(!args.no_resolve).await?;
+    if shows_text(args) {
+        display_list_text(&results.actions);
+    }
+    display_inventory(args, report, &results, &results.actions, true)?;
+    display_processor_metrics(args, processor);
+
+    report_error_codes(&results.errors_detail);
+    if args.fail_on.fails(results.errors, 0) {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+async fn run_strict_sha(args: &Args, report: &Report, processor: &WorkflowProcessor) -> Result<()> {
+    let results = processor.check_strict_sha(args.expand_short_shas).await?;
+    display_results(args, report, &results, |results| {
+        display_strict_sha_results(results, args.dry_run)
+    })?;
+    display_processor_metrics(args, processor);
+
+    // Expanded pins are fixed (unless only previewed), so they no
+    // longer count against the run
+    let remaining = if args.dry_run {
+        results.violations.len()
+    } else {
+        results
+            .violations
+            .len()
+            .saturating_sub(results.expanded.len())
+    } + results.sha_collisions.len();
+    report_error_codes(&results.errors_detail);
+    if args.fail_on != FailOn::Never && (results.errors > 0 || remaining > 0) {
+        std::process::exit(1);
+    }
+
     Ok(())
 }
+
+async fn run_unresolvable(
+    args: &Args,
+    report: &Report,
+    processor: &WorkflowProcessor,
+) -> Result<()> {
+    let results = processor
+        .report_unresolvable(args.candidate_refs.unwrap_or(0))
+        .await?;
+    display_results(args, report, &results, display_unresolvable_results)?;
+    display_processor_metrics(args, processor);
+
+    report_error_codes(&results.errors_detail);
+    if args.fail_on.fails(results.errors, 0) {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Everything `audit` found
+#[derive(Debug, serde::Serialize)]
+struct AuditResults {
+    outdated: drift::DriftResults,
+    short_shas: strict_sha::StrictShaResults,
+}
+
+async fn run_audit(args: &Args, report: &Report, processor: &WorkflowProcessor) -> Result<()> {
+    let (outdated, short_shas) = processor.audit().await?;
+    let results = AuditResults {
+        outdated,
+        short_shas,
+    };
+    display_findings(
+        args,
+        report,
+        &results,
+        |results| {
+            display_drift_results(&results.outdated);
+            display_strict_sha_results(&results.short_shas, args.dry_run);
+        },
+        |results| rdjson::from_audit(&results.outdated, &results.short_shas),
+    )?;
+    display_processor_metrics(args, processor);
+
+    let mut errors = results.outdated.errors_detail.clone();
+    errors.extend(results.short_shas.errors_detail.iter().cloned());
+    report_error_codes(&errors);
+
+    let findings = results.outdated.drifted_actions.len()
+        + results.short_shas.violations.len()
+        + results.short_shas.sha_collisions.len();
+    if args.fail_on != FailOn::Never && (!errors.is_empty() || findings > 0) {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+async fn run_unpin(args: &Args, report: &Report, processor: &WorkflowProcessor) -> Result<()> {
+    let results = processor.unpin().await?;
+    display_results(args, report, &results, display_unpin_results)?;
+    display_processor_metrics(args, processor);
+
+    report_error_codes(&results.errors_detail);
+    if args.fail_on.fails(results.errors, 0) {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// `.github/workflows` when it exists here; run from deeper in a
+/// repository, the nearest one above. Missing everywhere, it stays the
+/// plain default so the usual error (or workflow templates) applies
+fn default_workflows_dir() -> PathBuf {
+    let default = PathBuf::from(workflow::DEFAULT_WORKFLOWS_DIR);
+    if default.exists() {
+        return default;
+    }
+
+    match std::env::current_dir()
+        .ok()
+        .and_then(|cwd| workflow::find_workflows_dir(&cwd))
+    {
+        Some(found) => {
+            debug!("Using workflows directory {}", found.display());
+            found
+        },
+        None => default,
+    }
+}
+
+/// Parse `--jobs`: a count, or `auto` (the same as 0)
+fn parse_jobs(value: &str) -> Result<usize, String> {
+    if value.eq_ignore_ascii_case("auto") {
+        return Ok(0);
+    }
+
+    value
+        .parse()
+        .map_err(|_| format!("expected a number of jobs or `auto`, got `{}`", value))
+}
+
+/// Parse `--io-workers`: a count of at least 1, or `auto` (stored as 0)
+fn parse_io_workers(value: &str) -> Result<usize, String> {
+    if value.eq_ignore_ascii_case("auto") {
+        return Ok(0);
+    }
+
+    match value.parse() {
+        Ok(0) => Err("expected at least 1 worker".to_string()),
+        Ok(workers) => Ok(workers),
+        Err(_) => Err(format!(
+            "expected a number of workers or `auto`, got `{}`",
+            value
+        )),
+    }
+}
+
+/// The config file a run loads, if any
+fn config_file(explicit: Option<&std::path::Path>) -> Option<PathBuf> {
+    match explicit {
+        Some(path) => Some(path.to_path_buf()),
+        None => Some(PathBuf::from(DEFAULT_CONFIG_FILE)).filter(|path| path.is_file()),
+    }
+}
+
+/// Print the error count with each error code's tally as the last line on
+/// stderr, e.g. "Completed with 3 errors: E_AUTH=1 E_NETWORK=2"
+fn report_error_codes(errors: &[ErrorDetail]) {
+    if errors.is_empty() {
+        return;
+    }
+
+    let mut counts: BTreeMap<ErrorCode, usize> = BTreeMap::new();
+    for error in errors {
+        *counts.entry(error.code).or_insert(0) += 1;
+    }
+    let tally: Vec<String> = counts
+        .iter()
+        .map(|(code, count)| format!("{}={}", code, count))
+        .collect();
+
+    eprintln!(
+        "⚠️  Completed with {} errors: {}",
+        errors.len(),
+        tally.join(" ")
+    );
+}
+
+/// Print every ref that moved since first seen to stderr, where it can't be
+/// missed among the results
+fn report_moved_refs(moved: &[MovedRef]) {
+    for moved in moved {
+        let action = if moved.accepted {
+            "accepted"
+        } else {
+            "not pinned; rerun with --accept-changes to trust it"
+        };
+        eprintln!(
+            "{} {}\n    first seen {}: {}\n    now resolves to: {}\n    ({})",
+            "🚨 Tag moved:".red().bold(),
+            moved.action.yellow(),
+            moved.first_seen,
+            moved.known_sha,
+            moved.resolved_sha.red(),
+            action
+        );
+    }
+}
+
+/// Print every relocated action repository to stderr. Archived ones are
+/// left to [`report_archived_actions`], which shows where they are used
+fn report_unhealthy_actions(unhealthy: &[RepoHealth]) {
+    for health in unhealthy {
+        if let Some(moved_to) = &health.moved_to {
+            eprintln!(
+                "{} {} is moved to {}",
+                "⚠️  Unmaintained action:".yellow().bold(),
+                health.repository.yellow(),
+                moved_to.green()
+            );
+        }
+    }
+}
+
+/// Print every action whose repository is archived to stderr, with each
+/// place it is used
+fn report_archived_actions(archived: &[ArchivedAction]) {
+    for action in archived {
+        eprintln!(
+            "{} {} is archived and gets no more fixes",
+            "🗄️  Archived action:".red().bold(),
+            action.action.red()
+        );
+        for location in &action.locations {
+            eprintln!("     used at {}", location);
+        }
+    }
+}
+
+/// Whether uses of blocked actions should fail the run: always, short of
+/// `--fail-on never`, whatever their pinning status
+fn blocked_fails(fail_on: FailOn, violations: &[PolicyViolation]) -> bool {
+    fail_on != FailOn::Never && !violations.is_empty()
+}
+
+/// Whether archived action repositories should fail the run
+fn archived_fails(args: &Args, archived: &[ArchivedAction]) -> bool {
+    args.fail_on_archived && !archived.is_empty()
+}
+
+/// Whether unaccepted tag movement should fail the run
+fn tag_moved_fails(args: &Args, moved: &[MovedRef]) -> bool {
+    args.fail_on_tag_moved && moved.iter().any(|moved| !moved.accepted)
+}
+
+/// Print pinned actions clustered by file or repository, with subtotals
+fn display_grouped_pins(pinned: &[workflow::PinnedActionResult], group_by: GroupBy) {
+    let mut groups: BTreeMap<&str, Vec<&workflow::PinnedActionResult>> = BTreeMap::new();
+    for result in pinned {
+        let key = match group_by {
+            GroupBy::File => result.file.as_str(),
+            GroupBy::Repository => result.action.as_str(),
+        };
+        groups.entry(key).or_default().push(result);
+    }
+
+    println!();
+    println!("{}", "📌 Pinned Actions".bold().cyan());
+    println!("{}", "─".repeat(50).cyan());
+    for (group, results) in &groups {
+        println!("  {} ({})", group.bold(), results.len());
+        for result in results {
+            let reference = if result.was_unversioned {
+                " (was unversioned)".to_string()
+            } else {
+                format!("@{}", result.old_ref)
+            };
+            let label = match group_by {
+                GroupBy::File => format!("{}{}", result.action, reference),
+                GroupBy::Repository => format!("{} {}", result.file, reference),
+            };
+            let badge = result
+                .signature
+                .map(|status| format!(" {}", status.badge()))
+                .unwrap_or_default();
+            println!(
+                "    {} → {}{}",
+                label.yellow(),
+                result.sha[..8].green(),
+                badge
+            );
+        }
+    }
+}
+
+/// List each distinct pin with a link to its commit
+fn display_pin_links(pinned: &[workflow::PinnedActionResult]) {
+    let links: BTreeMap<String, &str> = pinned
+        .iter()
+        .map(|result| {
+            let from = if result.was_unversioned {
+                result.action.clone()
+            } else {
+                format!("{}@{}", result.action, result.old_ref)
+            };
+            (from, result.link.as_str())
+        })
+        .collect();
+    if links.is_empty() {
+        return;
+    }
+
+    println!();
+    println!("{}", "🔗 Pinned Commits".bold().cyan());
+    println!("{}", "─".repeat(50).cyan());
+    for (from, link) in links {
+        println!("  {} {}", from.yellow(), link);
+    }
+}
+
+/// Show each line a dry run would rewrite as a red `-` and green `+` pair,
+/// grouped under its file
+fn display_planned_changes(changes: &[PlannedChange]) {
+    let mut current_file = None;
+    for change in changes {
+        if current_file != Some(&change.file) {
+            println!();
+            println!("{}", change.file.bold());
+            current_file = Some(&change.file);
+        }
+        println!("{}", format!("-{}", change.before).red());
+        // A provenance comment inserted above makes the after two lines
+        for line in change.after.split('\n') {
+            println!("{}", format!("+{}", line).green());
+        }
+    }
+}
+
+/// The pin summary, followed by the pins planned or made and the files
+/// touched unless `summary_only`
+fn display_text_results(
+    results: &workflow::ProcessResults,
+    dry_run: bool,
+    group_by: Option<GroupBy>,
+    summary_only: bool,
+) {
+    if let Some(group_by) = group_by {
+        if !results.pinned_actions.is_empty() {
+            display_grouped_pins(&results.pinned_actions, group_by);
+        }
+    }
+
+    println!();
+    println!("{}", "📊 Summary".bold().cyan());
+    println!("{}", "─".repeat(50).cyan());
+    println!("  Files processed:  {}", results.files_processed);
+    if results
+        .files_by_kind
+        .keys()
+        .any(|kind| *kind != TargetKind::Workflow)
+    {
+        for (kind, count) in &results.files_by_kind {
+            let note = if *kind == TargetKind::Template {
+                " (starter workflows, not run in this repo)"
+            } else {
+                ""
+            };
+            println!(
+                "    {:<18}{}{}",
+                format!("{}:", kind.label()),
+                count,
+                note.dimmed()
+            );
+        }
+    }
+    if results.files_skipped > 0 {
+        println!("  Files skipped:    {}", results.files_skipped);
+    }
+    display_skipped_files(&results.skipped_files);
+    if results.files_unchanged > 0 {
+        println!("  Files unchanged:  {}", results.files_unchanged);
+    }
+    println!("  Actions found:    {}", results.actions_found);
+    println!(
+        "  Actions pinned:   {}",
+        results.actions_pinned.to_string().green()
+    );
+    println!("  Already pinned:   {}", results.already_pinned);
+    if results.actions_ignored > 0 {
+        println!("  Ignored:          {}", results.actions_ignored);
+    }
+    if results.actions_allowed > 0 {
+        println!("  Allowed (policy): {}", results.actions_allowed);
+    }
+    if results.actions_annotated > 0 {
+        println!(
+            "  Annotated:        {}",
+            results.actions_annotated.to_string().green()
+        );
+    }
+    if !results.unidentifiable.is_empty() {
+        println!(
+            "  Unidentifiable:   {}",
+            results.unidentifiable.len().to_string().yellow()
+        );
+    }
+    if results.images_found > 0 {
+        // Only split by kind once services are in play; a container-only run
+        // reads the same as before
+        let split = results
+            .images_found_by_kind
+            .contains_key(&ImageKind::Service);
+        let by_kind = |counts: &BTreeMap<ImageKind, usize>| {
+            if split {
+                for (kind, count) in counts {
+                    println!("    {:<18}{}", format!("{}:", kind.label()), count);
+                }
+            }
+        };
+        println!("  Images found:     {}", results.images_found);
+        by_kind(&results.images_found_by_kind);
+        println!(
+            "  Images pinned:    {}",
+            results.images_pinned.to_string().green()
+        );
+        by_kind(&results.images_pinned_by_kind);
+        println!("  Images at digest: {}", results.images_already_pinned);
+        by_kind(&results.images_already_pinned_by_kind);
+    }
+    if results.lines_changed > 0 {
+        println!(
+            "  Lines changed:    {} across {} file(s)",
+            results.lines_changed, results.files_changed
+        );
+    }
+    if results.files_untouched > 0 {
+        println!("  Files untouched:  {}", results.files_untouched);
+    }
+    println!(
+        "  Errors:           {}",
+        if results.errors > 0 {
+            results.errors.to_string().red()
+        } else {
+            results.errors.to_string().green()
+        }
+    );
+    println!("{}", "─".repeat(50).cyan());
+
+    if dry_run {
+        println!("\n{}", "ℹ️  Dry run mode - no files were modified".yellow());
+    } else if results.actions_unresolved() > 0 {
+        println!(
+            "\n{}",
+            format!(
+                "⚠️  {} use(s) are still unpinned",
+                results.actions_unresolved()
+            )
+            .yellow()
+        );
+    } else if results.actions_pinned > 0 || results.images_pinned > 0 {
+        println!(
+            "\n{}",
+            "✅ All unpinned actions have been pinned to commit SHAs".green()
+        );
+    } else {
+        println!("\n{}", "✨ No actions needed pinning".green());
+    }
+    if summary_only {
+        return;
+    }
+
+    if dry_run && !results.pinned_actions.is_empty() {
+        println!("\n{}", "Planned edits:".bold());
+        for pin in &results.pinned_actions {
+            let from = if pin.was_unversioned {
+                pin.action.clone()
+            } else {
+                format!("{}@{}", pin.action, pin.old_ref)
+            };
+            println!(
+                "  {}:{} {} -> @{}",
+                pin.file,
+                pin.line,
+                from.yellow(),
+                pin.sha.green()
+            );
+        }
+    }
+
+    display_sha_collisions(&results.sha_collisions);
+
+    if !results.unidentifiable.is_empty() {
+        println!("\n{}", "No tag or branch found for:".bold());
+        for pin in &results.unidentifiable {
+            println!("  {}:{} {}@{}", pin.file, pin.line, pin.action, pin.sha);
+        }
+    }
+
+    if !results.modified_files.is_empty() {
+        let heading = if dry_run {
+            "Would modify:"
+        } else {
+            "Modified:"
+        };
+        println!("\n{}", heading.bold());
+        for file in &results.modified_files {
+            println!("  {}", file);
+        }
+    }
+}
+
+fn display_strict_sha_results(results: &strict_sha::StrictShaResults, dry_run: bool) {
+    println!();
+    println!("{}", "🔎 Full-Length SHA Check".bold().cyan());
+    println!("{}", "─".repeat(50).cyan());
+
+    for violation in &results.violations {
+        println!(
+            "  {}:{} {}@{}",
+            violation.file,
+            violation.line,
+            violation.action,
+            violation.reference.red()
+        );
+    }
+
+    if !results.violations.is_empty() {
+        println!("{}", "─".repeat(50).cyan());
+    }
+
+    println!("  Files processed:  {}", results.files_processed);
+    println!("  Pins checked:     {}", results.pins_checked);
+    println!(
+        "  Short SHAs:       {}",
+        if results.violations.is_empty() {
+            "0".green()
+        } else {
+            results.violations.len().to_string().red()
+        }
+    );
+    if !results.expanded.is_empty() {
+        println!(
+            "  Expanded:         {}",
+            results.expanded.len().to_string().green()
+        );
+    }
+    println!("  Errors:           {}", results.errors);
+    println!("{}", "─".repeat(50).cyan());
+
+    if results.violations.is_empty() {
+        println!("\n{}", "✨ All pins are full-length SHAs".green());
+    } else if dry_run && !results.expanded.is_empty() {
+        println!("\n{}", "ℹ️  Dry run mode - no files were modified".yellow());
+    }
+    display_sha_collisions(&results.sha_collisions);
+}
+
+/// List full-length pins that are really tag or branch names
+fn display_sha_collisions(collisions: &[ShaRefCollision]) {
+    if collisions.is_empty() {
+        return;
+    }
+
+    println!("\n{}", "Tag or branch names that look like SHAs:".bold());
+    for collision in collisions {
+        println!(
+            "  {}:{} {}@{} is a {}, pointing at {}",
+            collision.file,
+            collision.line,
+            collision.action,
+            collision.reference.red(),
+            collision.kind,
+            collision.commit.green()
+        );
+    }
+}
+
+fn display_unpin_results(results: &unpin::UnpinResults) {
+    println!();
+    println!("{}", "🔓 Restored Refs".bold().cyan());
+    println!("{}", "─".repeat(50).cyan());
+    for unpinned in &results.unpinned {
+        println!(
+            "  {}:{} {}@{} → {}",
+            unpinned.file,
+            unpinned.line,
+            unpinned.action,
+            &unpinned.sha[..unpinned.sha.len().min(8)],
+            unpinned.reference.yellow()
+        );
+    }
+    if !results.unpinned.is_empty() {
+        println!("{}", "─".repeat(50).cyan());
+    }
+
+    println!("  Files processed:  {}", results.files_processed);
+    println!("  Pins found:       {}", results.pins_found);
+    println!("  Unpinned:         {}", results.unpinned.len());
+    if results.without_comment > 0 {
+        println!(
+            "  No version comment: {}",
+            results.without_comment.to_string().yellow()
+        );
+    }
+    println!("{}", "─".repeat(50).cyan());
+
+    if results.dry_run {
+        println!("\n{}", "ℹ️  Dry run mode - no files were modified".yellow());
+    }
+}
+
+fn display_unpinned_results(results: &check::UnpinnedResults) {
+    println!();
+    println!("{}", "📋 Unpinned Actions".bold().cyan());
+    println!("{}", "─".repeat(50).cyan());
+
+    for unpinned in &results.unpinned_actions {
+        let reference = match &unpinned.reference {
+            Some(reference) => format!("@{}", reference).yellow(),
+            None => " (default branch)".red(),
+        };
+        println!(
+            "  {}:{} {}{}",
+            unpinned.file, unpinned.line, unpinned.action, reference
+        );
+    }
+
+    if !results.unpinned_actions.is_empty() {
+        println!("{}", "─".repeat(50).cyan());
+    }
+
+    println!("  Files processed:  {}", results.files_processed);
+    if results.files_skipped > 0 {
+        println!("  Files skipped:    {}", results.files_skipped);
+    }
+    display_skipped_files(&results.skipped_files);
+    if results.files_unchanged > 0 {
+        println!("  Files unchanged:  {}", results.files_unchanged);
+    }
+    println!("  Actions found:    {}", results.actions_found);
+    println!("  Already pinned:   {}", results.already_pinned);
+    println!(
+        "  Unpinned:         {}",
+        if results.unpinned_actions.is_empty() {
+            "0".green()
+        } else {
+            results.unpinned_actions.len().to_string().yellow()
+        }
+    );
+    if results.dynamic_refs > 0 {
+        println!("  Dynamic refs:     {}", results.dynamic_refs);
+    }
+    println!("{}", "─".repeat(50).cyan());
+
+    if results.unpinned_actions.is_empty() {
+        println!("\n{}", "✨ All actions are pinned".green());
+    }
+}
+
+/// List the files skipped for a reason the user can fix, under the summary's
+/// count
+fn display_skipped_files(skipped: &[SkippedFile]) {
+    for file in skipped {
+        println!("    {} ({})", file.file.yellow(), file.reason);
+    }
+}
+
+/// List the uses violating the config's `[policy]`, with why
+fn display_policy_checks(checks: &[PolicyCheck]) {
+    let violations: Vec<&PolicyCheck> = checks.iter().filter(|check| !check.compliant).collect();
+
+    println!();
+    println!("{}", "📐 Policy".bold().cyan());
+    println!("{}", "─".repeat(50).cyan());
+    for check in &violations {
+        println!(
+            "  {}:{} {} ({})",
+            check.file,
+            check.line,
+            check.action.red(),
+            check.reason.describe()
+        );
+    }
+    if !violations.is_empty() {
+        println!("{}", "─".repeat(50).cyan());
+    }
+    println!("  Compliant:        {}", checks.len() - violations.len());
+    println!(
+        "  Violations:       {}",
+        if violations.is_empty() {
+            "0".green()
+        } else {
+            violations.len().to_string().red()
+        }
+    );
+}
+
+fn display_policy_violations(violations: &[PolicyViolation]) {
+    if violations.is_empty() {
+        return;
+    }
+
+    println!();
+    println!("{}", "🚫 Policy Violations".bold().red());
+    println!("{}", "─".repeat(50).red());
+    for violation in violations {
+        println!(
+            "  {}:{} {} (blocked by '{}')",
+            violation.file,
+            violation.line,
+            violation.action.red(),
+            violation.pattern
+        );
+    }
+    println!("{}", "─".repeat(50).red());
+    println!(
+        "  {} blocked action use(s); these were not pinned",
+        violations.len()
+    );
+}
+
+/// Show how resolution lookups queued behind --jobs, with --timings
+fn display_backpressure(backpressure: Option<&Backpressure>) {
+    let Some(backpressure) = backpressure.filter(|b| b.lookups > 0) else {
+        return;
+    };
+
+    println!();
+    println!("{}", "⏱️  Resolution Concurrency".bold().cyan());
+    println!("{}", "─".repeat(50).cyan());
+    println!(
+        "  Lookups:          {} ({:.0}ms each on average)",
+        backpressure.lookups, backpressure.lookup_mean_ms
+    );
+    println!(
+        "  Most in flight:   {} of {}",
+        backpressure.max_in_flight, backpressure.concurrency
+    );
+    println!(
+        "  Queue wait:       {:.0}ms mean, {:.0}ms max",
+        backpressure.queue_wait_mean_ms, backpressure.queue_wait_max_ms
+    );
+    println!("{}", "─".repeat(50).cyan());
+    // Queueing only happens once every slot is busy
+    if backpressure.max_in_flight < backpressure.concurrency {
+        println!("  --jobs was never reached, so it wasn't the limit");
+    } else if backpressure.queue_wait_mean_ms > backpressure.lookup_mean_ms {
+        println!("  Lookups mostly waited for a slot; a higher --jobs may help");
+    } else {
+        println!("  Lookups mostly waited on the network; a higher --jobs won't help much");
+    }
+}
+
+/// Show where the run spent its time, with --verbose
+fn display_metrics(metrics: &Metrics) {
+    let phases = &metrics.phases;
+    println!();
+    println!("{}", "⏱️  Timing".bold().cyan());
+    println!("{}", "─".repeat(50).cyan());
+    println!("  Discovery:        {:.0}ms", phases.discovery_ms);
+    println!("  Parse:            {:.0}ms", phases.parse_ms);
+    println!("  Resolve:          {:.0}ms", phases.resolve_ms);
+    println!("  Rewrite:          {:.0}ms", phases.rewrite_ms);
+    println!("  Total:            {:.0}ms", metrics.total_ms);
+    println!("{}", "─".repeat(50).cyan());
+    println!(
+        "  Lookups:          {} over the network, {} cached, {} retried",
+        metrics.network_resolutions, metrics.cache_hits, metrics.retries
+    );
+    if let (Some(p50), Some(p95)) = (metrics.resolution_p50_ms, metrics.resolution_p95_ms) {
+        println!("  Lookup time:      {:.0}ms p50, {:.0}ms p95", p50, p95);
+    }
+}
+
+/// Show where a command other than pinning spent its time, with --verbose;
+/// its results don't carry the metrics, so they're taken from the processor
+fn display_processor_metrics(args: &Args, processor: &WorkflowProcessor) {
+    if args.verbose && shows_text(args) {
+        display_metrics(&processor.metrics());
+    }
+}
+
+fn display_remaining_unpinned(remaining: &[RemainingAction]) {
+    if remaining.is_empty() {
+        return;
+    }
+
+    println!();
+    println!("{}", "📌 Still Unpinned".bold().yellow());
+    println!("{}", "─".repeat(50).yellow());
+    for action in remaining {
+        let uses = if action.reference.is_empty() {
+            action.action.clone()
+        } else {
+            format!("{}@{}", action.action, action.reference)
+        };
+        let reason = match action.reason {
+            RemainingReason::ResolutionFailed => "resolution failed",
+            RemainingReason::Excluded => "excluded",
+            RemainingReason::DynamicRef => "dynamic ref",
+            RemainingReason::Skipped => "skipped",
+        };
+        println!(
+            "  {}:{} {} ({}: {})",
+            action.file,
+            action.line,
+            uses.yellow(),
+            reason,
+            action.detail
+        );
+    }
+    println!("{}", "─".repeat(50).yellow());
+    println!("  {} use(s) left on a ref", remaining.len());
+}
+
+fn display_unresolvable_results(results: &unresolvable::UnresolvableResults) {
+    println!();
+    println!("{}", "❌ Unresolvable Actions".bold().cyan());
+    println!("{}", "─".repeat(50).cyan());
+
+    for failure in &results.unresolvable {
+        println!(
+            "  {} {}",
+            failure.action.yellow(),
+            format!("[{}]", failure.code).red()
+        );
+        println!("    Tried:      {}", failure.source);
+        println!("    Error:      {}", failure.message);
+        println!("    Used in:    {}", failure.files.join(", "));
+        if !failure.available_refs.is_empty() {
+            println!(
+                "    Available:  {}",
+                failure.available_refs.join(", ").cyan()
+            );
+        }
+    }
+
+    if !results.unresolvable.is_empty() {
+        println!("{}", "─".repeat(50).cyan());
+    }
+
+    println!("  Files processed:  {}", results.files_processed);
+    println!("  Actions checked:  {}", results.actions_checked);
+    println!(
+        "  Unresolvable:     {}",
+        if results.unresolvable.is_empty() {
+            "0".green()
+        } else {
+            results.unresolvable.len().to_string().red()
+        }
+    );
+    println!("{}", "─".repeat(50).cyan());
+
+    if results.unresolvable.is_empty() {
+        println!("\n{}", "✨ Every action resolves".green());
+    }
+}
+
+fn display_drift_results(results: &drift::DriftResults) {
+    println!();
+    println!("{}", "🔀 Drift Report".bold().cyan());
+    println!("{}", "─".repeat(50).cyan());
+
+    for drifted in &results.drifted_actions {
+        let newer_tag = drifted
+            .newer_tag
+            .as_ref()
+            .map(|tag| format!(" ({})", tag))
+            .unwrap_or_default();
+        println!(
+            "  {}:{} {}@{} {} → {}{}",
+            drifted.file,
+            drifted.line,
+            drifted.action,
+            drifted.comment_ref.yellow(),
+            drifted.pinned_sha[..8].red(),
+            drifted.current_sha[..8].green(),
+            newer_tag.cyan()
+        );
+    }
+
+    if !results.drifted_actions.is_empty() {
+        println!("{}", "─".repeat(50).cyan());
+    }
+
+    println!("  Files processed:  {}", results.files_processed);
+    println!("  Pins checked:     {}", results.pins_checked);
+    if results.uncommented_pins > 0 {
+        println!(
+            "  No version comment: {}",
+            results.uncommented_pins.to_string().yellow()
+        );
+    }
+    println!(
+        "  Drifted:          {}",
+        if results.drifted_actions.is_empty() {
+            "0".green()
+        } else {
+            results.drifted_actions.len().to_string().yellow()
+        }
+    );
+    if !results.updated.is_empty() {
+        println!(
+            "  Updated:          {}",
+            results.updated.len().to_string().green()
+        );
+    }
+    if !results.unresolved_comments.is_empty() {
+        println!(
+            "  Unresolved:       {}",
+            results.unresolved_comments.len().to_string().yellow()
+        );
+    }
+    println!("  Errors:           {}", results.errors);
+    println!("{}", "─".repeat(50).cyan());
+
+    if !results.unresolved_comments.is_empty() {
+        println!("\n{}", "Version comments that didn't resolve:".yellow());
+        for unresolved in &results.unresolved_comments {
+            println!(
+                "  {}:{} {}@{} ({})",
+                unresolved.file,
+                unresolved.line,
+                unresolved.action,
+                unresolved.comment_ref.yellow(),
+                unresolved.message
+            );
+        }
+    }
+
+    if results.drifted_actions.is_empty() {
+        println!("\n{}", "✨ All pins match their version comments".green());
+    }
+}
+
+/// Rows of an action inventory: action, ref, pinned, location
+fn inventory_rows(inventory: &[ActionInventory]) -> Vec<[String; 4]> {
+    inventory
+        .iter()
+        .flat_map(|entry| {
+            entry.usages.iter().map(|usage| {
+                let reference = match (&usage.reference, &usage.comment_ref) {
+                    (Some(sha), Some(comment)) if usage.pinned => {
+                        format!("{} # {}", &sha[..sha.len().min(12)], comment)
+                    },
+                    (Some(reference), _) => match &usage.sha {
+                        Some(sha) => format!("{} → {}", reference, &sha[..sha.len().min(12)]),
+                        None => reference.clone(),
+                    },
+                    (None, _) => match &usage.sha {
+                        Some(sha) => format!("(default branch) → {}", &sha[..sha.len().min(12)]),
+                        None => "(default branch)".to_string(),
+                    },
+                };
+                [
+                    entry.action.clone(),
+                    reference,
+                    if usage.pinned { "yes" } else { "no" }.to_string(),
+                    format!("{}:{}", usage.file, usage.line),
+                ]
+            })
+        })
+        .collect()
+}
+
+const INVENTORY_HEADERS: [&str; 4] = ["ACTION", "REF", "PINNED", "LOCATION"];
+
+fn display_list_text(inventory: &[ActionInventory]) {
+    let rows = inventory_rows(inventory);
+
+    let mut widths = INVENTORY_HEADERS.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    println!();
+    println!(
+        "{}",
+        format!(
+            "{:<w0$}  {:<w1$}  {:<w2$}  {}",
+            INVENTORY_HEADERS[0],
+            INVENTORY_HEADERS[1],
+            INVENTORY_HEADERS[2],
+            INVENTORY_HEADERS[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2]
+        )
+        .bold()
+        .cyan()
+    );
+    for [action, reference, pinned, location] in &rows {
+        let pinned = format!("{:<w$}", pinned, w = widths[2]);
+        println!(
+            "{:<w0$}  {:<w1$}  {}  {}",
+            action,
+            reference,
+            if pinned.starts_with("yes") {
+                pinned.green()
+            } else {
+                pinned.red()
+            },
+            location,
+            w0 = widths[0],
+            w1 = widths[1]
+        );
+    }
+
+    let unpinned = rows.iter().filter(|row| row[2] == "no").count();
+    println!();
+    println!(
+        "  {} action(s), {} use(s), {} unpinned",
+        inventory.len(),
+        rows.len(),
+        unpinned
+    );
+}
+
+/// One CSV row per use, with the commit each points at when `sha` is set
+fn display_list_csv(report: &Report, inventory: &[ActionInventory], sha: bool) -> Result<()> {
+    if sha {
+        report.writeln("action,ref,pinned,sha,file,line");
+    } else {
+        report.writeln("action,ref,pinned,file,line");
+    }
+    for entry in inventory {
+        for usage in &entry.usages {
+            let sha = if sha {
+                format!("{},", usage.sha.as_deref().unwrap_or(""))
+            } else {
+                String::new()
+            };
+            report.writeln(&format!(
+                "{},{},{},{}{},{}",
+                csv_field(&entry.action),
+                csv_field(usage.reference.as_deref().unwrap_or("")),
+                usage.pinned,
+                sha,
+                csv_field(&usage.file),
+                usage.line
+            ));
+        }
+    }
+    report.finish()
+}
+
+/// Quote a CSV field if it contains a delimiter, quote, or newline
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Write the `list` or --inventory document in the --format, or with
+/// --summary-only just its totals
+fn display_inventory<T: serde::Serialize>(
+    args: &Args,
+    report: &Report,
+    document: &T,
+    inventory: &[ActionInventory],
+    sha: bool,
+) -> Result<()> {
+    if args.summary_only && args.format != OutputFormat::Text {
+        let mut summary = report::summarize(&serde_json::to_value(document)?);
+        let uses = inventory
+            .iter()
+            .map(|entry| entry.usages.len())
+            .sum::<usize>();
+        let pinned = inventory
+            .iter()
+            .flat_map(|entry| &entry.usages)
+            .filter(|usage| usage.pinned)
+            .count();
+        summary.insert("actions".to_string(), inventory.len().into());
+        summary.insert("uses".to_string(), uses.into());
+        summary.insert("pinned".to_string(), pinned.into());
+        summary.insert("unpinned".to_string(), (uses - pinned).into());
+        return display_summary(args, report, &summary);
+    }
+
+    match args.format {
+        OutputFormat::Text => Ok(()),
+        OutputFormat::Csv => display_list_csv(report, inventory, sha),
+        OutputFormat::Markdown => display_list_markdown(report, inventory),
+        _ => display_json_results(args, report, document),
+    }
+}
+
+/// Write a --summary-only document: JSON, or one row per counter for CSV
+/// and Markdown
+fn display_summary(
+    args: &Args,
+    report: &Report,
+    summary: &serde_json::Map<String, serde_json::Value>,
+) -> Result<()> {
+    let value = |value: &serde_json::Value| match value {
+        serde_json::Value::String(value) => value.clone(),
+        value => value.to_string(),
+    };
+    match args.format {
+        OutputFormat::Csv => {
+            report.writeln("field,value");
+            for (field, counter) in summary {
+                report.writeln(&format!("{},{}", field, csv_field(&value(counter))));
+            }
+        },
+        OutputFormat::Markdown => {
+            report.writeln("| Field | Value |");
+            report.writeln("| --- | --- |");
+            for (field, counter) in summary {
+                report.writeln(&format!(
+                    "| {} | {} |",
+                    field,
+                    value(counter).replace('|', "\\|")
+                ));
+            }
+        },
+        _ => report.writeln(&serde_json::to_string_pretty(summary)?),
+    }
+    report.finish()
+}
+
+fn display_list_markdown(report: &Report, inventory: &[ActionInventory]) -> Result<()> {
+    report.writeln("| Action | Ref | Pinned | Location |");
+    report.writeln("| --- | --- | --- | --- |");
+    for [action, reference, pinned, location] in inventory_rows(inventory) {
+        report.writeln(&format!(
+            "| `{}` | `{}` | {} | `{}` |",
+            action,
+            reference.replace('|', "\\|"),
+            pinned,
+            location
+        ));
+    }
+    report.finish()
+}
+
+/// Write each process event as a JSON line as soon as it happens
+fn jsonl_sink(report: Arc<Report>) -> EventSink {
+    Arc::new(move |event: &ProcessEvent| {
+        if let Ok(line) = serde_json::to_string(event) {
+            report.writeln(&line);
+        }
+    })
+}
+
+/// Write the final summary line of a JSON-lines stream, completing it.
+/// With --summary-only it is the only line, and gives `schema_version`
+fn display_jsonl_summary(
+    args: &Args,
+    report: &Report,
+    results: &workflow::ProcessResults,
+) -> Result<()> {
+    let mut summary = serde_json::json!({
+        "event": "summary",
+        "status": results.status,
+        "dry_run": results.dry_run,
+        "files_processed": results.files_processed,
+        "files_skipped": results.files_skipped,
+        "skipped_files": results.skipped_files,
+        "files_unchanged": results.files_unchanged,
+        "actions_found": results.actions_found,
+        "actions_pinned": results.actions_pinned,
+        "already_pinned": results.already_pinned,
+        "actions_ignored": results.actions_ignored,
+        "actions_allowed": results.actions_allowed,
+        "actions_annotated": results.actions_annotated,
+        "errors": results.errors,
+        "lines_changed": results.lines_changed,
+        "files_changed": results.files_changed,
+        "files_untouched": results.files_untouched,
+        "modified_files": results.modified_files,
+        "jobs": results.jobs,
+        "io_workers": results.io_workers,
+        "images_pinned": results.images_pinned,
+        "policy_violations": results.policy_violations.len(),
+        "remaining_unpinned": results.remaining_unpinned.len(),
+    });
+    if args.summary_only {
+        summary["schema_version"] = report::SCHEMA_VERSION.into();
+    }
+    report.writeln(&serde_json::to_string(&summary)?);
+    report.finish()
+}
+
+/// Write results as the JSON document, keeping only the counters with
+/// --summary-only
+fn display_json_results<T: serde::Serialize>(
+    args: &Args,
+    report: &Report,
+    results: &T,
+) -> Result<()> {
+    if args.summary_only {
+        let summary = report::summarize(&serde_json::to_value(results)?);
+        return display_summary(args, report, &summary);
+    }
+    let json = serde_json::to_string_pretty(&results)?;
+    report.writeln(&json);
+    report.finish()
+}
+
+/// Whether to print the text summary: always for --format text, and next
+/// to a document written to --output
+fn shows_text(args: &Args) -> bool {
+    args.format == OutputFormat::Text || report::output_file(args.output.as_deref()).is_some()
+}
+
+/// Show results as the text summary, as the --format document (JSON), or
+/// as both when the document goes to --output
+fn display_results<T: serde::Serialize>(
+    args: &Args,
+    report: &Report,
+    results: &T,
+    text: impl FnOnce(&T),
+) -> Result<()> {
+    if shows_text(args) {
+        text(results);
+    }
+    if args.format != OutputFormat::Text {
+        display_json_results(args, report, results)?;
+    }
+    Ok(())
+}
+
+/// Like [`display_results`], with --format rdjson writing the `diagnostics`
+/// made of the results instead of the results themselves
+fn display_findings<T: serde::Serialize>(
+    args: &Args,
+    report: &Report,
+    results: &T,
+    text: impl FnOnce(&T),
+    diagnostics: impl FnOnce(&T) -> rdjson::DiagnosticResult,
+) -> Result<()> {
+    if args.format != OutputFormat::Rdjson {
+        return display_results(args, report, results, text);
+    }
+    if shows_text(args) {
+        text(results);
+    }
+    display_json_results(args, report, &diagnostics(results))
+}
+
+/// Whether the command run is a pin, whose events --format jsonl streams
+/// as they happen
+fn streams_events(args: &Args) -> bool {
+    match &args.command {
+        Some(Command::Pin(_)) => true,
+        Some(_) => false,
+        None => {
+            !(args.strict_sha
+                || args.sync_comments
+                || args.only_unresolvable
+                || args.inventory
+                || args.no_resolve
+                || args.report_drift
+                || args.outdated)
+        },
+    }
+}
+
+/// Whether the command run has findings to give as --format rdjson
+/// diagnostics: unpinned actions from `check`, audit findings, or the pins
+/// a dry run would make
+fn reports_diagnostics(args: &Args) -> bool {
+    match &args.command {
+        Some(Command::Check | Command::Audit) => true,
+        Some(Command::Pin(_)) => args.dry_run,
+        Some(_) => false,
+        None if args.no_resolve => true,
+        None => {
+            args.dry_run
+                && !(args.strict_sha
+                    || args.sync_comments
+                    || args.only_unresolvable
+                    || args.inventory
+                    || args.report_drift
+                    || args.outdated)
+        },
+    }
+}
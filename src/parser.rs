@@ -1,68 +1,887 @@
-use std::{fs, path::Path};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fs::File,
+    hash::{DefaultHasher, Hasher},
+    io::{BufRead, BufReader},
+    path::Path,
+};
 
 use anyhow::{Context, Result};
 use lazy_static::lazy_static;
 use regex::Regex;
+use tracing::warn;
 
-use crate::action::ActionRef;
+use crate::{
+    action::ActionRef,
+    error::InvalidUtf8,
+    image::{ImageKind, ImageRef},
+};
 
 lazy_static! {
-    /// Regex to match uses: lines in workflows
-    /// Matches: "uses: owner/repo@ref" and captures indentation, action, and ref
+    /// Regex to match each uses: key on a line, in block style or inside a
+    /// flow mapping (`- { uses: a/b@v1, with: {} }`), quoted or not
+    /// Matches: "uses: owner/repo@ref" (or a ref-less "uses: owner/repo") and
+    /// captures the action and, when present, the ref
     static ref USES_REGEX: Regex = Regex::new(
-        r"(?m)^\s*-?\s*uses:\s+([^@\s]+)@([^\s#]+)"
+        r#"(?:^\s*-?\s*|[{,]\s*)uses:\s+["']?([^@\s#,}\]"']+)(?:@((?:[^\s#,{}\]"']|\{\})+))?"#
     ).unwrap();
+
+    /// Regex to match a `with:` line opening a block of inputs
+    static ref WITH_BLOCK_REGEX: Regex = Regex::new(r"^(\s*(?:-\s+)?)with:\s*(?:#.*)?$").unwrap();
+
+    /// Regex to match a `with: { ... }` flow mapping of inputs
+    static ref WITH_FLOW_REGEX: Regex = Regex::new(r"^\s*(?:-\s+)?with:\s*\{").unwrap();
+
+    /// Regex to match a key opening a multi-line string (`key: |` or `key: >-`)
+    static ref BLOCK_SCALAR_REGEX: Regex = Regex::new(r":\s*[|>][-+0-9]*\s*(?:#.*)?$").unwrap();
+
+    /// Regex to match an action-valued input such as `action: owner/repo@ref`
+    static ref INPUT_REGEX: Regex = Regex::new(
+        r#"([\w-]+):\s*["']?([\w.-]+/[^\s"'#@,}]+)@([^\s"'#,}]+)"#
+    ).unwrap();
+
+    /// Regex to match an image given directly as `container: <image>`
+    static ref CONTAINER_IMAGE_REGEX: Regex = Regex::new(
+        r#"^\s*(?:-\s+)?container:\s*["']?([^\s"'#{]+)"#
+    ).unwrap();
+
+    /// Regex to match each `image: <image>` on a line, in block or flow
+    /// style, capturing the image
+    static ref IMAGE_KEY_REGEX: Regex = Regex::new(
+        r#"(?:^|[\s{,])image:\s*["']?([^\s"'#,}\]]+)"#
+    ).unwrap();
+
+    /// Regex to match the trailing comment of a uses: line
+    static ref COMMENT_REGEX: Regex = Regex::new(r"\s#\s*(.*)$").unwrap();
+
+    /// Regex to match a mapping key, capturing the key and whatever follows
+    static ref KEY_REGEX: Regex = Regex::new(
+        r#"^\s*(?:-\s+)?["']?([\w.-]+)["']?:(?:\s+|$)(.*)"#
+    ).unwrap();
+
+    /// Regex to match an inline `pin-actions: ignore` directive
+    static ref IGNORE_DIRECTIVE_REGEX: Regex = Regex::new(r"(?i)\bpin-actions:\s*ignore\b").unwrap();
+}
+
+/// Placeholder reference for ref-less uses, until the default branch is
+/// resolved
+pub const DEFAULT_BRANCH_REF: &str = "HEAD";
+
+/// Start of the comment --provenance-comment writes above a rewritten uses
+/// line, e.g. "# pinned by pin-actions v1.2.0 via ls-remote on 2024-05-01"
+pub const PROVENANCE_PREFIX: &str = "pinned by pin-actions";
+
+/// Annotations pin-actions writes after the version in a pin comment
+const ANNOTATIONS: [&str; 4] = ["constraint:", "(branch)", "(default ref)", "(pinned "];
+
+/// Extract the version ref from a pin comment such as "v4" or
+/// "v4 (pinned 2024-05-01)", ignoring any trailing annotations
+pub fn parse_version_comment(comment: &str) -> Option<String> {
+    version_word(comment).map(str::to_string)
+}
+
+/// The first word of a comment when it is a ref pin-actions wrote: a
+/// version such as "v4" or "1.2.3", a lone word such as "main", or any word
+/// followed by one of our annotations. Otherwise, as in "keep this", the
+/// comment is all the author's
+fn version_word(comment: &str) -> Option<&str> {
+    let comment = comment.trim();
+    let (word, rest) = comment
+        .split_once(char::is_whitespace)
+        .map_or((comment, ""), |(word, rest)| (word, rest.trim_start()));
+    if word.is_empty() || word.starts_with('(') || word.starts_with('#') {
+        return None;
+    }
+
+    let versioned = word
+        .strip_prefix(['v', 'V'])
+        .unwrap_or(word)
+        .starts_with(|c: char| c.is_ascii_digit());
+    let annotated = rest.starts_with('#')
+        || ANNOTATIONS
+            .iter()
+            .any(|annotation| rest.starts_with(annotation));
+    (versioned || rest.is_empty() || annotated).then_some(word)
+}
+
+/// Extract the version range recorded by --record-constraint from a pin
+/// comment such as "v4 constraint:^4.0.0"
+pub fn parse_constraint_comment(comment: &str) -> Option<String> {
+    comment
+        .split_whitespace()
+        .find_map(|word| word.strip_prefix("constraint:"))
+        .filter(|constraint| !constraint.is_empty())
+        .map(str::to_string)
+}
+
+/// The part of a trailing comment that pin-actions didn't write. On a SHA
+/// pin (full or abbreviated), the version (see [`version_word`]) and the
+/// "constraint:<range>", "(branch)", "(default ref)" and "(pinned <date>)"
+/// annotations after it are ours; anything following, or the whole comment
+/// on an unpinned line, belongs to the author
+pub fn comment_note(comment: &str, pinned: bool) -> Option<String> {
+    let mut rest = comment.trim();
+    if pinned {
+        if let Some(version) = version_word(rest) {
+            rest = rest[version.len()..].trim_start();
+        }
+        loop {
+            let end = if rest.starts_with("constraint:") {
+                char::is_whitespace
+            } else if ANNOTATIONS[1..]
+                .iter()
+                .any(|annotation| rest.starts_with(annotation))
+            {
+                |c| c == ')'
+            } else {
+                break;
+            };
+            rest = rest
+                .split_once(end)
+                .map_or("", |(_, rest)| rest.trim_start());
+        }
+    }
+
+    let note = rest.trim_start_matches('#').trim();
+    (!note.is_empty()).then(|| note.to_string())
+}
+
+/// Whether a line is a provenance comment written by --provenance-comment
+pub fn is_provenance_comment(line: &str) -> bool {
+    line.trim_start()
+        .strip_prefix('#')
+        .is_some_and(|comment| comment.trim_start().starts_with(PROVENANCE_PREFIX))
+}
+
+/// Whether a trailing comment asks for the line to be left alone, as in
+/// "# pin-actions: ignore"
+pub fn has_ignore_directive(comment: &str) -> bool {
+    IGNORE_DIRECTIVE_REGEX.is_match(comment)
+}
+
+/// Where a line sits, by the keys open on it (its own last): images are
+/// only looked for in a job's `container:` and the service containers
+/// under its `services:`, never deeper or elsewhere, such as in a step's
+/// `with:`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ImageSlot {
+    /// `container: <image>` or `container: { image: <image> }`
+    Container,
+    /// `image:` in a block `container:` mapping
+    ContainerImage,
+    /// `services: { <name>: { image: <image> } }` or `<name>: { image:
+    /// <image> }` under `services:`
+    Services,
+    /// `image:` in a block service mapping
+    ServiceImage,
+}
+
+impl ImageSlot {
+    fn of(path: &[&str]) -> Option<Self> {
+        match path {
+            ["jobs", _, "container"] => Some(Self::Container),
+            ["jobs", _, "container", "image"] => Some(Self::ContainerImage),
+            ["jobs", _, "services"] | ["jobs", _, "services", _] => Some(Self::Services),
+            ["jobs", _, "services", _, "image"] => Some(Self::ServiceImage),
+            _ => None,
+        }
+    }
+
+    fn kind(self) -> ImageKind {
+        match self {
+            Self::Container | Self::ContainerImage => ImageKind::Container,
+            Self::Services | Self::ServiceImage => ImageKind::Service,
+        }
+    }
+}
+
+/// Whether a line is a YAML document start ("---") or end ("...") marker
+fn is_document_marker(line: &str) -> bool {
+    let line = line.trim_end();
+    line == "..." || line == "---" || line.starts_with("--- ")
+}
+
+/// Column of the mapping key on a line, past any "- " sequence marker
+fn key_column(line: &str) -> usize {
+    let trimmed = line.trim_start();
+    match trimmed.strip_prefix('-') {
+        Some(rest) => line.len() - rest.trim_start().len(),
+        None => line.len() - trimmed.len(),
+    }
+}
+
+/// The job and step a line belongs to, as far as indentation tells
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StepContext {
+    /// Key of the job under `jobs:`
+    pub job: Option<String>,
+    /// The job's `name:`
+    pub job_name: Option<String>,
+    /// The step's `name:`
+    pub step: Option<String>,
+    /// The step's `id:`
+    pub step_id: Option<String>,
+}
+
+impl StepContext {
+    /// Whether the line is in one of `jobs` (by key or name) and one of
+    /// `steps` (by name or id); an empty list matches anything
+    pub fn matches(&self, jobs: &[String], steps: &[String]) -> bool {
+        let job = jobs.is_empty()
+            || jobs
+                .iter()
+                .any(|job| self.job.as_ref() == Some(job) || self.job_name.as_ref() == Some(job));
+        let step = steps.is_empty()
+            || steps.iter().any(|step| {
+                self.step.as_ref() == Some(step) || self.step_id.as_ref() == Some(step)
+            });
+        job && step
+    }
+}
+
+/// Follows the mapping keys of a workflow line by line to work out the job
+/// and step of each line. Names can follow the `uses:` they label, so they
+/// are only looked up once the whole file has been seen
+#[derive(Default)]
+struct Structure {
+    /// Open keys and their columns, innermost last
+    keys: Vec<(usize, String)>,
+    /// First line and key column of the step being read
+    step: Option<(usize, usize)>,
+    /// Job key and first step line of every line seen
+    lines: HashMap<usize, (Option<String>, Option<usize>)>,
+    job_names: HashMap<String, String>,
+    step_names: HashMap<usize, String>,
+    step_ids: HashMap<usize, String>,
+}
+
+impl Structure {
+    /// Track the line's place in the document, returning whether it opens a
+    /// key of its own
+    fn visit(&mut self, line_number: usize, line: &str) -> bool {
+        let column = key_column(line);
+        while self.keys.last().is_some_and(|(open, _)| *open >= column) {
+            self.keys.pop();
+        }
+
+        let in_steps = self.keys.last().is_some_and(|(_, key)| key == "steps");
+        if line.trim_start().starts_with('-') && in_steps {
+            self.step = Some((line_number, column));
+        } else if self.step.is_some_and(|(_, step)| column < step) {
+            self.step = None;
+        }
+
+        if let Some(captures) = KEY_REGEX.captures(line) {
+            let key = &captures[1];
+            let value = COMMENT_REGEX.replace(&captures[2], "");
+            let value = value.trim().trim_matches(['"', '\'']).to_string();
+
+            match (self.keys.as_slice(), self.step) {
+                ([(_, jobs), (_, job)], _) if jobs == "jobs" && key == "name" => {
+                    self.job_names.insert(job.clone(), value);
+                },
+                (_, Some((start, step))) if column == step && key == "name" => {
+                    self.step_names.insert(start, value);
+                },
+                (_, Some((start, step))) if column == step && key == "id" => {
+                    self.step_ids.insert(start, value);
+                },
+                _ => {},
+            }
+            self.keys.push((column, key.to_string()));
+        }
+
+        let job = match self.keys.as_slice() {
+            [(_, jobs), (_, job), ..] if jobs == "jobs" => Some(job.clone()),
+            _ => None,
+        };
+        self.lines
+            .insert(line_number, (job, self.step.map(|(start, _)| start)));
+        self.keys.last().is_some_and(|(open, _)| *open == column)
+    }
+
+    /// The keys open on the line last visited, outermost first
+    fn path(&self) -> Vec<&str> {
+        self.keys.iter().map(|(_, key)| key.as_str()).collect()
+    }
+
+    /// Forget the open keys at the start of a new document
+    fn reset(&mut self) {
+        self.keys.clear();
+        self.step = None;
+    }
+
+    fn context(&self, line_number: usize) -> StepContext {
+        let Some((job, step)) = self.lines.get(&line_number) else {
+            return StepContext::default();
+        };
+        StepContext {
+            job_name: job
+                .as_ref()
+                .and_then(|job| self.job_names.get(job))
+                .cloned(),
+            job: job.clone(),
+            step: step.and_then(|start| self.step_names.get(&start)).cloned(),
+            step_id: step.and_then(|start| self.step_ids.get(&start)).cloned(),
+        }
+    }
+}
+
+/// UTF-8 byte order mark some Windows editors start files with
+pub const BOM: &str = "\u{feff}";
+
+/// Whether `reader` starts with a [`BOM`], without consuming it
+pub fn starts_with_bom<R: BufRead>(reader: &mut R) -> std::io::Result<bool> {
+    Ok(reader.fill_buf()?.starts_with(BOM.as_bytes()))
+}
+
+/// A line as read by [`read_lines`]
+pub struct Line<'a> {
+    pub number: usize,
+    /// Byte offset of the line within the file
+    pub offset: usize,
+    /// The line without its ending; invalid UTF-8 is replaced when read
+    /// lossily
+    pub text: Cow<'a, str>,
+    /// The line's bytes as they are in the file, without its ending
+    pub raw: &'a [u8],
+    /// "\n", "\r\n" or nothing for a last line without one
+    pub ending: &'a str,
+}
+
+impl Line<'_> {
+    /// Whether the text is exactly what the file holds, i.e. nothing had to
+    /// be replaced to read it
+    pub fn is_exact(&self) -> bool {
+        matches!(self.text, Cow::Borrowed(_))
+    }
+}
+
+/// A hash of a workflow's lines as they are in the file, endings included,
+/// to tell whether it changed between being parsed and being rewritten
+#[derive(Default)]
+pub struct Fingerprint(DefaultHasher);
+
+impl Fingerprint {
+    pub fn add(&mut self, line: &Line) {
+        self.0.write(line.raw);
+        self.0.write(line.ending.as_bytes());
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0.finish()
+    }
+}
+
+/// Call `f` with each line of `reader`, split the way `str::lines` splits
+/// them. A leading [`BOM`] isn't part of the first line's text, though
+/// offsets still count it. Invalid UTF-8 fails with [`InvalidUtf8`] unless
+/// `lossy`. One line is buffered at a time, however large the input
+pub fn read_lines<R: BufRead>(
+    mut reader: R,
+    lossy: bool,
+    mut f: impl FnMut(&Line) -> Result<()>,
+) -> Result<()> {
+    let mut buffer = Vec::new();
+    let mut offset = 0;
+    let mut number = 0;
+
+    if starts_with_bom(&mut reader)? {
+        reader.consume(BOM.len());
+        offset = BOM.len();
+    }
+
+    loop {
+        buffer.clear();
+        let read = reader.read_until(b'\n', &mut buffer)?;
+        if read == 0 {
+            return Ok(());
+        }
+        number += 1;
+
+        let raw = buffer.strip_suffix(b"\n").unwrap_or(&buffer);
+        let raw = raw.strip_suffix(b"\r").unwrap_or(raw);
+        let text = match std::str::from_utf8(raw) {
+            Ok(text) => Cow::Borrowed(text),
+            Err(_) if lossy => String::from_utf8_lossy(raw),
+            Err(e) => return Err(InvalidUtf8(offset + e.valid_up_to()).into()),
+        };
+        let ending = std::str::from_utf8(&buffer[raw.len()..]).expect("Line endings are ASCII");
+
+        f(&Line {
+            number,
+            offset,
+            text,
+            raw,
+            ending,
+        })?;
+        offset += read;
+    }
+}
+
+/// Line-at-a-time state of [`WorkflowFile`] parsing
+struct LineParser<'a> {
+    input_keys: &'a [String],
+    actions: Vec<UsesLine>,
+    images: Vec<ImageLine>,
+    /// Column of the `with:` key whose block we're in, if any
+    with_column: Option<usize>,
+    /// Column of the key whose multi-line string we're in, if any
+    scalar_column: Option<usize>,
+    /// Line of the last provenance comment seen
+    provenance_line: Option<usize>,
+    structure: Structure,
+}
+
+impl<'a> LineParser<'a> {
+    fn new(input_keys: &'a [String]) -> Self {
+        Self {
+            input_keys,
+            actions: Vec::new(),
+            images: Vec::new(),
+            with_column: None,
+            scalar_column: None,
+            provenance_line: None,
+            structure: Structure::default(),
+        }
+    }
+
+    fn feed(&mut self, line_number: usize, offset: usize, line: &str) {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() {
+            return;
+        }
+
+        // Text inside `run: |` or `description: >` blocks only looks like
+        // YAML, so never pin anything found there
+        let column = line.len() - trimmed.len();
+        if self.scalar_column.is_some_and(|scalar| column > scalar) {
+            return;
+        }
+        self.scalar_column = None;
+
+        if trimmed.starts_with('#') {
+            if is_provenance_comment(line) {
+                self.provenance_line = Some(line_number);
+            }
+            return;
+        }
+
+        // A new document starts from a clean slate; the marker line itself
+        // is left untouched
+        if column == 0 && is_document_marker(line) {
+            self.with_column = None;
+            self.structure.reset();
+            return;
+        }
+        let keyed = self.structure.visit(line_number, line);
+
+        if self.with_column.is_some_and(|with| column <= with) {
+            self.with_column = None;
+        }
+
+        let slot = Some(self.structure.path())
+            .filter(|_| keyed)
+            .and_then(|path| ImageSlot::of(&path));
+        let line_images = match slot {
+            Some(slot) => WorkflowFile::parse_image_line(line, line_number, slot),
+            None => Vec::new(),
+        };
+        if !line_images.is_empty() {
+            self.images.extend(
+                line_images
+                    .into_iter()
+                    .map(|image| ImageLine { offset, ..image }),
+            );
+            return;
+        }
+
+        if BLOCK_SCALAR_REGEX.is_match(line) {
+            self.scalar_column = Some(key_column(line));
+            return;
+        }
+
+        let provenance = self.provenance_line == Some(line_number - 1);
+        let uses = WorkflowFile::parse_uses_line(line, line_number);
+        if !uses.is_empty() {
+            self.actions.extend(uses.into_iter().map(|uses| UsesLine {
+                offset,
+                provenance,
+                ..uses
+            }));
+            return;
+        }
+
+        if self.input_keys.is_empty() {
+            return;
+        }
+
+        if let Some(captures) = WITH_BLOCK_REGEX.captures(line) {
+            self.with_column = Some(captures.get(1).map_or(0, |m| m.len()));
+            return;
+        }
+
+        let in_inputs = self.with_column.is_some() || WITH_FLOW_REGEX.is_match(line);
+        if in_inputs {
+            let uses = WorkflowFile::parse_input_line(line, line_number, self.input_keys);
+            self.actions.extend(uses.into_iter().map(|uses| UsesLine {
+                offset,
+                provenance,
+                ..uses
+            }));
+        }
+    }
+
+    /// The actions and images found, with the job and step of each
+    fn finish(mut self) -> (Vec<UsesLine>, Vec<ImageLine>) {
+        for uses in &mut self.actions {
+            uses.context = self.structure.context(uses.line_number);
+        }
+        for image in &mut self.images {
+            image.context = self.structure.context(image.line_number);
+        }
+        (self.actions, self.images)
+    }
 }
 
 /// A parsed workflow file
 #[derive(Debug)]
 pub struct WorkflowFile {
     pub path: String,
-    pub content: String,
+    /// The content, for workflows parsed from memory (such as archive
+    /// entries). Files parsed from disk only keep what was found in them and
+    /// are streamed again when rewritten, so memory doesn't grow with their
+    /// size
+    pub content: Option<String>,
     pub actions: Vec<UsesLine>,
+    /// Job container images
+    pub images: Vec<ImageLine>,
+    /// Read with invalid UTF-8 replaced, so lines holding some must not be
+    /// rewritten
+    pub lossy: bool,
+    /// [`Fingerprint`] of the content parsed
+    pub fingerprint: u64,
 }
 
 /// Represents a single "uses:" line in a workflow
 #[derive(Debug, Clone)]
 pub struct UsesLine {
     pub line_number: usize,
-    pub indent: String,
+    /// Byte offset of the line within the file
+    pub offset: usize,
+    /// Byte range of "owner/repo@ref" within its line, so each of several
+    /// uses on one line can be spliced without disturbing the others
+    pub start: usize,
+    pub end: usize,
     pub action: ActionRef,
+    /// Version ref recorded in a trailing comment (e.g. "v4")
+    pub comment_ref: Option<String>,
+    /// Version range recorded after it (e.g. "^4.0.0" from
+    /// "# v4 constraint:^4.0.0")
+    pub constraint: Option<String>,
+    /// Written without an @ref, meaning the repository's default branch; the
+    /// action's reference is "HEAD" until the branch is known
+    pub unversioned: bool,
+    /// The `with:` input holding this reference, for action-valued inputs
+    pub input_key: Option<String>,
+    /// Marked `# pin-actions: ignore`, so left on its ref on purpose
+    pub ignored: bool,
+    /// Whatever the trailing comment says besides the version comment
+    /// pin-actions writes, kept as a comment of its own when the line is
+    /// rewritten
+    pub note: Option<String>,
+    /// Job and step the line is in
+    pub context: StepContext,
+    /// The line before is a provenance comment, replaced rather than
+    /// repeated when this line is rewritten
+    pub provenance: bool,
+}
+
+impl UsesLine {
+    /// 1-based column where "owner/repo@ref" starts, counted in bytes as
+    /// editors and linters report them
+    pub fn column(&self) -> usize {
+        self.start + 1
+    }
+
+    /// The author's note as a trailing comment, " # note", or nothing
+    pub fn note_comment(&self) -> String {
+        self.note
+            .as_ref()
+            .map(|note| format!(" # {}", note))
+            .unwrap_or_default()
+    }
+
+    /// This use's part of a comment shared with other uses on its line, as
+    /// [`assign_comments`] reads it back
+    pub fn version_comment(&self) -> String {
+        if self.ignored {
+            return "pin-actions: ignore".to_string();
+        }
+        let mut comment = self.comment_ref.clone().unwrap_or_default();
+        if let Some(constraint) = &self.constraint {
+            comment.push_str(&format!(" constraint:{}", constraint));
+        }
+        comment
+    }
+
+    /// Take the version, constraint, ignore directive and note from a
+    /// trailing comment of this use's own
+    fn read_comment(&mut self, comment: &str) {
+        let pinned = self.action.is_sha || self.action.is_short_sha();
+        self.ignored = has_ignore_directive(comment);
+        self.comment_ref = Some(comment)
+            .filter(|_| !self.ignored)
+            .and_then(parse_version_comment);
+        self.constraint = Some(comment)
+            .filter(|_| pinned && !self.ignored)
+            .and_then(parse_constraint_comment);
+        self.note = comment_note(comment, pinned);
+    }
+}
+
+/// The trailing comment of a line whose last use ends at `end`, and where
+/// it starts
+pub fn trailing_comment(line: &str, end: usize) -> Option<(usize, &str)> {
+    let captures = COMMENT_REGEX.captures(&line[end..])?;
+    Some((end + captures.get(0)?.start(), captures.get(1)?.as_str()))
+}
+
+/// Give each use on a line its part of the trailing comment. A lone use
+/// owns the whole comment; several share one, "v1, v2 # note", in order,
+/// with anything after a further "#" being the author's. A comment that
+/// doesn't list one version per use is taken as the author's note
+pub fn assign_comments(line: &str, uses: &mut [UsesLine]) {
+    let Some(end) = uses.last().map(|uses| uses.end) else {
+        return;
+    };
+    let Some((_, comment)) = trailing_comment(line, end) else {
+        return;
+    };
+    if let [uses] = uses {
+        uses.read_comment(comment);
+        return;
+    }
+
+    let (versions, note) = match comment.split_once('#') {
+        Some((versions, note)) => (versions, Some(note.trim())),
+        None => (comment, None),
+    };
+    let versions: Vec<&str> = versions.split(',').map(str::trim).collect();
+    let note = if versions.len() == uses.len() {
+        for (uses, version) in uses.iter_mut().zip(versions) {
+            uses.read_comment(version);
+            uses.note = None;
+        }
+        note
+    } else {
+        Some(comment.trim())
+    };
+
+    let ignored = note.is_some_and(has_ignore_directive);
+    for uses in uses.iter_mut() {
+        uses.ignored |= ignored;
+        if uses.ignored {
+            uses.comment_ref = None;
+            uses.constraint = None;
+        }
+    }
+    if let Some(last) = uses.last_mut() {
+        last.note = note.filter(|note| !note.is_empty()).map(str::to_string);
+    }
+}
+
+/// A container image reference in a workflow
+#[derive(Debug, Clone)]
+pub struct ImageLine {
+    pub line_number: usize,
+    /// Byte offset of the line within the file
+    pub offset: usize,
+    pub image: ImageRef,
+    pub kind: ImageKind,
+    /// Byte range of the image within its line, so it can be spliced out
+    /// of flow mappings without disturbing the rest of the line
+    pub start: usize,
+    pub end: usize,
+    /// Job the image is in
+    pub context: StepContext,
 }
 
 impl WorkflowFile {
     /// Parse a workflow file and extract all action uses
     pub fn parse<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::parse_with_inputs(path, &[])
+    }
+
+    /// Parse a workflow file, also extracting action references passed as
+    /// the given `with:` inputs. The file is read a line at a time and its
+    /// content isn't kept
+    pub fn parse_with_inputs<P: AsRef<Path>>(path: P, input_keys: &[String]) -> Result<Self> {
         let path_str = path.as_ref().to_string_lossy().to_string();
-        let content = fs::read_to_string(&path)
+        let file = File::open(&path)
             .with_context(|| format!("Failed to read workflow file: {}", path_str))?;
 
-        let mut actions = Vec::new();
+        Self::parse_reader(path_str, BufReader::new(file), input_keys, false)
+    }
 
-        for (line_num, line) in content.lines().enumerate() {
-            if let Some(uses) = Self::parse_uses_line(line, line_num + 1) {
-                actions.push(uses);
-            }
-        }
+    /// Like [`parse_with_inputs`](Self::parse_with_inputs), but reading
+    /// invalid UTF-8 as replacement characters instead of failing
+    pub fn parse_lossy<P: AsRef<Path>>(path: P, input_keys: &[String]) -> Result<Self> {
+        let path_str = path.as_ref().to_string_lossy().to_string();
+        let file = File::open(&path)
+            .with_context(|| format!("Failed to read workflow file: {}", path_str))?;
+
+        Self::parse_reader(path_str, BufReader::new(file), input_keys, true)
+    }
+
+    /// Parse workflow content read from `reader`, recording `path` as its
+    /// name, with invalid UTF-8 replaced if `lossy` and an error otherwise.
+    /// Only one line is held at a time
+    pub fn parse_reader<R: BufRead>(
+        path: String,
+        reader: R,
+        input_keys: &[String],
+        lossy: bool,
+    ) -> Result<Self> {
+        let mut parser = LineParser::new(input_keys);
+        let mut fingerprint = Fingerprint::default();
+        read_lines(reader, lossy, |line| {
+            parser.feed(line.number, line.offset, &line.text);
+            fingerprint.add(line);
+            Ok(())
+        })
+        .with_context(|| format!("Failed to read workflow file: {}", path))?;
 
+        let (actions, images) = parser.finish();
         Ok(WorkflowFile {
-            path: path_str,
-            content,
+            path,
+            content: None,
             actions,
+            images,
+            lossy,
+            fingerprint: fingerprint.finish(),
         })
     }
 
-    /// Parse a single uses: line
-    fn parse_uses_line(line: &str, line_number: usize) -> Option<UsesLine> {
-        let captures = USES_REGEX.captures(line)?;
+    /// Parse workflow content that didn't come from disk (e.g. an archive
+    /// entry), recording `path` as its name
+    pub fn parse_str(path: String, content: String, input_keys: &[String]) -> Self {
+        let mut parser = LineParser::new(input_keys);
+        let mut fingerprint = Fingerprint::default();
+        read_lines(content.as_bytes(), false, |line| {
+            parser.feed(line.number, line.offset, &line.text);
+            fingerprint.add(line);
+            Ok(())
+        })
+        .expect("Reading from memory can't fail");
 
-        // Extract indent (everything before "uses:")
-        let indent = line.split("uses:").next()?.to_string();
-        let repo = captures.get(1)?.as_str();
-        let reference = captures.get(2)?.as_str();
+        let (actions, images) = parser.finish();
+        WorkflowFile {
+            path,
+            content: Some(content),
+            actions,
+            images,
+            lossy: false,
+            fingerprint: fingerprint.finish(),
+        }
+    }
 
-        let action_str = format!("{}@{}", repo, reference);
-        let action = ActionRef::parse(&action_str)?;
+    /// The workflow's content, from memory or else from the file on disk
+    pub fn reader(&self) -> Result<Box<dyn BufRead + '_>> {
+        match &self.content {
+            Some(content) => Ok(Box::new(content.as_bytes())),
+            None => {
+                let file = File::open(&self.path)
+                    .with_context(|| format!("Failed to read workflow file: {}", self.path))?;
+                Ok(Box::new(BufReader::new(file)))
+            },
+        }
+    }
+
+    /// Parse the images on a line in `slot`: a `container: <image>`, or
+    /// each `image: <image>` of a container or service mapping, in block or
+    /// flow style
+    fn parse_image_line(line: &str, line_number: usize, slot: ImageSlot) -> Vec<ImageLine> {
+        let image_line = |value: regex::Match, kind| {
+            Some(ImageLine {
+                line_number,
+                offset: 0,
+                image: ImageRef::parse(value.as_str())?,
+                kind,
+                start: value.start(),
+                end: value.end(),
+                context: StepContext::default(),
+            })
+        };
+
+        if slot == ImageSlot::Container {
+            if let Some(value) = CONTAINER_IMAGE_REGEX.captures(line).and_then(|c| c.get(1)) {
+                return image_line(value, ImageKind::Container)
+                    .into_iter()
+                    .collect();
+            }
+        }
+
+        IMAGE_KEY_REGEX
+            .captures_iter(line)
+            .filter_map(|c| image_line(c.get(1)?, slot.kind()))
+            .collect()
+    }
+
+    /// Parse the uses: keys on a line, usually one but any number in flow
+    /// style
+    fn parse_uses_line(line: &str, line_number: usize) -> Vec<UsesLine> {
+        let mut found: Vec<UsesLine> = USES_REGEX
+            .captures_iter(line)
+            .filter_map(|captures| Self::parse_use(line, line_number, &captures))
+            .collect();
+        assign_comments(line, &mut found);
+        found
+    }
+
+    /// Parse one uses: key matched on a line, without its comment
+    fn parse_use(line: &str, line_number: usize, captures: &regex::Captures) -> Option<UsesLine> {
+        let repo = captures.get(1)?;
+        let rest = &line[captures.get(0)?.end()..];
+
+        let mut end = captures.get(0)?.end();
+        let (reference, unversioned) = match captures.get(2) {
+            // An expression such as "${{ inputs.ref }}" is only known when
+            // the workflow runs; kept whole so it can be reported
+            Some(reference) if reference.as_str() == "$" && rest.starts_with("{{") => {
+                let close = rest.find("}}")? + 2;
+                end += close;
+                (&line[reference.start()..end], false)
+            },
+            Some(reference) => (reference.as_str(), false),
+            // Only "owner/repo" can be ref-less; docker:// images, expressions
+            // and a dangling "@" are not actions we can pin this way
+            None if repo.as_str().contains('/')
+                && !repo.as_str().contains("://")
+                && !rest.starts_with('@') =>
+            {
+                (DEFAULT_BRANCH_REF, true)
+            },
+            None if rest.starts_with('@') => {
+                warn!(
+                    "Line {}: skipping '{}@' with an empty reference",
+                    line_number,
+                    repo.as_str()
+                );
+                return None;
+            },
+            None => return None,
+        };
+
+        let action_str = format!("{}@{}", repo.as_str(), reference);
+        let Some(action) = ActionRef::parse(&action_str) else {
+            warn!(
+                "Line {}: skipping invalid action reference '{}'",
+                line_number, action_str
+            );
+            return None;
+        };
 
         // Skip local actions
         if action.is_local() {
@@ -71,16 +890,81 @@ impl WorkflowFile {
 
         Some(UsesLine {
             line_number,
-            indent,
+            offset: 0,
+            start: repo.start(),
+            end,
             action,
+            comment_ref: None,
+            constraint: None,
+            unversioned,
+            input_key: None,
+            ignored: false,
+            note: None,
+            context: StepContext::default(),
+            provenance: false,
         })
     }
 
-    /// Get all actions that need pinning (not already SHAs)
+    /// Parse a line inside a `with:` block (or a `with: { ... }` flow
+    /// mapping) for action references under any of `input_keys`
+    fn parse_input_line(line: &str, line_number: usize, input_keys: &[String]) -> Vec<UsesLine> {
+        let mut found: Vec<UsesLine> = INPUT_REGEX
+            .captures_iter(line)
+            .filter(|c| input_keys.iter().any(|key| key == &c[1]))
+            .filter_map(|captures| {
+                let repo = captures.get(2)?;
+                let reference = captures.get(3)?;
+
+                let action =
+                    ActionRef::parse(&format!("{}@{}", repo.as_str(), reference.as_str()))?;
+                if action.is_local() {
+                    return None;
+                }
+
+                Some(UsesLine {
+                    line_number,
+                    offset: 0,
+                    start: repo.start(),
+                    end: reference.end(),
+                    action,
+                    comment_ref: None,
+                    constraint: None,
+                    unversioned: false,
+                    input_key: Some(captures[1].to_string()),
+                    ignored: false,
+                    note: None,
+                    context: StepContext::default(),
+                    provenance: false,
+                })
+            })
+            .collect();
+        assign_comments(line, &mut found);
+        found
+    }
+
+    /// Get all actions that need pinning (not already SHAs, and not marked
+    /// `# pin-actions: ignore`)
     pub fn unpinned_actions(&self) -> Vec<&UsesLine> {
         self.actions
             .iter()
-            .filter(|uses| !uses.action.is_sha)
+            .filter(|uses| !uses.action.is_sha && !uses.ignored)
+            .collect()
+    }
+
+    /// Get unpinned actions left on their ref by an ignore directive
+    pub fn ignored_actions(&self) -> Vec<&UsesLine> {
+        self.actions
+            .iter()
+            .filter(|uses| !uses.action.is_sha && uses.ignored)
+            .collect()
+    }
+
+    /// Get actions pinned to a SHA without a version comment saying what
+    /// the SHA is (and not marked `# pin-actions: ignore`)
+    pub fn uncommented_pins(&self) -> Vec<&UsesLine> {
+        self.actions
+            .iter()
+            .filter(|uses| uses.action.is_sha && uses.comment_ref.is_none() && !uses.ignored)
             .collect()
     }
 
@@ -91,18 +975,29 @@ impl WorkflowFile {
             .filter(|uses| uses.action.is_sha)
             .count()
     }
+
+    /// Get all container images not yet pinned to a digest
+    pub fn unpinned_images(&self) -> Vec<&ImageLine> {
+        self.images
+            .iter()
+            .filter(|line| !line.image.is_pinned())
+            .collect()
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::fs;
+
     use super::*;
+    use crate::action::PinnedAction;
 
     #[test]
     fn test_parse_uses_line() {
         let line = "      - uses: actions/checkout@v4";
-        let uses = WorkflowFile::parse_uses_line(line, 1).unwrap();
+        let uses = WorkflowFile::parse_uses_line(line, 1).remove(0);
 
-        assert_eq!(uses.indent, "      - ");
+        assert_eq!(&line[uses.start..uses.end], "actions/checkout@v4");
         assert_eq!(uses.action.repository, "actions/checkout");
         assert_eq!(uses.action.reference, "v4");
         assert!(!uses.action.is_sha);
@@ -111,7 +1006,7 @@ mod tests {
     #[test]
     fn test_parse_uses_line_with_sha() {
         let line = "      - uses: actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11";
-        let uses = WorkflowFile::parse_uses_line(line, 1).unwrap();
+        let uses = WorkflowFile::parse_uses_line(line, 1).remove(0);
 
         assert!(uses.action.is_sha);
     }
@@ -119,9 +1014,177 @@ mod tests {
     #[test]
     fn test_parse_uses_line_with_comment() {
         let line = "      - uses: actions/checkout@v4 # Comment";
-        let uses = WorkflowFile::parse_uses_line(line, 1).unwrap();
+        let uses = WorkflowFile::parse_uses_line(line, 1).remove(0);
 
         assert_eq!(uses.action.reference, "v4");
+        assert_eq!(uses.comment_ref.as_deref(), Some("Comment"));
+    }
+
+    #[test]
+    fn test_parse_uses_line_with_dated_comment() {
+        let action = ActionRef::parse("actions/checkout@v4").unwrap();
+        let pinned = PinnedAction::new(action, "b4ffde65f46336ab88eb53be808477a3936bae11".into())
+            .with_pinned_on("2024-05-01".to_string());
+
+        let line = format!("      - uses: {}", pinned.format_uses_line());
+        let uses = WorkflowFile::parse_uses_line(&line, 1).remove(0);
+
+        assert!(uses.action.is_sha);
+        assert_eq!(uses.action.reference, pinned.sha);
+        assert_eq!(uses.comment_ref.as_deref(), Some("v4"));
+    }
+
+    #[test]
+    fn test_parse_uses_line_with_ignore_directive() {
+        let line = "      - uses: actions/checkout@v4 # pin-actions: ignore";
+        let uses = WorkflowFile::parse_uses_line(line, 1).remove(0);
+        assert!(uses.ignored);
+        assert_eq!(uses.comment_ref, None);
+
+        let line = "      - uses: actions/checkout@v4 # v4";
+        let uses = WorkflowFile::parse_uses_line(line, 1).remove(0);
+        assert!(!uses.ignored);
+
+        let yaml = "steps:\n  - uses: actions/checkout@v4 # pin-actions: ignore\n  - uses: actions/setup-node@v4\n";
+        let workflow = WorkflowFile::parse_str("ci.yml".to_string(), yaml.to_string(), &[]);
+        let unpinned: Vec<usize> = workflow
+            .unpinned_actions()
+            .iter()
+            .map(|uses| uses.line_number)
+            .collect();
+        assert_eq!(unpinned, vec![3]);
+        assert_eq!(workflow.ignored_actions().len(), 1);
+    }
+
+    #[test]
+    fn test_parse_provenance_comment() {
+        let yaml = "steps:\n  # pinned by pin-actions v1.0.0 via ls-remote on 2024-05-01\n  - uses: actions/checkout@v4\n  # pinned by hand\n  - uses: actions/setup-node@v4\n  # pinned by pin-actions v1.0.0 via ls-remote on 2024-05-01\n\n  - uses: actions/cache@v4\n";
+        let workflow = WorkflowFile::parse_str("ci.yml".to_string(), yaml.to_string(), &[]);
+
+        // Only a comment directly above counts
+        let provenance: Vec<bool> = workflow.actions.iter().map(|u| u.provenance).collect();
+        assert_eq!(provenance, vec![true, false, false]);
+        assert!(is_provenance_comment("\t#pinned by pin-actions"));
+        assert!(!is_provenance_comment(
+            "- uses: a/b@v1 # pinned by pin-actions"
+        ));
+    }
+
+    #[test]
+    fn test_parse_version_comment() {
+        assert_eq!(parse_version_comment("v4").as_deref(), Some("v4"));
+        assert_eq!(
+            parse_version_comment("v4.1.7 (pinned 2024-05-01)").as_deref(),
+            Some("v4.1.7")
+        );
+        assert_eq!(parse_version_comment("(pinned 2024-05-01)"), None);
+        assert_eq!(parse_version_comment("  "), None);
+    }
+
+    #[test]
+    fn test_comment_note() {
+        // Our own version comments leave nothing over
+        for comment in [
+            "v4",
+            "main (branch)",
+            "v1 (default ref) (pinned 2024-05-01)",
+            "v4.1.7 (pinned 2024-05-01)",
+            "(pinned 2024-05-01)",
+            "v4 constraint:^4.0.0 (pinned 2024-05-01)",
+        ] {
+            assert_eq!(comment_note(comment, true), None, "{}", comment);
+        }
+        assert_eq!(
+            comment_note("v4 (pinned 2024-05-01) # keep in sync with ci.yml", true).as_deref(),
+            Some("keep in sync with ci.yml")
+        );
+        assert_eq!(
+            comment_note("needed for the build", false).as_deref(),
+            Some("needed for the build")
+        );
+
+        // Only the version is ours, never the author's first word
+        assert_eq!(
+            comment_note("v4 keep this", true).as_deref(),
+            Some("keep this")
+        );
+        assert_eq!(
+            comment_note("keep this in sync", true).as_deref(),
+            Some("keep this in sync")
+        );
+        assert_eq!(parse_version_comment("keep this in sync"), None);
+        assert_eq!(parse_version_comment("v4 keep this").as_deref(), Some("v4"));
+        assert_eq!(
+            parse_version_comment("main (branch)").as_deref(),
+            Some("main")
+        );
+    }
+
+    #[test]
+    fn test_parse_constraint_comment() {
+        let line = "      - uses: actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11 # v4 constraint:^4.0.0 # ours";
+        let uses = WorkflowFile::parse_uses_line(line, 1).remove(0);
+        assert_eq!(uses.comment_ref.as_deref(), Some("v4"));
+        assert_eq!(uses.constraint.as_deref(), Some("^4.0.0"));
+        assert_eq!(uses.note.as_deref(), Some("ours"));
+
+        // On an unpinned line the comment is the author's
+        let uses =
+            WorkflowFile::parse_uses_line("      - uses: actions/checkout@v4 # constraint:^4", 1)
+                .remove(0);
+        assert_eq!(uses.constraint, None);
+        assert_eq!(parse_constraint_comment("v4 constraint:"), None);
+    }
+
+    #[test]
+    fn test_parse_several_uses_on_one_line() {
+        let line = "    steps: [{uses: actions/checkout@v4}, {name: Node, uses: actions/setup-node@v3}] # v4, v3 # flow style";
+        let uses = WorkflowFile::parse_uses_line(line, 1);
+
+        let spans: Vec<&str> = uses.iter().map(|u| &line[u.start..u.end]).collect();
+        assert_eq!(spans, vec!["actions/checkout@v4", "actions/setup-node@v3"]);
+        let comments: Vec<Option<&str>> = uses.iter().map(|u| u.comment_ref.as_deref()).collect();
+        assert_eq!(comments, vec![Some("v4"), Some("v3")]);
+        assert_eq!(uses[0].note, None);
+        assert_eq!(uses[1].note.as_deref(), Some("flow style"));
+
+        // The same action twice with different refs keeps both apart
+        let line = "    steps: [{uses: actions/cache@v3}, {uses: actions/cache@v4}]";
+        let uses = WorkflowFile::parse_uses_line(line, 1);
+        let refs: Vec<(usize, &str)> = uses
+            .iter()
+            .map(|u| (u.start, u.action.reference.as_str()))
+            .collect();
+        assert_eq!(refs, vec![(19, "v3"), (45, "v4")]);
+
+        // A comment that isn't one version per use is the author's
+        let line = "    steps: [{uses: a/b@v1}, {uses: c/d@v2}] # both vendored";
+        let uses = WorkflowFile::parse_uses_line(line, 1);
+        assert_eq!(uses[0].comment_ref, None);
+        assert_eq!(uses[1].comment_ref, None);
+        assert_eq!(uses[1].note.as_deref(), Some("both vendored"));
+    }
+
+    #[test]
+    fn test_parse_flow_mapping_step() {
+        for (line, action) in [
+            (
+                "      - { uses: actions/checkout@v4, with: {} }  ",
+                "actions/checkout@v4",
+            ),
+            (
+                "      - {uses: 'actions/cache@v4', with: {path: x}}",
+                "actions/cache@v4",
+            ),
+            (
+                "      - uses: \"actions/setup-node@v4\" # v4",
+                "actions/setup-node@v4",
+            ),
+        ] {
+            let uses = WorkflowFile::parse_uses_line(line, 1).remove(0);
+            assert_eq!(uses.action.to_string(), action);
+            assert_eq!(&line[uses.start..uses.end], action, "{}", line);
+        }
     }
 
     #[test]
@@ -129,7 +1192,277 @@ mod tests {
         let line = "      - uses: ./local-action@v1";
         let uses = WorkflowFile::parse_uses_line(line, 1);
 
-        assert!(uses.is_none());
+        assert!(uses.is_empty());
+    }
+
+    #[test]
+    fn test_parse_unversioned_uses_line() {
+        let uses = WorkflowFile::parse_uses_line("      - uses: owner/repo # latest", 1).remove(0);
+        assert!(uses.unversioned);
+        assert_eq!(uses.action.repository, "owner/repo");
+        assert_eq!(uses.action.reference, DEFAULT_BRANCH_REF);
+
+        let uses = WorkflowFile::parse_uses_line("      - uses: actions/checkout@v4", 1).remove(0);
+        assert!(!uses.unversioned);
+
+        for line in [
+            "      - uses: docker://alpine:3.8",
+            "      - uses: ./local-action",
+            "      - uses: owner/repo@",
+            "      - uses: owner/repo@   ",
+            "      - uses: owner/repo@tag:",
+            "      - uses: ${{ matrix.action }}",
+        ] {
+            assert!(
+                WorkflowFile::parse_uses_line(line, 1).is_empty(),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_expression_ref() {
+        let line = "      - uses: owner/repo@${{ inputs.ref }} # from the caller";
+        let uses = WorkflowFile::parse_uses_line(line, 1).remove(0);
+        assert_eq!(uses.action.repository, "owner/repo");
+        assert_eq!(uses.action.reference, "${{ inputs.ref }}");
+        assert!(uses.action.is_expression());
+        assert_eq!(&line[uses.start..uses.end], "owner/repo@${{ inputs.ref }}");
+
+        let uses = WorkflowFile::parse_uses_line("      - uses: owner/repo@v1", 1).remove(0);
+        assert!(!uses.action.is_expression());
+    }
+
+    #[test]
+    fn test_parse_action_inputs() {
+        let yaml = r#"
+steps:
+  - uses: some/meta-action@v1
+    with:
+      action: actions/setup-node@v4 # old comment
+      other: actions/cache@v3
+      script: |
+        echo action: not/this@v1
+  - name: flow
+    with: { action: "actions/checkout@v4", other: 1 }
+  - run: echo "action: nor/this@v2"
+"#;
+
+        let temp = tempfile::NamedTempFile::new().unwrap();
+        fs::write(temp.path(), yaml).unwrap();
+
+        let keys = vec!["action".to_string()];
+        let workflow = WorkflowFile::parse_with_inputs(temp.path(), &keys).unwrap();
+
+        let refs: Vec<String> = workflow
+            .actions
+            .iter()
+            .map(|u| u.action.to_string())
+            .collect();
+        assert_eq!(
+            refs,
+            vec![
+                "some/meta-action@v1",
+                "actions/setup-node@v4",
+                "actions/checkout@v4",
+            ]
+        );
+
+        let block = &workflow.actions[1];
+        assert_eq!(block.input_key.as_deref(), Some("action"));
+        assert_eq!((block.start, block.end), (14, 35));
+        assert_eq!(block.note.as_deref(), Some("old comment"));
+
+        let flow = &workflow.actions[2];
+        assert_eq!((flow.start, flow.end), (21, 40));
+
+        // Without input keys only uses: lines are picked up
+        let workflow = WorkflowFile::parse(temp.path()).unwrap();
+        assert_eq!(workflow.actions.len(), 1);
+    }
+
+    #[test]
+    fn test_ignore_uses_text_outside_uses_keys() {
+        let yaml = r#"
+steps:
+  - name: Step that uses: actions/checkout@v4
+    run: |
+      uses: actions/setup-node@v4
+      - uses: actions/cache@v4
+    shell: bash
+  - run: >-
+      uses: actions/upload-artifact@v4
+    uses: actions/checkout@v4
+  - name: >
+      folded uses: actions/download-artifact@v4
+  - uses: actions/setup-python@v5
+"#;
+
+        let temp = tempfile::NamedTempFile::new().unwrap();
+        fs::write(temp.path(), yaml).unwrap();
+
+        let workflow = WorkflowFile::parse(temp.path()).unwrap();
+
+        let refs: Vec<(usize, String)> = workflow
+            .actions
+            .iter()
+            .map(|u| (u.line_number, u.action.to_string()))
+            .collect();
+        assert_eq!(
+            refs,
+            vec![
+                (10, "actions/checkout@v4".to_string()),
+                (13, "actions/setup-python@v5".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_multi_document_file() {
+        let yaml = "---\nname: First\njobs:\n  a:\n    steps:\n      - uses: actions/checkout@v4\n...\n--- # second\nname: Second\njobs:\n  b:\n    steps:\n      - uses: actions/setup-node@v4\n";
+
+        let temp = tempfile::NamedTempFile::new().unwrap();
+        fs::write(temp.path(), yaml).unwrap();
+
+        let workflow = WorkflowFile::parse(temp.path()).unwrap();
+
+        let refs: Vec<(usize, String)> = workflow
+            .actions
+            .iter()
+            .map(|u| (u.line_number, u.action.to_string()))
+            .collect();
+        assert_eq!(
+            refs,
+            vec![
+                (6, "actions/checkout@v4".to_string()),
+                (13, "actions/setup-node@v4".to_string()),
+            ]
+        );
+        assert_eq!(workflow.unpinned_actions().len(), 2);
+    }
+
+    #[test]
+    fn test_read_lines_invalid_utf8() {
+        let content = b"steps:\n  - uses: actions/checkout@v4 # caf\xe9\r\n";
+
+        let error = read_lines(&content[..], false, |_| Ok(())).unwrap_err();
+        assert_eq!(error.downcast_ref::<InvalidUtf8>(), Some(&InvalidUtf8(42)));
+
+        let mut lines = Vec::new();
+        read_lines(&content[..], true, |line| {
+            lines.push((line.text.to_string(), line.raw.to_vec(), line.is_exact()));
+            Ok(())
+        })
+        .unwrap();
+        assert!(lines[0].2);
+        assert_eq!(lines[1].0, "  - uses: actions/checkout@v4 # caf\u{fffd}");
+        assert_eq!(lines[1].1, &content[7..43]);
+        assert!(!lines[1].2);
+    }
+
+    #[test]
+    fn test_parse_file_with_bom() {
+        let yaml = "\u{feff}jobs:\n  build:\n    steps:\n\t\t- uses:\tactions/checkout@v4\n";
+        let workflow = WorkflowFile::parse_str("ci.yml".to_string(), yaml.to_string(), &[]);
+
+        // The mark doesn't hide the first key, and offsets still count it
+        let uses = &workflow.actions[0];
+        assert_eq!(uses.context.job.as_deref(), Some("build"));
+        assert_eq!(
+            &yaml[uses.offset..][uses.start..uses.end],
+            "actions/checkout@v4"
+        );
+
+        let from_reader =
+            WorkflowFile::parse_reader("ci.yml".to_string(), yaml.as_bytes(), &[], false).unwrap();
+        assert_eq!(from_reader.actions[0].offset, uses.offset);
+    }
+
+    #[test]
+    fn test_step_context() {
+        let yaml = r#"
+jobs:
+  build:
+    name: Build
+    container: node:20
+    steps:
+      - uses: actions/checkout@v4
+        name: Checkout
+      - name: Setup
+        id: setup
+        uses: actions/setup-node@v4
+        with:
+          args:
+            - --verbose
+      - run: |
+          uses: not/an-action@v1
+  deploy:
+    uses: org/repo/.github/workflows/deploy.yml@v1
+runs:
+  steps:
+  - uses: actions/cache@v4
+"#;
+        let workflow = WorkflowFile::parse_str("ci.yml".to_string(), yaml.to_string(), &[]);
+
+        let contexts: Vec<(usize, &StepContext)> = workflow
+            .actions
+            .iter()
+            .map(|uses| (uses.line_number, &uses.context))
+            .collect();
+        let step = |job: Option<&str>, step: Option<&str>, id: Option<&str>| StepContext {
+            job: job.map(str::to_string),
+            job_name: (job == Some("build")).then(|| "Build".to_string()),
+            step: step.map(str::to_string),
+            step_id: id.map(str::to_string),
+        };
+        assert_eq!(
+            contexts,
+            vec![
+                (7, &step(Some("build"), Some("Checkout"), None)),
+                (11, &step(Some("build"), Some("Setup"), Some("setup"))),
+                (18, &step(Some("deploy"), None, None)),
+                (21, &step(None, None, None)),
+            ]
+        );
+        assert_eq!(workflow.images[0].context, step(Some("build"), None, None));
+
+        let build = &workflow.actions[1].context;
+        assert!(build.matches(&["Build".to_string()], &["setup".to_string()]));
+        assert!(build.matches(&["build".to_string()], &[]));
+        assert!(!build.matches(&["deploy".to_string()], &[]));
+        assert!(
+            !workflow.actions[3]
+                .context
+                .matches(&[], &["Setup".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_parse_reader_records_offsets() {
+        let content = "steps:\r\n  - uses: actions/checkout@v4\n  - uses: actions/cache@v4";
+        let workflow =
+            WorkflowFile::parse_reader("ci.yml".to_string(), content.as_bytes(), &[], false)
+                .unwrap();
+
+        assert!(workflow.content.is_none());
+        let offsets: Vec<(usize, usize)> = workflow
+            .actions
+            .iter()
+            .map(|uses| (uses.line_number, uses.offset))
+            .collect();
+        assert_eq!(
+            offsets,
+            vec![
+                (2, content.find("  - uses: actions/checkout").unwrap()),
+                (3, content.find("  - uses: actions/cache").unwrap()),
+            ]
+        );
+
+        let from_str = WorkflowFile::parse_str("ci.yml".to_string(), content.to_string(), &[]);
+        assert_eq!(from_str.content.as_deref(), Some(content));
+        assert_eq!(from_str.actions.len(), 2);
+        assert_eq!(from_str.actions[1].offset, offsets[1].1);
     }
 
     #[test]
@@ -156,4 +1489,120 @@ jobs:
         assert_eq!(workflow.unpinned_actions().len(), 2);
         assert_eq!(workflow.pinned_count(), 1);
     }
+
+    #[test]
+    fn test_parse_container_images() {
+        let yaml = r#"
+jobs:
+  build:
+    container: node:20
+    steps:
+      - uses: actions/checkout@v4
+  test:
+    container:
+      image: "ghcr.io/owner/app:1.2" # app image
+      options: --cpus 1
+    steps:
+      - run: |
+          image: not-an-image:1
+  matrix:
+    container: ${{ matrix.image }}
+    image: ignored:1
+"#;
+
+        let workflow = WorkflowFile::parse_str("ci.yml".to_string(), yaml.to_string(), &[]);
+        let images: Vec<(usize, String)> = workflow
+            .images
+            .iter()
+            .map(|line| (line.line_number, line.image.to_string()))
+            .collect();
+        assert_eq!(
+            images,
+            vec![
+                (4, "node:20".to_string()),
+                (9, "ghcr.io/owner/app:1.2".to_string()),
+            ]
+        );
+        assert_eq!(workflow.images[1].start, "      image: \"".len());
+        assert_eq!(workflow.images[1].kind, ImageKind::Container);
+        assert_eq!(workflow.actions.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_images_only_in_job_containers() {
+        let yaml = r#"
+jobs:
+  deploy:
+    steps:
+      - uses: docker/build-push-action@v6
+        with:
+          container: builder:1
+          image: app:2
+          services: { db: { image: postgres:16 } }
+    services:
+      redis:
+        env:
+          image: not-an-image:3
+        image: redis:7
+    container:
+      credentials:
+        image: not-an-image:4
+      image: node:20
+"#;
+
+        let workflow = WorkflowFile::parse_str("ci.yml".to_string(), yaml.to_string(), &[]);
+        let images: Vec<(usize, String, ImageKind)> = workflow
+            .images
+            .iter()
+            .map(|line| (line.line_number, line.image.to_string(), line.kind))
+            .collect();
+        assert_eq!(
+            images,
+            vec![
+                (14, "redis:7".to_string(), ImageKind::Service),
+                (18, "node:20".to_string(), ImageKind::Container),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_service_images() {
+        let yaml = r#"
+jobs:
+  test:
+    services:
+      redis:
+        image: redis:7
+        ports:
+          - 6379:6379
+      postgres: { image: "postgres:16", ports: ["5432:5432"] }
+    steps:
+      - uses: actions/checkout@v4
+  flow:
+    services: { a: { image: redis:7 }, b: { image: mysql:8 } }
+    container: { image: node:20, options: --cpus 1 }
+"#;
+
+        let workflow = WorkflowFile::parse_str("ci.yml".to_string(), yaml.to_string(), &[]);
+        let images: Vec<(usize, String, ImageKind)> = workflow
+            .images
+            .iter()
+            .map(|line| (line.line_number, line.image.to_string(), line.kind))
+            .collect();
+        assert_eq!(
+            images,
+            vec![
+                (6, "redis:7".to_string(), ImageKind::Service),
+                (9, "postgres:16".to_string(), ImageKind::Service),
+                (13, "redis:7".to_string(), ImageKind::Service),
+                (13, "mysql:8".to_string(), ImageKind::Service),
+                (14, "node:20".to_string(), ImageKind::Container),
+            ]
+        );
+
+        let line = yaml.lines().nth(8).unwrap();
+        let postgres = &workflow.images[1];
+        assert_eq!(&line[postgres.start..postgres.end], "postgres:16");
+        assert_eq!(workflow.actions.len(), 1);
+    }
 }
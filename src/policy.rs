@@ -0,0 +1,175 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{action::RefKind, parser::UsesLine};
+
+/// Which actions must be pinned to a commit SHA
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RequirePin {
+    /// Every action outside `trusted_owners`
+    ThirdParty,
+    /// Every action
+    #[default]
+    All,
+}
+
+/// The `[policy]` table of the config file: which actions may stay on a
+/// tag and whether any may reference a branch
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    /// Owners (e.g. your organization, "actions") whose actions may be
+    /// used by tag when `require_pin = "third-party"`
+    pub trusted_owners: Vec<String>,
+    pub require_pin: RequirePin,
+    /// Whether actions may reference a branch (or no ref at all, which
+    /// follows the default branch) rather than a tag or SHA
+    pub allow_branches: bool,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            trusted_owners: Vec::new(),
+            require_pin: RequirePin::default(),
+            allow_branches: true,
+        }
+    }
+}
+
+/// Why a use complies with the policy or violates it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyReason {
+    /// Pinned to a commit SHA
+    Pinned,
+    /// Left on its ref by a `# pin-actions: ignore` comment
+    Ignored,
+    /// On a tag, from a trusted owner
+    TrustedOwner,
+    /// Not pinned to a SHA, though the policy requires it
+    Unpinned,
+    /// References a branch, which the policy forbids
+    Branch,
+    /// The ref is a `${{ ... }}` expression, only known when the workflow
+    /// runs, so there's nothing to pin
+    DynamicRef,
+}
+
+impl PolicyReason {
+    pub fn is_violation(self) -> bool {
+        matches!(self, PolicyReason::Unpinned | PolicyReason::Branch)
+    }
+
+    /// What the reason means, for text output
+    pub fn describe(self) -> &'static str {
+        match self {
+            PolicyReason::Pinned => "pinned to a SHA",
+            PolicyReason::Ignored => "ignored by comment",
+            PolicyReason::TrustedOwner => "tag from a trusted owner",
+            PolicyReason::Unpinned => "must be pinned to a SHA",
+            PolicyReason::Branch => "branch references are not allowed",
+            PolicyReason::DynamicRef => "ref only known when the workflow runs",
+        }
+    }
+}
+
+impl Policy {
+    /// Whether the action's owner is one of `trusted_owners`, ignoring case
+    /// like GitHub does
+    fn is_trusted(&self, uses: &UsesLine) -> bool {
+        let owner = uses.action.repository.split('/').next().unwrap_or_default();
+        self.trusted_owners
+            .iter()
+            .any(|trusted| trusted.eq_ignore_ascii_case(owner))
+    }
+
+    /// Classify one use. A ref is taken for a branch when it says so, has
+    /// no ref at all, or doesn't look like a version or an abbreviated SHA
+    pub fn evaluate(&self, uses: &UsesLine) -> PolicyReason {
+        if uses.action.is_sha {
+            return PolicyReason::Pinned;
+        }
+        if uses.ignored {
+            return PolicyReason::Ignored;
+        }
+        if uses.action.is_expression() {
+            return PolicyReason::DynamicRef;
+        }
+
+        let on_branch = uses.unversioned
+            || match uses.action.ref_kind {
+                Some(kind) => kind == RefKind::Branch,
+                None => !uses.action.is_version_like() && !uses.action.is_short_sha(),
+            };
+        if on_branch && !self.allow_branches {
+            return PolicyReason::Branch;
+        }
+
+        match self.require_pin {
+            RequirePin::ThirdParty if self.is_trusted(uses) && !on_branch => {
+                PolicyReason::TrustedOwner
+            },
+            _ => PolicyReason::Unpinned,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::WorkflowFile;
+
+    #[test]
+    fn test_evaluate() {
+        let policy = Policy {
+            trusted_owners: vec!["Actions".to_string()],
+            require_pin: RequirePin::ThirdParty,
+            allow_branches: false,
+        };
+        let workflow = WorkflowFile::parse_str(
+            "ci.yml".to_string(),
+            [
+                "steps:",
+                "  - uses: actions/checkout@v4",
+                "  - uses: actions/setup-node@main",
+                "  - uses: docker/login-action@v3",
+                "  - uses: docker/login-action@b4ffde65f46336ab88eb53be808477a3936bae11",
+                "  - uses: docker/build-push-action@v5 # pin-actions: ignore",
+                "  - uses: actions/cache@tag:stable",
+                "  - uses: docker/login-action@${{ inputs.ref }}",
+            ]
+            .join("\n"),
+            &[],
+        );
+
+        let reasons: Vec<PolicyReason> = workflow
+            .actions
+            .iter()
+            .map(|uses| policy.evaluate(uses))
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                PolicyReason::TrustedOwner,
+                PolicyReason::Branch,
+                PolicyReason::Unpinned,
+                PolicyReason::Pinned,
+                PolicyReason::Ignored,
+                PolicyReason::TrustedOwner,
+                PolicyReason::DynamicRef,
+            ]
+        );
+
+        // Without the third-party carve-out everything unpinned violates
+        let strict = Policy::default();
+        assert_eq!(
+            strict.evaluate(&workflow.actions[0]),
+            PolicyReason::Unpinned
+        );
+        assert_eq!(
+            strict.evaluate(&workflow.actions[1]),
+            PolicyReason::Unpinned
+        );
+    }
+}
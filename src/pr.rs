@@ -0,0 +1,899 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use git2::{
+    Commit, Cred, CredentialType, Index, IndexEntry, IndexTime, PushOptions, RemoteCallbacks,
+    Repository, Signature,
+};
+use reqwest::{StatusCode, header};
+use serde_json::{Value, json};
+use tracing::{debug, info};
+
+use crate::{commit, git, workflow::ProcessResults};
+
+/// Default GitHub REST API endpoint
+pub const DEFAULT_API_URL: &str = "https://api.github.com";
+
+/// The REST API endpoint serving the git host `host`: api.github.com for
+/// github.com, and `/api/v3` on the host itself for GitHub Enterprise Server
+pub fn api_url(host: &str) -> String {
+    if host.eq_ignore_ascii_case(git::DEFAULT_GIT_HOST) {
+        DEFAULT_API_URL.to_string()
+    } else {
+        format!("https://{}/api/v3", host)
+    }
+}
+
+/// Branch a pull request is opened from; `{date}` is replaced with the
+/// run's date, so reruns on the same day update the same pull request
+pub const DEFAULT_BRANCH_TEMPLATE: &str = "pin-actions/update-{date}";
+
+/// Identity used for the commit when git has no user configured
+const FALLBACK_NAME: &str = "pin-actions";
+const FALLBACK_EMAIL: &str = "pin-actions@users.noreply.github.com";
+
+/// A failure at one stage of opening a pull request, so callers can tell
+/// them apart
+#[derive(Debug, thiserror::Error)]
+pub enum PrError {
+    /// The remote refused the branch update
+    #[error("Push of {branch} was rejected: {reason}")]
+    PushRejected { branch: String, reason: String },
+    /// GitHub refused to open a second pull request for the branch
+    #[error("A pull request from {branch} already exists: {reason}")]
+    AlreadyExists { branch: String, reason: String },
+    /// The token may not push or manage pull requests on the repository
+    #[error(
+        "The token can't {action} on {repository} ({reason}); it needs the `repo` scope, or `contents: write` and `pull-requests: write` for a fine-grained token"
+    )]
+    InsufficientScopes {
+        action: String,
+        repository: String,
+        reason: String,
+    },
+}
+
+/// How `--create-pr` names, targets and labels its pull request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrOptions {
+    /// Branch to merge into; the current branch when unset
+    pub base: Option<String>,
+    /// Branch name, with `{date}` replaced
+    pub branch_template: String,
+    pub labels: Vec<String>,
+    pub draft: bool,
+    /// Private key for pushing to an SSH remote; the ssh-agent when unset
+    pub ssh_key: Option<PathBuf>,
+}
+
+impl Default for PrOptions {
+    fn default() -> Self {
+        Self {
+            base: None,
+            branch_template: DEFAULT_BRANCH_TEMPLATE.to_string(),
+            labels: Vec::new(),
+            draft: false,
+            ssh_key: None,
+        }
+    }
+}
+
+impl PrOptions {
+    /// The branch name for a run on `date`
+    pub fn branch(&self, date: &str) -> String {
+        self.branch_template.replace("{date}", date)
+    }
+}
+
+/// The branch, commit message and pull request describing a pinning run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PullRequest {
+    pub branch: String,
+    pub base: String,
+    pub title: String,
+    /// Markdown report of every reference pinned
+    pub body: String,
+    pub labels: Vec<String>,
+    pub draft: bool,
+}
+
+impl PullRequest {
+    /// Describe the changes a run applied as a Markdown table of each
+    /// pinned action and image
+    pub fn describe(results: &ProcessResults, branch: String, base: String) -> Self {
+        let pairs = pinned_pairs(results);
+        let mut body = format!(
+            "Pins {} reference(s) to immutable commit SHAs and digests.\n\n| Reference | Pinned to |\n| --- | --- |\n",
+            pairs.len()
+        );
+        for (from, to) in &pairs {
+            body.push_str(&format!("| `{}` | `{}` |\n", from, to));
+        }
+
+        Self {
+            branch,
+            base,
+            title: "Pin GitHub Actions to commit SHAs".to_string(),
+            body,
+            labels: Vec::new(),
+            draft: false,
+        }
+    }
+
+    /// Title, then one line per reference pinned
+    pub fn commit_message(&self, results: &ProcessResults) -> String {
+        commit::commit_message(results, &self.title)
+    }
+}
+
+/// Each distinct action (as written) and image a run applied, with what it
+/// was pinned to, sorted
+fn pinned_pairs(results: &ProcessResults) -> BTreeSet<(String, String)> {
+    let mut pairs = BTreeSet::new();
+    for pinned in results.pinned_actions.iter().filter(|p| p.applied) {
+        let from = if pinned.was_unversioned {
+            pinned.action.clone()
+        } else {
+            format!("{}@{}", pinned.action, pinned.old_ref)
+        };
+        pairs.insert((from, pinned.sha.clone()));
+    }
+    for pinned in results.pinned_images.iter().filter(|p| p.applied) {
+        pairs.insert((pinned.image.clone(), pinned.digest.clone()));
+    }
+    pairs
+}
+
+/// One "- `from` → `to`" line per distinct action and image a run applied,
+/// sorted
+pub fn pinned_lines(results: &ProcessResults) -> Vec<String> {
+    pinned_pairs(results)
+        .into_iter()
+        .map(|(from, to)| format!("- `{}` → `{}`", from, to))
+        .collect()
+}
+
+/// Files a run actually changed
+pub fn changed_files(results: &ProcessResults) -> Vec<PathBuf> {
+    let files: BTreeSet<&String> = results
+        .pinned_actions
+        .iter()
+        .filter(|p| p.applied)
+        .map(|p| &p.file)
+        .chain(
+            results
+                .pinned_images
+                .iter()
+                .filter(|p| p.applied)
+                .map(|p| &p.file),
+        )
+        .collect();
+    files.into_iter().map(PathBuf::from).collect()
+}
+
+/// Name of the branch HEAD is on
+pub fn current_branch(repo: &Repository) -> Result<String> {
+    let head = repo.head().context("Repository has no HEAD")?;
+    if !head.is_branch() {
+        anyhow::bail!("HEAD is detached; check out the branch to open a pull request against");
+    }
+    head.shorthand()
+        .map(str::to_string)
+        .context("Branch name is not valid UTF-8")
+}
+
+/// The commit a pull request into `base` starts from: the remote's copy
+/// of the branch when it has been fetched, else the local one
+pub fn base_commit<'r>(repo: &'r Repository, base: &str) -> Result<Commit<'r>> {
+    repo.revparse_single(&format!("refs/remotes/origin/{}", base))
+        .or_else(|_| repo.revparse_single(base))
+        .and_then(|object| object.peel_to_commit())
+        .with_context(|| format!("No branch {} to open the pull request against", base))
+}
+
+/// Point `branch` at a commit of `files`, as they are in the working tree,
+/// on top of `base` (replacing the branch if a previous run left it). The
+/// commit is built apart from the index and HEAD is left where it is, so
+/// neither staged changes nor commits on the current branch that `base`
+/// lacks end up in the pull request
+pub fn commit_to_branch(
+    repo: &Repository,
+    branch: &str,
+    base: &Commit,
+    message: &str,
+    files: &[PathBuf],
+) -> Result<git2::Oid> {
+    let workdir = repo
+        .workdir()
+        .context("Cannot commit in a bare repository")?;
+    let workdir = fs::canonicalize(workdir)?;
+
+    let mut index = Index::new()?;
+    index.read_tree(&base.tree()?)?;
+    for file in files {
+        let path = relative_to(&workdir, file)?;
+        let content =
+            fs::read(file).with_context(|| format!("Failed to read {}", file.display()))?;
+        let entry = IndexEntry {
+            ctime: IndexTime::new(0, 0),
+            mtime: IndexTime::new(0, 0),
+            dev: 0,
+            ino: 0,
+            // Keep the executable bit of a file the base already has
+            mode: index
+                .get_path(&path, 0)
+                .map_or(0o100644, |existing| existing.mode),
+            uid: 0,
+            gid: 0,
+            file_size: content.len() as u32,
+            id: repo.blob(&content)?,
+            flags: 0,
+            flags_extended: 0,
+            path: path.to_string_lossy().into_owned().into_bytes(),
+        };
+        index.add(&entry)?;
+    }
+    let tree = repo.find_tree(index.write_tree_to(repo)?)?;
+
+    let signature = repo
+        .signature()
+        .or_else(|_| Signature::now(FALLBACK_NAME, FALLBACK_EMAIL))?;
+    let oid = repo.commit(None, &signature, &signature, message, &tree, &[base])?;
+    repo.reference(
+        &format!("refs/heads/{}", branch),
+        oid,
+        true,
+        "pin-actions: pull request branch",
+    )
+    .with_context(|| format!("Failed to create branch {}", branch))?;
+
+    debug!("Committed {} on {}", oid, branch);
+    Ok(oid)
+}
+
+/// Path of `file` relative to the repository's working directory
+pub fn relative_to(workdir: &Path, file: &Path) -> Result<PathBuf> {
+    let absolute =
+        fs::canonicalize(file).with_context(|| format!("Failed to locate {}", file.display()))?;
+    absolute
+        .strip_prefix(workdir)
+        .map(Path::to_path_buf)
+        .with_context(|| format!("{} is outside the repository", file.display()))
+}
+
+/// The "owner/repo" of a GitHub remote URL (https or ssh)
+pub fn github_repository(url: &str) -> Option<String> {
+    let path = url
+        .strip_prefix("https://github.com/")
+        .or_else(|| url.strip_prefix("ssh://git@github.com/"))
+        .or_else(|| url.strip_prefix("git@github.com:"))?;
+    let path = path.trim_end_matches('/').trim_end_matches(".git");
+
+    let (owner, repo) = path.split_once('/')?;
+    if owner.is_empty() || repo.is_empty() || repo.contains('/') {
+        return None;
+    }
+    Some(format!("{}/{}", owner, repo))
+}
+
+/// Force-push `branch` to `remote`, authenticating over HTTPS with the
+/// token, or over SSH with `ssh_key` (the ssh-agent when unset). A previous
+/// run's branch is replaced so its pull request updates
+pub fn push_branch(
+    repo: &Repository,
+    remote: &str,
+    branch: &str,
+    token: &str,
+    ssh_key: Option<&Path>,
+) -> Result<()> {
+    let name = remote;
+    let mut remote = repo
+        .find_remote(name)
+        .with_context(|| format!("No remote named {}", name))?;
+
+    let rejected = std::cell::RefCell::new(None);
+    let mut callbacks = RemoteCallbacks::new();
+    let mut attempted = false;
+    callbacks.credentials(move |_url, username, allowed| {
+        // libgit2 keeps asking after rejected credentials; give up after
+        // one try
+        if attempted {
+            return Err(git2::Error::from_str("credentials were rejected"));
+        }
+        attempted = true;
+
+        if allowed.contains(CredentialType::SSH_KEY) {
+            let username = username.unwrap_or("git");
+            return match ssh_key {
+                Some(key) => Cred::ssh_key(username, None, key, None),
+                None => Cred::ssh_key_from_agent(username),
+            };
+        }
+        Cred::userpass_plaintext("x-access-token", token)
+    });
+    callbacks.push_update_reference(|_refname, status| {
+        if let Some(reason) = status {
+            *rejected.borrow_mut() = Some(reason.to_string());
+        }
+        Ok(())
+    });
+    let mut options = PushOptions::new();
+    options.remote_callbacks(callbacks);
+
+    let refspec = format!("+refs/heads/{0}:refs/heads/{0}", branch);
+    if let Err(e) = remote.push(&[refspec.as_str()], Some(&mut options)) {
+        let denied = e.code() == git2::ErrorCode::Auth
+            || ["401", "403"].iter().any(|code| e.message().contains(code));
+        if denied {
+            return Err(PrError::InsufficientScopes {
+                action: "push".to_string(),
+                repository: remote.url().unwrap_or(name).to_string(),
+                reason: e.message().to_string(),
+            }
+            .into());
+        }
+        return Err(e).with_context(|| format!("Failed to push {}", branch));
+    }
+    drop(options);
+
+    if let Some(reason) = rejected.into_inner() {
+        return Err(PrError::PushRejected {
+            branch: branch.to_string(),
+            reason,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Talks to the pull request endpoints of one repository
+struct PullRequestApi<'a> {
+    client: reqwest::Client,
+    api_url: &'a str,
+    repository: &'a str,
+    token: &'a str,
+}
+
+impl PullRequestApi<'_> {
+    async fn send(
+        &self,
+        action: &str,
+        request: reqwest::RequestBuilder,
+        pr: &PullRequest,
+    ) -> Result<Value> {
+        let response = request
+            .bearer_auth(self.token)
+            .header(header::ACCEPT, "application/vnd.github+json")
+            .header(header::USER_AGENT, "pin-actions")
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach {}", self.api_url))?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response.json().await?);
+        }
+
+        let reason = response
+            .json::<Value>()
+            .await
+            .ok()
+            .map(|body| api_error_reason(&body))
+            .unwrap_or_else(|| status.to_string());
+        let error = match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => PrError::InsufficientScopes {
+                action: action.to_string(),
+                repository: self.repository.to_string(),
+                reason,
+            }
+            .into(),
+            StatusCode::UNPROCESSABLE_ENTITY if reason.contains("already exists") => {
+                PrError::AlreadyExists {
+                    branch: pr.branch.clone(),
+                    reason,
+                }
+                .into()
+            },
+            _ => anyhow::anyhow!(
+                "Failed to {} on {}: {} ({})",
+                action,
+                self.repository,
+                reason,
+                status
+            ),
+        };
+        Err(error)
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/repos/{}/{}", self.api_url, self.repository, path)
+    }
+}
+
+/// The message of a GitHub API error response, with any detailed errors
+fn api_error_reason(body: &Value) -> String {
+    let message = body["message"].as_str().unwrap_or("request failed");
+    let details: Vec<&str> = body["errors"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|error| error["message"].as_str())
+        .collect();
+    if details.is_empty() {
+        message.to_string()
+    } else {
+        format!("{}: {}", message, details.join("; "))
+    }
+}
+
+/// Open a pull request from `pr.branch` into `pr.base`, or update the open
+/// one a previous run left for the branch, and apply its labels. Returns the
+/// pull request URL
+pub async fn open_pull_request(
+    api_url: &str,
+    repository: &str,
+    token: &str,
+    pr: &PullRequest,
+) -> Result<String> {
+    let api = PullRequestApi {
+        client: reqwest::Client::new(),
+        api_url,
+        repository,
+        token,
+    };
+    let owner = repository.split('/').next().unwrap_or(repository);
+
+    let open: Value = api
+        .send(
+            "list pull requests",
+            api.client.get(api.url("pulls")).query(&[
+                ("head", format!("{}:{}", owner, pr.branch)),
+                ("state", "open".to_string()),
+            ]),
+            pr,
+        )
+        .await?;
+
+    let response = match open.as_array().and_then(|open| open.first()) {
+        Some(existing) => {
+            let number = existing["number"]
+                .as_u64()
+                .context("Pull request has no number")?;
+            info!("Updating pull request #{} from a previous run", number);
+            api.send(
+                "update the pull request",
+                api.client
+                    .patch(api.url(&format!("pulls/{}", number)))
+                    .json(&json!({
+                        "title": pr.title,
+                        "body": pr.body,
+                        "base": pr.base,
+                    })),
+                pr,
+            )
+            .await?
+        },
+        None => {
+            api.send(
+                "open a pull request",
+                api.client.post(api.url("pulls")).json(&json!({
+                    "title": pr.title,
+                    "body": pr.body,
+                    "head": pr.branch,
+                    "base": pr.base,
+                    "draft": pr.draft,
+                })),
+                pr,
+            )
+            .await?
+        },
+    };
+
+    if !pr.labels.is_empty() {
+        let number = response["number"]
+            .as_u64()
+            .context("Pull request response has no number")?;
+        api.send(
+            "label the pull request",
+            api.client
+                .post(api.url(&format!("issues/{}/labels", number)))
+                .json(&json!({ "labels": pr.labels })),
+            pr,
+        )
+        .await?;
+    }
+
+    response["html_url"]
+        .as_str()
+        .map(str::to_string)
+        .context("Pull request response has no html_url")
+}
+
+/// Commit a run's changes to a branch of the repository holding them and,
+/// with a token, force-push it and open (or update) a pull request.
+/// Returns the pull request URL when one was opened
+pub async fn open_pr(
+    results: &ProcessResults,
+    token: Option<&str>,
+    api_url: &str,
+    options: &PrOptions,
+    date: &str,
+) -> Result<Option<String>> {
+    let files = changed_files(results);
+    let Some(first) = files.first() else {
+        info!("No changes to open a pull request for");
+        return Ok(None);
+    };
+
+    let repo = Repository::discover(first.parent().unwrap_or(Path::new(".")))
+        .with_context(|| format!("{} is not in a git repository", first.display()))?;
+    let base = match &options.base {
+        Some(base) => base.clone(),
+        None => current_branch(&repo)?,
+    };
+    let mut pr = PullRequest::describe(results, options.branch(date), base);
+    pr.labels = options.labels.clone();
+    pr.draft = options.draft;
+
+    let parent = base_commit(&repo, &pr.base)?;
+    let oid = commit_to_branch(
+        &repo,
+        &pr.branch,
+        &parent,
+        &pr.commit_message(results),
+        &files,
+    )?;
+    info!(
+        "Committed {} on branch {}",
+        &oid.to_string()[..8],
+        pr.branch
+    );
+
+    let Some(token) = token else {
+        info!("No GitHub token; push the branch and open the pull request yourself");
+        return Ok(None);
+    };
+
+    let remote = repo.find_remote("origin").context("No origin remote")?;
+    let repository = remote
+        .url()
+        .and_then(github_repository)
+        .context("The origin remote is not a GitHub repository")?;
+
+    push_branch(
+        &repo,
+        "origin",
+        &pr.branch,
+        token,
+        options.ssh_key.as_deref(),
+    )?;
+    let url = open_pull_request(api_url, &repository, token, &pr).await?;
+    Ok(Some(url))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::workflow::PinnedActionResult;
+
+    const SHA: &str = "b4ffde65f46336ab88eb53be808477a3936bae11";
+
+    #[test]
+    fn test_api_url() {
+        assert_eq!(api_url("github.com"), DEFAULT_API_URL);
+        assert_eq!(api_url("GitHub.com"), DEFAULT_API_URL);
+        assert_eq!(api_url("ghe.example.com"), "https://ghe.example.com/api/v3");
+        assert_eq!(
+            crate::graphql::graphql_url("ghe.example.com"),
+            "https://ghe.example.com/api/graphql"
+        );
+    }
+
+    fn results(file: &Path) -> ProcessResults {
+        ProcessResults {
+            pinned_actions: vec![PinnedActionResult {
+                file: file.to_string_lossy().to_string(),
+                line: 3,
+                column: 11,
+                action: "actions/checkout".to_string(),
+                old_ref: "v4".to_string(),
+                sha: SHA.to_string(),
+                previous_sha: None,
+                applied: true,
+                was_unversioned: false,
+                on_branch: false,
+                signature: None,
+                link: String::new(),
+                source: None,
+            }],
+            ..Default::default()
+        }
+    }
+
+    /// A repository with one commit holding an unpinned workflow
+    fn repo_with_workflow(temp: &TempDir) -> (Repository, PathBuf) {
+        let repo = Repository::init(temp.path()).unwrap();
+        let workflows = temp.path().join(".github/workflows");
+        fs::create_dir_all(&workflows).unwrap();
+        let workflow = workflows.join("ci.yml");
+        fs::write(&workflow, "steps:\n  - uses: actions/checkout@v4\n").unwrap();
+
+        let mut index = repo.index().unwrap();
+        index
+            .add_path(Path::new(".github/workflows/ci.yml"))
+            .unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("test", "test@example.com").unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "init", &tree, &[])
+            .unwrap();
+        drop(tree);
+
+        (repo, workflow)
+    }
+
+    const BRANCH: &str = "pin-actions/update-2024-05-01";
+
+    fn pull_request() -> PullRequest {
+        PullRequest::describe(
+            &results(Path::new("ci.yml")),
+            BRANCH.to_string(),
+            "main".to_string(),
+        )
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(PrOptions::default().branch("2024-05-01"), BRANCH);
+
+        let results = results(Path::new("ci.yml"));
+        let pr = pull_request();
+        assert_eq!(pr.branch, BRANCH);
+        assert!(pr.body.contains("| Reference | Pinned to |"));
+        assert!(
+            pr.body
+                .contains(&format!("| `actions/checkout@v4` | `{}` |", SHA))
+        );
+        let message = pr.commit_message(&results);
+        assert!(message.starts_with("Pin GitHub Actions to commit SHAs\n\n"));
+        assert!(message.contains(&format!("- `actions/checkout@v4` → `{}`", SHA)));
+    }
+
+    #[test]
+    fn test_commit_to_branch() {
+        let temp = TempDir::new().unwrap();
+        let (repo, workflow) = repo_with_workflow(&temp);
+        let base = current_branch(&repo).unwrap();
+
+        let pinned = format!("steps:\n  - uses: actions/checkout@{} # v4\n", SHA);
+        fs::write(&workflow, &pinned).unwrap();
+
+        let results = results(&workflow);
+        let message = pull_request().commit_message(&results);
+        let parent = base_commit(&repo, &base).unwrap();
+        let oid = commit_to_branch(&repo, BRANCH, &parent, &message, &[workflow]).unwrap();
+
+        // HEAD stays on the base branch, which is untouched
+        assert_eq!(current_branch(&repo).unwrap(), base);
+        assert_eq!(repo.head().unwrap().target(), Some(parent.id()));
+
+        let commit = repo.find_commit(oid).unwrap();
+        assert_eq!(commit.summary(), Some("Pin GitHub Actions to commit SHAs"));
+        assert_eq!(commit.parent_id(0).unwrap(), parent.id());
+        let branch = repo.find_branch(BRANCH, git2::BranchType::Local).unwrap();
+        assert_eq!(branch.get().target(), Some(oid));
+
+        let blob = commit
+            .tree()
+            .unwrap()
+            .get_path(Path::new(".github/workflows/ci.yml"))
+            .unwrap()
+            .to_object(&repo)
+            .unwrap()
+            .peel_to_blob()
+            .unwrap();
+        assert_eq!(blob.content(), pinned.as_bytes());
+    }
+
+    #[test]
+    fn test_commit_to_branch_starts_from_base() {
+        let temp = TempDir::new().unwrap();
+        let (repo, workflow) = repo_with_workflow(&temp);
+        let init = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.reference("refs/remotes/origin/main", init.id(), false, "test")
+            .unwrap();
+
+        // A local commit the base lacks, and an unrelated staged change
+        fs::write(temp.path().join("local.txt"), "local\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("local.txt")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("test", "test@example.com").unwrap();
+        let local = repo
+            .commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                "local",
+                &tree,
+                &[&init],
+            )
+            .unwrap();
+        repo.branch("main", &repo.find_commit(local).unwrap(), true)
+            .unwrap();
+        fs::write(temp.path().join("notes.txt"), "unrelated\n").unwrap();
+        index.add_path(Path::new("notes.txt")).unwrap();
+        index.write().unwrap();
+
+        fs::write(&workflow, "steps:\n  - uses: actions/checkout@abc # v4\n").unwrap();
+
+        // The fetched origin/main wins over the local main
+        let parent = base_commit(&repo, "main").unwrap();
+        assert_eq!(parent.id(), init.id());
+        assert!(base_commit(&repo, "nope").is_err());
+
+        let oid = commit_to_branch(&repo, BRANCH, &parent, "pin", &[workflow]).unwrap();
+        let commit = repo.find_commit(oid).unwrap();
+        assert_eq!(commit.parent_id(0).unwrap(), init.id());
+        let tree = commit.tree().unwrap();
+        assert!(tree.get_path(Path::new(".github/workflows/ci.yml")).is_ok());
+        assert!(tree.get_path(Path::new("local.txt")).is_err());
+        assert!(tree.get_path(Path::new("notes.txt")).is_err());
+
+        // HEAD and the index are as they were
+        assert_eq!(repo.head().unwrap().target(), Some(local));
+        let index = repo.index().unwrap();
+        assert!(index.get_path(Path::new("notes.txt"), 0).is_some());
+    }
+
+    #[test]
+    fn test_push_branch() {
+        let temp = TempDir::new().unwrap();
+        let (repo, workflow) = repo_with_workflow(&temp);
+        let remote = TempDir::new().unwrap();
+        Repository::init_bare(remote.path()).unwrap();
+        repo.remote("origin", remote.path().to_str().unwrap())
+            .unwrap();
+
+        let base = current_branch(&repo).unwrap();
+        let parent = base_commit(&repo, &base).unwrap();
+        let oid = commit_to_branch(&repo, BRANCH, &parent, "pin", &[workflow]).unwrap();
+        push_branch(&repo, "origin", BRANCH, "token", None).unwrap();
+
+        let pushed = Repository::open_bare(remote.path()).unwrap();
+        let branch = pushed.find_branch(BRANCH, git2::BranchType::Local).unwrap();
+        assert_eq!(branch.get().target(), Some(oid));
+    }
+
+    #[test]
+    fn test_github_repository() {
+        assert_eq!(
+            github_repository("https://github.com/owner/repo.git").as_deref(),
+            Some("owner/repo")
+        );
+        assert_eq!(
+            github_repository("git@github.com:owner/repo.git").as_deref(),
+            Some("owner/repo")
+        );
+        assert_eq!(github_repository("https://gitlab.com/owner/repo"), None);
+    }
+
+    /// Mock the lookup of open pull requests from BRANCH
+    async fn mock_open_pulls(server: &mut mockito::Server, body: &str) -> mockito::Mock {
+        server
+            .mock("GET", "/repos/owner/repo/pulls")
+            .match_query(mockito::Matcher::AllOf(vec![
+                mockito::Matcher::UrlEncoded("head".into(), format!("owner:{}", BRANCH)),
+                mockito::Matcher::UrlEncoded("state".into(), "open".into()),
+            ]))
+            .with_body(body)
+            .create_async()
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_open_pull_request() {
+        let mut server = mockito::Server::new_async().await;
+        mock_open_pulls(&mut server, "[]").await;
+        let create = server
+            .mock("POST", "/repos/owner/repo/pulls")
+            .match_header("authorization", "Bearer token")
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "head": BRANCH,
+                "base": "main",
+                "draft": true,
+            })))
+            .with_status(201)
+            .with_body(r#"{"number": 1, "html_url": "https://github.com/owner/repo/pull/1"}"#)
+            .create_async()
+            .await;
+        let label = server
+            .mock("POST", "/repos/owner/repo/issues/1/labels")
+            .match_body(mockito::Matcher::Json(json!({"labels": ["security"]})))
+            .with_body("[]")
+            .create_async()
+            .await;
+
+        let mut pr = pull_request();
+        pr.draft = true;
+        pr.labels = vec!["security".to_string()];
+        let url = open_pull_request(&server.url(), "owner/repo", "token", &pr)
+            .await
+            .unwrap();
+
+        assert_eq!(url, "https://github.com/owner/repo/pull/1");
+        create.assert_async().await;
+        label.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_open_pull_request_updates_existing() {
+        let mut server = mockito::Server::new_async().await;
+        mock_open_pulls(
+            &mut server,
+            r#"[{"number": 7, "html_url": "https://github.com/owner/repo/pull/7"}]"#,
+        )
+        .await;
+        let update = server
+            .mock("PATCH", "/repos/owner/repo/pulls/7")
+            .match_body(mockito::Matcher::PartialJson(json!({"base": "main"})))
+            .with_body(r#"{"number": 7, "html_url": "https://github.com/owner/repo/pull/7"}"#)
+            .create_async()
+            .await;
+        let create = server
+            .mock("POST", "/repos/owner/repo/pulls")
+            .expect(0)
+            .create_async()
+            .await;
+
+        let url = open_pull_request(&server.url(), "owner/repo", "token", &pull_request())
+            .await
+            .unwrap();
+
+        assert_eq!(url, "https://github.com/owner/repo/pull/7");
+        update.assert_async().await;
+        create.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_open_pull_request_errors() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/repos/owner/repo/pulls")
+            .match_query(mockito::Matcher::Any)
+            .with_status(403)
+            .with_body(r#"{"message": "Resource not accessible by personal access token"}"#)
+            .create_async()
+            .await;
+        let error = open_pull_request(&server.url(), "owner/repo", "token", &pull_request())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<PrError>(),
+            Some(PrError::InsufficientScopes { .. })
+        ));
+
+        let mut server = mockito::Server::new_async().await;
+        mock_open_pulls(&mut server, "[]").await;
+        server
+            .mock("POST", "/repos/owner/repo/pulls")
+            .with_status(422)
+            .with_body(
+                r#"{"message": "Validation Failed", "errors": [{"message": "A pull request already exists for owner:pin-actions/update-2024-05-01."}]}"#,
+            )
+            .create_async()
+            .await;
+        let error = open_pull_request(&server.url(), "owner/repo", "token", &pull_request())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<PrError>(),
+            Some(PrError::AlreadyExists { .. })
+        ));
+    }
+}
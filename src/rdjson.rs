@@ -0,0 +1,531 @@
+//! reviewdog's rdjson format: one diagnostic per finding, with a suggested
+//! fix where the replacement is known, for posting as inline review
+//! comments
+
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    check::UnpinnedResults,
+    drift::DriftResults,
+    strict_sha::StrictShaResults,
+    workflow::{ProcessResults, RemainingReason},
+};
+
+/// Name diagnostics are reported under
+const SOURCE_NAME: &str = "pin-actions";
+
+/// Length of a full commit SHA, as replaced by suggestions
+const SHA_LEN: usize = 40;
+
+/// A run's diagnostics, the document reviewdog reads with `-f=rdjson`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiagnosticResult {
+    pub source: Source,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// The tool that reported a diagnostic
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Source {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+/// One finding at a place in a file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub message: String,
+    pub location: Location,
+    pub severity: Severity,
+    pub code: Code,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub suggestions: Vec<Suggestion>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Location {
+    pub path: String,
+    pub range: Range,
+}
+
+/// Span of text; `end` is exclusive, and left out for a bare position
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Range {
+    pub start: Position,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<Position>,
+}
+
+/// 1-based line and byte column; a column of None means the whole line
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Position {
+    pub line: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+}
+
+/// Kind of finding, e.g. "unpinned"
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Code {
+    pub value: String,
+}
+
+/// Text to put in place of a range to fix the finding
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Suggestion {
+    pub range: Range,
+    pub text: String,
+}
+
+impl Range {
+    /// A position on `line`, at `column` when known (0 is unknown)
+    fn at(line: usize, column: usize) -> Self {
+        Self {
+            start: Position {
+                line,
+                column: (column > 0).then_some(column),
+            },
+            end: None,
+        }
+    }
+
+    /// `len` bytes on `line` from `column`
+    fn span(line: usize, column: usize, len: usize) -> Self {
+        Self {
+            start: Position {
+                line,
+                column: Some(column),
+            },
+            end: Some(Position {
+                line,
+                column: Some(column + len),
+            }),
+        }
+    }
+}
+
+impl Diagnostic {
+    fn new(path: &str, range: Range, severity: Severity, code: &str, message: String) -> Self {
+        Self {
+            message,
+            location: Location {
+                path: path.to_string(),
+                range,
+            },
+            severity,
+            code: Code {
+                value: code.to_string(),
+            },
+            suggestions: Vec::new(),
+        }
+    }
+
+    fn with_suggestion(mut self, range: Range, text: String) -> Self {
+        self.suggestions.push(Suggestion { range, text });
+        self
+    }
+}
+
+impl DiagnosticResult {
+    fn new(diagnostics: Vec<Diagnostic>) -> Self {
+        Self {
+            source: Source {
+                name: SOURCE_NAME.to_string(),
+            },
+            diagnostics,
+        }
+    }
+}
+
+/// A dry run's pins as findings, each suggesting its rewritten line, plus
+/// the uses it couldn't pin. A SHA pin only gaining a comment isn't a
+/// finding. Of several pins on one line only the first carries the line's
+/// suggestion, as overlapping suggestions would conflict
+pub fn from_pin(results: &ProcessResults) -> DiagnosticResult {
+    let mut suggested = HashSet::new();
+    let mut diagnostics: Vec<Diagnostic> = results
+        .pinned_actions
+        .iter()
+        .filter(|pinned| pinned.previous_sha.as_ref() != Some(&pinned.sha))
+        .map(|pinned| {
+            let message = if pinned.was_unversioned {
+                format!(
+                    "{} follows its default branch; pin it to commit {}",
+                    pinned.action, pinned.sha
+                )
+            } else {
+                format!(
+                    "{}@{} is not pinned to a commit SHA; pin it to {}",
+                    pinned.action, pinned.old_ref, pinned.sha
+                )
+            };
+            let diagnostic = Diagnostic::new(
+                &pinned.file,
+                Range::at(pinned.line, pinned.column),
+                Severity::Warning,
+                "unpinned",
+                message,
+            );
+            match results
+                .planned_changes
+                .iter()
+                .find(|change| change.file == pinned.file && change.line == pinned.line)
+                .filter(|change| suggested.insert((&change.file, change.line)))
+            {
+                Some(change) => diagnostic.with_suggestion(
+                    Range::span(change.line, 1, change.before.len()),
+                    change.after.clone(),
+                ),
+                None => diagnostic,
+            }
+        })
+        .collect();
+
+    diagnostics.extend(
+        results
+            .remaining_unpinned
+            .iter()
+            .filter(|remaining| remaining.reason != RemainingReason::Excluded)
+            .map(|remaining| {
+                let action = match remaining.reference.as_str() {
+                    "" => remaining.action.clone(),
+                    reference => format!("{}@{}", remaining.action, reference),
+                };
+                let severity = match remaining.reason {
+                    RemainingReason::ResolutionFailed => Severity::Error,
+                    _ => Severity::Warning,
+                };
+                Diagnostic::new(
+                    &remaining.file,
+                    Range::at(remaining.line, 0),
+                    severity,
+                    "unpinned",
+                    format!(
+                        "{} is not pinned to a commit SHA and couldn't be: {}",
+                        action, remaining.detail
+                    ),
+                )
+            }),
+    );
+
+    DiagnosticResult::new(diagnostics)
+}
+
+/// Every use not pinned to a SHA, without fixes since nothing was resolved
+pub fn from_unpinned(results: &UnpinnedResults) -> DiagnosticResult {
+    DiagnosticResult::new(
+        results
+            .unpinned_actions
+            .iter()
+            .map(|unpinned| {
+                let message = match &unpinned.reference {
+                    Some(reference) => format!(
+                        "{}@{} is not pinned to a commit SHA",
+                        unpinned.action, reference
+                    ),
+                    None => format!(
+                        "{} follows its default branch rather than a commit SHA",
+                        unpinned.action
+                    ),
+                };
+                Diagnostic::new(
+                    &unpinned.file,
+                    Range::at(unpinned.line, unpinned.column),
+                    Severity::Warning,
+                    "unpinned",
+                    message,
+                )
+            })
+            .collect(),
+    )
+}
+
+/// Outdated pins and pins that aren't full commit SHAs. Outdated pins and
+/// SHA-named refs suggest the commit to pin instead, in place of the SHA
+pub fn from_audit(outdated: &DriftResults, short_shas: &StrictShaResults) -> DiagnosticResult {
+    let mut diagnostics: Vec<Diagnostic> = outdated
+        .drifted_actions
+        .iter()
+        .map(|drifted| {
+            let message = format!(
+                "{} is pinned to {} but {} now points at {}",
+                drifted.action, drifted.pinned_sha, drifted.comment_ref, drifted.current_sha
+            );
+            let diagnostic = Diagnostic::new(
+                &drifted.file,
+                Range::at(drifted.line, drifted.column),
+                Severity::Warning,
+                "outdated",
+                message,
+            );
+            // The SHA follows "owner/repo@"
+            match drifted.column {
+                0 => diagnostic,
+                column => diagnostic.with_suggestion(
+                    Range::span(drifted.line, column + drifted.action.len() + 1, SHA_LEN),
+                    drifted.current_sha.clone(),
+                ),
+            }
+        })
+        .collect();
+
+    diagnostics.extend(short_shas.violations.iter().map(|violation| {
+        Diagnostic::new(
+            &violation.file,
+            Range::at(violation.line, violation.column),
+            Severity::Warning,
+            "short-sha",
+            format!(
+                "{}@{} is an abbreviated SHA; pin the full 40-character commit SHA",
+                violation.action, violation.reference
+            ),
+        )
+    }));
+
+    diagnostics.extend(short_shas.sha_collisions.iter().map(|collision| {
+        let diagnostic = Diagnostic::new(
+            &collision.file,
+            Range::at(collision.line, collision.column),
+            Severity::Error,
+            "sha-ref-collision",
+            format!(
+                "{}@{} is a {} name, not a commit; it points at {}",
+                collision.action, collision.reference, collision.kind, collision.commit
+            ),
+        );
+        match collision.column {
+            0 => diagnostic,
+            column => diagnostic.with_suggestion(
+                Range::span(
+                    collision.line,
+                    column + collision.action.len() + 1,
+                    collision.reference.len(),
+                ),
+                collision.commit.clone(),
+            ),
+        }
+    }));
+
+    DiagnosticResult::new(diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{
+        action::RefKind,
+        check::UnpinnedAction,
+        drift::DriftedAction,
+        strict_sha::{ShaRefCollision, ShaViolation},
+        workflow::{PinnedActionResult, PlannedChange},
+    };
+
+    const SHA: &str = "b4ffde65f46336ab88eb53be808477a3936bae11";
+    const NEW_SHA: &str = "11bd71901bbe5b1630ceea73d27597364c9af683";
+
+    /// reviewdog's schema for rdjson documents, from its
+    /// proto/rdf/jsonschema/DiagnosticResult.jsonschema
+    const SCHEMA: &str = include_str!("../tests/fixtures/reviewdog/DiagnosticResult.jsonschema");
+
+    /// Check a document against reviewdog's DiagnosticResult schema, closed
+    /// to keys it doesn't define so a misspelt one can't slip through
+    fn validate(document: &Value) {
+        let mut schema: Value = serde_json::from_str(SCHEMA).unwrap();
+        for definition in schema["definitions"].as_object_mut().unwrap().values_mut() {
+            definition["additionalProperties"] = Value::Bool(false);
+        }
+        let validator = jsonschema::draft4::new(&schema).unwrap();
+        let errors: Vec<String> = validator
+            .iter_errors(document)
+            .map(|e| format!("{} at {}", e, e.instance_path()))
+            .collect();
+        assert!(errors.is_empty(), "not valid rdjson: {:?}", errors);
+    }
+
+    fn pinned(line: usize, old_ref: &str, previous_sha: Option<&str>) -> PinnedActionResult {
+        PinnedActionResult {
+            file: "ci.yml".to_string(),
+            line,
+            column: 15,
+            action: "actions/checkout".to_string(),
+            old_ref: old_ref.to_string(),
+            sha: SHA.to_string(),
+            previous_sha: previous_sha.map(str::to_string),
+            applied: false,
+            was_unversioned: false,
+            on_branch: false,
+            signature: None,
+            link: String::new(),
+            source: None,
+        }
+    }
+
+    #[test]
+    fn test_from_pin() {
+        let before = "      - uses: actions/checkout@v4";
+        let results = ProcessResults {
+            pinned_actions: vec![pinned(3, "v4", None), pinned(4, SHA, Some(SHA))],
+            planned_changes: vec![PlannedChange {
+                file: "ci.yml".to_string(),
+                line: 3,
+                before: before.to_string(),
+                after: format!("      - uses: actions/checkout@{} # v4", SHA),
+            }],
+            ..Default::default()
+        };
+
+        let document = from_pin(&results);
+        validate(&serde_json::to_value(&document).unwrap());
+
+        // The annotated pin isn't a finding
+        let [diagnostic] = document.diagnostics.as_slice() else {
+            panic!("expected one diagnostic: {:?}", document.diagnostics);
+        };
+        assert_eq!(diagnostic.location.range, Range::at(3, 15));
+        assert_eq!(diagnostic.severity, Severity::Warning);
+        assert_eq!(
+            diagnostic.suggestions,
+            vec![Suggestion {
+                range: Range::span(3, 1, before.len()),
+                text: results.planned_changes[0].after.clone(),
+            }]
+        );
+
+        // Two pins on one flow-style line share one rewritten line, which is
+        // suggested once
+        let before = "      - { uses: actions/checkout@v4, with: { action: actions/checkout@v4 } }";
+        let mut second = pinned(3, "v4", None);
+        second.column = 53;
+        let results = ProcessResults {
+            pinned_actions: vec![pinned(3, "v4", None), second],
+            planned_changes: vec![PlannedChange {
+                file: "ci.yml".to_string(),
+                line: 3,
+                before: before.to_string(),
+                after: before.replace("@v4", &format!("@{}", SHA)),
+            }],
+            ..Default::default()
+        };
+        let document = from_pin(&results);
+        validate(&serde_json::to_value(&document).unwrap());
+        let suggestions: Vec<usize> = document
+            .diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.suggestions.len())
+            .collect();
+        assert_eq!(suggestions, vec![1, 0]);
+    }
+
+    #[test]
+    fn test_from_unpinned() {
+        let results = UnpinnedResults {
+            unpinned_actions: vec![
+                UnpinnedAction {
+                    file: "ci.yml".to_string(),
+                    line: 2,
+                    column: 11,
+                    action: "actions/checkout".to_string(),
+                    reference: Some("v4".to_string()),
+                },
+                UnpinnedAction {
+                    file: "ci.yml".to_string(),
+                    line: 3,
+                    column: 11,
+                    action: "owner/moving".to_string(),
+                    reference: None,
+                },
+            ],
+            ..Default::default()
+        };
+
+        let document = from_unpinned(&results);
+        let json = serde_json::to_value(&document).unwrap();
+        validate(&json);
+        assert_eq!(json["source"]["name"], "pin-actions");
+        assert_eq!(
+            json["diagnostics"][0]["location"],
+            serde_json::json!({
+                "path": "ci.yml",
+                "range": {"start": {"line": 2, "column": 11}}
+            })
+        );
+        assert_eq!(
+            json["diagnostics"][1]["message"],
+            "owner/moving follows its default branch rather than a commit SHA"
+        );
+        assert!(json["diagnostics"][0].get("suggestions").is_none());
+    }
+
+    #[test]
+    fn test_from_audit() {
+        // "  - uses: actions/checkout@<sha> # v4"
+        let outdated = DriftResults {
+            drifted_actions: vec![DriftedAction {
+                file: "ci.yml".to_string(),
+                line: 2,
+                column: 11,
+                action: "actions/checkout".to_string(),
+                comment_ref: "v4".to_string(),
+                pinned_sha: SHA.to_string(),
+                current_sha: NEW_SHA.to_string(),
+                newer_tag: None,
+            }],
+            ..Default::default()
+        };
+        let short_shas = StrictShaResults {
+            violations: vec![ShaViolation {
+                file: "ci.yml".to_string(),
+                line: 3,
+                column: 11,
+                action: "actions/checkout".to_string(),
+                reference: "b4ffde6".to_string(),
+            }],
+            sha_collisions: vec![ShaRefCollision {
+                file: "ci.yml".to_string(),
+                line: 4,
+                column: 11,
+                action: "owner/repo".to_string(),
+                reference: SHA.to_string(),
+                kind: RefKind::Tag,
+                commit: NEW_SHA.to_string(),
+            }],
+            ..Default::default()
+        };
+
+        let document = from_audit(&outdated, &short_shas);
+        validate(&serde_json::to_value(&document).unwrap());
+
+        let codes: Vec<&str> = document
+            .diagnostics
+            .iter()
+            .map(|d| d.code.value.as_str())
+            .collect();
+        assert_eq!(codes, vec!["outdated", "short-sha", "sha-ref-collision"]);
+
+        // Each suggestion replaces exactly the 40-hex reference
+        let line = format!("  - uses: actions/checkout@{} # v4", SHA);
+        let suggestion = &document.diagnostics[0].suggestions[0];
+        let start = suggestion.range.start.column.unwrap() - 1;
+        let end = suggestion.range.end.as_ref().unwrap().column.unwrap() - 1;
+        assert_eq!(&line[start..end], SHA);
+        assert_eq!(suggestion.text, NEW_SHA);
+
+        let line = format!("  - uses: owner/repo@{}", SHA);
+        let suggestion = &document.diagnostics[2].suggestions[0];
+        let start = suggestion.range.start.column.unwrap() - 1;
+        let end = suggestion.range.end.as_ref().unwrap().column.unwrap() - 1;
+        assert_eq!(&line[start..end], SHA);
+        assert_eq!(document.diagnostics[2].severity, Severity::Error);
+    }
+}
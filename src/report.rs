@@ -0,0 +1,238 @@
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{Context, Result};
+use serde_json::{Map, Value};
+
+/// Version of the layout of `--summary-only` documents, raised when a
+/// counter is renamed or removed
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The document `--format` selects, printed to stdout or collected for an
+/// `--output` file that is replaced in one go once the document is complete
+#[derive(Debug)]
+pub struct Report {
+    /// File the document goes to; stdout when None
+    path: Option<PathBuf>,
+    /// The document so far, when it goes to a file
+    buffer: Mutex<String>,
+}
+
+/// The file a document for `--output path` goes to; None for stdout, which
+/// "-" also names
+pub fn output_file(path: Option<&Path>) -> Option<&Path> {
+    path.filter(|path| path.as_os_str() != "-")
+}
+
+impl Report {
+    /// Send the document to `path`, or to stdout for none or "-"
+    pub fn new(path: Option<&Path>) -> Self {
+        Self {
+            path: output_file(path).map(Path::to_path_buf),
+            buffer: Mutex::new(String::new()),
+        }
+    }
+
+    /// The file the document goes to, if not stdout
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Add a line to the document: printed straight away to stdout, so
+    /// streamed formats stay streamed, or held until [`Report::finish`]
+    pub fn writeln(&self, line: &str) {
+        if self.path.is_some() {
+            let mut buffer = self.buffer.lock().unwrap();
+            buffer.push_str(line);
+            buffer.push('\n');
+        } else {
+            println!("{}", line);
+        }
+    }
+
+    /// Write the document held so far to the file, if there is one
+    pub fn finish(&self) -> Result<()> {
+        match &self.path {
+            Some(path) => write_atomically(path, self.buffer.lock().unwrap().as_bytes()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The counters of a results document, after `schema_version`: every
+/// field but the per-item arrays, at any depth, so a document made of
+/// several results (such as `audit`'s) keeps only their counters too. A
+/// document that is itself a list has no counters of its own
+pub fn summarize(document: &Value) -> Map<String, Value> {
+    let mut summary = Map::new();
+    summary.insert("schema_version".to_string(), SCHEMA_VERSION.into());
+    if let Some(fields) = document.as_object() {
+        summary.extend(counters(fields));
+    }
+    summary
+}
+
+/// `fields` without its arrays, recursing into nested objects
+fn counters(fields: &Map<String, Value>) -> Map<String, Value> {
+    fields
+        .iter()
+        .filter(|(_, value)| !value.is_array())
+        .map(|(key, value)| {
+            let value = match value {
+                Value::Object(nested) => Value::Object(counters(nested)),
+                value => value.clone(),
+            };
+            (key.clone(), value)
+        })
+        .collect()
+}
+
+/// Replace `path` with `contents` through a temporary file beside it, so
+/// readers see the old file or the new one but never half of it. Missing
+/// parent directories are created
+pub fn write_atomically(path: &Path, mut contents: &[u8]) -> Result<()> {
+    copy_atomically(path, &mut contents)
+}
+
+/// Like [`write_atomically`], but streaming the new content from `reader`.
+/// A file being replaced keeps its permissions, and a symlink stays one: the
+/// file it points at is replaced instead
+pub fn copy_atomically(path: &Path, reader: &mut dyn Read) -> Result<()> {
+    let target = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let path = target.as_path();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+
+    let mut temp = tempfile::NamedTempFile::new_in(dir)
+        .with_context(|| format!("Failed to create a temporary file in {}", dir.display()))?;
+    io::copy(reader, &mut temp).with_context(|| format!("Failed to write {}", path.display()))?;
+    if let Ok(metadata) = fs::metadata(path) {
+        temp.as_file()
+            .set_permissions(metadata.permissions())
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    temp.persist(path)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_to_file() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join("reports/ci/pin.json");
+
+        let report = Report::new(Some(&path));
+        report.writeln("{");
+        report.writeln("}");
+        // Nothing lands until the document is complete
+        assert!(!path.exists());
+
+        report.finish().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\n}\n");
+        // The temporary file became the report
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_atomically_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join("ci.yml");
+        fs::write(&path, "old\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+
+        copy_atomically(&path, &mut "new\n".as_bytes()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+        assert_eq!(fs::read_dir(temp.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_copy_atomically_through_symlink() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let shared = temp.path().join("shared");
+        fs::create_dir(&shared).unwrap();
+        let target = shared.join("ci.yml");
+        fs::write(&target, "old\n").unwrap();
+        let workflows = temp.path().join("workflows");
+        fs::create_dir(&workflows).unwrap();
+        let link = workflows.join("ci.yml");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        copy_atomically(&link, &mut "new\n".as_bytes()).unwrap();
+        assert!(
+            fs::symlink_metadata(&link)
+                .unwrap()
+                .file_type()
+                .is_symlink()
+        );
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new\n");
+        // The temporary file was made beside the target, not the link
+        assert_eq!(fs::read_dir(&workflows).unwrap().count(), 1);
+        assert_eq!(fs::read_dir(&shared).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_summarize() {
+        let document = serde_json::json!({
+            "files_processed": 2,
+            "pinned_actions": [{"action": "actions/checkout"}],
+            "files_by_kind": {"workflow": 2},
+            "policy_checks": null,
+        });
+        assert_eq!(
+            Value::Object(summarize(&document)),
+            serde_json::json!({
+                "schema_version": SCHEMA_VERSION,
+                "files_processed": 2,
+                "files_by_kind": {"workflow": 2},
+                "policy_checks": null,
+            })
+        );
+
+        assert_eq!(
+            Value::Object(summarize(&serde_json::json!([1, 2]))),
+            serde_json::json!({"schema_version": SCHEMA_VERSION})
+        );
+
+        // Results nested in a document lose their arrays too
+        let audit = serde_json::json!({
+            "outdated": {"pins_checked": 3, "drifted_actions": [{"line": 2}]},
+            "short_shas": {"violations": [{"line": 4}], "errors": 0},
+        });
+        assert_eq!(
+            Value::Object(summarize(&audit)),
+            serde_json::json!({
+                "schema_version": SCHEMA_VERSION,
+                "outdated": {"pins_checked": 3},
+                "short_shas": {"errors": 0},
+            })
+        );
+    }
+
+    #[test]
+    fn test_report_to_stdout() {
+        assert_eq!(Report::new(None).path(), None);
+        assert_eq!(Report::new(Some(Path::new("-"))).path(), None);
+        assert_eq!(
+            Report::new(Some(Path::new("out.json"))).path(),
+            Some(Path::new("out.json"))
+        );
+    }
+}
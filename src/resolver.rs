@@ -0,0 +1,710 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs,
+    path::Path,
+    sync::{
+        Arc, Mutex, OnceLock,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::{
+    action::{ActionRef, RefKind},
+    error::RefNotFound,
+};
+
+/// Where a resolution was answered from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    /// An earlier resolution in the run, or a primed cache entry
+    Cache,
+    /// A local clone under the mirror directory
+    Mirror,
+    /// The repository's refs, listed over git
+    Remote,
+    /// A batched GitHub GraphQL query
+    Graphql,
+    /// A ref lookup through the GitHub REST API
+    Api,
+    /// A resolution map given up front
+    Fixed,
+    /// A SHA mandated by the config file's `[overrides]` table
+    Override,
+}
+
+impl Source {
+    /// Whether the answer took a trip over the network: git, GraphQL or the
+    /// REST API
+    pub fn is_network(self) -> bool {
+        matches!(self, Source::Remote | Source::Graphql | Source::Api)
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Cache => write!(f, "cache"),
+            Source::Mirror => write!(f, "mirror"),
+            Source::Remote => write!(f, "remote"),
+            Source::Graphql => write!(f, "graphql"),
+            Source::Api => write!(f, "api"),
+            Source::Fixed => write!(f, "fixed"),
+            Source::Override => write!(f, "override"),
+        }
+    }
+}
+
+/// A ref resolved to the commit it points at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resolution {
+    pub sha: String,
+    /// Whether the ref named a tag or a branch; None for abbreviated SHAs
+    /// and primed cache entries, where that isn't known
+    pub ref_type: Option<RefKind>,
+    pub source: Source,
+}
+
+impl Resolution {
+    pub fn new(sha: String, ref_type: Option<RefKind>, source: Source) -> Self {
+        Self {
+            sha,
+            ref_type,
+            source,
+        }
+    }
+}
+
+/// A backend resolving action refs to commit SHAs. The processor holds one
+/// as `Arc<dyn Resolver>`, so `--resolver` (or a test) picks the backend
+pub trait Resolver: Send + Sync {
+    /// Resolve one action's ref
+    fn resolve<'a>(&'a self, action: &'a ActionRef) -> BoxFuture<'a, Result<Resolution>>;
+
+    /// Resolve many actions, up to `concurrency` at once, in any order.
+    /// Backends that can batch lookups override this
+    fn resolve_all<'a>(
+        &'a self,
+        actions: Vec<ActionRef>,
+        concurrency: usize,
+    ) -> BoxFuture<'a, Vec<(ActionRef, Result<Resolution>)>> {
+        Box::pin(resolve_concurrently(self, actions, concurrency))
+    }
+
+    /// Seed the backend's cache with SHAs keyed by "owner/repo@ref", for
+    /// backends that have one
+    fn prime(&self, _entries: HashMap<String, String>) {}
+
+    /// Everything the backend has cached, keyed by "owner/repo@ref"
+    fn snapshot(&self) -> HashMap<String, String> {
+        HashMap::new()
+    }
+
+    /// Whether each repository looked up is archived, for backends that
+    /// learn it while resolving
+    fn archived(&self) -> BTreeMap<String, bool> {
+        BTreeMap::new()
+    }
+
+    /// The default branch of each repository looked up, by "owner/repo",
+    /// for backends that learn it while resolving
+    fn default_branches(&self) -> BTreeMap<String, String> {
+        BTreeMap::new()
+    }
+
+    /// Whether `resolve_all` batches lookups rather than resolving them one
+    /// at a time
+    fn batches(&self) -> bool {
+        false
+    }
+
+    /// Lookups sent again through git after a request to another backend
+    /// failed
+    fn retries(&self) -> usize {
+        0
+    }
+}
+
+/// Resolve `actions` one [`Resolver::resolve`] call at a time, running up
+/// to `concurrency` of them at once
+pub async fn resolve_concurrently<R: Resolver + ?Sized>(
+    resolver: &R,
+    actions: Vec<ActionRef>,
+    concurrency: usize,
+) -> Vec<(ActionRef, Result<Resolution>)> {
+    use futures::stream::{self, StreamExt};
+
+    stream::iter(actions)
+        .map(|action| async move {
+            let result = resolver.resolve(&action).await;
+            (action, result)
+        })
+        .buffer_unordered(concurrency.max(1))
+        .collect()
+        .await
+}
+
+/// Resolve `actions` like [`resolve_concurrently`], but stop at the first
+/// failure: lookups still in flight are dropped (cancelling them) and those
+/// not started never are. The failure is the last entry returned
+pub async fn resolve_until_error<R: Resolver + ?Sized>(
+    resolver: &R,
+    actions: Vec<ActionRef>,
+    concurrency: usize,
+) -> Vec<(ActionRef, Result<Resolution>)> {
+    use futures::stream::{self, StreamExt};
+
+    let mut lookups = stream::iter(actions)
+        .map(|action| async move {
+            let result = resolver.resolve(&action).await;
+            (action, result)
+        })
+        .buffer_unordered(concurrency.max(1));
+
+    let mut results = Vec::new();
+    while let Some((action, result)) = lookups.next().await {
+        let failed = result.is_err();
+        results.push((action, result));
+        if failed {
+            break;
+        }
+    }
+    results
+}
+
+/// How a batch of lookups queued behind the concurrency limit, for tuning
+/// --jobs: lookups rarely waiting, or fewer in flight than allowed, mean
+/// more concurrency won't help; long lookups with little waiting point at
+/// the network rather than the limit
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Backpressure {
+    /// Lookups allowed at once
+    pub concurrency: usize,
+    pub lookups: usize,
+    /// Most lookups in flight at any moment
+    pub max_in_flight: usize,
+    /// Time lookups spent queued before starting, from the first one
+    pub queue_wait_mean_ms: f64,
+    pub queue_wait_max_ms: f64,
+    /// Time a lookup took once started
+    pub lookup_mean_ms: f64,
+}
+
+impl Backpressure {
+    /// Fold in the lookups of another batch, so a run resolving in several
+    /// batches reports all of them
+    pub fn merge(&mut self, other: &Backpressure) {
+        let lookups = self.lookups + other.lookups;
+        let mean = |ours: f64, theirs: f64| match lookups {
+            0 => 0.0,
+            n => (ours * self.lookups as f64 + theirs * other.lookups as f64) / n as f64,
+        };
+        self.queue_wait_mean_ms = mean(self.queue_wait_mean_ms, other.queue_wait_mean_ms);
+        self.lookup_mean_ms = mean(self.lookup_mean_ms, other.lookup_mean_ms);
+        self.queue_wait_max_ms = self.queue_wait_max_ms.max(other.queue_wait_max_ms);
+        self.max_in_flight = self.max_in_flight.max(other.max_in_flight);
+        self.concurrency = self.concurrency.max(other.concurrency);
+        self.lookups = lookups;
+    }
+}
+
+/// Wraps a resolver to record its [`Backpressure`] and how long each
+/// lookup took, with atomic counters around each one. Lookups go through
+/// [`Resolver::resolve`] one at a time, except that a batching backend
+/// keeps its own `resolve_all`, whose lookups go untimed
+pub struct MeteredResolver {
+    inner: Arc<dyn Resolver>,
+    /// When the first lookup started; every lookup is queued by then
+    started: OnceLock<Instant>,
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+    lookups: AtomicUsize,
+    queue_wait_us: AtomicU64,
+    max_queue_wait_us: AtomicU64,
+    lookup_us: AtomicU64,
+    /// Time each lookup answered over the network took once started;
+    /// cache hits would drag the percentiles down to nothing
+    latencies: Mutex<Vec<Duration>>,
+}
+
+impl MeteredResolver {
+    pub fn new(inner: Arc<dyn Resolver>) -> Self {
+        Self {
+            inner,
+            started: OnceLock::new(),
+            in_flight: AtomicUsize::new(0),
+            max_in_flight: AtomicUsize::new(0),
+            lookups: AtomicUsize::new(0),
+            queue_wait_us: AtomicU64::new(0),
+            max_queue_wait_us: AtomicU64::new(0),
+            lookup_us: AtomicU64::new(0),
+            latencies: Mutex::default(),
+        }
+    }
+
+    /// How long each timed lookup answered over the network took, in the
+    /// order they finished
+    pub fn latencies(&self) -> Vec<Duration> {
+        self.latencies.lock().unwrap().clone()
+    }
+
+    /// What was recorded so far, for lookups run `concurrency` at a time
+    pub fn backpressure(&self, concurrency: usize) -> Backpressure {
+        let lookups = self.lookups.load(Ordering::SeqCst);
+        let mean_ms = |total: &AtomicU64| match lookups {
+            0 => 0.0,
+            n => total.load(Ordering::SeqCst) as f64 / n as f64 / 1000.0,
+        };
+        Backpressure {
+            concurrency,
+            lookups,
+            max_in_flight: self.max_in_flight.load(Ordering::SeqCst),
+            queue_wait_mean_ms: mean_ms(&self.queue_wait_us),
+            queue_wait_max_ms: self.max_queue_wait_us.load(Ordering::SeqCst) as f64 / 1000.0,
+            lookup_mean_ms: mean_ms(&self.lookup_us),
+        }
+    }
+}
+
+impl Resolver for MeteredResolver {
+    fn resolve<'a>(&'a self, action: &'a ActionRef) -> BoxFuture<'a, Result<Resolution>> {
+        Box::pin(async move {
+            let waited = self.started.get_or_init(Instant::now).elapsed();
+            let waited = waited.as_micros() as u64;
+            self.queue_wait_us.fetch_add(waited, Ordering::SeqCst);
+            self.max_queue_wait_us.fetch_max(waited, Ordering::SeqCst);
+
+            let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(now, Ordering::SeqCst);
+            let start = Instant::now();
+            let result = self.inner.resolve(action).await;
+            let elapsed = start.elapsed();
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            self.lookup_us
+                .fetch_add(elapsed.as_micros() as u64, Ordering::SeqCst);
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            if matches!(&result, Ok(resolution) if resolution.source.is_network()) {
+                self.latencies.lock().unwrap().push(elapsed);
+            }
+            result
+        })
+    }
+
+    fn resolve_all<'a>(
+        &'a self,
+        actions: Vec<ActionRef>,
+        concurrency: usize,
+    ) -> BoxFuture<'a, Vec<(ActionRef, Result<Resolution>)>> {
+        if self.inner.batches() {
+            self.inner.resolve_all(actions, concurrency)
+        } else {
+            Box::pin(resolve_concurrently(self, actions, concurrency))
+        }
+    }
+
+    fn prime(&self, entries: HashMap<String, String>) {
+        self.inner.prime(entries);
+    }
+
+    fn snapshot(&self) -> HashMap<String, String> {
+        self.inner.snapshot()
+    }
+
+    fn archived(&self) -> BTreeMap<String, bool> {
+        self.inner.archived()
+    }
+
+    fn default_branches(&self) -> BTreeMap<String, String> {
+        self.inner.default_branches()
+    }
+
+    fn batches(&self) -> bool {
+        self.inner.batches()
+    }
+
+    fn retries(&self) -> usize {
+        self.inner.retries()
+    }
+}
+
+/// Resolver answering only from a map of "owner/repo@ref" to SHA, for
+/// hermetic runs and for applying SHAs vetted elsewhere; it never touches
+/// the network and anything not in the map fails
+#[derive(Debug, Clone, Default)]
+pub struct FixedResolver {
+    map: HashMap<String, String>,
+}
+
+impl FixedResolver {
+    pub fn new(map: HashMap<String, String>) -> Self {
+        Self { map }
+    }
+
+    /// Read a JSON map of "owner/repo@ref" to full SHA, rejecting entries
+    /// that aren't 40 hex digits
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read resolution map: {}", path.display()))?;
+        let map: HashMap<String, String> = serde_json::from_str(&content)
+            .with_context(|| format!("Invalid resolution map: {}", path.display()))?;
+
+        if let Some((key, sha)) = map
+            .iter()
+            .find(|(_, sha)| sha.len() != 40 || !sha.chars().all(|c| c.is_ascii_hexdigit()))
+        {
+            anyhow::bail!(
+                "Invalid SHA '{}' for {} in resolution map {}",
+                sha,
+                key,
+                path.display()
+            );
+        }
+
+        Ok(Self::new(map))
+    }
+}
+
+impl Resolver for FixedResolver {
+    fn resolve<'a>(&'a self, action: &'a ActionRef) -> BoxFuture<'a, Result<Resolution>> {
+        Box::pin(async move {
+            let sha = self
+                .map
+                .get(&action.to_string())
+                .ok_or_else(|| RefNotFound(format!("{} not in map", action)))?;
+            Ok(Resolution::new(sha.clone(), action.ref_kind, Source::Fixed))
+        })
+    }
+}
+
+/// The SHA `overrides` mandates for `action`. Keys are "owner/repo@ref" or
+/// "owner/repo"; an entry for the exact ref wins over one for the repository
+pub fn override_for<'a>(
+    overrides: &'a BTreeMap<String, String>,
+    action: &ActionRef,
+) -> Option<&'a String> {
+    overrides
+        .get(&action.to_string())
+        .or_else(|| overrides.get(&action.repository))
+        .or_else(|| overrides.get(action.base_repository()))
+}
+
+/// Resolver answering with the configured override SHA for actions that
+/// have one. The wrapped resolver still runs for them so that a natural
+/// resolution differing from the override can be warned about
+pub struct OverrideResolver {
+    inner: Arc<dyn Resolver>,
+    overrides: BTreeMap<String, String>,
+}
+
+impl OverrideResolver {
+    pub fn new(inner: Arc<dyn Resolver>, overrides: BTreeMap<String, String>) -> Self {
+        Self { inner, overrides }
+    }
+
+    /// Replace the natural resolution of `action` with its override, if any
+    fn apply(&self, action: &ActionRef, natural: Result<Resolution>) -> Result<Resolution> {
+        let Some(sha) = override_for(&self.overrides, action) else {
+            return natural;
+        };
+
+        let ref_type = match &natural {
+            Ok(resolution) => {
+                if !resolution.sha.eq_ignore_ascii_case(sha) {
+                    warn!(
+                        action = %action,
+                        "{} resolves to {}, but the config overrides it to {}",
+                        action,
+                        resolution.sha,
+                        sha
+                    );
+                }
+                resolution.ref_type
+            },
+            Err(e) => {
+                debug!(
+                    "Using the override for {}, which didn't resolve: {:#}",
+                    action, e
+                );
+                None
+            },
+        };
+        Ok(Resolution::new(sha.clone(), ref_type, Source::Override))
+    }
+}
+
+impl Resolver for OverrideResolver {
+    fn resolve<'a>(&'a self, action: &'a ActionRef) -> BoxFuture<'a, Result<Resolution>> {
+        Box::pin(async move { self.apply(action, self.inner.resolve(action).await) })
+    }
+
+    fn resolve_all<'a>(
+        &'a self,
+        actions: Vec<ActionRef>,
+        concurrency: usize,
+    ) -> BoxFuture<'a, Vec<(ActionRef, Result<Resolution>)>> {
+        Box::pin(async move {
+            self.inner
+                .resolve_all(actions, concurrency)
+                .await
+                .into_iter()
+                .map(|(action, natural)| {
+                    let result = self.apply(&action, natural);
+                    (action, result)
+                })
+                .collect()
+        })
+    }
+
+    fn prime(&self, entries: HashMap<String, String>) {
+        self.inner.prime(entries);
+    }
+
+    fn snapshot(&self) -> HashMap<String, String> {
+        self.inner.snapshot()
+    }
+
+    fn archived(&self) -> BTreeMap<String, bool> {
+        self.inner.archived()
+    }
+
+    fn default_branches(&self) -> BTreeMap<String, String> {
+        self.inner.default_branches()
+    }
+
+    fn batches(&self) -> bool {
+        self.inner.batches()
+    }
+
+    fn retries(&self) -> usize {
+        self.inner.retries()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use super::*;
+
+    /// Resolves everything to the same SHA after a pause, recording how many
+    /// lookups were in flight at most
+    #[derive(Default)]
+    struct Slow {
+        in_flight: AtomicUsize,
+        most: AtomicUsize,
+    }
+
+    impl Resolver for Slow {
+        fn resolve<'a>(&'a self, action: &'a ActionRef) -> BoxFuture<'a, Result<Resolution>> {
+            Box::pin(async move {
+                let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                self.most.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                self.in_flight.fetch_sub(1, Ordering::SeqCst);
+                if action.reference == "missing" {
+                    anyhow::bail!("no such ref");
+                }
+                let source = match action.reference.as_str() {
+                    "cached" => Source::Cache,
+                    _ => Source::Remote,
+                };
+                Ok(Resolution::new("a".repeat(40), None, source))
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fixed_resolver() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join("pins.json");
+        fs::write(
+            &path,
+            r#"{"actions/checkout@v4": "b4ffde65f46336ab88eb53be808477a3936bae11"}"#,
+        )
+        .unwrap();
+        let resolver = FixedResolver::load(&path).unwrap();
+
+        let found = resolver
+            .resolve(&ActionRef::parse("actions/checkout@v4").unwrap())
+            .await
+            .unwrap();
+        assert_eq!(found.sha, "b4ffde65f46336ab88eb53be808477a3936bae11");
+        assert_eq!(found.source, Source::Fixed);
+
+        let missing = resolver
+            .resolve(&ActionRef::parse("actions/checkout@v3").unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(missing.to_string(), "actions/checkout@v3 not in map");
+        assert!(missing.downcast_ref::<RefNotFound>().is_some());
+
+        fs::write(&path, r#"{"actions/checkout@v4": "b4ffde65"}"#).unwrap();
+        let err = FixedResolver::load(&path).unwrap_err();
+        assert!(err.to_string().contains("Invalid SHA 'b4ffde65'"));
+    }
+
+    #[tokio::test]
+    async fn test_override_resolver() {
+        const NATURAL: &str = "b4ffde65f46336ab88eb53be808477a3936bae11";
+        const PINNED: &str = "11bd71901bbe5b1630ceea73d27597364c9af683";
+
+        let inner = Arc::new(FixedResolver::new(HashMap::from([
+            ("actions/checkout@v4".to_string(), NATURAL.to_string()),
+            ("actions/cache@v4".to_string(), NATURAL.to_string()),
+        ])));
+        let resolver = OverrideResolver::new(
+            inner,
+            BTreeMap::from([
+                ("actions/checkout".to_string(), "a".repeat(40)),
+                ("actions/checkout@v4".to_string(), PINNED.to_string()),
+                ("tj-actions/changed-files".to_string(), PINNED.to_string()),
+            ]),
+        );
+        let actions = [
+            "actions/checkout@v4",
+            "actions/cache@v4",
+            "tj-actions/changed-files@v45",
+        ]
+        .into_iter()
+        .map(|action| ActionRef::parse(action).unwrap())
+        .collect();
+
+        let mut results: Vec<(String, String, Source)> = resolver
+            .resolve_all(actions, 2)
+            .await
+            .into_iter()
+            .map(|(action, result)| {
+                let resolution = result.unwrap();
+                (action.to_string(), resolution.sha, resolution.source)
+            })
+            .collect();
+        results.sort_by(|a, b| a.0.cmp(&b.0));
+
+        assert_eq!(
+            results,
+            vec![
+                (
+                    "actions/cache@v4".to_string(),
+                    NATURAL.to_string(),
+                    Source::Fixed
+                ),
+                (
+                    "actions/checkout@v4".to_string(),
+                    PINNED.to_string(),
+                    Source::Override
+                ),
+                (
+                    "tj-actions/changed-files@v45".to_string(),
+                    PINNED.to_string(),
+                    Source::Override
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_all_bounds_concurrency() {
+        let resolver = Slow::default();
+        let actions: Vec<ActionRef> = (0..6)
+            .map(|i| ActionRef::parse(&format!("owner/repo{}@v1", i)).unwrap())
+            .chain(ActionRef::parse("owner/repo@missing"))
+            .collect();
+
+        let results = resolver.resolve_all(actions, 2).await;
+
+        assert_eq!(results.len(), 7);
+        assert_eq!(results.iter().filter(|(_, r)| r.is_err()).count(), 1);
+        assert_eq!(resolver.most.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_backpressure_merge() {
+        let mut total = Backpressure {
+            concurrency: 4,
+            lookups: 1,
+            max_in_flight: 1,
+            queue_wait_mean_ms: 0.0,
+            queue_wait_max_ms: 0.0,
+            lookup_mean_ms: 40.0,
+        };
+        total.merge(&Backpressure {
+            concurrency: 4,
+            lookups: 3,
+            max_in_flight: 3,
+            queue_wait_mean_ms: 20.0,
+            queue_wait_max_ms: 30.0,
+            lookup_mean_ms: 20.0,
+        });
+        assert_eq!(
+            total,
+            Backpressure {
+                concurrency: 4,
+                lookups: 4,
+                max_in_flight: 3,
+                queue_wait_mean_ms: 15.0,
+                queue_wait_max_ms: 30.0,
+                lookup_mean_ms: 25.0,
+            }
+        );
+
+        // Nothing looked up yet is no lookups at all
+        let mut empty = Backpressure::default();
+        empty.merge(&Backpressure::default());
+        assert_eq!(empty, Backpressure::default());
+    }
+
+    #[tokio::test]
+    async fn test_metered_resolver_backpressure() {
+        let slow = Arc::new(Slow::default());
+        let resolver = MeteredResolver::new(slow.clone());
+        let actions: Vec<ActionRef> = (0..8)
+            .map(|i| ActionRef::parse(&format!("owner/repo{}@v1", i)).unwrap())
+            .collect();
+
+        let results = resolver.resolve_all(actions.clone(), 3).await;
+        assert_eq!(results.len(), 8);
+
+        // The most in flight is the concurrency, and lookups beyond the
+        // first three had to wait for a slot
+        let backpressure = resolver.backpressure(3);
+        assert_eq!(backpressure.concurrency, 3);
+        assert_eq!(backpressure.lookups, 8);
+        assert_eq!(backpressure.max_in_flight, 3);
+        assert_eq!(backpressure.max_in_flight, slow.most.load(Ordering::SeqCst));
+        assert!(backpressure.queue_wait_max_ms >= 10.0);
+        assert!(backpressure.lookup_mean_ms >= 10.0);
+        assert_eq!(resolver.latencies().len(), 8);
+        assert!(
+            resolver
+                .latencies()
+                .iter()
+                .all(|latency| latency.as_millis() >= 10)
+        );
+
+        // Only lookups that went over the network have a latency
+        let resolver = MeteredResolver::new(slow.clone());
+        let mixed = vec![
+            ActionRef::parse("owner/repo@cached").unwrap(),
+            ActionRef::parse("owner/repo@missing").unwrap(),
+            ActionRef::parse("owner/repo@v1").unwrap(),
+        ];
+        resolver.resolve_all(mixed, 3).await;
+        assert_eq!(resolver.backpressure(3).lookups, 3);
+        assert_eq!(resolver.latencies().len(), 1);
+
+        // Stopping at the first error goes through the same stage
+        let resolver = MeteredResolver::new(slow);
+        resolve_until_error(&resolver, actions, 5).await;
+        assert_eq!(resolver.backpressure(5).max_in_flight, 5);
+    }
+}
@@ -0,0 +1,303 @@
+use std::fmt;
+
+use anyhow::{Context, Result};
+use colored::{ColoredString, Colorize};
+use reqwest::{StatusCode, header};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::http;
+
+/// Whether a tag carries a signature GitHub could verify
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureStatus {
+    /// A GPG or SSH signature GitHub reports as verified
+    Verified,
+    /// Signed, but GitHub couldn't verify the signature
+    Unverified,
+    /// A lightweight tag, or an annotated tag without a signature
+    Unsigned,
+}
+
+impl SignatureStatus {
+    /// Short marker shown next to a pin in text output
+    pub fn badge(&self) -> ColoredString {
+        match self {
+            SignatureStatus::Verified => "🔏 verified".green(),
+            SignatureStatus::Unverified => "⚠️  unverified".yellow(),
+            SignatureStatus::Unsigned => "unsigned".yellow(),
+        }
+    }
+}
+
+impl fmt::Display for SignatureStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureStatus::Verified => write!(f, "verified"),
+            SignatureStatus::Unverified => write!(f, "unverified"),
+            SignatureStatus::Unsigned => write!(f, "unsigned"),
+        }
+    }
+}
+
+/// Tags of tags followed before giving up on reaching a commit
+const MAX_TAG_DEPTH: usize = 8;
+
+/// A tag whose commit isn't the SHA being pinned, so its signature says
+/// nothing about the pin
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetMismatch {
+    pub tag: String,
+    /// What the tag peels to
+    pub target: String,
+    pub pinned: String,
+}
+
+impl fmt::Display for TargetMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "tag {} points at {}, not the pinned {}",
+            self.tag, self.target, self.pinned
+        )
+    }
+}
+
+impl std::error::Error for TargetMismatch {}
+
+/// Looks up tag objects and their signature verification through the
+/// GitHub REST API
+#[derive(Clone)]
+pub struct SignatureChecker {
+    client: reqwest::Client,
+    api_url: String,
+    token: Option<String>,
+}
+
+impl SignatureChecker {
+    pub fn new(api_url: String, token: Option<String>) -> Self {
+        Self {
+            client: http::default_client(),
+            api_url,
+            token,
+        }
+    }
+
+    /// Send requests through `client`, e.g. one from [`HttpOptions::client`]
+    ///
+    /// [`HttpOptions::client`]: crate::http::HttpOptions::client
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Check the signature of `tag` in "owner/repo", which is about to be
+    /// pinned to `sha`. Returns None when the repository has no such tag
+    /// (e.g. the ref is a branch), and fails with [`TargetMismatch`] when the
+    /// tag, peeled to its commit, isn't `sha`: a signature only vouches for
+    /// the commit it covers
+    pub async fn check_tag(
+        &self,
+        repository: &str,
+        tag: &str,
+        sha: &str,
+    ) -> Result<Option<SignatureStatus>> {
+        let Some(reference) = self
+            .get(&format!("repos/{}/git/ref/tags/{}", repository, tag))
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        // Lightweight tags point straight at a commit and can't be signed;
+        // annotated ones are peeled, possibly through tags of tags, with the
+        // outermost tag's signature being the one that counts
+        let mut status = SignatureStatus::Unsigned;
+        let mut object = reference["object"].clone();
+        for depth in 0..MAX_TAG_DEPTH {
+            if object["type"].as_str() != Some("tag") {
+                break;
+            }
+            let tag_sha = object["sha"]
+                .as_str()
+                .context("Tag reference has no object SHA")?;
+            let tag_object = self
+                .get(&format!("repos/{}/git/tags/{}", repository, tag_sha))
+                .await?
+                .with_context(|| format!("Tag object {} not found in {}", tag_sha, repository))?;
+            if depth == 0 {
+                status = parse_verification(&tag_object);
+            }
+            object = tag_object["object"].clone();
+        }
+
+        let target = object["sha"]
+            .as_str()
+            .with_context(|| format!("Tag {} of {} doesn't point at a commit", tag, repository))?;
+        if object["type"].as_str() != Some("commit") || !target.eq_ignore_ascii_case(sha) {
+            return Err(TargetMismatch {
+                tag: tag.to_string(),
+                target: target.to_string(),
+                pinned: sha.to_string(),
+            }
+            .into());
+        }
+
+        Ok(Some(status))
+    }
+
+    /// GET an API path, returning None on 404
+    async fn get(&self, path: &str) -> Result<Option<Value>> {
+        let url = format!("{}/{}", self.api_url, path);
+        let mut request = self
+            .client
+            .get(&url)
+            .header(header::ACCEPT, "application/vnd.github+json");
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach {}", url))?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let body = response
+            .error_for_status()
+            .with_context(|| format!("Request to {} was rejected", url))?
+            .json()
+            .await
+            .with_context(|| format!("Invalid response from {}", url))?;
+        Ok(Some(body))
+    }
+}
+
+/// Classify the `verification` block of an annotated tag object
+pub fn parse_verification(tag_object: &Value) -> SignatureStatus {
+    let verification = &tag_object["verification"];
+    if verification["verified"].as_bool() == Some(true) {
+        SignatureStatus::Verified
+    } else if verification["reason"].as_str() == Some("unsigned")
+        || verification["signature"].is_null()
+    {
+        SignatureStatus::Unsigned
+    } else {
+        SignatureStatus::Unverified
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TAG_SHA: &str = "9c8f5b2e0d1a3f4e5b6c7d8e9f0a1b2c3d4e5f60";
+    const COMMIT: &str = "b4ffde65f46336ab88eb53be808477a3936bae11";
+    const OTHER: &str = "11bd71901bbe5b1630ceea73d27597364c9af683";
+
+    #[test]
+    fn test_parse_verification() {
+        let verified = serde_json::json!({
+            "verification": {"verified": true, "reason": "valid", "signature": "-----BEGIN PGP SIGNATURE-----"}
+        });
+        assert_eq!(parse_verification(&verified), SignatureStatus::Verified);
+
+        let unverified = serde_json::json!({
+            "verification": {"verified": false, "reason": "unknown_key", "signature": "-----BEGIN PGP SIGNATURE-----"}
+        });
+        assert_eq!(parse_verification(&unverified), SignatureStatus::Unverified);
+
+        let unsigned = serde_json::json!({
+            "verification": {"verified": false, "reason": "unsigned", "signature": null}
+        });
+        assert_eq!(parse_verification(&unsigned), SignatureStatus::Unsigned);
+    }
+
+    #[tokio::test]
+    async fn test_check_signed_tag() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/repos/actions/checkout/git/ref/tags/v4")
+            .with_body(format!(
+                r#"{{"ref": "refs/tags/v4", "object": {{"type": "tag", "sha": "{}"}}}}"#,
+                TAG_SHA
+            ))
+            .create_async()
+            .await;
+        server
+            .mock(
+                "GET",
+                format!("/repos/actions/checkout/git/tags/{}", TAG_SHA).as_str(),
+            )
+            .with_body(format!(
+                r#"{{"object": {{"type": "commit", "sha": "{}"}}, "verification": {{"verified": true, "reason": "valid", "signature": "sig"}}}}"#,
+                COMMIT
+            ))
+            .create_async()
+            .await;
+
+        let checker = SignatureChecker::new(server.url(), None);
+        let status = checker
+            .check_tag("actions/checkout", "v4", COMMIT)
+            .await
+            .unwrap();
+        assert_eq!(status, Some(SignatureStatus::Verified));
+
+        // A verified signature on a tag of some other commit doesn't count
+        let error = checker
+            .check_tag("actions/checkout", "v4", OTHER)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<TargetMismatch>(),
+            Some(&TargetMismatch {
+                tag: "v4".to_string(),
+                target: COMMIT.to_string(),
+                pinned: OTHER.to_string(),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_lightweight_and_missing_tags() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/repos/actions/cache/git/ref/tags/v4")
+            .with_body(format!(
+                r#"{{"ref": "refs/tags/v4", "object": {{"type": "commit", "sha": "{}"}}}}"#,
+                COMMIT
+            ))
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/repos/actions/cache/git/ref/tags/main")
+            .with_status(404)
+            .create_async()
+            .await;
+
+        let checker = SignatureChecker::new(server.url(), None);
+        assert_eq!(
+            checker
+                .check_tag("actions/cache", "v4", COMMIT)
+                .await
+                .unwrap(),
+            Some(SignatureStatus::Unsigned)
+        );
+        assert!(
+            checker
+                .check_tag("actions/cache", "v4", OTHER)
+                .await
+                .is_err()
+        );
+        assert_eq!(
+            checker
+                .check_tag("actions/cache", "main", COMMIT)
+                .await
+                .unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::{
+    action::{ActionRef, PinnedAction, RefKind},
+    error::{ErrorCode, ErrorDetail},
+    parser::WorkflowFile,
+    resolver::Resolution,
+    workflow::{CollectedActions, FilePatch, PinnedActionResult, WorkflowProcessor, write_error},
+};
+
+/// Results from checking that every pin is a full-length commit SHA
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StrictShaResults {
+    pub files_processed: usize,
+    pub pins_checked: usize,
+    pub errors: usize,
+    #[serde(default)]
+    pub errors_detail: Vec<ErrorDetail>,
+    /// Pins using an abbreviated SHA, including any that were expanded
+    pub violations: Vec<ShaViolation>,
+    /// Short SHAs rewritten to full length (or that would be, under dry-run)
+    pub expanded: Vec<PinnedActionResult>,
+    /// Full-length pins naming a tag or branch, with --verify-shas
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sha_collisions: Vec<ShaRefCollision>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub patches: Vec<FilePatch>,
+}
+
+/// A pin using an abbreviated commit SHA
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShaViolation {
+    pub file: String,
+    pub line: usize,
+    /// 1-based byte column of the "owner/repo@ref" reference
+    #[serde(default)]
+    pub column: usize,
+    pub action: String,
+    pub reference: String,
+}
+
+/// A 40-hex pin that is really the name of a tag or branch, so can move
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShaRefCollision {
+    pub file: String,
+    pub line: usize,
+    /// 1-based byte column of the "owner/repo@ref" reference
+    #[serde(default)]
+    pub column: usize,
+    /// The repository, "owner/repo"
+    pub action: String,
+    /// The 40-hex reference as written
+    pub reference: String,
+    /// Whether a tag or a branch carries that name
+    pub kind: RefKind,
+    /// The commit the ref points at now
+    pub commit: String,
+}
+
+impl WorkflowProcessor {
+    /// Flag pins using abbreviated SHAs. With `expand`, resolve each one
+    /// against its repository and rewrite it to the full SHA
+    pub async fn check_strict_sha(&self, expand: bool) -> Result<StrictShaResults> {
+        let collected = self.collect_actions().await?;
+        self.strict_sha_in(&collected, expand).await
+    }
+
+    /// [`Self::check_strict_sha`] over files already collected
+    pub(crate) async fn strict_sha_in(
+        &self,
+        collected: &CollectedActions,
+        expand: bool,
+    ) -> Result<StrictShaResults> {
+        let mut results = StrictShaResults {
+            files_processed: collected.files_processed,
+            errors: collected.errors.len(),
+            errors_detail: collected.errors.clone(),
+            ..Default::default()
+        };
+
+        for workflow in &collected.workflows {
+            for uses in &workflow.actions {
+                if uses.action.is_sha {
+                    results.pins_checked += 1;
+                } else if uses.action.is_short_sha() {
+                    results.pins_checked += 1;
+                    warn!(
+                        "Short SHA in {}:{}: {}",
+                        workflow.path, uses.line_number, uses.action
+                    );
+                    results.violations.push(ShaViolation {
+                        file: workflow.path.clone(),
+                        line: uses.line_number,
+                        column: uses.column(),
+                        action: uses.action.repository.clone(),
+                        reference: uses.action.reference.clone(),
+                    });
+                }
+            }
+        }
+
+        let (sha_collisions, collision_errors) =
+            self.find_sha_collisions(&collected.workflows).await;
+        results.sha_collisions = sha_collisions;
+        results.errors += collision_errors.len();
+        results.errors_detail.extend(collision_errors);
+
+        if !expand || results.violations.is_empty() {
+            return Ok(results);
+        }
+
+        // Keep each pin's version comment, falling back to the short SHA
+        let mut short_shas: HashMap<String, (ActionRef, Option<String>)> = HashMap::new();
+        for workflow in &collected.workflows {
+            for uses in workflow.actions.iter().filter(|u| u.action.is_short_sha()) {
+                short_shas
+                    .entry(uses.action.to_string())
+                    .or_insert_with(|| (uses.action.clone(), uses.comment_ref.clone()));
+            }
+        }
+
+        let mut lockfile = self.load_lockfile()?;
+        let (shas, errors) = self
+            .resolve_actions(
+                short_shas
+                    .values()
+                    .map(|(action, _)| action.clone())
+                    .collect(),
+                lockfile.as_mut(),
+            )
+            .await;
+        self.stop_on_error(&errors, None)?;
+        results.errors += errors.len();
+        results.errors_detail.extend(errors);
+
+        let pinned_map: HashMap<String, PinnedAction> = short_shas
+            .into_iter()
+            .filter_map(|(key, (action, comment_ref))| {
+                let sha = shas.get(&key)?;
+                if !sha.starts_with(&action.reference.to_ascii_lowercase()) {
+                    warn!("{} resolved to unrelated commit {}", key, sha);
+                    return None;
+                }
+                let mut pinned = self.pin(action, sha.clone());
+                if let Some(comment_ref) = comment_ref {
+                    pinned.original_ref = comment_ref;
+                }
+                Some((key, pinned))
+            })
+            .collect();
+
+        for workflow in &collected.workflows {
+            if !workflow
+                .actions
+                .iter()
+                .any(|u| pinned_map.contains_key(&u.action.to_string()))
+            {
+                continue;
+            }
+            if let Err(e) = self.rewrite_workflow(workflow, &pinned_map, &mut results.expanded) {
+                error!("Failed to rewrite {}: {}", workflow.path, e);
+                results.errors += 1;
+                results.errors_detail.push(write_error(workflow, &e));
+            }
+        }
+        if let Some(error) = self.save_archive() {
+            results.errors += 1;
+            results.errors_detail.push(error);
+        }
+        results.patches = self.take_patches();
+
+        Ok(results)
+    }
+
+    /// Look for tags and branches named like the full-length SHA pins, with
+    /// --verify-shas. Each pin is looked up once, however often it is used
+    pub(crate) async fn find_sha_collisions(
+        &self,
+        workflows: &[WorkflowFile],
+    ) -> (Vec<ShaRefCollision>, Vec<ErrorDetail>) {
+        use futures::stream::{self, StreamExt};
+
+        let mut collisions = Vec::new();
+        let mut errors = Vec::new();
+        if !self.verify_shas {
+            return (collisions, errors);
+        }
+
+        let pins: HashMap<String, ActionRef> = workflows
+            .iter()
+            .flat_map(|workflow| &workflow.actions)
+            .filter(|uses| uses.action.is_sha && !uses.ignored)
+            .map(|uses| (uses.action.to_string(), uses.action.clone()))
+            .collect();
+        if pins.is_empty() {
+            return (collisions, errors);
+        }
+        info!("Checking {} SHA pin(s) for clashing ref names", pins.len());
+
+        let git = self.git_resolver();
+        let git = &git;
+        let named: HashMap<String, Resolution> = stream::iter(pins)
+            .map(|(key, action)| async move {
+                let result = git.ref_named(&action).await;
+                (key, result)
+            })
+            .buffer_unordered(self.concurrency)
+            .filter_map(|(key, result)| {
+                let found = match result {
+                    Ok(found) => found.map(|resolution| (key, resolution)),
+                    Err(e) => {
+                        warn!("Couldn't check {} for a clashing ref: {:#}", key, e);
+                        errors.push(self.fail(
+                            ErrorCode::classify_resolve(&e),
+                            key,
+                            format!("{:#}", e),
+                        ));
+                        None
+                    },
+                };
+                futures::future::ready(found)
+            })
+            .collect()
+            .await;
+
+        for workflow in workflows {
+            for uses in &workflow.actions {
+                let Some(resolution) = named.get(&uses.action.to_string()) else {
+                    continue;
+                };
+                let kind = resolution.ref_type.unwrap_or(RefKind::Tag);
+                warn!(
+                    "{}:{}: {} is a {} name, not a commit; it points at {}",
+                    workflow.path, uses.line_number, uses.action, kind, resolution.sha
+                );
+                collisions.push(ShaRefCollision {
+                    file: workflow.path.clone(),
+                    line: uses.line_number,
+                    column: uses.column(),
+                    action: uses.action.repository.clone(),
+                    reference: uses.action.reference.clone(),
+                    kind,
+                    commit: resolution.sha.clone(),
+                });
+            }
+        }
+
+        (collisions, errors)
+    }
+}
@@ -0,0 +1,196 @@
+use std::{collections::HashSet, time::Instant};
+
+use anyhow::Result;
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::{
+    error::ErrorDetail,
+    workflow::{
+        FilePatch, Rendering, WorkflowProcessor, millis, rewrite_uses, splice_lines, uses_by_line,
+        write_error,
+    },
+};
+
+/// A SHA pin rewritten back to the ref in its version comment
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnpinnedPin {
+    pub file: String,
+    pub line: usize,
+    pub action: String,
+    pub sha: String,
+    pub reference: String,
+    /// Whether the change was written (false under dry-run)
+    pub applied: bool,
+}
+
+/// Results from unpinning SHA pins
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UnpinResults {
+    pub dry_run: bool,
+    pub files_processed: usize,
+    pub pins_found: usize,
+    /// Pins left alone because no version comment names their ref
+    pub without_comment: usize,
+    pub errors: usize,
+    #[serde(default)]
+    pub errors_detail: Vec<ErrorDetail>,
+    pub unpinned: Vec<UnpinnedPin>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub patches: Vec<FilePatch>,
+}
+
+impl WorkflowProcessor {
+    /// Rewrite SHA pins back to the ref named in their version comment,
+    /// leaving pins without one alone
+    pub async fn unpin(&self) -> Result<UnpinResults> {
+        let collected = self.collect_actions().await?;
+
+        let mut results = UnpinResults {
+            dry_run: self.dry_run,
+            files_processed: collected.files_processed,
+            errors: collected.errors.len(),
+            errors_detail: collected.errors.clone(),
+            ..Default::default()
+        };
+
+        let rewriting = Instant::now();
+        for workflow in &collected.workflows {
+            let mut file_results = Vec::new();
+            let uses_by_line = uses_by_line(workflow);
+            // A provenance comment goes with the pins below it, once none
+            // of them is left
+            let provenance: HashSet<usize> = uses_by_line
+                .iter()
+                .filter(|(_, line_uses)| line_uses.iter().any(|uses| uses.provenance))
+                .filter(|(_, line_uses)| {
+                    let mut pins = line_uses.iter().filter(|uses| uses.action.is_sha);
+                    pins.clone().next().is_some() && pins.all(|uses| uses.comment_ref.is_some())
+                })
+                .map(|(&line_num, _)| line_num - 1)
+                .collect();
+
+            let mut rendering = Rendering::for_workflow(workflow)?;
+            let spliced = rendering.write(|out| {
+                splice_lines(
+                    workflow,
+                    workflow.reader()?,
+                    out,
+                    &provenance,
+                    |line_num, line| {
+                        let line_uses = uses_by_line.get(&line_num)?;
+                        let edits: Vec<Option<(String, Option<String>)>> = line_uses
+                            .iter()
+                            .map(|uses| {
+                                if !uses.action.is_sha {
+                                    return None;
+                                }
+                                results.pins_found += 1;
+                                let Some(reference) = &uses.comment_ref else {
+                                    results.without_comment += 1;
+                                    return None;
+                                };
+
+                                file_results.push(UnpinnedPin {
+                                    file: workflow.path.clone(),
+                                    line: line_num,
+                                    action: uses.action.repository.clone(),
+                                    sha: uses.action.reference.clone(),
+                                    reference: reference.clone(),
+                                    applied: false,
+                                });
+                                if !self.summary_only {
+                                    info!(
+                                        "  {} {} → {}",
+                                        "🔓".cyan(),
+                                        uses.action.to_string().yellow(),
+                                        reference.green()
+                                    );
+                                }
+                                Some((format!("{}@{}", uses.action.repository, reference), None))
+                            })
+                            .collect();
+
+                        edits
+                            .iter()
+                            .any(Option::is_some)
+                            .then(|| rewrite_uses(line, line_uses, &edits))
+                    },
+                )
+            });
+            if let Err(e) = spliced {
+                error!("Failed to rewrite {}: {}", workflow.path, e);
+                results.errors += 1;
+                results.errors_detail.push(write_error(workflow, &e));
+                continue;
+            }
+
+            if file_results.is_empty() {
+                continue;
+            }
+
+            match self.write_workflow(workflow, rendering) {
+                Ok(applied) => {
+                    for result in &mut file_results {
+                        result.applied = applied;
+                    }
+                    results.unpinned.extend(file_results);
+                },
+                Err(e) => {
+                    error!("Failed to rewrite {}: {}", workflow.path, e);
+                    results.errors += 1;
+                    results.errors_detail.push(write_error(workflow, &e));
+                },
+            }
+        }
+        self.phases.lock().unwrap().rewrite_ms += millis(rewriting.elapsed());
+
+        if let Some(error) = self.save_archive() {
+            results.errors += 1;
+            results.errors_detail.push(error);
+        }
+        results.patches = self.take_patches();
+
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_unpin_drops_provenance_comments() {
+        let sha = "b4ffde65f46336ab88eb53be808477a3936bae11";
+        let provenance = "# pinned by pin-actions v1.0.0 via ls-remote on 2024-05-01";
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("ci.yml");
+        // The second pin has no version comment, so it and its provenance
+        // comment stay
+        fs::write(
+            &path,
+            format!(
+                "steps:\n  {provenance}\n  - uses: actions/checkout@{sha} # v4\n  {provenance}\n  - uses: actions/cache@{sha}\n"
+            ),
+        )
+        .unwrap();
+
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 10);
+        let results = processor.unpin().await.unwrap();
+
+        assert_eq!(results.unpinned.len(), 1);
+        assert_eq!(results.unpinned[0].line, 3);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!(
+                "steps:\n  - uses: actions/checkout@v4\n  {provenance}\n  - uses: actions/cache@{sha}\n"
+            )
+        );
+    }
+}
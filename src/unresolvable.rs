@@ -0,0 +1,108 @@
+use std::collections::{BTreeSet, HashMap};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::{
+    action::ActionRef,
+    error::{ErrorCode, ErrorDetail},
+    workflow::WorkflowProcessor,
+};
+
+/// An action whose ref couldn't be resolved, with what was tried
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnresolvableAction {
+    /// "owner/repo@ref"
+    pub action: String,
+    pub code: ErrorCode,
+    /// Remote URL or mirror clone the ref was looked up in
+    pub source: String,
+    pub message: String,
+    /// Files using the action
+    pub files: Vec<String>,
+    /// Tags and branches the repository does have, closest to the ref first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub available_refs: Vec<String>,
+}
+
+/// Results from resolving every unpinned action and keeping only the
+/// failures
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UnresolvableResults {
+    pub files_processed: usize,
+    pub actions_checked: usize,
+    pub errors: usize,
+    #[serde(default)]
+    pub errors_detail: Vec<ErrorDetail>,
+    pub unresolvable: Vec<UnresolvableAction>,
+}
+
+impl WorkflowProcessor {
+    /// Resolve every unpinned action as a run would, without writing
+    /// anything, and report only those that fail. With `candidates`, refs
+    /// that don't exist come with up to that many the repository does have
+    pub async fn report_unresolvable(&self, candidates: usize) -> Result<UnresolvableResults> {
+        let mut collected = self.collect_actions().await?;
+        let mut results = UnresolvableResults {
+            files_processed: collected.files_processed,
+            errors_detail: collected.errors.clone(),
+            ..Default::default()
+        };
+        results
+            .errors_detail
+            .extend(self.resolve_unversioned(&mut collected.workflows).await);
+
+        let mut actions: HashMap<String, (ActionRef, BTreeSet<String>)> = HashMap::new();
+        for workflow in &collected.workflows {
+            for uses in workflow.unpinned_actions() {
+                actions
+                    .entry(uses.action.to_string())
+                    .or_insert_with(|| (uses.action.clone(), BTreeSet::new()))
+                    .1
+                    .insert(workflow.path.clone());
+            }
+        }
+        results.actions_checked = actions.len();
+
+        // The lockfile answers as it would for a real run, but isn't saved
+        let mut lockfile = self.load_lockfile()?;
+        let (_, errors) = self
+            .resolve_actions(
+                actions.values().map(|(action, _)| action.clone()).collect(),
+                lockfile.as_mut(),
+            )
+            .await;
+
+        let git = self.git_resolver();
+        for error in &errors {
+            let Some((action, files)) = actions.get(&error.target) else {
+                continue;
+            };
+            let available_refs = if candidates > 0 && error.code == ErrorCode::RefNotFound {
+                git.available_refs(action, candidates)
+                    .await
+                    .unwrap_or_else(|e| {
+                        debug!("Couldn't list the refs of {}: {:#}", action, e);
+                        Vec::new()
+                    })
+            } else {
+                Vec::new()
+            };
+
+            results.unresolvable.push(UnresolvableAction {
+                action: error.target.clone(),
+                code: error.code,
+                source: git.source(action),
+                message: error.message.clone(),
+                files: files.iter().cloned().collect(),
+                available_refs,
+            });
+        }
+        results.unresolvable.sort_by(|a, b| a.action.cmp(&b.action));
+
+        results.errors_detail.extend(errors);
+        results.errors = results.errors_detail.len();
+        Ok(results)
+    }
+}
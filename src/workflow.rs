@@ -1,309 +1,5611 @@
-use std::{collections::HashMap, fs, path::PathBuf};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use anyhow::{Context, Result};
 use colored::Colorize;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
 use indicatif::{ProgressBar, ProgressStyle};
 use serde::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
+use tokio::task;
 use tracing::{debug, error, info, warn};
 use walkdir::WalkDir;
 
 use crate::{
-    action::{ActionRef, PinnedAction},
-    git::GitResolver,
-    parser::WorkflowFile,
+    action::{ActionRef, PinnedAction, RefKind},
+    archive::Archive,
+    auth::Auth,
+    changed,
+    error::{ErrorCode, ErrorDetail, InvalidUtf8},
+    git::{self, CommitName, GitRemote, GitResolver},
+    health::{HealthChecker, RepoHealth},
+    http,
+    image::ImageKind,
+    known::KnownRefs,
+    lockfile::Lockfile,
+    parser::{self, ImageLine, UsesLine, WorkflowFile},
+    policy::{Policy, PolicyReason},
+    report,
+    resolver::{
+        self, Backpressure, MeteredResolver, OverrideResolver, Resolution, Resolver, Source,
+    },
+    signature::{SignatureChecker, SignatureStatus, TargetMismatch},
+    strict_sha::ShaRefCollision,
 };
 
+/// How a run went overall, for scripts that would rather not interpret
+/// the counts. Reported as `status` in JSON output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    /// Nothing needed changing
+    #[default]
+    Ok,
+    /// Lines were rewritten (or would be, under dry-run)
+    ChangesMade,
+    /// Actions remain unpinned: blocked, left on their ref, or only
+    /// checked
+    Unresolved,
+    /// Something failed; `errors_detail` says what
+    Errors,
+}
+
 /// Results from processing workflows
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct ProcessResults {
+    #[serde(default)]
+    pub status: RunStatus,
+    pub dry_run: bool,
     pub files_processed: usize,
+    pub files_skipped: usize,
+    /// Files skipped for a reason worth fixing, such as invalid UTF-8
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped_files: Vec<SkippedFile>,
+    /// Files left out because they didn't change since the --changed-only
+    /// base
+    #[serde(default)]
+    pub files_unchanged: usize,
+    /// Discovered files per kind
+    pub files_by_kind: BTreeMap<TargetKind, usize>,
     pub actions_found: usize,
     pub actions_pinned: usize,
     pub already_pinned: usize,
+    /// Left on their ref by a `# pin-actions: ignore` comment
+    #[serde(default)]
+    pub actions_ignored: usize,
+    /// Left on their tag because the config's `[policy]` trusts their owner
+    #[serde(default)]
+    pub actions_allowed: usize,
+    /// SHA pins given a version comment by --annotate
+    #[serde(default)]
+    pub actions_annotated: usize,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub annotated_actions: Vec<PinnedActionResult>,
+    /// SHA pins --annotate found no tag or branch for
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unidentifiable: Vec<UnidentifiablePin>,
     pub errors: usize,
+    /// What went wrong, one classified entry per error
+    #[serde(default)]
+    pub errors_detail: Vec<ErrorDetail>,
+    /// Lines rewritten (or that would be, under dry-run)
+    pub lines_changed: usize,
+    /// Files with at least one rewritten line
+    pub files_changed: usize,
+    /// Files that needed no change, so weren't written
+    #[serde(default)]
+    pub files_untouched: usize,
+    /// Paths of the files whose content changed (or would, under dry-run)
+    #[serde(default)]
+    pub modified_files: Vec<String>,
+    /// Concurrent resolutions allowed during the run
+    #[serde(default)]
+    pub jobs: usize,
+    /// Files parsed at once during the run
+    #[serde(default)]
+    pub io_workers: usize,
     pub pinned_actions: Vec<PinnedActionResult>,
+    /// Container images seen, with --pin-images
+    #[serde(default)]
+    pub images_found: usize,
+    /// Images seen, split into job containers and service containers
+    #[serde(default)]
+    pub images_found_by_kind: BTreeMap<ImageKind, usize>,
+    #[serde(default)]
+    pub images_pinned: usize,
+    /// Images pinned, split into job containers and service containers
+    #[serde(default)]
+    pub images_pinned_by_kind: BTreeMap<ImageKind, usize>,
+    #[serde(default)]
+    pub images_already_pinned: usize,
+    /// Images already at a digest, split into job containers and service
+    /// containers
+    #[serde(default)]
+    pub images_already_pinned_by_kind: BTreeMap<ImageKind, usize>,
+    #[serde(default)]
+    pub pinned_images: Vec<PinnedImageResult>,
+    /// Refs resolving somewhere other than when first seen, left unpinned
+    /// unless changes were accepted
+    #[serde(default)]
+    pub moved_refs: Vec<MovedRef>,
+    /// Action repositories that are archived or have moved, with
+    /// --check-health
+    #[serde(default)]
+    pub unhealthy_actions: Vec<RepoHealth>,
+    /// Actions whose repository is archived, with where each is used
+    #[serde(default)]
+    pub archived_actions: Vec<ArchivedAction>,
+    /// Uses of blocked actions, which are neither resolved nor pinned nor
+    /// counted with the other actions
+    #[serde(default)]
+    pub policy_violations: Vec<PolicyViolation>,
+    /// Full before/after content of each changed file, with --emit-patches
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub patches: Vec<FilePatch>,
+    /// Lines that would be rewritten, when changes weren't applied
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub planned_changes: Vec<PlannedChange>,
+    /// Full-length pins that turned out to name a tag or branch, with
+    /// --verify-shas; each is rewritten to the commit the ref points at
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sha_collisions: Vec<ShaRefCollision>,
+    /// Uses still on a ref after the run (or that would be, under dry-run),
+    /// with why
+    #[serde(default)]
+    pub remaining_unpinned: Vec<RemainingAction>,
+    /// How resolution lookups queued behind --jobs, with --timings, over
+    /// all of the run's lookups
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backpressure: Option<Backpressure>,
+    #[serde(default)]
+    pub metrics: Metrics,
+}
+
+/// Where a run spent its time and how its refs were answered, for tuning
+/// --jobs and the caches
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Metrics {
+    /// Wall time of the whole run
+    pub total_ms: f64,
+    pub phases: PhaseDurations,
+    /// Refs looked up over the network, through git, GraphQL or the REST API
+    pub network_resolutions: usize,
+    /// Refs answered by the lockfile, the --warm-cache file, the
+    /// --resolution-map or an earlier lookup in the run
+    pub cache_hits: usize,
+    /// Lookups sent again through git after a GraphQL or REST API request
+    /// failed
+    pub retries: usize,
+    /// Median and 95th percentile time of a single lookup; absent when
+    /// nothing was looked up, or lookups were batched into GraphQL queries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolution_p50_ms: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolution_p95_ms: Option<f64>,
+}
+
+/// Wall time of each phase of a run
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PhaseDurations {
+    /// Finding the workflow files
+    pub discovery_ms: f64,
+    /// Reading and parsing them
+    pub parse_ms: f64,
+    /// Resolving refs, default branches, images and signatures
+    pub resolve_ms: f64,
+    /// Rendering and writing the pinned files
+    pub rewrite_ms: f64,
+}
+
+/// What the resolver lookups of a run added up to, collected across
+/// [`WorkflowProcessor::resolve_actions`] calls for [`Metrics`]
+#[derive(Debug, Default)]
+struct LookupStats {
+    network: usize,
+    cache_hits: usize,
+    retries: usize,
+    latencies: Vec<Duration>,
+}
+
+impl LookupStats {
+    /// Fill in the lookup half of `metrics`
+    fn record(&self, metrics: &mut Metrics) {
+        metrics.network_resolutions = self.network;
+        metrics.cache_hits = self.cache_hits;
+        metrics.retries = self.retries;
+        let mut latencies = self.latencies.clone();
+        latencies.sort();
+        metrics.resolution_p50_ms = percentile(&latencies, 50).map(millis);
+        metrics.resolution_p95_ms = percentile(&latencies, 95).map(millis);
+    }
+}
+
+/// The nearest-rank `p`th percentile of sorted `values`
+fn percentile(values: &[Duration], p: usize) -> Option<Duration> {
+    let rank = (values.len() * p).div_ceil(100).max(1);
+    values.get(rank - 1).copied()
+}
+
+/// A duration in fractional milliseconds, as metrics report them
+pub(crate) fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+impl ProcessResults {
+    /// Uses still on a ref once the run is over, not counting those left
+    /// there on purpose or only known at run time. Blocked uses count, as
+    /// they still need removing
+    pub fn actions_unresolved(&self) -> usize {
+        self.remaining_unpinned
+            .iter()
+            .filter(|remaining| {
+                matches!(
+                    remaining.reason,
+                    RemainingReason::ResolutionFailed | RemainingReason::Skipped
+                )
+            })
+            .count()
+            + self.policy_violations.len()
+    }
+
+    /// The run's overall status. Errors outrank changes, which outrank
+    /// uses left unpinned
+    pub fn run_status(&self) -> RunStatus {
+        if self.errors > 0 {
+            RunStatus::Errors
+        } else if self.lines_changed > 0 {
+            RunStatus::ChangesMade
+        } else if self.actions_unresolved() > 0 {
+            RunStatus::Unresolved
+        } else {
+            RunStatus::Ok
+        }
+    }
+}
+
+/// Why a use was left on its ref
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RemainingReason {
+    /// Its ref (or default branch) couldn't be resolved
+    ResolutionFailed,
+    /// Left alone on purpose: an ignore comment, a trusted owner under the
+    /// `[policy]`, or a blocked action
+    Excluded,
+    /// The ref is a `${{ ... }}` expression, only known at run time
+    DynamicRef,
+    /// Resolved but not pinned: --skip-unversioned, a ref that moved since
+    /// the known refs store saw it, a rejected signature or a file that
+    /// couldn't be written
+    Skipped,
+}
+
+/// A use still on its ref after a run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemainingAction {
+    pub file: String,
+    pub line: usize,
+    /// The action's repository, with any subdirectory ("owner/repo/path")
+    pub action: String,
+    /// The ref as written; empty for a use without one
+    pub reference: String,
+    pub reason: RemainingReason,
+    /// What exactly kept it unpinned, e.g. the resolution error
+    pub detail: String,
+}
+
+/// A use of an action the config's `blocked_actions` forbids
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyViolation {
+    pub file: String,
+    pub line: usize,
+    /// The action as used, "owner/repo@ref"
+    pub action: String,
+    /// The blocked pattern it matched
+    pub pattern: String,
+}
+
+/// An action whose repository is archived, so will get no more fixes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchivedAction {
+    /// The repository, "owner/repo"
+    pub action: String,
+    /// Every use of it, as "file:line"
+    pub locations: Vec<String>,
+}
+
+/// A line a dry run would rewrite, before and after
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlannedChange {
+    pub file: String,
+    pub line: usize,
+    pub before: String,
+    pub after: String,
+}
+
+/// The original and rewritten content of a changed file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilePatch {
+    pub file: String,
+    pub original: String,
+    pub rewritten: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PinnedActionResult {
     pub file: String,
+    /// Line of the `uses:` reference rewritten
+    #[serde(default)]
+    pub line: usize,
+    /// 1-based byte column of the "owner/repo@ref" reference
+    #[serde(default)]
+    pub column: usize,
     pub action: String,
     pub old_ref: String,
     pub sha: String,
+    /// The SHA the line was pinned to before, when it already was one (as
+    /// with --update), for an audit trail of SHA transitions
+    #[serde(default)]
+    pub previous_sha: Option<String>,
+    /// Whether the change was written to disk (false under dry-run)
+    pub applied: bool,
+    /// The action had no @ref and was pinned to its default branch
+    #[serde(default)]
+    pub was_unversioned: bool,
+    /// The ref pinned is a branch rather than a release tag
+    #[serde(default)]
+    pub on_branch: bool,
+    /// Signature of the tag pinned, with --verify-signatures
+    #[serde(default)]
+    pub signature: Option<SignatureStatus>,
+    /// Web page of the pinned commit on the configured git host
+    #[serde(default)]
+    pub link: String,
+    /// Where the SHA came from when not from resolving the ref: `override`
+    /// for a SHA mandated by the config file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<Source>,
 }
 
-/// Workflow processor
-pub struct WorkflowProcessor {
-    workflows_dir: PathBuf,
-    dry_run: bool,
-    backup: bool,
-    concurrency: usize,
+/// A ref that resolves to a different SHA than the one recorded when it was
+/// first seen, which can mean its tag was moved or tampered with
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MovedRef {
+    /// "owner/repo@ref"
+    pub action: String,
+    pub known_sha: String,
+    /// When the known SHA was recorded
+    pub first_seen: String,
+    pub resolved_sha: String,
+    /// Whether the store was updated to the new SHA (--accept-changes)
+    pub accepted: bool,
 }
 
-impl WorkflowProcessor {
-    pub fn new(
-        workflows_dir: PathBuf,
-        dry_run: bool,
-        backup: bool,
-        _skip_pinned: bool,
-        concurrency: usize,
-    ) -> Self {
-        Self {
-            workflows_dir,
-            dry_run,
-            backup,
-            concurrency,
+/// A container image pinned to its manifest digest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PinnedImageResult {
+    pub file: String,
+    pub line: usize,
+    /// The image as written, e.g. "node:20"
+    pub image: String,
+    #[serde(default)]
+    pub kind: ImageKind,
+    pub digest: String,
+    /// Whether the change was written to disk (false under dry-run)
+    pub applied: bool,
+}
+
+/// Per-action outcome reported while processing, for streaming output
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ProcessEvent {
+    /// An action was pinned (or would be, under dry-run)
+    Pinned(PinnedActionResult),
+    /// A container image was pinned (or would be, under dry-run)
+    ImagePinned(PinnedImageResult),
+    /// An action's ref could not be resolved
+    Failed {
+        action: String,
+        code: ErrorCode,
+        error: String,
+    },
+    /// An action was left alone
+    Skipped {
+        file: String,
+        line: usize,
+        action: String,
+        reason: String,
+    },
+}
+
+/// Callback receiving process events as they happen
+pub type EventSink = Arc<dyn Fn(&ProcessEvent) + Send + Sync>;
+
+/// A SHA pin that --annotate found no tag or branch for
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnidentifiablePin {
+    pub file: String,
+    pub line: usize,
+    pub action: String,
+    pub sha: String,
+}
+
+/// Files found and parsed by [`WorkflowProcessor::collect_actions`]
+#[derive(Debug, Default)]
+pub struct CollectedActions {
+    pub files_processed: usize,
+    pub files_skipped: usize,
+    /// Left out by --changed-only
+    pub files_unchanged: usize,
+    pub files_by_kind: BTreeMap<TargetKind, usize>,
+    pub workflows: Vec<WorkflowFile>,
+    /// Files that couldn't be read or parsed
+    pub errors: Vec<ErrorDetail>,
+    /// Files left out because they aren't valid UTF-8 (counted in
+    /// `files_skipped`)
+    pub skipped_files: Vec<SkippedFile>,
+    /// Uses of blocked actions, already removed from `workflows` so no
+    /// command resolves or rewrites them
+    pub policy_violations: Vec<PolicyViolation>,
+    /// Time spent finding the files, then reading and parsing them
+    pub discovery: Duration,
+    pub parse: Duration,
+}
+
+/// A file left out rather than processed, and why
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkippedFile {
+    pub file: String,
+    pub reason: String,
+}
+
+/// Backend used to resolve refs to commit SHAs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ResolverKind {
+    /// git ls-remote against each action repository
+    #[default]
+    Git,
+    /// Batched GitHub GraphQL queries, falling back to git
+    Graphql,
+    /// One GitHub REST API lookup per ref, falling back to git
+    Api,
+    /// Only the SHAs in the --resolution-map file; nothing is looked up
+    Fixed,
+}
+
+impl ResolverKind {
+    /// How refs are resolved, as named in provenance comments
+    pub fn method(&self) -> &'static str {
+        match self {
+            ResolverKind::Git => "ls-remote",
+            ResolverKind::Graphql => "graphql",
+            ResolverKind::Api => "rest-api",
+            ResolverKind::Fixed => "resolution-map",
         }
     }
+}
 
-    /// Process all workflow files
-    pub async fn process(&self) -> Result<ProcessResults> {
-        let resolver = GitResolver::new();
-
-        // Find all workflow files
-        let workflow_files = self.find_workflow_files()?;
+/// When re-pinning a SHA pin that already has a version comment, whether
+/// that comment is rewritten
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReplaceComment {
+    /// Write a fresh comment for the new pin
+    #[default]
+    Always,
+    /// Keep the comment as it is, changing only the SHA
+    Never,
+    /// Write a fresh comment only when it names a different ref than the
+    /// new pin, so an update within the same tag leaves it alone
+    IfStale,
+}
 
-        if workflow_files.is_empty() {
-            info!("No workflow files found");
-            return Ok(ProcessResults {
-                files_processed: 0,
-                actions_found: 0,
-                actions_pinned: 0,
-                already_pinned: 0,
-                errors: 0,
-                pinned_actions: Vec::new(),
-            });
+impl ReplaceComment {
+    /// Whether a pin's existing comment naming `comment_ref` gives way to
+    /// one for `pinned`
+    fn replaces(&self, comment_ref: &str, pinned: &PinnedAction) -> bool {
+        match self {
+            ReplaceComment::Always => true,
+            ReplaceComment::Never => false,
+            ReplaceComment::IfStale => comment_ref != pinned.original_ref,
         }
+    }
+}
 
-        info!("Found {} workflow file(s)", workflow_files.len());
+/// Kind of file that action references are pinned in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetKind {
+    /// A workflow under .github/workflows
+    Workflow,
+    /// A composite action definition (action.yml)
+    CompositeAction,
+    /// A starter workflow under .github/workflow-templates
+    Template,
+}
 
-        // Parse all workflow files
-        let mut parsed_workflows = Vec::new();
-        for path in &workflow_files {
-            match WorkflowFile::parse(path) {
-                Ok(workflow) => parsed_workflows.push(workflow),
-                Err(e) => {
-                    error!("Failed to parse {}: {}", path.display(), e);
-                    continue;
-                },
-            }
+impl TargetKind {
+    /// Human-readable plural label for summaries
+    pub fn label(&self) -> &'static str {
+        match self {
+            TargetKind::Workflow => "Workflows",
+            TargetKind::CompositeAction => "Composite actions",
+            TargetKind::Template => "Templates",
         }
+    }
+}
 
-        // Collect all unique actions that need pinning
-        let mut actions_to_resolve = HashMap::new();
-        let mut already_pinned = 0;
+/// Upper bound on automatically chosen concurrency
+const MAX_AUTO_JOBS: usize = 32;
 
-        for workflow in &parsed_workflows {
-            already_pinned += workflow.pinned_count();
+/// The concurrency to use for a `--jobs` value, where 0 means twice the
+/// available parallelism, capped at 32
+pub fn effective_jobs(jobs: usize) -> usize {
+    if jobs > 0 {
+        return jobs;
+    }
 
-            for uses in workflow.unpinned_actions() {
-                let key = uses.action.to_string();
-                actions_to_resolve
-                    .entry(key)
-                    .or_insert_with(|| uses.action.clone());
-            }
-        }
+    let cpus = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    (2 * cpus).min(MAX_AUTO_JOBS)
+}
 
-        let actions_found = parsed_workflows
-            .iter()
-            .map(|w| w.actions.len())
-            .sum::<usize>();
+/// The number of files to parse at once for an `--io-workers` value, where
+/// 0 means the available parallelism. Parsing is CPU-bound, so unlike
+/// network requests there's nothing to gain from going beyond it
+pub fn effective_io_workers(workers: usize) -> usize {
+    if workers > 0 {
+        return workers;
+    }
 
-        if actions_to_resolve.is_empty() {
-            info!("No actions need pinning");
-            return Ok(ProcessResults {
-                files_processed: parsed_workflows.len(),
-                actions_found,
-                actions_pinned: 0,
-                already_pinned,
-                errors: 0,
-                pinned_actions: Vec::new(),
-            });
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+/// Where GitHub looks for a repository's workflows
+pub const DEFAULT_WORKFLOWS_DIR: &str = ".github/workflows";
+
+/// The comment --provenance-comment writes above each rewritten uses line,
+/// without its "# ", e.g. "pinned by pin-actions v1.2.0 via ls-remote on
+/// 2024-05-01"
+pub fn provenance_comment(resolver: ResolverKind, date: &str) -> String {
+    format!(
+        "{} v{} via {} on {}",
+        parser::PROVENANCE_PREFIX,
+        env!("CARGO_PKG_VERSION"),
+        resolver.method(),
+        date
+    )
+}
+
+/// The nearest `.github/workflows` in `start` or a directory above it, as a
+/// path relative to `start` such as `../../.github/workflows`. Like git's
+/// search for `.git`, the walk stops at the repository root
+pub fn find_workflows_dir(start: &Path) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    for dir in start.ancestors() {
+        if dir.join(DEFAULT_WORKFLOWS_DIR).is_dir() {
+            return Some(relative.join(DEFAULT_WORKFLOWS_DIR));
         }
+        if dir.join(".git").exists() {
+            return None;
+        }
+        relative.push("..");
+    }
+    None
+}
 
-        info!("Resolving {} unique action(s)", actions_to_resolve.len());
+/// The starter workflow directory (`workflow-templates/`) of the repository
+/// whose `.github/workflows` this is, if it exists
+pub fn templates_dir_for(workflows_dir: &Path) -> Option<PathBuf> {
+    if !workflows_dir.ends_with(".github/workflows") {
+        return None;
+    }
 
-        // Resolve SHAs with progress bar
-        let progress = ProgressBar::new(actions_to_resolve.len() as u64);
-        progress.set_style(
-            ProgressStyle::default_bar()
-                .template("{spinner:.green} [{bar:40.cyan/blue}] {pos}/{len} {msg}")
-                .unwrap()
-                .progress_chars("#>-"),
-        );
+    let root = workflows_dir.parent()?.parent()?;
+    let templates_dir = root.join("workflow-templates");
+    templates_dir.is_dir().then_some(templates_dir)
+}
 
-        let actions_vec: Vec<ActionRef> = actions_to_resolve.values().cloned().collect();
-        let results = resolver.batch_resolve(actions_vec, self.concurrency).await;
+/// What was pinned in a rewritten workflow
+struct Rendered {
+    lines_changed: usize,
+    changes: Vec<PlannedChange>,
+    /// Not yet applied; the caller decides once it writes the content
+    actions: Vec<PinnedActionResult>,
+    images: Vec<PinnedImageResult>,
+    /// Rendered line numbers of the provenance comments inserted, in order
+    inserted: Vec<usize>,
+}
 
-        let mut pinned_map = HashMap::new();
-        let mut errors = 0;
+/// The repository of every action used, "owner/repo"
+fn used_repositories(workflows: &[WorkflowFile]) -> BTreeSet<String> {
+    workflows
+        .iter()
+        .flat_map(|w| &w.actions)
+        .map(|uses| uses.action.base_repository().to_string())
+        .collect()
+}
 
-        for (action, result) in results {
-            progress.inc(1);
-            match result {
-                Ok(sha) => {
-                    progress.set_message(format!("✓ {}", action.repository.green()));
-                    debug!("Resolved {} → {}", action, sha);
-                    pinned_map.insert(action.to_string(), PinnedAction::new(action, sha));
-                },
-                Err(e) => {
-                    progress.set_message(format!("✗ {}", action.repository.red()));
-                    warn!("Failed to resolve {}: {}", action, e);
-                    errors += 1;
-                },
-            }
+/// How rewritten uses lines are written
+#[derive(Debug, Clone, Copy)]
+struct LineStyle<'a> {
+    /// Git host pins link to their commit on
+    host: &'a str,
+    /// Comment written on a line of its own above each rewritten uses line
+    provenance: Option<&'a str>,
+    replace_comment: ReplaceComment,
+}
+
+impl Default for LineStyle<'_> {
+    fn default() -> Self {
+        Self {
+            host: git::DEFAULT_GIT_HOST,
+            provenance: None,
+            replace_comment: ReplaceComment::default(),
         }
+    }
+}
 
-        progress.finish_with_message("Resolution complete");
+/// Render a workflow read from `reader` into `out` with its actions and
+/// container images pinned, keeping every other line, each line's ending and
+/// the trailing newline (or lack of one) exactly as they were. With a
+/// provenance comment in `style`, each rewritten uses line gets it on a line
+/// of its own above, refreshing the one an earlier run left there instead of
+/// adding another. A SHA pin re-pinned keeps its version comment as written
+/// unless `style` says it's replaced
+fn render_workflow<R: BufRead, W: Write + ?Sized>(
+    workflow: &WorkflowFile,
+    reader: R,
+    out: &mut W,
+    pinned_map: &HashMap<String, PinnedAction>,
+    digests: &HashMap<String, String>,
+    style: &LineStyle,
+) -> Result<Rendered> {
+    let LineStyle {
+        host,
+        provenance,
+        replace_comment,
+    } = *style;
+    let mut lines_changed = 0;
+    let mut changes = Vec::new();
+    let mut file_results = Vec::new();
+    let mut file_image_results = Vec::new();
+    let mut inserted = Vec::new();
 
-        // Rewrite workflow files
-        let mut pinned_actions = Vec::new();
-        let mut actions_pinned = 0;
+    // A line can hold several uses in flow style, each rewritten by its span
+    let uses_by_line = uses_by_line(workflow);
+    // Provenance comments above lines about to be rewritten
+    let refreshed: HashSet<usize> = workflow
+        .actions
+        .iter()
+        .filter(|uses| uses.provenance && pinned_map.contains_key(&uses.action.to_string()))
+        .map(|uses| uses.line_number - 1)
+        .collect();
+    // Flow mappings can hold several images on one line
+    let mut images_by_line: HashMap<usize, Vec<&ImageLine>> = HashMap::new();
+    for image_line in workflow.unpinned_images() {
+        images_by_line
+            .entry(image_line.line_number)
+            .or_default()
+            .push(image_line);
+    }
 
-        for workflow in parsed_workflows {
-            if let Err(e) = self.rewrite_workflow(&workflow, &pinned_map, &mut pinned_actions) {
-                error!("Failed to rewrite {}: {}", workflow.path, e);
-                errors += 1;
-            } else {
-                actions_pinned += workflow.unpinned_actions().len();
+    splice_lines(workflow, reader, out, &HashSet::new(), |line_num, line| {
+        let indent = &line[..line.len() - line.trim_start().len()];
+        if let Some(provenance) = provenance.filter(|_| refreshed.contains(&line_num)) {
+            if parser::is_provenance_comment(line) {
+                let new_line = format!("{}# {}", indent, provenance);
+                if new_line != line {
+                    lines_changed += 1;
+                    changes.push(PlannedChange {
+                        file: workflow.path.clone(),
+                        line: line_num,
+                        before: line.to_string(),
+                        after: new_line.clone(),
+                    });
+                }
+                return Some(new_line);
             }
         }
 
-        Ok(ProcessResults {
-            files_processed: workflow_files.len(),
-            actions_found,
-            actions_pinned,
-            already_pinned,
-            errors,
-            pinned_actions,
-        })
-    }
-
-    /// Find all workflow YAML files
-    fn find_workflow_files(&self) -> Result<Vec<PathBuf>> {
-        let mut files = Vec::new();
+        // Find the actions on this line we need to pin
+        let line_uses = uses_by_line.get(&line_num).map_or(&[][..], Vec::as_slice);
+        let edits: Vec<Option<(String, Option<String>, bool)>> = line_uses
+            .iter()
+            .map(|uses| {
+                let pinned = pinned_map.get(&uses.action.to_string())?;
+                // A use that recorded a constraint keeps one, following the
+                // ref it is pinned to now
+                let kept;
+                let pinned = if pinned.constraint.is_none() && uses.constraint.is_some() {
+                    kept = pinned
+                        .clone()
+                        .with_constraint(pinned.action.caret_constraint());
+                    &kept
+                } else {
+                    pinned
+                };
+                file_results.push(PinnedActionResult {
+                    file: workflow.path.clone(),
+                    line: line_num,
+                    column: uses.column(),
+                    action: uses.action.repository.clone(),
+                    old_ref: if uses.unversioned {
+                        String::new()
+                    } else {
+                        uses.action.qualified_reference()
+                    },
+                    sha: pinned.sha.clone(),
+                    previous_sha: uses.action.is_sha.then(|| uses.action.reference.clone()),
+                    applied: false,
+                    was_unversioned: uses.unversioned,
+                    on_branch: pinned.on_branch,
+                    signature: pinned.signature,
+                    link: uses.action.commit_url(host, &pinned.sha),
+                    source: pinned.source,
+                });
 
-        for entry in WalkDir::new(&self.workflows_dir)
-            .follow_links(false)
-            .max_depth(1)
-        {
-            let entry = entry?;
-            let path = entry.path();
+                // Replace with pinned version, unless the pin's comment is
+                // kept as it was
+                let kept = uses.action.is_sha
+                    && uses
+                        .comment_ref
+                        .as_ref()
+                        .is_some_and(|comment_ref| !replace_comment.replaces(comment_ref, pinned));
+                let comment = if kept {
+                    Some(uses.version_comment())
+                } else {
+                    pinned.version_comment()
+                };
+                Some((pinned.pinned_ref(), comment, kept))
+            })
+            .collect();
 
-            if path.is_file() {
-                if let Some(ext) = path.extension() {
-                    if ext == "yml" || ext == "yaml" {
-                        files.push(path.to_path_buf());
+        let new_line = if edits.iter().any(Option::is_some) {
+            let new_line = if edits.iter().flatten().all(|(_, _, kept)| *kept) {
+                // Every comment stays word for word, so only the refs change
+                let mut new_line = line.to_string();
+                for (uses, edit) in line_uses.iter().zip(&edits).rev() {
+                    if let Some((pinned_ref, _, _)) = edit {
+                        new_line.replace_range(uses.start..uses.end, pinned_ref);
                     }
                 }
+                new_line
+            } else {
+                let edits: Vec<_> = edits
+                    .into_iter()
+                    .map(|edit| edit.map(|(pinned_ref, comment, _)| (pinned_ref, comment)))
+                    .collect();
+                rewrite_uses(line, line_uses, &edits)
+            };
+            match provenance {
+                Some(provenance)
+                    if new_line != line && !line_uses.iter().any(|uses| uses.provenance) =>
+                {
+                    inserted.push(line_num + inserted.len());
+                    format!("{}# {}\n{}", indent, provenance, new_line)
+                },
+                _ => new_line,
+            }
+        } else if let Some(image_lines) = images_by_line.get(&line_num) {
+            // Splice from the right so earlier offsets stay valid
+            let mut new_line = line.to_string();
+            for image_line in image_lines.iter().rev() {
+                let Some(digest) = digests.get(&image_line.image.to_string()) else {
+                    continue;
+                };
+                new_line.replace_range(
+                    image_line.start..image_line.end,
+                    &image_line.image.pinned(digest),
+                );
+
+                file_image_results.push(PinnedImageResult {
+                    file: workflow.path.clone(),
+                    line: line_num,
+                    image: image_line.image.to_string(),
+                    kind: image_line.kind,
+                    digest: digest.clone(),
+                    applied: false,
+                });
             }
+            new_line
+        } else {
+            // Keep original line
+            return None;
+        };
+
+        if new_line != line {
+            lines_changed += 1;
+            changes.push(PlannedChange {
+                file: workflow.path.clone(),
+                line: line_num,
+                before: line.to_string(),
+                after: new_line.clone(),
+            });
         }
+        Some(new_line)
+    })?;
 
-        Ok(files)
+    Ok(Rendered {
+        lines_changed,
+        changes,
+        actions: file_results,
+        images: file_image_results,
+        inserted,
+    })
+}
+
+/// The uses of a workflow by line, in the order they appear on it
+pub(crate) fn uses_by_line(workflow: &WorkflowFile) -> HashMap<usize, Vec<&UsesLine>> {
+    let mut by_line: HashMap<usize, Vec<&UsesLine>> = HashMap::new();
+    for uses in &workflow.actions {
+        by_line.entry(uses.line_number).or_default().push(uses);
     }
+    by_line
+}
 
-    /// Rewrite a workflow file with pinned actions
-    fn rewrite_workflow(
-        &self,
-        workflow: &WorkflowFile,
-        pinned_map: &HashMap<String, PinnedAction>,
-        results: &mut Vec<PinnedActionResult>,
-    ) -> Result<()> {
-        let mut new_content = String::new();
-        let lines: Vec<&str> = workflow.content.lines().collect();
+/// Rewrite the uses on a line, giving those with an edit a new
+/// "owner/repo@ref" and version comment. Spans are spliced from the right so
+/// earlier ones stay valid. Comments can't go inside a flow mapping, so the
+/// version comment always ends the line: a lone use's replaces the old one,
+/// while several uses share one listing each version in order. The author's
+/// note is kept after it
+pub(crate) fn rewrite_uses(
+    line: &str,
+    uses: &[&UsesLine],
+    edits: &[Option<(String, Option<String>)>],
+) -> String {
+    let Some(last) = uses.last() else {
+        return line.to_string();
+    };
+    let code_end = parser::trailing_comment(line, last.end).map_or(line.len(), |(start, _)| start);
 
-        for (idx, line) in lines.iter().enumerate() {
-            let line_num = idx + 1;
+    let mut new_line = line[..code_end].to_string();
+    for (uses, edit) in uses.iter().zip(edits).rev() {
+        if let Some((pinned_ref, _)) = edit {
+            new_line.replace_range(uses.start..uses.end, pinned_ref);
+        }
+    }
+    let mut new_line = new_line.trim_end().to_string();
 
-            // Find if this line contains an action we need to pin
-            if let Some(uses) = workflow.actions.iter().find(|u| u.line_number == line_num) {
-                let key = uses.action.to_string();
+    let versions: Vec<String> = if let [_] = uses {
+        edits[0]
+            .iter()
+            .filter_map(|(_, comment)| comment.clone())
+            .collect()
+    } else {
+        uses.iter()
+            .zip(edits)
+            .map(|(uses, edit)| match edit {
+                Some((_, comment)) => comment.clone().unwrap_or_default(),
+                None => uses.version_comment(),
+            })
+            .collect()
+    };
+    if versions.iter().any(|version| !version.is_empty()) {
+        new_line.push_str(&format!(" # {}", versions.join(", ").trim_end()));
+    }
+    new_line.push_str(&last.note_comment());
+    new_line
+}
 
-                if let Some(pinned) = pinned_map.get(&key) {
-                    // Replace with pinned version
-                    let new_line = format!("{}uses: {}", uses.indent, pinned.format_uses_line());
-                    new_content.push_str(&new_line);
-                    new_content.push('\n');
-
-                    info!(
-                        "  {} {} → {}",
-                        "📌".cyan(),
-                        uses.action.to_string().yellow(),
-                        pinned.sha[..8].green()
-                    );
+/// The `n`th newest backup of `path`: `path.bak`, then `path.bak.1`, ...
+fn backup_path(path: &str, n: usize) -> String {
+    match n {
+        0 => format!("{}.bak", path),
+        n => format!("{}.bak.{}", path, n),
+    }
+}
 
-                    results.push(PinnedActionResult {
-                        file: workflow.path.clone(),
-                        action: uses.action.repository.clone(),
-                        old_ref: uses.action.reference.clone(),
-                        sha: pinned.sha.clone(),
-                    });
-                } else {
-                    // Keep original if we couldn't resolve
-                    new_content.push_str(line);
-                    new_content.push('\n');
-                }
-            } else {
-                // Keep original line
-                new_content.push_str(line);
-                new_content.push('\n');
-            }
+/// Make room for a new backup of `path` by shifting each existing one a
+/// place older, so that `keep` remain once it's written; the oldest is
+/// overwritten
+fn rotate_backups(path: &str, keep: usize) -> Result<()> {
+    for n in (0..keep.saturating_sub(1)).rev() {
+        let from = backup_path(path, n);
+        if Path::new(&from).exists() {
+            let to = backup_path(path, n + 1);
+            fs::rename(&from, &to)
+                .with_context(|| format!("Failed to move backup {} to {}", from, to))?;
         }
+    }
+    Ok(())
+}
 
-        // Remove trailing newline if original didn't have one
-        if !workflow.content.ends_with('\n') {
-            new_content.pop();
-        }
+/// Copy a workflow from `reader` to `out` a line at a time, writing the line
+/// `replace` returns in place of the original wherever it returns one. Each
+/// line keeps its own ending, and lines left alone keep their exact bytes. A
+/// replacement holding several lines separates them with "\n", which is
+/// written as the replaced line's ending, or for a last line without one as
+/// the ending of the line before it ("\n" in a one-line file). Lines in
+/// `dropped` are left out altogether.
+/// Fails if the workflow changed since it was parsed, since the replacements
+/// would then land on the wrong lines or undo the change, and when asked to replace a line of a lossily
+/// read workflow whose invalid UTF-8 would be lost
+pub(crate) fn splice_lines<R: BufRead, W: Write + ?Sized>(
+    workflow: &WorkflowFile,
+    mut reader: R,
+    out: &mut W,
+    dropped: &HashSet<usize>,
+    mut replace: impl FnMut(usize, &str) -> Option<String>,
+) -> Result<()> {
+    let offsets: HashMap<usize, usize> = workflow
+        .actions
+        .iter()
+        .map(|uses| (uses.line_number, uses.offset))
+        .chain(
+            workflow
+                .images
+                .iter()
+                .map(|image| (image.line_number, image.offset)),
+        )
+        .collect();
+    let changed = || anyhow::anyhow!("{} changed since it was read", workflow.path);
 
-        if self.dry_run {
-            debug!("Dry run: would write to {}", workflow.path);
+    // The parser reads past a byte order mark, so it is copied as it is
+    if parser::starts_with_bom(&mut reader)? {
+        out.write_all(parser::BOM.as_bytes())?;
+    }
+
+    let mut found = 0;
+    let mut fingerprint = parser::Fingerprint::default();
+    let mut previous_ending = String::from("\n");
+    parser::read_lines(reader, workflow.lossy, |line| {
+        fingerprint.add(line);
+        if let Some(&expected) = offsets.get(&line.number) {
+            if expected != line.offset {
+                return Err(changed());
+            }
+            found += 1;
+        }
+        if dropped.contains(&line.number) {
             return Ok(());
         }
+        match replace(line.number, &line.text) {
+            Some(_) if !line.is_exact() => anyhow::bail!(
+                "Not rewriting {}: line {} isn't valid UTF-8, so rewriting it would lose bytes",
+                workflow.path,
+                line.number
+            ),
+            Some(new_line) => {
+                let ending = if line.ending.is_empty() {
+                    previous_ending.as_str()
+                } else {
+                    line.ending
+                };
+                for (i, part) in new_line.split('\n').enumerate() {
+                    if i > 0 {
+                        out.write_all(ending.as_bytes())?;
+                    }
+                    out.write_all(part.as_bytes())?;
+                }
+            },
+            None => out.write_all(line.raw)?,
+        }
+        out.write_all(line.ending.as_bytes())?;
+        if !line.ending.is_empty() {
+            previous_ending = line.ending.to_string();
+        }
+        Ok(())
+    })?;
+    // Offsets catch lines that moved as they are read; the fingerprint
+    // catches edits that kept every line where it was
+    if found < offsets.len() || fingerprint.finish() != workflow.fingerprint {
+        return Err(changed());
+    }
 
-        // Create backup if requested
-        if self.backup {
-            let backup_path = format!("{}.bak", workflow.path);
-            fs::copy(&workflow.path, &backup_path)
-                .with_context(|| format!("Failed to create backup at {}", backup_path))?;
-            debug!("Created backup: {}", backup_path);
+    Ok(())
+}
+
+/// Where a rewritten workflow is rendered before it is written: memory for
+/// workflows held in memory, otherwise a temporary file, so a large file is
+/// never held whole
+pub(crate) enum Rendering {
+    Memory(Vec<u8>),
+    File(NamedTempFile),
+}
+
+impl Rendering {
+    pub(crate) fn for_workflow(workflow: &WorkflowFile) -> Result<Self> {
+        Ok(match workflow.content {
+            Some(_) => Self::Memory(Vec::new()),
+            None => Self::File(NamedTempFile::new().context("Failed to create a temporary file")?),
+        })
+    }
+
+    /// Run `render` with a buffered writer into the rendering
+    pub(crate) fn write<T>(
+        &mut self,
+        render: impl FnOnce(&mut dyn Write) -> Result<T>,
+    ) -> Result<T> {
+        match self {
+            Self::Memory(bytes) => render(bytes),
+            Self::File(file) => {
+                let mut out = BufWriter::new(file.as_file_mut());
+                let result = render(&mut out)?;
+                out.flush()?;
+                Ok(result)
+            },
         }
+    }
 
-        // Write the new content
-        fs::write(&workflow.path, new_content)
-            .with_context(|| format!("Failed to write to {}", workflow.path))?;
+    fn reader(&self) -> Result<Box<dyn BufRead + '_>> {
+        Ok(match self {
+            Self::Memory(bytes) => Box::new(bytes.as_slice()),
+            Self::File(file) => Box::new(BufReader::new(file.reopen()?)),
+        })
+    }
 
-        Ok(())
+    /// The whole rendered content, for the few consumers that need it at
+    /// once
+    fn into_bytes(self) -> Result<Vec<u8>> {
+        match self {
+            Self::Memory(bytes) => Ok(bytes),
+            Self::File(file) => Ok(fs::read(file.path())?),
+        }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use tempfile::TempDir;
+/// Error entry for a workflow file that couldn't be rewritten
+pub(crate) fn write_error(workflow: &WorkflowFile, error: &anyhow::Error) -> ErrorDetail {
+    ErrorDetail::new(ErrorCode::Write, &workflow.path, format!("{:#}", error))
+}
 
-    use super::*;
+/// A file discovered for processing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub path: PathBuf,
+    pub kind: TargetKind,
+}
 
-    #[tokio::test]
-    async fn test_process_empty_directory() {
-        let temp = TempDir::new().unwrap();
-        let processor = WorkflowProcessor::new(temp.path().to_path_buf(), false, false, true, 10);
+/// Files found during discovery
+#[derive(Debug, Default)]
+struct Discovery {
+    targets: Vec<Target>,
+    skipped: usize,
+    /// Unchanged since the --changed-only base
+    unchanged: usize,
+}
 
-        let results = processor.process().await.unwrap();
-        assert_eq!(results.files_processed, 0);
+/// Compiled `--ignore-files` patterns
+#[derive(Debug, Clone)]
+pub struct IgnorePatterns {
+    patterns: Vec<String>,
+    set: GlobSet,
+}
+
+impl IgnorePatterns {
+    /// Compile glob patterns matched against paths relative to the workflows
+    /// directory
+    pub fn new(patterns: Vec<String>) -> Result<Self> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in &patterns {
+            let glob = GlobBuilder::new(pattern)
+                .literal_separator(true)
+                .build()
+                .with_context(|| format!("Invalid ignore pattern: {}", pattern))?;
+            builder.add(glob);
+        }
+
+        Ok(Self {
+            set: builder.build()?,
+            patterns,
+        })
     }
 
-    #[test]
-    fn test_find_workflow_files() {
+    /// Return the first pattern matching the given relative path
+    pub fn matching_pattern<P: AsRef<Path>>(&self, path: P) -> Option<&str> {
+        self.set
+            .matches(path)
+            .first()
+            .map(|&idx| self.patterns[idx].as_str())
+    }
+}
+
+impl Default for IgnorePatterns {
+    fn default() -> Self {
+        Self {
+            patterns: Vec::new(),
+            set: GlobSet::empty(),
+        }
+    }
+}
+
+/// Compiled `blocked_actions` patterns
+#[derive(Debug, Clone)]
+pub struct BlockedActions {
+    patterns: Vec<String>,
+    set: GlobSet,
+}
+
+impl BlockedActions {
+    /// Compile glob patterns matched against "owner/repo", ignoring case
+    /// like GitHub does
+    pub fn new(patterns: Vec<String>) -> Result<Self> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in &patterns {
+            let glob = GlobBuilder::new(pattern)
+                .literal_separator(true)
+                .case_insensitive(true)
+                .build()
+                .with_context(|| format!("Invalid blocked action pattern: {}", pattern))?;
+            builder.add(glob);
+        }
+
+        Ok(Self {
+            set: builder.build()?,
+            patterns,
+        })
+    }
+
+    /// Return the first pattern blocking the action's repository
+    pub fn matching_pattern(&self, action: &ActionRef) -> Option<&str> {
+        self.set
+            .matches(action.base_repository())
+            .first()
+            .map(|&idx| self.patterns[idx].as_str())
+    }
+}
+
+impl Default for BlockedActions {
+    fn default() -> Self {
+        Self {
+            patterns: Vec::new(),
+            set: GlobSet::empty(),
+        }
+    }
+}
+
+/// Workflow processor
+pub struct WorkflowProcessor {
+    workflows_dirs: Vec<PathBuf>,
+    pub(crate) dry_run: bool,
+    backup: bool,
+    /// Backups kept per file, counting the newest
+    backup_keep: usize,
+    /// Network requests made at once
+    pub(crate) concurrency: usize,
+    /// Files parsed at once
+    io_workers: usize,
+    ignore_files: IgnorePatterns,
+    /// Actions that must not be used at all, let alone pinned
+    blocked_actions: BlockedActions,
+    /// Which actions must be pinned, from the config's `[policy]`
+    pub(crate) policy: Option<Policy>,
+    respect_gitignore: bool,
+    comment_date: Option<String>,
+    /// Pin to bare SHAs, without version comments
+    no_comment: bool,
+    /// Comment written above each rewritten uses line, noting how the pin
+    /// was made
+    provenance: Option<String>,
+    /// Whether re-pinning a commented SHA pin rewrites its comment
+    replace_comment: ReplaceComment,
+    /// Log only totals, not each pin (--summary-only)
+    pub(crate) summary_only: bool,
+    lockfile: Option<PathBuf>,
+    /// Trust-on-first-use store of ref resolutions, if enabled
+    known_refs: Option<PathBuf>,
+    /// Local clones of action repositories, consulted before the network
+    mirror_dir: Option<PathBuf>,
+    /// Resolver cache primed before resolving and saved after
+    warm_cache: Option<PathBuf>,
+    /// Suggest prerelease tags as newer tags
+    allow_prerelease: bool,
+    /// Only process files changed since this base ref
+    changed_only: Option<String>,
+    /// Give SHA pins without a version comment one naming their tag
+    annotate: bool,
+    /// Record the caret range of each version tag pinned in its comment
+    record_constraint: bool,
+    /// Stop at the first action that fails to resolve
+    fail_fast: bool,
+    /// Confirm 40-hex pins are commits rather than tag or branch names
+    pub(crate) verify_shas: bool,
+    /// Hold abbreviated SHAs to full length, even from trusted owners
+    pub(crate) strict_sha: bool,
+    accept_changes: bool,
+    pub(crate) offline: bool,
+    repo_root: Option<PathBuf>,
+    /// Backend resolving refs to SHAs; a git resolver for the configured
+    /// remote when none is given
+    resolver: Option<Arc<dyn Resolver>>,
+    pub(crate) auth: Auth,
+    git_remote: GitRemote,
+    /// Only pin actions in these jobs (by key or name); all when empty
+    jobs: Vec<String>,
+    /// Only pin actions in these steps (by name or id); all when empty
+    steps: Vec<String>,
+    /// Client for API requests, sending the configured user agent and headers
+    pub(crate) http_client: reqwest::Client,
+    git_tmp_dir: Option<PathBuf>,
+    skip_unversioned: bool,
+    /// Ref assumed for uses written without one, instead of the default
+    /// branch
+    default_ref: Option<String>,
+    input_keys: Vec<String>,
+    events: Option<EventSink>,
+    /// Archive whose workflows are pinned in memory instead of on disk
+    archive: Option<Mutex<Archive>>,
+    /// Where the rewritten archive is saved, if anywhere
+    archive_out: Option<PathBuf>,
+    /// Patches recorded for each rewrite, when enabled
+    patches: Option<Mutex<Vec<FilePatch>>>,
+    /// Also pin job container images to digests
+    pin_images: bool,
+    /// REST API used to check action repositories are still maintained
+    health_api: Option<String>,
+    /// REST API used to look up whether action repositories are archived,
+    /// for those the GraphQL resolver didn't already answer
+    archived_api: Option<String>,
+    /// Whether each repository the GraphQL resolver looked up is archived
+    archived: Mutex<BTreeMap<String, bool>>,
+    /// Default branch of each repository the resolver listed while
+    /// resolving, by "owner/repo"
+    default_branches: Mutex<BTreeMap<String, String>>,
+    /// How the run's lookups were answered, for the results' metrics
+    lookups: Mutex<LookupStats>,
+    /// Time spent in each phase over every call so far, and when the
+    /// processor was made, for [`metrics`](Self::metrics)
+    pub(crate) phases: Mutex<PhaseDurations>,
+    created: Instant,
+    /// Record how lookups queue behind --jobs
+    timings: bool,
+    /// What the last resolution recorded, with `timings`
+    backpressure: Mutex<Option<Backpressure>>,
+    /// Verify each rewrite is stable under a second pass before writing it
+    assert_idempotent: bool,
+    /// Read workflows that aren't valid UTF-8 with replacement characters
+    /// rather than skipping them
+    lossy_utf8: bool,
+    /// REST API used to check the signatures of pinned tags
+    signatures_api: Option<String>,
+    /// Refuse to pin tags that aren't signed and verified
+    require_signed: bool,
+    /// Refuse to pin anything but signed, verified tags, branches included
+    require_signed_tags: bool,
+    /// SHAs mandated by the config file, by "owner/repo" or "owner/repo@ref"
+    overrides: BTreeMap<String, String>,
+}
+
+impl WorkflowProcessor {
+    pub fn new(
+        workflows_dirs: Vec<PathBuf>,
+        dry_run: bool,
+        backup: bool,
+        _skip_pinned: bool,
+        concurrency: usize,
+    ) -> Self {
+        Self {
+            workflows_dirs,
+            dry_run,
+            backup,
+            backup_keep: 1,
+            concurrency: effective_jobs(concurrency),
+            io_workers: effective_io_workers(0),
+            ignore_files: IgnorePatterns::default(),
+            blocked_actions: BlockedActions::default(),
+            policy: None,
+            respect_gitignore: false,
+            comment_date: None,
+            no_comment: false,
+            provenance: None,
+            replace_comment: ReplaceComment::default(),
+            summary_only: false,
+            lockfile: None,
+            known_refs: None,
+            mirror_dir: None,
+            warm_cache: None,
+            allow_prerelease: false,
+            changed_only: None,
+            annotate: false,
+            record_constraint: false,
+            fail_fast: false,
+            verify_shas: false,
+            strict_sha: false,
+            accept_changes: false,
+            offline: false,
+            repo_root: None,
+            resolver: None,
+            auth: Auth::anonymous(),
+            git_remote: GitRemote::default(),
+            jobs: Vec::new(),
+            steps: Vec::new(),
+            http_client: http::default_client(),
+            git_tmp_dir: None,
+            skip_unversioned: false,
+            default_ref: None,
+            input_keys: Vec::new(),
+            events: None,
+            archive: None,
+            archive_out: None,
+            patches: None,
+            pin_images: false,
+            health_api: None,
+            archived_api: None,
+            archived: Mutex::default(),
+            default_branches: Mutex::default(),
+            lookups: Mutex::default(),
+            phases: Mutex::default(),
+            created: Instant::now(),
+            timings: false,
+            backpressure: Mutex::default(),
+            assert_idempotent: false,
+            lossy_utf8: false,
+            signatures_api: None,
+            require_signed: false,
+            require_signed_tags: false,
+            overrides: BTreeMap::new(),
+        }
+    }
+
+    /// Parse up to `workers` files at once, independently of how many
+    /// requests resolution makes at once; 0 means the available parallelism
+    pub fn with_io_workers(mut self, workers: usize) -> Self {
+        self.io_workers = effective_io_workers(workers);
+        self
+    }
+
+    /// Skip workflow files matching any of the given patterns
+    pub fn with_ignore_files(mut self, ignore_files: IgnorePatterns) -> Self {
+        self.ignore_files = ignore_files;
+        self
+    }
+
+    /// Report uses of actions matching these patterns as policy violations,
+    /// never resolving or pinning them
+    pub fn with_blocked_actions(mut self, blocked_actions: BlockedActions) -> Self {
+        self.blocked_actions = blocked_actions;
+        self
+    }
+
+    /// Classify uses against `policy` in check mode, and leave the uses it
+    /// lets stay on a tag alone when pinning
+    pub fn with_policy(mut self, policy: Option<Policy>) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Resolve SHAs through `resolver` instead of a git resolver built from
+    /// the processor's own settings
+    pub fn with_resolver(mut self, resolver: Arc<dyn Resolver>) -> Self {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    /// Credentials for API-backed resolvers
+    pub fn with_auth(mut self, auth: Auth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Host and transport used to reach action repositories over git
+    pub fn with_git_remote(mut self, git_remote: GitRemote) -> Self {
+        self.git_remote = git_remote;
+        self
+    }
+
+    /// Restrict pinning to actions in the given jobs, matched by key or
+    /// `name:`
+    pub fn with_jobs(mut self, jobs: Vec<String>) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
+    /// Restrict pinning to actions in the given steps, matched by `name:`
+    /// or `id:`
+    pub fn with_steps(mut self, steps: Vec<String>) -> Self {
+        self.steps = steps;
+        self
+    }
+
+    /// Client used for the GraphQL, REST and registry APIs
+    pub fn with_http_client(mut self, http_client: reqwest::Client) -> Self {
+        self.http_client = http_client;
+        self
+    }
+
+    /// Directory in which git's scratch repository is created
+    pub fn with_git_tmp_dir(mut self, git_tmp_dir: Option<PathBuf>) -> Self {
+        self.git_tmp_dir = git_tmp_dir;
+        self
+    }
+
+    /// Leave actions without an @ref untouched instead of pinning their
+    /// default branch
+    pub fn with_skip_unversioned(mut self, skip_unversioned: bool) -> Self {
+        self.skip_unversioned = skip_unversioned;
+        self
+    }
+
+    /// Pin actions without an @ref at this ref rather than their default
+    /// branch, for actions that only publish e.g. a moving `main`. The pin
+    /// comment records that the ref was assumed
+    pub fn with_default_ref(mut self, default_ref: Option<String>) -> Self {
+        self.default_ref = default_ref;
+        self
+    }
+
+    /// Also pin `owner/repo@ref` values passed to these `with:` inputs
+    pub fn with_input_keys(mut self, input_keys: Vec<String>) -> Self {
+        self.input_keys = input_keys;
+        self
+    }
+
+    /// Read workflows from an archive rather than the workflows directories,
+    /// saving the rewritten archive to `out` when given
+    pub fn with_archive(mut self, archive: Option<Archive>, out: Option<PathBuf>) -> Self {
+        self.archive = archive.map(Mutex::new);
+        self.archive_out = out;
+        self
+    }
+
+    /// Record the original and rewritten content of every changed file in
+    /// the results, for tools that apply changes themselves
+    pub fn with_emit_patches(mut self, emit_patches: bool) -> Self {
+        self.patches = emit_patches.then(|| Mutex::new(Vec::new()));
+        self
+    }
+
+    /// Also pin job container images to their manifest digests
+    pub fn with_pin_images(mut self, pin_images: bool) -> Self {
+        self.pin_images = pin_images;
+        self
+    }
+
+    /// Warn about actions whose repository is archived or has moved, looked
+    /// up through the REST API at `api_url`
+    pub fn with_check_health(mut self, api_url: Option<String>) -> Self {
+        self.health_api = api_url;
+        self
+    }
+
+    /// Look up whether the repository of each action used is archived,
+    /// through the REST API at `api_url`. Repositories the GraphQL resolver
+    /// already reported on aren't looked up again
+    pub fn with_check_archived(mut self, api_url: Option<String>) -> Self {
+        self.archived_api = api_url;
+        self
+    }
+
+    /// Check whether each tag pinned carries a signature GitHub verified,
+    /// looked up through the REST API at `api_url`
+    pub fn with_verify_signatures(mut self, api_url: Option<String>) -> Self {
+        self.signatures_api = api_url;
+        self
+    }
+
+    /// Leave tags that are unsigned or unverified unpinned and report them
+    /// as errors (only with signature verification enabled)
+    pub fn with_require_signed(mut self, require_signed: bool) -> Self {
+        self.require_signed = require_signed;
+        self
+    }
+
+    /// Pin these actions to the given SHAs whatever their ref resolves to,
+    /// keyed by "owner/repo" or "owner/repo@ref"
+    pub fn with_overrides(mut self, overrides: BTreeMap<String, String>) -> Self {
+        self.overrides = overrides;
+        self
+    }
+
+    /// Pin only refs resolving to a signed, verified tag: on top of
+    /// [`with_require_signed`](Self::with_require_signed), branches and refs
+    /// with no tag of that name are errors too
+    pub fn with_require_signed_tags(mut self, require_signed_tags: bool) -> Self {
+        self.require_signed_tags = require_signed_tags;
+        self
+    }
+
+    /// Render each rewritten file a second time in memory and refuse to
+    /// write it if the second pass would change anything
+    pub fn with_assert_idempotent(mut self, assert_idempotent: bool) -> Self {
+        self.assert_idempotent = assert_idempotent;
+        self
+    }
+
+    /// Process workflows that aren't valid UTF-8 instead of skipping them,
+    /// rewriting only lines that are
+    pub fn with_lossy_utf8(mut self, lossy_utf8: bool) -> Self {
+        self.lossy_utf8 = lossy_utf8;
+        self
+    }
+
+    /// Patches recorded since the last call
+    pub(crate) fn take_patches(&self) -> Vec<FilePatch> {
+        self.patches
+            .as_ref()
+            .map(|patches| std::mem::take(&mut *patches.lock().unwrap()))
+            .unwrap_or_default()
+    }
+
+    /// Report each action's outcome to the given sink as it is processed
+    pub fn with_event_sink(mut self, events: Option<EventSink>) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Scan a whole repository for workflows, composite actions and
+    /// workflow templates
+    pub fn with_repo_root(mut self, repo_root: Option<PathBuf>) -> Self {
+        self.repo_root = repo_root;
+        self
+    }
+
+    /// Keep the last `keep` backups of each file rather than only the
+    /// newest
+    pub fn with_backup_keep(mut self, keep: usize) -> Self {
+        self.backup_keep = keep.max(1);
+        self
+    }
+
+    /// Honor .gitignore, global excludes and .git/info/exclude during
+    /// discovery
+    pub fn with_respect_gitignore(mut self, respect_gitignore: bool) -> Self {
+        self.respect_gitignore = respect_gitignore;
+        self
+    }
+
+    /// Record the given date (YYYY-MM-DD) in each new pin comment
+    pub fn with_comment_date(mut self, date: Option<String>) -> Self {
+        self.comment_date = date;
+        self
+    }
+
+    /// Pin to bare SHAs without a version comment, keeping any comment the
+    /// line already had
+    pub fn with_no_comment(mut self, no_comment: bool) -> Self {
+        self.no_comment = no_comment;
+        self
+    }
+
+    /// Write this comment on a line of its own above each rewritten uses
+    /// line, see [`provenance_comment`]
+    pub fn with_provenance(mut self, provenance: Option<String>) -> Self {
+        self.provenance = provenance;
+        self
+    }
+
+    /// Decide whether re-pinning a SHA pin that has a version comment, as
+    /// `update` does, rewrites the comment or keeps it as written
+    pub fn with_replace_comment(mut self, replace_comment: ReplaceComment) -> Self {
+        self.replace_comment = replace_comment;
+        self
+    }
+
+    /// Don't log each action and image as it is pinned or unpinned, for
+    /// runs that only want the totals
+    pub fn with_summary_only(mut self, summary_only: bool) -> Self {
+        self.summary_only = summary_only;
+        self
+    }
+
+    /// Record the caret range each version tag allows in its pin comment,
+    /// e.g. "# v4 constraint:^4.0.0"
+    pub fn with_record_constraint(mut self, record_constraint: bool) -> Self {
+        self.record_constraint = record_constraint;
+        self
+    }
+
+    /// Abort the run at the first action that fails to resolve, cancelling
+    /// the lookups still pending. Backends that batch lookups resolve one
+    /// action at a time then
+    pub fn with_fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+
+    /// Record the most lookups in flight and how long they queued across
+    /// every resolution of the run, reported as `backpressure` in the
+    /// results. Lookups a backend batches (GraphQL) aren't timed
+    pub fn with_timings(mut self, timings: bool) -> Self {
+        self.timings = timings;
+        self
+    }
+
+    /// Confirm each full-length SHA pin is a commit, not a tag or branch
+    /// named like one: reported by the strict-SHA check and `audit`, and
+    /// repinned to the ref's commit when pinning
+    pub fn with_verify_shas(mut self, verify_shas: bool) -> Self {
+        self.verify_shas = verify_shas;
+        self
+    }
+
+    /// Count abbreviated SHA pins against `check` even when the
+    /// `[policy]` trusts their owner
+    pub fn with_strict_sha(mut self, strict_sha: bool) -> Self {
+        self.strict_sha = strict_sha;
+        self
+    }
+
+    /// Read and record resolutions in the given lockfile
+    pub fn with_lockfile(mut self, lockfile: Option<PathBuf>) -> Self {
+        self.lockfile = lockfile;
+        self
+    }
+
+    /// Record each ref's first resolution in a trust-on-first-use store and
+    /// flag later resolutions that differ
+    pub fn with_known_refs(mut self, known_refs: Option<PathBuf>) -> Self {
+        self.known_refs = known_refs;
+        self
+    }
+
+    /// Accept refs that moved since first seen, updating the store and
+    /// pinning them to their new SHA
+    pub fn with_accept_changes(mut self, accept_changes: bool) -> Self {
+        self.accept_changes = accept_changes;
+        self
+    }
+
+    /// Resolve refs from local clones under `mirror_dir` before the network
+    pub fn with_mirror_dir(mut self, mirror_dir: Option<PathBuf>) -> Self {
+        self.mirror_dir = mirror_dir;
+        self
+    }
+
+    /// Prime the git resolver's cache from this file before resolving, and
+    /// save everything it resolved back to it afterwards
+    pub fn with_warm_cache(mut self, warm_cache: Option<PathBuf>) -> Self {
+        self.warm_cache = warm_cache;
+        self
+    }
+
+    /// Append a version comment naming the tag (or branch) at the SHA to
+    /// pins that have none
+    pub fn with_annotate(mut self, annotate: bool) -> Self {
+        self.annotate = annotate;
+        self
+    }
+
+    /// Only process files added or modified in the working tree since HEAD
+    /// forked from `base`
+    pub fn with_changed_only(mut self, base: Option<String>) -> Self {
+        self.changed_only = base;
+        self
+    }
+
+    /// Let --outdated suggest prerelease tags such as "v4.1.0-rc.1"
+    pub fn with_allow_prerelease(mut self, allow_prerelease: bool) -> Self {
+        self.allow_prerelease = allow_prerelease;
+        self
+    }
+
+    /// Resolve exclusively from the lockfile (and mirror), never touching
+    /// the network
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Find and parse every file to pin, without network access or changes.
+    /// Uses of blocked actions are set aside here, so whichever command runs
+    /// never resolves or rewrites them
+    pub async fn collect_actions(&self) -> Result<CollectedActions> {
+        let collected = self.collect_files().await?;
+        let mut phases = self.phases.lock().unwrap();
+        phases.discovery_ms += millis(collected.discovery);
+        phases.parse_ms += millis(collected.parse);
+        Ok(collected)
+    }
+
+    /// [`collect_actions`](Self::collect_actions) without adding to the
+    /// run's phase times
+    async fn collect_files(&self) -> Result<CollectedActions> {
+        let started = Instant::now();
+        if let Some(archive) = &self.archive {
+            let mut collected = self.collect_archive_actions(&archive.lock().unwrap());
+            self.select_steps(&mut collected.workflows);
+            collected.policy_violations = self.drop_blocked(&mut collected.workflows);
+            collected.parse = started.elapsed();
+            return Ok(collected);
+        }
+
+        let Discovery {
+            targets,
+            skipped: files_skipped,
+            unchanged: files_unchanged,
+        } = self.discover_targets()?;
+        let discovery = started.elapsed();
+
+        if files_unchanged > 0 {
+            info!(
+                "Skipping {} file(s) unchanged since {}",
+                files_unchanged,
+                self.changed_only.as_deref().unwrap_or_default()
+            );
+        }
+        if targets.is_empty() {
+            info!("No workflow files found");
+            return Ok(CollectedActions {
+                files_skipped,
+                files_unchanged,
+                discovery,
+                ..Default::default()
+            });
+        }
+
+        let mut files_by_kind = BTreeMap::new();
+        for target in &targets {
+            *files_by_kind.entry(target.kind).or_insert(0) += 1;
+        }
+        let workflow_files: Vec<PathBuf> = targets.into_iter().map(|t| t.path).collect();
+
+        info!("Found {} workflow file(s)", workflow_files.len());
+
+        let (mut workflows, errors, skipped_files) = self.parse_workflows(&workflow_files).await;
+        self.select_steps(&mut workflows);
+        let policy_violations = self.drop_blocked(&mut workflows);
+        let parse = started.elapsed() - discovery;
+
+        Ok(CollectedActions {
+            files_processed: workflow_files.len() - skipped_files.len(),
+            files_skipped: files_skipped + skipped_files.len(),
+            files_unchanged,
+            files_by_kind,
+            workflows,
+            errors,
+            skipped_files,
+            policy_violations,
+            discovery,
+            parse,
+        })
+    }
+
+    /// Drop the actions and images outside the jobs and steps given with
+    /// --job and --step, so their lines are left as they are
+    fn select_steps(&self, workflows: &mut [WorkflowFile]) {
+        if self.jobs.is_empty() && self.steps.is_empty() {
+            return;
+        }
+        for workflow in workflows {
+            let found = workflow.actions.len();
+            workflow
+                .actions
+                .retain(|uses| uses.context.matches(&self.jobs, &self.steps));
+            workflow
+                .images
+                .retain(|image| image.context.matches(&self.jobs, &self.steps));
+            debug!(
+                "Selected {} of {} action(s) in {}",
+                workflow.actions.len(),
+                found,
+                workflow.path
+            );
+        }
+    }
+
+    /// Parse the workflow files of an archive
+    fn collect_archive_actions(&self, archive: &Archive) -> CollectedActions {
+        let mut collected = CollectedActions::default();
+
+        for entry in archive.workflow_entries() {
+            let file_name = entry.name.rsplit('/').next().unwrap_or(&entry.name);
+            if let Some(pattern) = self.ignore_files.matching_pattern(file_name) {
+                debug!(
+                    "Skipping {} (matches ignore pattern '{}')",
+                    entry.name, pattern
+                );
+                collected.files_skipped += 1;
+                continue;
+            }
+
+            *collected
+                .files_by_kind
+                .entry(TargetKind::Workflow)
+                .or_insert(0) += 1;
+
+            // Entries are rewritten from memory, so they can't be read lossily
+            let content = match String::from_utf8(entry.data.clone()) {
+                Ok(content) => content,
+                Err(e) => {
+                    let reason = InvalidUtf8(e.utf8_error().valid_up_to());
+                    warn!("Skipping {}: {}", entry.name, reason);
+                    collected.files_skipped += 1;
+                    collected.skipped_files.push(SkippedFile {
+                        file: entry.name.clone(),
+                        reason: reason.to_string(),
+                    });
+                    continue;
+                },
+            };
+            collected.files_processed += 1;
+            collected.workflows.push(WorkflowFile::parse_str(
+                entry.name.clone(),
+                content,
+                &self.input_keys,
+            ));
+        }
+
+        info!(
+            "Found {} workflow file(s) in archive",
+            collected.files_processed
+        );
+        collected
+    }
+
+    /// Save the rewritten archive to the configured output, if any
+    pub(crate) fn save_archive(&self) -> Option<ErrorDetail> {
+        let (Some(archive), Some(out)) = (&self.archive, &self.archive_out) else {
+            return None;
+        };
+        if self.dry_run {
+            return None;
+        }
+
+        let path = out.display().to_string();
+        match archive.lock().unwrap().write(out) {
+            Ok(()) => {
+                info!("Wrote pinned archive to {}", path);
+                None
+            },
+            Err(e) => {
+                error!("Failed to write {}: {:#}", path, e);
+                Some(ErrorDetail::new(ErrorCode::Write, path, format!("{:#}", e)))
+            },
+        }
+    }
+
+    /// Pin (or plan pinning) every unpinned use
+    pub async fn process(&self) -> Result<ProcessResults> {
+        let mut results = self.pin_workflows().await?;
+        results.status = results.run_status();
+        Ok(results)
+    }
+
+    async fn pin_workflows(&self) -> Result<ProcessResults> {
+        let started = Instant::now();
+        let CollectedActions {
+            files_processed,
+            files_skipped,
+            files_unchanged,
+            files_by_kind,
+            workflows: mut parsed_workflows,
+            errors: mut errors_detail,
+            skipped_files,
+            policy_violations,
+            discovery,
+            parse,
+        } = self.collect_actions().await?;
+        let mut metrics = Metrics {
+            phases: PhaseDurations {
+                discovery_ms: millis(discovery),
+                parse_ms: millis(parse),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        if files_processed == 0 {
+            return Ok(ProcessResults {
+                dry_run: self.dry_run,
+                files_skipped,
+                skipped_files,
+                files_unchanged,
+                jobs: self.concurrency,
+                io_workers: self.io_workers,
+                metrics: self.take_metrics(metrics, started),
+                ..Default::default()
+            });
+        }
+        let resolving = Instant::now();
+
+        let mut remaining_unpinned: Vec<RemainingAction> = policy_violations
+            .iter()
+            .map(|violation| {
+                let (action, reference) = violation
+                    .action
+                    .split_once('@')
+                    .unwrap_or((&violation.action, ""));
+                RemainingAction {
+                    file: violation.file.clone(),
+                    line: violation.line,
+                    action: action.to_string(),
+                    reference: reference.to_string(),
+                    reason: RemainingReason::Excluded,
+                    detail: format!("blocked by '{}'", violation.pattern),
+                }
+            })
+            .collect();
+
+        let actions_found = parsed_workflows
+            .iter()
+            .map(|w| w.actions.len())
+            .sum::<usize>();
+
+        // Ref-less uses whose default branch is skipped or unknown are
+        // dropped by resolve_unversioned, so note them beforehand
+        let unversioned: Vec<(String, usize, String)> = parsed_workflows
+            .iter()
+            .flat_map(|workflow| {
+                workflow
+                    .actions
+                    .iter()
+                    .filter(|uses| uses.unversioned && !uses.ignored)
+                    .map(|uses| {
+                        (
+                            workflow.path.clone(),
+                            uses.line_number,
+                            uses.action.repository.clone(),
+                        )
+                    })
+            })
+            .collect();
+        let unversioned_errors = self.resolve_unversioned(&mut parsed_workflows).await;
+        for (file, line, action) in unversioned {
+            let kept = parsed_workflows.iter().any(|workflow| {
+                workflow.path == file
+                    && workflow
+                        .actions
+                        .iter()
+                        .any(|uses| uses.unversioned && uses.line_number == line)
+            });
+            if kept {
+                continue;
+            }
+            let (reason, detail) = match unversioned_errors.iter().find(|e| e.target == action) {
+                Some(error) => (RemainingReason::ResolutionFailed, error.message.clone()),
+                None => (RemainingReason::Skipped, "unversioned".to_string()),
+            };
+            remaining_unpinned.push(RemainingAction {
+                file,
+                line,
+                action,
+                reference: String::new(),
+                reason,
+                detail,
+            });
+        }
+        errors_detail.extend(unversioned_errors);
+        let unhealthy_actions = self.check_health(&parsed_workflows).await;
+
+        // Collect all unique actions that need pinning
+        let mut actions_to_resolve = HashMap::new();
+        let mut already_pinned = 0;
+        let mut actions_ignored = 0;
+        let mut actions_allowed = 0;
+
+        for workflow in &parsed_workflows {
+            already_pinned += workflow.pinned_count();
+
+            for uses in workflow.ignored_actions() {
+                actions_ignored += 1;
+                debug!(
+                    "Ignoring {} at {}:{}",
+                    uses.action, workflow.path, uses.line_number
+                );
+                self.emit(ProcessEvent::Skipped {
+                    file: workflow.path.clone(),
+                    line: uses.line_number,
+                    action: uses.action.to_string(),
+                    reason: "ignored by comment".to_string(),
+                });
+            }
+
+            for uses in workflow.actions.iter().filter(|u| u.action.is_sha) {
+                self.emit(ProcessEvent::Skipped {
+                    file: workflow.path.clone(),
+                    line: uses.line_number,
+                    action: uses.action.to_string(),
+                    reason: "already pinned".to_string(),
+                });
+            }
+
+            for uses in workflow.unpinned_actions() {
+                if self.allowed_by_policy(uses) {
+                    actions_allowed += 1;
+                    self.emit(ProcessEvent::Skipped {
+                        file: workflow.path.clone(),
+                        line: uses.line_number,
+                        action: uses.action.to_string(),
+                        reason: "allowed by policy".to_string(),
+                    });
+                    continue;
+                }
+                if uses.action.is_expression() {
+                    self.emit(ProcessEvent::Skipped {
+                        file: workflow.path.clone(),
+                        line: uses.line_number,
+                        action: uses.action.to_string(),
+                        reason: "dynamic ref".to_string(),
+                    });
+                    continue;
+                }
+                let key = uses.action.to_string();
+                actions_to_resolve
+                    .entry(key)
+                    .or_insert_with(|| uses.action.clone());
+            }
+        }
+
+        let mut pins_to_annotate = HashMap::new();
+        if self.annotate {
+            for workflow in &parsed_workflows {
+                for uses in workflow.uncommented_pins() {
+                    pins_to_annotate
+                        .entry(uses.action.to_string())
+                        .or_insert_with(|| uses.action.clone());
+                }
+            }
+        }
+
+        let mut images_to_resolve = HashMap::new();
+        let mut images_found_by_kind = BTreeMap::new();
+        let mut images_already_pinned_by_kind = BTreeMap::new();
+        if self.pin_images {
+            for workflow in &parsed_workflows {
+                for line in &workflow.images {
+                    *images_found_by_kind.entry(line.kind).or_insert(0) += 1;
+                    if line.image.is_pinned() {
+                        *images_already_pinned_by_kind.entry(line.kind).or_insert(0) += 1;
+                    }
+                }
+                for line in workflow.unpinned_images() {
+                    images_to_resolve
+                        .entry(line.image.to_string())
+                        .or_insert_with(|| line.image.clone());
+                }
+            }
+        }
+        let images_found = images_found_by_kind.values().sum();
+        let images_already_pinned = images_already_pinned_by_kind.values().sum();
+
+        let (sha_collisions, collision_errors) = self.find_sha_collisions(&parsed_workflows).await;
+        errors_detail.extend(collision_errors);
+
+        if actions_to_resolve.is_empty()
+            && images_to_resolve.is_empty()
+            && pins_to_annotate.is_empty()
+            && sha_collisions.is_empty()
+        {
+            info!("No actions need pinning");
+            for workflow in &parsed_workflows {
+                remaining_unpinned.extend(self.remaining_in(
+                    workflow,
+                    &HashMap::new(),
+                    &errors_detail,
+                    &[],
+                    true,
+                ));
+            }
+            remaining_unpinned.sort_by(|a, b| (&a.file, a.line).cmp(&(&b.file, b.line)));
+            return Ok(ProcessResults {
+                dry_run: self.dry_run,
+                files_processed,
+                files_skipped,
+                skipped_files,
+                files_unchanged,
+                files_by_kind,
+                actions_found,
+                already_pinned,
+                actions_ignored,
+                actions_allowed,
+                errors: errors_detail.len(),
+                errors_detail,
+                jobs: self.concurrency,
+                io_workers: self.io_workers,
+                images_found,
+                images_found_by_kind,
+                images_already_pinned,
+                images_already_pinned_by_kind,
+                policy_violations,
+                archived_actions: self.archived_actions(
+                    &parsed_workflows,
+                    &[
+                        unhealthy_actions.clone(),
+                        self.check_archived(&parsed_workflows).await,
+                    ]
+                    .concat(),
+                ),
+                unhealthy_actions,
+                remaining_unpinned,
+                ..Default::default()
+            });
+        }
+
+        let mut lockfile = self.load_lockfile()?;
+        let (mut shas, resolve_errors) = if actions_to_resolve.is_empty() {
+            Default::default()
+        } else {
+            info!("Resolving {} unique action(s)", actions_to_resolve.len());
+            self.resolve_actions(
+                actions_to_resolve.values().cloned().collect(),
+                lockfile.as_mut(),
+            )
+            .await
+        };
+
+        self.stop_on_error(&resolve_errors, lockfile.as_ref())?;
+        errors_detail.extend(resolve_errors);
+        let moved_refs = self.check_known_refs(&mut shas, true).await?;
+
+        let (digests, image_errors) = self
+            .resolve_images(images_to_resolve.into_values().collect(), lockfile.as_mut())
+            .await;
+        errors_detail.extend(image_errors);
+
+        let branch_refs = self
+            .default_branch_refs(&parsed_workflows, &actions_to_resolve)
+            .await;
+
+        // Refs only ever assumed for ref-less uses say so in their comment
+        let mut assumed_refs = HashSet::new();
+        if self.default_ref.is_some() {
+            let uses = parsed_workflows
+                .iter()
+                .flat_map(|workflow| &workflow.actions);
+            assumed_refs.extend(
+                uses.clone()
+                    .filter(|uses| uses.unversioned)
+                    .map(|uses| uses.action.to_string()),
+            );
+            for uses in uses.filter(|uses| !uses.unversioned) {
+                assumed_refs.remove(&uses.action.to_string());
+            }
+        }
+
+        let (signatures, signature_errors) = self
+            .verify_signatures(&actions_to_resolve, &mut shas, &branch_refs)
+            .await;
+        errors_detail.extend(signature_errors);
+
+        let mut pinned_map: HashMap<String, PinnedAction> = actions_to_resolve
+            .into_iter()
+            .filter_map(|(key, action)| {
+                let sha = shas.get(&key)?.clone();
+                let on_branch = branch_refs.contains(&key);
+                let pinned = self
+                    .pin(action, sha)
+                    .with_branch(on_branch)
+                    .with_default_ref(assumed_refs.contains(&key))
+                    .with_signature(signatures.get(&key).copied());
+                Some((key, pinned))
+            })
+            .collect();
+
+        // A tag or branch named like a SHA is pinned to the commit it points
+        // at, keeping its name in the comment
+        pinned_map.extend(sha_collisions.iter().map(|collision| {
+            let action = ActionRef {
+                repository: collision.action.clone(),
+                reference: collision.reference.clone(),
+                is_sha: true,
+                ref_kind: None,
+            };
+            let pinned = self
+                .pin(action.clone(), collision.commit.clone())
+                .with_branch(collision.kind == RefKind::Branch);
+            (action.to_string(), pinned)
+        }));
+
+        // A SHA pin maps to itself, only gaining a comment
+        let (annotations, unnamed, annotate_errors) = self.annotate_pins(&pins_to_annotate).await;
+        errors_detail.extend(annotate_errors);
+        pinned_map.extend(annotations);
+        let unidentifiable: Vec<UnidentifiablePin> = parsed_workflows
+            .iter()
+            .flat_map(|workflow| {
+                workflow
+                    .uncommented_pins()
+                    .into_iter()
+                    .filter(|uses| unnamed.contains(&uses.action.to_string()))
+                    .map(|uses| UnidentifiablePin {
+                        file: workflow.path.clone(),
+                        line: uses.line_number,
+                        action: uses.action.repository.clone(),
+                        sha: uses.action.reference.clone(),
+                    })
+            })
+            .collect();
+
+        if let (Some(lockfile), Some(path)) = (&lockfile, &self.lockfile) {
+            if !self.dry_run {
+                lockfile.save(path)?;
+            }
+        }
+
+        let archived_actions = self.archived_actions(
+            &parsed_workflows,
+            &[
+                unhealthy_actions.clone(),
+                self.check_archived(&parsed_workflows).await,
+            ]
+            .concat(),
+        );
+
+        // Rewrite workflow files
+        metrics.phases.resolve_ms = millis(resolving.elapsed());
+        let rewriting = Instant::now();
+        let mut pinned_actions = Vec::new();
+        let mut pinned_images = Vec::new();
+        let mut planned_changes = Vec::new();
+        let mut modified_files = Vec::new();
+        let mut lines_changed = 0;
+        let mut files_changed = 0;
+        let mut files_untouched = 0;
+
+        for mut workflow in parsed_workflows {
+            // Pins that already have a comment share their key with bare
+            // ones being annotated, so keep them out of the rewrite
+            if self.annotate {
+                workflow.actions.retain(|uses| {
+                    !uses.action.is_sha || (uses.comment_ref.is_none() && !uses.ignored)
+                });
+            }
+            let rewritten = self.rewrite_workflow_with_images(
+                &workflow,
+                &pinned_map,
+                &digests,
+                &mut pinned_actions,
+                &mut pinned_images,
+                &mut planned_changes,
+            );
+            let written = rewritten.is_ok();
+            match rewritten {
+                Ok(changed) => {
+                    lines_changed += changed;
+                    if changed > 0 {
+                        files_changed += 1;
+                        modified_files.push(workflow.path.clone());
+                    } else {
+                        files_untouched += 1;
+                    }
+                },
+                Err(e) => {
+                    error!("Failed to rewrite {}: {}", workflow.path, e);
+                    errors_detail.push(write_error(&workflow, &e));
+                },
+            }
+            remaining_unpinned.extend(self.remaining_in(
+                &workflow,
+                &pinned_map,
+                &errors_detail,
+                &moved_refs,
+                written,
+            ));
+        }
+        errors_detail.extend(self.save_archive());
+        metrics.phases.rewrite_ms = millis(rewriting.elapsed());
+        remaining_unpinned.sort_by(|a, b| (&a.file, a.line).cmp(&(&b.file, b.line)));
+
+        let (annotated_actions, pinned_actions): (Vec<_>, Vec<_>) = pinned_actions
+            .into_iter()
+            .partition(|pinned| pinned.old_ref == pinned.sha);
+        // Only pins that made it into a file (or would, under dry-run)
+        let actions_pinned = pinned_actions
+            .iter()
+            .filter(|pinned| pinned.applied || self.dry_run)
+            .count();
+
+        let mut images_pinned_by_kind = BTreeMap::new();
+        for pinned in &pinned_images {
+            *images_pinned_by_kind.entry(pinned.kind).or_insert(0) += 1;
+        }
+
+        Ok(ProcessResults {
+            status: RunStatus::default(),
+            dry_run: self.dry_run,
+            files_processed,
+            files_skipped,
+            skipped_files,
+            files_unchanged,
+            files_by_kind,
+            actions_found,
+            actions_pinned,
+            already_pinned,
+            actions_ignored,
+            actions_allowed,
+            actions_annotated: annotated_actions.len(),
+            annotated_actions,
+            unidentifiable,
+            errors: errors_detail.len(),
+            errors_detail,
+            lines_changed,
+            files_changed,
+            files_untouched,
+            modified_files,
+            jobs: self.concurrency,
+            io_workers: self.io_workers,
+            pinned_actions,
+            images_found,
+            images_pinned: pinned_images.len(),
+            images_pinned_by_kind,
+            moved_refs,
+            archived_actions,
+            policy_violations,
+            unhealthy_actions,
+            images_already_pinned,
+            images_already_pinned_by_kind,
+            images_found_by_kind,
+            pinned_images,
+            patches: self.take_patches(),
+            planned_changes,
+            sha_collisions,
+            remaining_unpinned,
+            backpressure: self.backpressure.lock().unwrap().take(),
+            metrics: self.take_metrics(metrics, started),
+        })
+    }
+
+    /// Complete `metrics` with the lookups made since the last call and the
+    /// wall time since `started`
+    fn take_metrics(&self, mut metrics: Metrics, started: Instant) -> Metrics {
+        std::mem::take(&mut *self.lookups.lock().unwrap()).record(&mut metrics);
+        metrics.total_ms = millis(started.elapsed());
+        metrics
+    }
+
+    /// Where the processor's calls so far spent their time and how their
+    /// lookups were answered, for commands whose results don't carry
+    /// [`Metrics`] of their own
+    pub fn metrics(&self) -> Metrics {
+        let mut metrics = Metrics {
+            phases: self.phases.lock().unwrap().clone(),
+            ..Default::default()
+        };
+        self.lookups.lock().unwrap().record(&mut metrics);
+        metrics.total_ms = millis(self.created.elapsed());
+        metrics
+    }
+
+    /// Name the commit of each bare SHA pin after the tag (or branch) at it,
+    /// returning the pins to rewrite with that comment, the keys nothing
+    /// names and the lookups that failed
+    async fn annotate_pins(
+        &self,
+        pins: &HashMap<String, ActionRef>,
+    ) -> (
+        HashMap<String, PinnedAction>,
+        HashSet<String>,
+        Vec<ErrorDetail>,
+    ) {
+        use futures::stream::{self, StreamExt};
+
+        let mut annotations = HashMap::new();
+        let mut unnamed = HashSet::new();
+        let mut errors = Vec::new();
+        if pins.is_empty() {
+            return (annotations, unnamed, errors);
+        }
+
+        info!("Naming {} pinned commit(s)", pins.len());
+        let git = self.git_resolver();
+        let results: Vec<_> = stream::iter(pins)
+            .map(|(key, action)| {
+                let git = &git;
+                async move {
+                    (
+                        key,
+                        action,
+                        git.name_commit(action, &action.reference).await,
+                    )
+                }
+            })
+            .buffer_unordered(self.concurrency)
+            .collect()
+            .await;
+
+        for (key, action, result) in results {
+            match result {
+                Ok(Some(name)) => {
+                    let (name, on_branch) = match name {
+                        CommitName::Tag(tag) => (tag, false),
+                        CommitName::Branch(branch) => (branch, true),
+                    };
+                    debug!("{} is {}", key, name);
+                    let mut pinned = self
+                        .pin(action.clone(), action.reference.clone())
+                        .with_branch(on_branch);
+                    pinned.original_ref = name;
+                    annotations.insert(key.clone(), pinned);
+                },
+                Ok(None) => {
+                    warn!("No tag or branch points at {}; leaving it unannotated", key);
+                    unnamed.insert(key.clone());
+                },
+                Err(e) => {
+                    warn!(action = %key, "Failed to name {}: {:#}", key, e);
+                    errors.push(self.fail(
+                        ErrorCode::classify_resolve(&e),
+                        key.clone(),
+                        format!("{:#}", e),
+                    ));
+                },
+            }
+        }
+
+        (annotations, unnamed, errors)
+    }
+
+    /// Send an event to the configured sink, if any
+    fn emit(&self, event: ProcessEvent) {
+        if let Some(events) = &self.events {
+            events(&event);
+        }
+    }
+
+    /// Report an action that failed to resolve, returning its error entry
+    pub(crate) fn fail(&self, code: ErrorCode, action: String, message: String) -> ErrorDetail {
+        self.emit(ProcessEvent::Failed {
+            action: action.clone(),
+            code,
+            error: message.clone(),
+        });
+        ErrorDetail::new(code, action, message)
+    }
+
+    /// A git resolver for the configured remote and scratch location
+    pub(crate) fn git_resolver(&self) -> GitResolver {
+        GitResolver::new()
+            .with_remote(self.git_remote.clone())
+            .with_scratch_parent(self.git_tmp_dir.clone())
+            .with_mirror_dir(self.mirror_dir.clone())
+            .with_offline(self.offline)
+            .with_allow_prerelease(self.allow_prerelease)
+    }
+
+    /// Point ref-less uses at --default-ref or their repository's default
+    /// branch, or drop them from pinning when skipped or unresolvable.
+    /// Returns the errors
+    pub(crate) async fn resolve_unversioned(
+        &self,
+        workflows: &mut [WorkflowFile],
+    ) -> Vec<ErrorDetail> {
+        let mut repositories: Vec<ActionRef> = Vec::new();
+        for workflow in workflows.iter() {
+            for uses in workflow
+                .actions
+                .iter()
+                .filter(|u| u.unversioned && !u.ignored)
+            {
+                if self.skip_unversioned {
+                    self.emit(ProcessEvent::Skipped {
+                        file: workflow.path.clone(),
+                        line: uses.line_number,
+                        action: uses.action.repository.clone(),
+                        reason: "unversioned".to_string(),
+                    });
+                } else if !repositories.contains(&uses.action) {
+                    repositories.push(uses.action.clone());
+                }
+            }
+        }
+
+        let mut branches = HashMap::new();
+        let mut errors = Vec::new();
+
+        if let Some(reference) = &self.default_ref {
+            for action in repositories.drain(..) {
+                debug!("Assuming {}@{}", action.repository, reference);
+                branches.insert(action.repository, reference.clone());
+            }
+        }
+
+        if !repositories.is_empty() {
+            let git = self.git_resolver();
+            for action in repositories {
+                let branch = if self.offline && git.mirror_path(&action).is_none() {
+                    Err((
+                        ErrorCode::Offline,
+                        "default branch unknown (offline mode)".to_string(),
+                    ))
+                } else {
+                    git.default_branch(&action)
+                        .await
+                        .map_err(|e| (ErrorCode::classify_resolve(&e), format!("{:#}", e)))
+                };
+
+                match branch {
+                    Ok(branch) => {
+                        debug!("Default branch of {} is {}", action.repository, branch);
+                        branches.insert(action.repository, branch);
+                    },
+                    Err((code, message)) => {
+                        warn!("Failed to resolve {}: {}", action.repository, message);
+                        errors.push(self.fail(code, action.repository.clone(), message));
+                    },
+                }
+            }
+        }
+
+        for workflow in workflows.iter_mut() {
+            workflow.actions.retain_mut(|uses| {
+                if !uses.unversioned || uses.ignored {
+                    return true;
+                }
+                match branches.get(&uses.action.repository) {
+                    Some(branch) => {
+                        uses.action.reference = branch.clone();
+                        true
+                    },
+                    None => false,
+                }
+            });
+        }
+
+        errors
+    }
+
+    /// Keys of the actions to pin whose ref is their repository's default
+    /// branch: ref-less uses, plus names such as "master" that match it.
+    /// Repositories the resolver listed already are answered from that; the
+    /// rest (e.g. lockfile hits) are looked up together
+    async fn default_branch_refs(
+        &self,
+        workflows: &[WorkflowFile],
+        actions: &HashMap<String, ActionRef>,
+    ) -> HashSet<String> {
+        use futures::stream::{self, StreamExt};
+
+        // An assumed --default-ref may be a tag, so is looked up like any
+        // other name
+        let mut keys: HashSet<String> = workflows
+            .iter()
+            .flat_map(|workflow| &workflow.actions)
+            .filter(|uses| uses.unversioned && self.default_ref.is_none())
+            .map(|uses| uses.action.to_string())
+            .collect();
+
+        // Only names that could be a branch are worth a lookup
+        let candidates: Vec<(&String, &ActionRef)> = actions
+            .iter()
+            .filter(|(key, action)| {
+                !keys.contains(*key)
+                    && action.ref_kind.is_none()
+                    && !action.is_version_like()
+                    && !action.is_short_sha()
+            })
+            .collect();
+        if candidates.is_empty() {
+            return keys;
+        }
+
+        let mut branches = self.default_branches.lock().unwrap().clone();
+        let mut unknown: HashMap<&str, &ActionRef> = HashMap::new();
+        for (_, action) in &candidates {
+            let repository = action.base_repository();
+            if !branches.contains_key(repository) {
+                unknown.entry(repository).or_insert(action);
+            }
+        }
+        let can_look_up = !self.offline || self.mirror_dir.is_some();
+        if can_look_up && !unknown.is_empty() {
+            let git = self.git_resolver();
+            let git = &git;
+            let found: Vec<(String, String)> = stream::iter(unknown)
+                .map(|(repository, action)| async move {
+                    let branch = git
+                        .default_branch(action)
+                        .await
+                        .map_err(|e| debug!("No default branch for {}: {:#}", repository, e))
+                        .ok();
+                    branch.map(|branch| (repository.to_string(), branch))
+                })
+                .buffer_unordered(self.concurrency)
+                .filter_map(|found| async move { found })
+                .collect()
+                .await;
+            branches.extend(found);
+        }
+
+        for (key, action) in candidates {
+            if branches.get(action.base_repository()) == Some(&action.reference) {
+                keys.insert(key.clone());
+            }
+        }
+
+        keys
+    }
+
+    /// Load the configured lockfile, if any
+    pub(crate) fn load_lockfile(&self) -> Result<Option<Lockfile>> {
+        self.lockfile.as_ref().map(Lockfile::load).transpose()
+    }
+
+    /// Resolve actions to SHAs keyed by "owner/repo@ref", answering from the
+    /// lockfile before going to the network and recording new resolutions in
+    /// it. Actions with a config override skip the lockfile so the override
+    /// always wins. Returns the SHAs and the actions that failed to resolve.
+    pub(crate) async fn resolve_actions(
+        &self,
+        actions: Vec<ActionRef>,
+        lockfile: Option<&mut Lockfile>,
+    ) -> (HashMap<String, String>, Vec<ErrorDetail>) {
+        let started = Instant::now();
+        let resolved = self.lookup_actions(actions, lockfile).await;
+        self.phases.lock().unwrap().resolve_ms += millis(started.elapsed());
+        resolved
+    }
+
+    /// [`resolve_actions`](Self::resolve_actions) without adding to the
+    /// run's phase times
+    async fn lookup_actions(
+        &self,
+        actions: Vec<ActionRef>,
+        mut lockfile: Option<&mut Lockfile>,
+    ) -> (HashMap<String, String>, Vec<ErrorDetail>) {
+        let mut shas = HashMap::new();
+        let mut errors = Vec::new();
+        let git = self.git_resolver();
+        let resolver: Arc<dyn Resolver> = match &self.resolver {
+            Some(resolver) => resolver.clone(),
+            None => Arc::new(git.clone()),
+        };
+        let resolver: Arc<dyn Resolver> = if self.overrides.is_empty() {
+            resolver
+        } else {
+            Arc::new(OverrideResolver::new(resolver, self.overrides.clone()))
+        };
+        // Always metered for the results' metrics; a batching backend keeps
+        // its batches, which go untimed
+        let metered = Arc::new(MeteredResolver::new(resolver));
+        let resolver: Arc<dyn Resolver> = metered.clone();
+        // The backend outlives this call, so only count its retries from now
+        let earlier_retries = resolver.retries();
+        if let Some(path) = &self.warm_cache {
+            match git::load_cache(path) {
+                Ok(entries) => resolver.prime(entries),
+                Err(e) => warn!("Ignoring resolver cache: {:#}", e),
+            }
+        }
+
+        let mut actions_vec: Vec<ActionRef> = Vec::new();
+        for action in actions {
+            let key = action.to_string();
+            // Refspecs and patterns would otherwise fall into partial matching
+            if let Some(c) = action.refspec_char() {
+                let message = format!(
+                    "'{}' is refspec syntax, not part of a tag or branch name (only a trailing \
+                     ^{{}} for the commit a tag marks is understood)",
+                    c
+                );
+                warn!(action = %key, "Failed to resolve {}: {}", key, message);
+                errors.push(self.fail(ErrorCode::RefNotFound, key, message));
+                continue;
+            }
+            let overridden = resolver::override_for(&self.overrides, &action).is_some();
+            match lockfile
+                .as_deref()
+                .and_then(|lockfile| lockfile.get(&key))
+                .filter(|_| !overridden)
+            {
+                Some(sha) => {
+                    debug!(action = %key, %sha, source = "lockfile", "Lockfile hit for {} → {}", key, sha);
+                    self.lookups.lock().unwrap().cache_hits += 1;
+                    shas.insert(key, sha.clone());
+                },
+                None if self.offline && git.mirror_path(&action).is_none() && !overridden => {
+                    warn!(
+                        action = %key,
+                        "Failed to resolve {}: not in the lockfile (offline mode)",
+                        key
+                    );
+                    errors.push(self.fail(
+                        ErrorCode::Offline,
+                        key,
+                        "not in the lockfile (offline mode)".to_string(),
+                    ));
+                },
+                None => actions_vec.push(action),
+            }
+        }
+
+        if self.fail_fast && !errors.is_empty() {
+            return (shas, errors);
+        }
+
+        // Resolve SHAs with progress bar
+        let progress = ProgressBar::new(actions_vec.len() as u64);
+        progress.set_style(
+            ProgressStyle::default_bar()
+                .template("{spinner:.green} [{bar:40.cyan/blue}] {pos}/{len} {msg}")
+                .unwrap()
+                .progress_chars("#>-"),
+        );
+
+        let results = if self.fail_fast {
+            resolver::resolve_until_error(resolver.as_ref(), actions_vec, self.concurrency).await
+        } else {
+            resolver.resolve_all(actions_vec, self.concurrency).await
+        };
+        self.archived.lock().unwrap().extend(resolver.archived());
+        self.default_branches
+            .lock()
+            .unwrap()
+            .extend(resolver.default_branches());
+        if self.timings {
+            // A run can resolve in several calls, e.g. actions and then refs
+            // looked up again for the known refs store
+            let batch = metered.backpressure(self.concurrency);
+            self.backpressure
+                .lock()
+                .unwrap()
+                .get_or_insert_with(Backpressure::default)
+                .merge(&batch);
+        }
+        {
+            let mut lookups = self.lookups.lock().unwrap();
+            for (_, result) in &results {
+                match result.as_ref().map(|resolution| resolution.source) {
+                    Ok(Source::Cache | Source::Fixed) => lookups.cache_hits += 1,
+                    Ok(source) if source.is_network() => lookups.network += 1,
+                    _ => {},
+                }
+            }
+            lookups.retries += metered.retries() - earlier_retries;
+            lookups.latencies.extend(metered.latencies());
+        }
+
+        for (action, result) in results {
+            progress.inc(1);
+            match result {
+                Ok(Resolution { sha, source, .. }) => {
+                    progress.set_message(format!("✓ {}", action.repository.green()));
+                    debug!(action = %action, %sha, %source, "Resolved {} → {}", action, sha);
+                    if let Some(lockfile) = lockfile.as_deref_mut() {
+                        lockfile.insert(action.to_string(), sha.clone());
+                    }
+                    shas.insert(action.to_string(), sha);
+                },
+                Err(e) => {
+                    progress.set_message(format!("✗ {}", action.repository.red()));
+                    warn!(action = %action, "Failed to resolve {}: {}", action, e);
+                    errors.push(self.fail(
+                        ErrorCode::classify_resolve(&e),
+                        action.to_string(),
+                        format!("{:#}", e),
+                    ));
+                },
+            }
+        }
+
+        progress.finish_with_message("Resolution complete");
+
+        if let Some(path) = &self.warm_cache {
+            if let Err(e) = git::save_cache(path, &resolver.snapshot()) {
+                warn!("Failed to save resolver cache: {:#}", e);
+            }
+        }
+
+        (shas, errors)
+    }
+
+    /// With --fail-fast, turn the first resolution error into the run's
+    /// error. What did resolve isn't lost: `resolve_actions` has already
+    /// saved the warm cache, and a `lockfile` being updated is saved first
+    pub(crate) fn stop_on_error(
+        &self,
+        errors: &[ErrorDetail],
+        lockfile: Option<&Lockfile>,
+    ) -> Result<()> {
+        let Some(error) = errors.first().filter(|_| self.fail_fast) else {
+            return Ok(());
+        };
+        if let (Some(lockfile), Some(path)) = (lockfile, &self.lockfile) {
+            if !self.dry_run {
+                lockfile.save(path)?;
+            }
+        }
+        anyhow::bail!(
+            "Stopped at the first resolution error (--fail-fast): {}: {}",
+            error.target,
+            error.message
+        )
+    }
+
+    /// Check the signature of every resolved tag. Branches aren't tags and
+    /// are skipped. With `require_signed`, tags that aren't verified (or
+    /// couldn't be checked) are reported as errors and dropped from `shas`
+    /// so they stay unpinned; with `require_signed_tags`, so are branches
+    /// and refs that turn out not to be tags
+    async fn verify_signatures(
+        &self,
+        actions: &HashMap<String, ActionRef>,
+        shas: &mut HashMap<String, String>,
+        branch_refs: &HashSet<String>,
+    ) -> (HashMap<String, SignatureStatus>, Vec<ErrorDetail>) {
+        use futures::stream::{self, StreamExt};
+
+        let mut signatures = HashMap::new();
+        let mut errors = Vec::new();
+        let Some(api_url) = &self.signatures_api else {
+            return (signatures, errors);
+        };
+
+        let (tags, branches): (Vec<_>, Vec<_>) = actions
+            .iter()
+            .filter(|(key, _)| shas.contains_key(*key))
+            .partition(|(key, action)| {
+                !branch_refs.contains(*key) && action.ref_kind != Some(RefKind::Branch)
+            });
+        if self.require_signed_tags {
+            for (key, _) in branches {
+                warn!("Not pinning {}: a branch, not a signed tag", key);
+                shas.remove(key);
+                errors.push(self.fail(
+                    ErrorCode::Signature,
+                    key.clone(),
+                    "a branch, not a signed tag".to_string(),
+                ));
+            }
+        }
+        if tags.is_empty() {
+            return (signatures, errors);
+        }
+
+        info!("Checking signatures of {} tag(s)", tags.len());
+        let checker = SignatureChecker::new(api_url.clone(), self.auth.token.clone())
+            .with_client(self.http_client.clone());
+        let results: Vec<(&String, Result<Option<SignatureStatus>>)> = stream::iter(tags)
+            .map(|(key, action)| {
+                let checker = &checker;
+                let sha = &shas[key];
+                async move {
+                    let status = checker
+                        .check_tag(action.base_repository(), &action.reference, sha)
+                        .await;
+                    (key, status)
+                }
+            })
+            .buffer_unordered(self.concurrency)
+            .collect()
+            .await;
+
+        for (key, result) in results {
+            let (code, problem) = match result {
+                Ok(Some(SignatureStatus::Verified)) => {
+                    signatures.insert(key.clone(), SignatureStatus::Verified);
+                    continue;
+                },
+                // Not a tag after all, so there's no signature to check
+                Ok(None) if !self.require_signed_tags => continue,
+                Ok(None) => (ErrorCode::Signature, "not a tag".to_string()),
+                Ok(Some(status)) => {
+                    signatures.insert(key.clone(), status);
+                    (ErrorCode::Signature, format!("tag is {}", status))
+                },
+                // Whatever the signature, it isn't on the commit being pinned
+                Err(e) if e.downcast_ref::<TargetMismatch>().is_some() => {
+                    warn!("Not pinning {}: {:#}", key, e);
+                    shas.remove(key);
+                    errors.push(self.fail(ErrorCode::Signature, key.clone(), format!("{:#}", e)));
+                    continue;
+                },
+                Err(e) => (
+                    ErrorCode::classify_resolve(&e),
+                    format!("failed to check signature: {:#}", e),
+                ),
+            };
+
+            if self.require_signed || self.require_signed_tags {
+                warn!("Not pinning {}: {}", key, problem);
+                shas.remove(key);
+                errors.push(self.fail(code, key.clone(), problem));
+            } else {
+                debug!("{}: {}", key, problem);
+            }
+        }
+
+        (signatures, errors)
+    }
+
+    /// Whether the config's `[policy]` lets the use stay on its tag
+    fn allowed_by_policy(&self, uses: &UsesLine) -> bool {
+        self.policy
+            .as_ref()
+            .is_some_and(|policy| policy.evaluate(uses) == PolicyReason::TrustedOwner)
+    }
+
+    /// The uses of `workflow` still on a ref once `pinned` is applied, or
+    /// all of them when the file wasn't `written`, each with why. Failures
+    /// are looked up in `errors`, and refs held back by the known refs store
+    /// in `moved`, by the "owner/repo@ref" they concern
+    fn remaining_in(
+        &self,
+        workflow: &WorkflowFile,
+        pinned: &HashMap<String, PinnedAction>,
+        errors: &[ErrorDetail],
+        moved: &[MovedRef],
+        written: bool,
+    ) -> Vec<RemainingAction> {
+        workflow
+            .actions
+            .iter()
+            .filter(|uses| !uses.action.is_sha)
+            .filter_map(|uses| {
+                let key = uses.action.to_string();
+                let (reason, detail) = if uses.ignored {
+                    (RemainingReason::Excluded, "ignored by comment".to_string())
+                } else if self.allowed_by_policy(uses) {
+                    (RemainingReason::Excluded, "allowed by policy".to_string())
+                } else if uses.action.is_expression() {
+                    (
+                        RemainingReason::DynamicRef,
+                        "only known when the workflow runs".to_string(),
+                    )
+                } else if !pinned.contains_key(&key) {
+                    match errors.iter().find(|e| e.target == key) {
+                        Some(error) if error.code == ErrorCode::Signature => {
+                            (RemainingReason::Skipped, error.message.clone())
+                        },
+                        Some(error) => (RemainingReason::ResolutionFailed, error.message.clone()),
+                        None => match moved.iter().find(|moved| moved.action == key) {
+                            Some(moved) => (
+                                RemainingReason::Skipped,
+                                format!(
+                                    "ref moved from {} (first seen {}) to {}",
+                                    moved.known_sha, moved.first_seen, moved.resolved_sha
+                                ),
+                            ),
+                            None => (
+                                RemainingReason::ResolutionFailed,
+                                "not resolved".to_string(),
+                            ),
+                        },
+                    }
+                } else if !written {
+                    (
+                        RemainingReason::Skipped,
+                        "the file couldn't be rewritten".to_string(),
+                    )
+                } else {
+                    return None;
+                };
+                Some(RemainingAction {
+                    file: workflow.path.clone(),
+                    line: uses.line_number,
+                    action: uses.action.repository.clone(),
+                    reference: if uses.unversioned {
+                        String::new()
+                    } else {
+                        uses.action.reference.clone()
+                    },
+                    reason,
+                    detail,
+                })
+            })
+            .collect()
+    }
+
+    /// Remove every use of a blocked action from `workflows`, returning them
+    /// as violations in file and line order. Pinning a blocked action would
+    /// look like endorsing it, so this runs before anything is resolved
+    fn drop_blocked(&self, workflows: &mut [WorkflowFile]) -> Vec<PolicyViolation> {
+        let mut violations = Vec::new();
+        for workflow in workflows.iter_mut() {
+            for uses in &workflow.actions {
+                let Some(pattern) = self.blocked_actions.matching_pattern(&uses.action) else {
+                    continue;
+                };
+                warn!(
+                    "{}:{}: {} is blocked by '{}'",
+                    workflow.path, uses.line_number, uses.action, pattern
+                );
+                self.emit(ProcessEvent::Skipped {
+                    file: workflow.path.clone(),
+                    line: uses.line_number,
+                    action: uses.action.to_string(),
+                    reason: format!("blocked by '{}'", pattern),
+                });
+                violations.push(PolicyViolation {
+                    file: workflow.path.clone(),
+                    line: uses.line_number,
+                    action: uses.action.to_string(),
+                    pattern: pattern.to_string(),
+                });
+            }
+            workflow.actions.retain(|uses| {
+                self.blocked_actions
+                    .matching_pattern(&uses.action)
+                    .is_none()
+            });
+        }
+        violations
+    }
+
+    /// Group the uses of the repositories the GraphQL resolver or a REST
+    /// lookup found archived
+    pub(crate) fn archived_actions(
+        &self,
+        workflows: &[WorkflowFile],
+        unhealthy: &[RepoHealth],
+    ) -> Vec<ArchivedAction> {
+        let mut archived: BTreeSet<String> = std::mem::take(&mut *self.archived.lock().unwrap())
+            .into_iter()
+            .filter_map(|(repository, archived)| archived.then_some(repository))
+            .collect();
+        archived.extend(
+            unhealthy
+                .iter()
+                .filter(|health| health.archived)
+                .map(|health| health.repository.clone()),
+        );
+
+        let mut locations: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+        for workflow in workflows {
+            for uses in &workflow.actions {
+                let repository = uses.action.base_repository();
+                if archived.contains(repository) {
+                    locations
+                        .entry(repository)
+                        .or_default()
+                        .push(format!("{}:{}", workflow.path, uses.line_number));
+                }
+            }
+        }
+
+        locations
+            .into_iter()
+            .map(|(action, locations)| ArchivedAction {
+                action: action.to_string(),
+                locations,
+            })
+            .collect()
+    }
+
+    /// Look up the repository of every action used and return those that
+    /// are archived or have moved. Failed lookups are only logged, since
+    /// the check is advisory
+    pub(crate) async fn check_health(&self, workflows: &[WorkflowFile]) -> Vec<RepoHealth> {
+        let Some(api_url) = &self.health_api else {
+            return Vec::new();
+        };
+
+        self.look_up_health(api_url, used_repositories(workflows))
+            .await
+    }
+
+    /// Look up the repositories neither the health check nor the GraphQL
+    /// resolver covered and return the archived ones
+    pub(crate) async fn check_archived(&self, workflows: &[WorkflowFile]) -> Vec<RepoHealth> {
+        let Some(api_url) = self
+            .archived_api
+            .as_ref()
+            .filter(|_| self.health_api.is_none())
+        else {
+            return Vec::new();
+        };
+
+        let known = self.archived.lock().unwrap().clone();
+        let repositories: BTreeSet<String> = used_repositories(workflows)
+            .into_iter()
+            .filter(|repository| !known.contains_key(repository))
+            .collect();
+        if repositories.is_empty() {
+            return Vec::new();
+        }
+
+        let mut archived = self.look_up_health(api_url, repositories).await;
+        archived.retain(|health| health.archived);
+        archived
+    }
+
+    /// Look up `repositories` through the REST API at `api_url`, returning
+    /// those that are archived or have moved
+    async fn look_up_health(
+        &self,
+        api_url: &str,
+        repositories: BTreeSet<String>,
+    ) -> Vec<RepoHealth> {
+        use futures::stream::{self, StreamExt};
+
+        info!("Checking health of {} repositories", repositories.len());
+        let checker = HealthChecker::new(api_url.to_string(), self.auth.token.clone())
+            .with_client(self.http_client.clone());
+        let results: Vec<(String, Result<RepoHealth>)> = stream::iter(repositories)
+            .map(|repository| {
+                let checker = &checker;
+                async move {
+                    let health = checker.check(&repository).await;
+                    (repository, health)
+                }
+            })
+            .buffer_unordered(self.concurrency)
+            .collect()
+            .await;
+
+        let mut unhealthy: Vec<RepoHealth> = results
+            .into_iter()
+            .filter_map(|(repository, health)| match health {
+                Ok(health) => Some(health),
+                Err(e) => {
+                    debug!("Failed to check health of {}: {:#}", repository, e);
+                    None
+                },
+            })
+            .filter(RepoHealth::is_unhealthy)
+            .collect();
+        unhealthy.sort_by(|a, b| a.repository.cmp(&b.repository));
+        unhealthy
+    }
+
+    /// Compare resolutions against the trust-on-first-use store, recording
+    /// refs seen for the first time. Refs now resolving elsewhere are
+    /// dropped from `shas` unless changes are accepted. The store is only
+    /// written when `record` is set
+    pub(crate) async fn check_known_refs(
+        &self,
+        shas: &mut HashMap<String, String>,
+        record: bool,
+    ) -> Result<Vec<MovedRef>> {
+        let Some(path) = &self.known_refs else {
+            return Ok(Vec::new());
+        };
+
+        // The lockfile keeps answering with what was first pinned, so it
+        // would never show a tag moving: refs it answered are looked up
+        // afresh, unless offline where it is all there is
+        let mut current = shas.clone();
+        if let Some(lockfile) = self.load_lockfile()?.filter(|_| !self.offline) {
+            let answered: Vec<ActionRef> = shas
+                .iter()
+                .filter(|(key, sha)| {
+                    lockfile
+                        .get(key)
+                        .is_some_and(|locked| locked.eq_ignore_ascii_case(sha))
+                })
+                .filter_map(|(key, _)| ActionRef::parse(key))
+                .collect();
+            if !answered.is_empty() {
+                debug!(
+                    "Looking up {} lockfile ref(s) afresh for the known refs store",
+                    answered.len()
+                );
+                // A ref that fails now is compared as the lockfile has it
+                let (fresh, _) = self.resolve_actions(answered, None).await;
+                current.extend(fresh);
+            }
+        }
+
+        let mut known = KnownRefs::load(path)?;
+        let today = chrono::Local::now().format("%Y-%m-%d").to_string();
+        let mut moved = Vec::new();
+        let mut changed = false;
+
+        let mut keys: Vec<String> = current.keys().cloned().collect();
+        keys.sort();
+        for key in keys {
+            let sha = current[&key].clone();
+            match known.get(&key) {
+                None => {
+                    known.insert(key, sha, today.clone());
+                    changed = true;
+                },
+                Some(entry) if entry.sha.eq_ignore_ascii_case(&sha) => {},
+                Some(entry) => {
+                    warn!(
+                        "{} {} resolved to {} when first seen on {}, but now resolves to {}",
+                        "⚠️  Tag moved:".red().bold(),
+                        key,
+                        entry.sha,
+                        entry.first_seen,
+                        sha
+                    );
+                    moved.push(MovedRef {
+                        action: key.clone(),
+                        known_sha: entry.sha.clone(),
+                        first_seen: entry.first_seen.clone(),
+                        resolved_sha: sha.clone(),
+                        accepted: self.accept_changes,
+                    });
+
+                    if self.accept_changes {
+                        known.insert(key, sha, today.clone());
+                        changed = true;
+                    } else {
+                        shas.remove(&key);
+                    }
+                },
+            }
+        }
+
+        if changed && record && !self.dry_run {
+            known.save(path)?;
+        }
+
+        Ok(moved)
+    }
+
+    /// Build a pinned action, applying the configured comment options
+    pub(crate) fn pin(&self, action: ActionRef, sha: String) -> PinnedAction {
+        let source = resolver::override_for(&self.overrides, &action)
+            .is_some_and(|forced| forced.eq_ignore_ascii_case(&sha))
+            .then_some(Source::Override);
+        let constraint = self
+            .record_constraint
+            .then(|| action.caret_constraint())
+            .flatten();
+        let pinned = PinnedAction::new(action, sha)
+            .with_source(source)
+            .with_constraint(constraint)
+            .with_no_comment(self.no_comment);
+        match &self.comment_date {
+            Some(date) => pinned.with_pinned_on(date.clone()),
+            None => pinned,
+        }
+    }
+
+    /// Parse workflow files concurrently, preserving the input order. Files
+    /// that aren't valid UTF-8 are skipped, unless read lossily
+    async fn parse_workflows(
+        &self,
+        paths: &[PathBuf],
+    ) -> (Vec<WorkflowFile>, Vec<ErrorDetail>, Vec<SkippedFile>) {
+        use futures::stream::{self, StreamExt};
+
+        let results: Vec<_> = stream::iter(paths.iter().cloned())
+            .map(|path| async move {
+                let result = task::spawn_blocking({
+                    let path = path.clone();
+                    let input_keys = self.input_keys.clone();
+                    let lossy = self.lossy_utf8;
+                    move || match lossy {
+                        true => WorkflowFile::parse_lossy(path, &input_keys),
+                        false => WorkflowFile::parse_with_inputs(path, &input_keys),
+                    }
+                })
+                .await
+                .context("Failed to spawn parse task")
+                .and_then(|result| result);
+                (path, result)
+            })
+            .buffered(self.io_workers)
+            .collect()
+            .await;
+
+        let mut workflows = Vec::new();
+        let mut errors = Vec::new();
+        let mut skipped = Vec::new();
+        for (path, result) in results {
+            match result {
+                Ok(workflow) => workflows.push(workflow),
+                Err(e) if e.chain().any(|cause| cause.is::<InvalidUtf8>()) => {
+                    warn!(
+                        "Skipping {}: {:#} (--lossy-utf8 processes it anyway)",
+                        path.display(),
+                        e.root_cause()
+                    );
+                    skipped.push(SkippedFile {
+                        file: path.to_string_lossy().to_string(),
+                        reason: e.root_cause().to_string(),
+                    });
+                },
+                Err(e) => {
+                    error!("Failed to parse {}: {}", path.display(), e);
+                    errors.push(ErrorDetail::new(
+                        ErrorCode::Parse,
+                        path.to_string_lossy(),
+                        format!("{:#}", e),
+                    ));
+                },
+            }
+        }
+        (workflows, errors, skipped)
+    }
+
+    /// Find all files to pin: workflow YAML files in the workflows
+    /// directories and, in repository mode, composite actions and workflow
+    /// templates as well, leaving out ignored ones
+    fn discover_targets(&self) -> Result<Discovery> {
+        let mut discovery = Discovery::default();
+
+        for workflows_dir in &self.workflows_dirs {
+            // An organization's .github repository may only have templates
+            if workflows_dir.is_dir() {
+                self.collect_targets(workflows_dir, Some(1), TargetKind::Workflow, &mut discovery)?;
+            }
+
+            if let Some(templates_dir) = templates_dir_for(workflows_dir) {
+                self.collect_targets(
+                    &templates_dir,
+                    Some(1),
+                    TargetKind::Template,
+                    &mut discovery,
+                )?;
+            }
+        }
+
+        if let Some(root) = &self.repo_root {
+            let github_dir = root.join(".github");
+
+            let workflows_dir = github_dir.join("workflows");
+            if workflows_dir.is_dir() {
+                self.collect_targets(
+                    &workflows_dir,
+                    Some(1),
+                    TargetKind::Workflow,
+                    &mut discovery,
+                )?;
+            }
+
+            let actions_dir = github_dir.join("actions");
+            if actions_dir.is_dir() {
+                self.collect_targets(
+                    &actions_dir,
+                    None,
+                    TargetKind::CompositeAction,
+                    &mut discovery,
+                )?;
+            }
+
+            // Action repositories define their action at the root
+            self.collect_targets(root, Some(1), TargetKind::CompositeAction, &mut discovery)?;
+
+            // Organization .github repositories keep starter workflows at the
+            // root; .github/workflow-templates is accepted too
+            for templates_dir in [
+                root.join("workflow-templates"),
+                github_dir.join("workflow-templates"),
+            ] {
+                if templates_dir.is_dir() {
+                    self.collect_targets(
+                        &templates_dir,
+                        Some(1),
+                        TargetKind::Template,
+                        &mut discovery,
+                    )?;
+                }
+            }
+        }
+
+        if let Some(base) = &self.changed_only {
+            let dir = self
+                .repo_root
+                .iter()
+                .chain(&self.workflows_dirs)
+                .find(|dir| dir.is_dir())
+                .map_or(Path::new("."), PathBuf::as_path);
+            let changed = changed::changed_paths(dir, base)?;
+
+            let before = discovery.targets.len();
+            discovery.targets.retain(|target| {
+                fs::canonicalize(&target.path).is_ok_and(|path| changed.contains(&path))
+            });
+            discovery.unchanged = before - discovery.targets.len();
+        }
+
+        Ok(discovery)
+    }
+
+    /// Add the matching YAML files below `base_dir` to the discovery
+    fn collect_targets(
+        &self,
+        base_dir: &Path,
+        max_depth: Option<usize>,
+        kind: TargetKind,
+        discovery: &mut Discovery,
+    ) -> Result<()> {
+        for path in self.candidate_files(base_dir, max_depth)? {
+            let Some(ext) = path.extension() else {
+                continue;
+            };
+            if ext != "yml" && ext != "yaml" {
+                continue;
+            }
+
+            // Composite actions are only ever defined in action.yml
+            if kind == TargetKind::CompositeAction
+                && path.file_stem().is_none_or(|stem| stem != "action")
+            {
+                continue;
+            }
+
+            let relative = path.strip_prefix(base_dir).unwrap_or(&path);
+            if let Some(pattern) = self.ignore_files.matching_pattern(relative) {
+                debug!(
+                    "Skipping {} (matches ignore pattern '{}')",
+                    path.display(),
+                    pattern
+                );
+                discovery.skipped += 1;
+                continue;
+            }
+
+            // The same directory may be given twice
+            if !discovery.targets.iter().any(|t| t.path == path) {
+                discovery.targets.push(Target { path, kind });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// List the files inside a directory, honoring gitignore rules when
+    /// requested
+    fn candidate_files(&self, dir: &Path, max_depth: Option<usize>) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+
+        if self.respect_gitignore {
+            for entry in WalkBuilder::new(dir)
+                .max_depth(max_depth)
+                .follow_links(false)
+                .hidden(false)
+                .ignore(false)
+                .git_ignore(true)
+                .git_global(true)
+                .git_exclude(true)
+                .build()
+            {
+                let entry = entry?;
+                if entry.path().is_file() {
+                    files.push(entry.into_path());
+                }
+            }
+        } else {
+            let mut walker = WalkDir::new(dir).follow_links(false);
+            if let Some(max_depth) = max_depth {
+                walker = walker.max_depth(max_depth);
+            }
+
+            for entry in walker {
+                let entry = entry?;
+                if entry.path().is_file() {
+                    files.push(entry.into_path());
+                }
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// How this run writes rewritten uses lines
+    fn line_style(&self) -> LineStyle<'_> {
+        LineStyle {
+            host: &self.git_remote.host,
+            provenance: self.provenance.as_deref(),
+            replace_comment: self.replace_comment,
+        }
+    }
+
+    /// Rewrite a workflow file with pinned actions, returning how many lines
+    /// differ from the original
+    pub(crate) fn rewrite_workflow(
+        &self,
+        workflow: &WorkflowFile,
+        pinned_map: &HashMap<String, PinnedAction>,
+        results: &mut Vec<PinnedActionResult>,
+    ) -> Result<usize> {
+        self.rewrite_workflow_with_images(
+            workflow,
+            pinned_map,
+            &HashMap::new(),
+            results,
+            &mut Vec::new(),
+            &mut Vec::new(),
+        )
+    }
+
+    /// Rewrite a workflow file with pinned actions and container images
+    /// (digests keyed by the image as written), returning how many lines
+    /// differ from the original. Lines that change without being written
+    /// are added to `planned`
+    fn rewrite_workflow_with_images(
+        &self,
+        workflow: &WorkflowFile,
+        pinned_map: &HashMap<String, PinnedAction>,
+        digests: &HashMap<String, String>,
+        results: &mut Vec<PinnedActionResult>,
+        image_results: &mut Vec<PinnedImageResult>,
+        planned: &mut Vec<PlannedChange>,
+    ) -> Result<usize> {
+        let started = Instant::now();
+        let rewritten = self.render_and_write(
+            workflow,
+            pinned_map,
+            digests,
+            results,
+            image_results,
+            planned,
+        );
+        self.phases.lock().unwrap().rewrite_ms += millis(started.elapsed());
+        rewritten
+    }
+
+    /// [`rewrite_workflow_with_images`](Self::rewrite_workflow_with_images)
+    /// without adding to the run's phase times
+    fn render_and_write(
+        &self,
+        workflow: &WorkflowFile,
+        pinned_map: &HashMap<String, PinnedAction>,
+        digests: &HashMap<String, String>,
+        results: &mut Vec<PinnedActionResult>,
+        image_results: &mut Vec<PinnedImageResult>,
+        planned: &mut Vec<PlannedChange>,
+    ) -> Result<usize> {
+        let mut rendering = Rendering::for_workflow(workflow)?;
+        let Rendered {
+            lines_changed,
+            changes,
+            actions: mut file_results,
+            images: mut file_image_results,
+            inserted,
+        } = rendering.write(|out| {
+            render_workflow(
+                workflow,
+                workflow.reader()?,
+                out,
+                pinned_map,
+                digests,
+                &self.line_style(),
+            )
+        })?;
+
+        if !self.summary_only {
+            for result in &file_results {
+                let from = if result.was_unversioned {
+                    result.action.yellow()
+                } else {
+                    format!("{}@{}", result.action, result.old_ref).yellow()
+                };
+                let note = if result.was_unversioned {
+                    format!(" {}", "(was unversioned)".red())
+                } else if result.on_branch {
+                    format!(" {}", "(branch)".yellow())
+                } else {
+                    String::new()
+                };
+                let badge = result
+                    .signature
+                    .map(|status| format!(" {}", status.badge()))
+                    .unwrap_or_default();
+                info!(
+                    "  {} {} → {}{}{}",
+                    "📌".cyan(),
+                    from,
+                    result.sha[..8].green(),
+                    note,
+                    badge
+                );
+            }
+            for result in &file_image_results {
+                info!(
+                    "  {} {} → {}",
+                    "📌".cyan(),
+                    result.image.yellow(),
+                    result.digest[..19].green()
+                );
+            }
+        }
+
+        if self.assert_idempotent {
+            self.check_idempotent(workflow, &rendering, &inserted, pinned_map, digests)?;
+        }
+
+        // Leave untouched files alone, so their mtime doesn't change and no
+        // backup is made
+        let applied = if lines_changed == 0 {
+            debug!("{} needs no changes; not writing it", workflow.path);
+            false
+        } else {
+            self.write_workflow(workflow, rendering)?
+        };
+        if !applied {
+            planned.extend(changes);
+        }
+        for result in &mut file_results {
+            result.applied = applied;
+        }
+        for result in &mut file_image_results {
+            result.applied = applied;
+        }
+
+        for result in &file_results {
+            self.emit(ProcessEvent::Pinned(result.clone()));
+        }
+        for result in &file_image_results {
+            self.emit(ProcessEvent::ImagePinned(result.clone()));
+        }
+        results.extend(file_results);
+        image_results.extend(file_image_results);
+
+        Ok(lines_changed)
+    }
+
+    /// Reparse rewritten content and render it again with the same
+    /// resolutions, failing if that second pass would change anything.
+    /// Catches rewrites a rerun wouldn't recognise as already pinned
+    fn check_idempotent(
+        &self,
+        workflow: &WorkflowFile,
+        rendering: &Rendering,
+        inserted: &[usize],
+        pinned_map: &HashMap<String, PinnedAction>,
+        digests: &HashMap<String, String>,
+    ) -> Result<()> {
+        let mut reparsed = WorkflowFile::parse_reader(
+            workflow.path.clone(),
+            rendering.reader()?,
+            &self.input_keys,
+            workflow.lossy,
+        )?;
+        // Rewrites keep line numbers, apart from the provenance comments
+        // inserted, so lines the first pass wasn't asked to touch are left
+        // out of the second too
+        let lines: HashSet<usize> = workflow.actions.iter().map(|u| u.line_number).collect();
+        reparsed.actions.retain(|uses| {
+            let shift = inserted.partition_point(|&line| line < uses.line_number);
+            lines.contains(&(uses.line_number - shift))
+        });
+        let second = render_workflow(
+            &reparsed,
+            rendering.reader()?,
+            &mut io::sink(),
+            pinned_map,
+            digests,
+            &self.line_style(),
+        )?;
+
+        // Even a rewrite reproducing the same text means the pin wasn't
+        // recognised as one
+        if let Some(repinned) = second.actions.first() {
+            anyhow::bail!(
+                "Rewrite is not idempotent: a second pass would pin {}@{} again; file left unchanged",
+                repinned.action,
+                repinned.old_ref
+            );
+        }
+        if let Some(repinned) = second.images.first() {
+            anyhow::bail!(
+                "Rewrite is not idempotent: a second pass would pin {} again at line {}; file left unchanged",
+                repinned.image,
+                repinned.line
+            );
+        }
+        if let Some(change) = second.changes.first() {
+            anyhow::bail!(
+                "Rewrite is not idempotent: a second pass would change line {}; file left unchanged",
+                change.line
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Replace a workflow's content on disk (or in the archive), returning
+    /// whether the change was applied
+    pub(crate) fn write_workflow(
+        &self,
+        workflow: &WorkflowFile,
+        rendering: Rendering,
+    ) -> Result<bool> {
+        if let Some(patches) = &self.patches {
+            // --lossy-utf8 lets files with invalid bytes this far, so the
+            // patch shows them as U+FFFD rather than failing the write
+            let mut original = Vec::new();
+            workflow.reader()?.read_to_end(&mut original)?;
+            let mut rewritten = Vec::new();
+            rendering.reader()?.read_to_end(&mut rewritten)?;
+            patches.lock().unwrap().push(FilePatch {
+                file: workflow.path.clone(),
+                original: String::from_utf8_lossy(&original).into_owned(),
+                rewritten: String::from_utf8_lossy(&rewritten).into_owned(),
+            });
+        }
+
+        if self.dry_run {
+            debug!("Dry run: would write to {}", workflow.path);
+            return Ok(false);
+        }
+
+        if let Some(archive) = &self.archive {
+            archive
+                .lock()
+                .unwrap()
+                .replace(&workflow.path, rendering.into_bytes()?);
+
+            // Only counts as applied once it lands in an output archive
+            return Ok(self.archive_out.is_some());
+        }
+
+        // Create backup if requested, moving older ones along first
+        if self.backup {
+            rotate_backups(&workflow.path, self.backup_keep)?;
+            let backup_path = format!("{}.bak", workflow.path);
+            fs::copy(&workflow.path, &backup_path)
+                .with_context(|| format!("Failed to create backup at {}", backup_path))?;
+            debug!("Created backup: {}", backup_path);
+        }
+
+        // Write the new content, streamed from the rendering into a file
+        // beside the workflow that then replaces it, so an interrupted write
+        // never leaves half a workflow
+        report::copy_atomically(Path::new(&workflow.path), &mut rendering.reader()?)?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future::BoxFuture;
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{
+        check::{UnpinnedAction, UnpinnedResults},
+        error::RefNotFound,
+    };
+
+    fn paths(discovery: &Discovery) -> Vec<PathBuf> {
+        discovery.targets.iter().map(|t| t.path.clone()).collect()
+    }
+
+    /// Answers each "owner/repo@ref" from a script: a SHA, or the error code
+    /// the failure should be classified as. Records every lookup. Slow
+    /// lookups yield once before answering, so lookups in flight with them
+    /// answer first
+    #[derive(Default)]
+    struct FakeResolver {
+        script: HashMap<String, std::result::Result<&'static str, ErrorCode>>,
+        slow: HashSet<String>,
+        branches: BTreeMap<String, String>,
+        asked: Mutex<Vec<String>>,
+    }
+
+    impl FakeResolver {
+        fn with(
+            mut self,
+            key: &str,
+            outcome: std::result::Result<&'static str, ErrorCode>,
+        ) -> Self {
+            self.script.insert(key.to_string(), outcome);
+            self
+        }
+
+        fn slow(mut self, key: &str) -> Self {
+            self.slow.insert(key.to_string());
+            self
+        }
+
+        fn branch(mut self, repository: &str, name: &str) -> Self {
+            self.branches
+                .insert(repository.to_string(), name.to_string());
+            self
+        }
+    }
+
+    impl Resolver for FakeResolver {
+        fn resolve<'a>(&'a self, action: &'a ActionRef) -> BoxFuture<'a, Result<Resolution>> {
+            Box::pin(async move {
+                let key = action.to_string();
+                self.asked.lock().unwrap().push(key.clone());
+                if self.slow.contains(&key) {
+                    tokio::task::yield_now().await;
+                }
+                match self.script.get(&key) {
+                    Some(Ok(sha)) => Ok(Resolution::new(
+                        sha.to_string(),
+                        Some(RefKind::Tag),
+                        Source::Remote,
+                    )),
+                    Some(Err(ErrorCode::RefNotFound)) => {
+                        Err(RefNotFound(format!("no ref '{}'", action.reference)).into())
+                    },
+                    _ => anyhow::bail!("connection reset"),
+                }
+            })
+        }
+
+        fn default_branches(&self) -> BTreeMap<String, String> {
+            self.branches.clone()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_with_fake_resolver() {
+        const SHA: &str = "b4ffde65f46336ab88eb53be808477a3936bae11";
+
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("ci.yml");
+        fs::write(
+            &path,
+            "steps:\n  - uses: actions/checkout@v4\n  - uses: actions/cache@v9\n  - uses: flaky/action@v1\n",
+        )
+        .unwrap();
+
+        let resolver = Arc::new(
+            FakeResolver::default()
+                .with("actions/checkout@v4", Ok(SHA))
+                .with("actions/cache@v9", Err(ErrorCode::RefNotFound))
+                .with("flaky/action@v1", Err(ErrorCode::Network)),
+        );
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 10)
+                .with_resolver(resolver.clone());
+        let results = processor.process().await.unwrap();
+
+        assert_eq!(results.pinned_actions.len(), 1);
+        assert_eq!(results.pinned_actions[0].sha, SHA);
+        let mut errors: Vec<(&str, ErrorCode)> = results
+            .errors_detail
+            .iter()
+            .map(|e| (e.target.as_str(), e.code))
+            .collect();
+        errors.sort();
+        assert_eq!(
+            errors,
+            vec![
+                ("actions/cache@v9", ErrorCode::RefNotFound),
+                ("flaky/action@v1", ErrorCode::Network),
+            ]
+        );
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!(
+                "steps:\n  - uses: actions/checkout@{} # v4\n  - uses: actions/cache@v9\n  - uses: flaky/action@v1\n",
+                SHA
+            )
+        );
+        assert_eq!(resolver.asked.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_refspec_references() {
+        const SHA: &str = "b4ffde65f46336ab88eb53be808477a3936bae11";
+
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("ci.yml");
+        fs::write(
+            &path,
+            "steps:\n  - uses: actions/checkout@v4^{}\n  - uses: actions/cache@v*\n",
+        )
+        .unwrap();
+
+        let resolver = Arc::new(FakeResolver::default().with("actions/checkout@v4^{}", Ok(SHA)));
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 10)
+                .with_resolver(resolver.clone());
+        let results = processor.process().await.unwrap();
+
+        // The glob is rejected without asking the resolver to guess
+        assert_eq!(results.errors_detail.len(), 1);
+        let error = &results.errors_detail[0];
+        assert_eq!(error.target, "actions/cache@v*");
+        assert_eq!(error.code, ErrorCode::RefNotFound);
+        assert!(error.message.contains("'*' is refspec syntax"));
+        assert_eq!(
+            *resolver.asked.lock().unwrap(),
+            vec!["actions/checkout@v4^{}".to_string()]
+        );
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!(
+                "steps:\n  - uses: actions/checkout@{} # v4^{{}}\n  - uses: actions/cache@v*\n",
+                SHA
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remaining_unpinned() {
+        const SHA: &str = "b4ffde65f46336ab88eb53be808477a3936bae11";
+
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("ci.yml");
+        let content = [
+            "steps:",
+            "  - uses: actions/checkout@v4",
+            "  - uses: gone/action@v1",
+            "  - uses: actions/cache@v4 # pin-actions: ignore",
+            "  - uses: owner/reusable@${{ inputs.ref }}",
+            "  - uses: evil/action@v2",
+            "",
+        ]
+        .join("\n");
+        fs::write(&path, &content).unwrap();
+
+        let resolver = Arc::new(
+            FakeResolver::default()
+                .with("actions/checkout@v4", Ok(SHA))
+                .with("gone/action@v1", Err(ErrorCode::RefNotFound)),
+        );
+        for dry_run in [true, false] {
+            let processor =
+                WorkflowProcessor::new(vec![temp.path().to_path_buf()], dry_run, false, true, 10)
+                    .with_resolver(resolver.clone())
+                    .with_blocked_actions(BlockedActions::new(vec!["evil/*".to_string()]).unwrap());
+            let results = processor.process().await.unwrap();
+
+            let remaining: Vec<(usize, &str, &str, RemainingReason)> = results
+                .remaining_unpinned
+                .iter()
+                .map(|r| (r.line, r.action.as_str(), r.reference.as_str(), r.reason))
+                .collect();
+            assert_eq!(
+                remaining,
+                vec![
+                    (3, "gone/action", "v1", RemainingReason::ResolutionFailed),
+                    (4, "actions/cache", "v4", RemainingReason::Excluded),
+                    (
+                        5,
+                        "owner/reusable",
+                        "${{ inputs.ref }}",
+                        RemainingReason::DynamicRef
+                    ),
+                    (6, "evil/action", "v2", RemainingReason::Excluded),
+                ]
+            );
+            assert_eq!(results.remaining_unpinned[0].detail, "no ref 'v1'");
+            assert_eq!(results.remaining_unpinned[3].detail, "blocked by 'evil/*'");
+        }
+
+        // The expression was never looked up, and only the pinnable use changed
+        assert!(
+            !resolver
+                .asked
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|key| key.contains("${{"))
+        );
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            content.replace(
+                "actions/checkout@v4",
+                &format!("actions/checkout@{} # v4", SHA)
+            )
+        );
+
+        // A check doesn't count the expression as unpinned either
+        let check = WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 10)
+            .with_blocked_actions(BlockedActions::new(vec!["evil/*".to_string()]).unwrap())
+            .report_unpinned()
+            .await
+            .unwrap();
+        let unpinned: Vec<&str> = check
+            .unpinned_actions
+            .iter()
+            .map(|u| u.action.as_str())
+            .collect();
+        assert_eq!(unpinned, vec!["gone/action"]);
+        assert_eq!(check.dynamic_refs, 1);
+    }
+
+    #[tokio::test]
+    async fn test_default_ref() {
+        const SHA: &str = "b4ffde65f46336ab88eb53be808477a3936bae11";
+
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("ci.yml");
+        let content = "steps:\n  - uses: owner/moving\n  - uses: owner/pinned@v2\n";
+        fs::write(&path, content).unwrap();
+
+        let resolver = Arc::new(
+            FakeResolver::default()
+                .with("owner/moving@v1", Ok(SHA))
+                .with("owner/pinned@v2", Ok(SHA)),
+        );
+
+        // Without a default ref the default branch is needed, which offline
+        // can't be looked up
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], true, false, true, 10)
+                .with_resolver(resolver.clone())
+                .with_offline(true);
+        let results = processor.process().await.unwrap();
+        assert_eq!(results.errors_detail[0].target, "owner/moving");
+        assert_eq!(
+            results.remaining_unpinned[0].reason,
+            RemainingReason::ResolutionFailed
+        );
+
+        // With one, the ref-less use resolves at it and the comment says so
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 10)
+                .with_resolver(resolver.clone())
+                .with_default_ref(Some("v1".to_string()));
+        let results = processor.process().await.unwrap();
+        assert_eq!(results.errors, 0);
+        assert!(results.remaining_unpinned.is_empty());
+        assert!(
+            resolver
+                .asked
+                .lock()
+                .unwrap()
+                .contains(&"owner/moving@v1".to_string())
+        );
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!(
+                "steps:\n  - uses: owner/moving@{sha} # v1 (default ref)\n  - uses: owner/pinned@{sha} # v2\n",
+                sha = SHA
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fail_fast_stops_at_first_error() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("ci.yml");
+        let content =
+            "steps:\n  - uses: gone/one@v1\n  - uses: gone/two@v1\n  - uses: gone/three@v1\n";
+        fs::write(&path, content).unwrap();
+
+        // Nothing is scripted, so every lookup fails
+        let resolver = Arc::new(FakeResolver::default());
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 1)
+                .with_resolver(resolver.clone())
+                .with_fail_fast(true);
+        let err = processor.process().await.unwrap_err();
+
+        assert!(
+            err.to_string()
+                .starts_with("Stopped at the first resolution error (--fail-fast): gone/")
+        );
+        assert!(err.to_string().ends_with("connection reset"));
+        assert_eq!(resolver.asked.lock().unwrap().len(), 1);
+        assert_eq!(fs::read_to_string(&path).unwrap(), content);
+
+        // Without it every action is tried and the errors tallied
+        let resolver = Arc::new(FakeResolver::default());
+        let results =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 1)
+                .with_resolver(resolver.clone())
+                .process()
+                .await
+                .unwrap();
+        assert_eq!(results.errors, 3);
+        assert_eq!(resolver.asked.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_fail_fast_saves_the_lockfile() {
+        const SHA: &str = "b4ffde65f46336ab88eb53be808477a3936bae11";
+
+        let temp = TempDir::new().unwrap();
+        let lockfile = temp.path().join("pin-actions.lock");
+        fs::write(
+            temp.path().join("ci.yml"),
+            "steps:\n  - uses: actions/checkout@v4\n  - uses: gone/action@v1\n",
+        )
+        .unwrap();
+
+        // The failure answers last, so the checkout resolved before the stop
+        let resolver = Arc::new(
+            FakeResolver::default()
+                .with("actions/checkout@v4", Ok(SHA))
+                .slow("gone/action@v1"),
+        );
+        let err = WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 10)
+            .with_resolver(resolver)
+            .with_lockfile(Some(lockfile.clone()))
+            .with_fail_fast(true)
+            .process()
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("gone/action@v1"));
+
+        let saved = Lockfile::load(&lockfile).unwrap();
+        assert_eq!(
+            saved.get("actions/checkout@v4").map(String::as_str),
+            Some(SHA)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lockfile_answers_before_resolver() {
+        const SHA: &str = "b4ffde65f46336ab88eb53be808477a3936bae11";
+
+        let temp = TempDir::new().unwrap();
+        let lockfile = temp.path().join("pin-actions.lock");
+        fs::write(
+            &lockfile,
+            format!(r#"{{"actions": {{"actions/checkout@v4": "{}"}}}}"#, SHA),
+        )
+        .unwrap();
+        fs::write(
+            temp.path().join("ci.yml"),
+            "steps:\n  - uses: actions/checkout@v4\n  - uses: actions/cache@v4\n",
+        )
+        .unwrap();
+
+        let resolver = Arc::new(FakeResolver::default().with("actions/cache@v4", Ok(SHA)));
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], true, false, true, 10)
+                .with_lockfile(Some(lockfile))
+                .with_resolver(resolver.clone());
+        let results = processor.process().await.unwrap();
+
+        assert_eq!(results.errors, 0);
+        assert_eq!(results.pinned_actions.len(), 2);
+        assert_eq!(
+            *resolver.asked.lock().unwrap(),
+            vec!["actions/cache@v4".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_empty_directory() {
+        let temp = TempDir::new().unwrap();
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 10);
+
+        let results = processor.process().await.unwrap();
+        assert_eq!(results.files_processed, 0);
+    }
+
+    #[test]
+    fn test_find_workflow_files() {
+        let temp = TempDir::new().unwrap();
+        let workflows_dir = temp.path().join(".github/workflows");
+        fs::create_dir_all(&workflows_dir).unwrap();
+
+        fs::write(workflows_dir.join("ci.yml"), "").unwrap();
+        fs::write(workflows_dir.join("test.yaml"), "").unwrap();
+        fs::write(workflows_dir.join("readme.md"), "").unwrap();
+
+        let processor = WorkflowProcessor::new(vec![workflows_dir], false, false, true, 10);
+
+        let discovery = processor.discover_targets().unwrap();
+        assert_eq!(discovery.targets.len(), 2);
+        assert_eq!(discovery.skipped, 0);
+    }
+
+    #[test]
+    fn test_find_workflow_files_with_ignore() {
+        let temp = TempDir::new().unwrap();
+        fs::write(temp.path().join("ci.yml"), "").unwrap();
+        fs::write(temp.path().join("release-linux.yml"), "").unwrap();
+        fs::write(temp.path().join("release-macos.yaml"), "").unwrap();
+
+        let ignore = IgnorePatterns::new(vec![
+            "release-*.yml".to_string(),
+            "release-?????.yaml".to_string(),
+        ])
+        .unwrap();
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 10)
+                .with_ignore_files(ignore);
+
+        let discovery = processor.discover_targets().unwrap();
+        assert_eq!(paths(&discovery), vec![temp.path().join("ci.yml")]);
+        assert_eq!(discovery.skipped, 2);
+    }
+
+    #[test]
+    fn test_find_workflow_files_respects_gitignore() {
+        let temp = TempDir::new().unwrap();
+        git2::Repository::init(temp.path()).unwrap();
+        fs::write(temp.path().join(".gitignore"), "*.local.yml\n").unwrap();
+
+        let workflows_dir = temp.path().join(".github/workflows");
+        fs::create_dir_all(&workflows_dir).unwrap();
+        fs::write(workflows_dir.join("ci.yml"), "").unwrap();
+        fs::write(workflows_dir.join("wip-test.yml.local.yml"), "").unwrap();
+
+        let processor = WorkflowProcessor::new(vec![workflows_dir.clone()], false, false, true, 10);
+        assert_eq!(processor.discover_targets().unwrap().targets.len(), 2);
+
+        let processor = processor.with_respect_gitignore(true);
+        let discovery = processor.discover_targets().unwrap();
+        assert_eq!(paths(&discovery), vec![workflows_dir.join("ci.yml")]);
+    }
+
+    #[test]
+    fn test_find_workflow_files_multiple_dirs() {
+        let temp = TempDir::new().unwrap();
+        let root_dir = temp.path().join(".github/workflows");
+        let app_dir = temp.path().join("apps/web/.github/workflows");
+        fs::create_dir_all(&root_dir).unwrap();
+        fs::create_dir_all(&app_dir).unwrap();
+        fs::write(root_dir.join("ci.yml"), "").unwrap();
+        fs::write(app_dir.join("ci.yml"), "").unwrap();
+
+        let processor = WorkflowProcessor::new(
+            vec![root_dir.clone(), app_dir.clone(), root_dir.clone()],
+            false,
+            false,
+            true,
+            10,
+        );
+
+        let discovery = processor.discover_targets().unwrap();
+        assert_eq!(
+            paths(&discovery),
+            vec![root_dir.join("ci.yml"), app_dir.join("ci.yml")]
+        );
+    }
+
+    #[test]
+    fn test_discover_targets_repo_mode() {
+        let temp = TempDir::new().unwrap();
+        let root = temp.path();
+        fs::create_dir_all(root.join(".github/workflows")).unwrap();
+        fs::create_dir_all(root.join(".github/actions/setup/nested")).unwrap();
+        fs::create_dir_all(root.join(".github/workflow-templates")).unwrap();
+        fs::create_dir_all(root.join("workflow-templates")).unwrap();
+        fs::write(root.join(".github/workflows/ci.yml"), "").unwrap();
+        fs::write(root.join("workflow-templates/starter.yml"), "").unwrap();
+        fs::write(root.join("workflow-templates/starter.properties.json"), "").unwrap();
+        fs::write(root.join(".github/actions/setup/action.yml"), "").unwrap();
+        fs::write(root.join(".github/actions/setup/nested/action.yaml"), "").unwrap();
+        fs::write(root.join(".github/actions/setup/config.yml"), "").unwrap();
+        fs::write(root.join(".github/workflow-templates/ci.yml"), "").unwrap();
+        fs::write(
+            root.join(".github/workflow-templates/ci.properties.json"),
+            "",
+        )
+        .unwrap();
+        fs::write(root.join("action.yml"), "").unwrap();
+        fs::write(root.join("docker-compose.yml"), "").unwrap();
+
+        let processor = WorkflowProcessor::new(Vec::new(), false, false, true, 10)
+            .with_repo_root(Some(root.to_path_buf()));
+
+        let discovery = processor.discover_targets().unwrap();
+        let mut found = discovery
+            .targets
+            .iter()
+            .map(|t| (t.kind, t.path.strip_prefix(root).unwrap().to_path_buf()))
+            .collect::<Vec<_>>();
+        found.sort();
+
+        assert_eq!(
+            found,
+            vec![
+                (
+                    TargetKind::Workflow,
+                    PathBuf::from(".github/workflows/ci.yml")
+                ),
+                (
+                    TargetKind::CompositeAction,
+                    PathBuf::from(".github/actions/setup/action.yml")
+                ),
+                (
+                    TargetKind::CompositeAction,
+                    PathBuf::from(".github/actions/setup/nested/action.yaml")
+                ),
+                (TargetKind::CompositeAction, PathBuf::from("action.yml")),
+                (
+                    TargetKind::Template,
+                    PathBuf::from(".github/workflow-templates/ci.yml")
+                ),
+                (
+                    TargetKind::Template,
+                    PathBuf::from("workflow-templates/starter.yml")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_workflows_dir() {
+        let temp = TempDir::new().unwrap();
+        let repo = temp.path().join("repo");
+        let deep = repo.join("src/nested");
+        fs::create_dir_all(repo.join(".github/workflows")).unwrap();
+        fs::create_dir_all(repo.join(".git")).unwrap();
+        fs::create_dir_all(&deep).unwrap();
+
+        assert_eq!(
+            find_workflows_dir(&deep),
+            Some(PathBuf::from("../../.github/workflows"))
+        );
+        assert_eq!(
+            find_workflows_dir(&repo),
+            Some(PathBuf::from(".github/workflows"))
+        );
+
+        // The search ends at the repository root, not at workflows beyond it
+        let inner = repo.join("vendor/lib");
+        fs::create_dir_all(inner.join(".git")).unwrap();
+        fs::create_dir_all(inner.join("src")).unwrap();
+        assert_eq!(find_workflows_dir(&inner.join("src")), None);
+    }
+
+    #[test]
+    fn test_discover_templates_next_to_workflows() {
         let temp = TempDir::new().unwrap();
-        let workflows_dir = temp.path().join(".github/workflows");
-        fs::create_dir_all(&workflows_dir).unwrap();
+        let root = temp.path();
+        fs::create_dir_all(root.join(".github/workflows")).unwrap();
+        fs::create_dir_all(root.join("workflow-templates")).unwrap();
+        fs::write(root.join(".github/workflows/ci.yml"), "").unwrap();
+        fs::write(root.join("workflow-templates/starter.yml"), "").unwrap();
+        fs::write(root.join("workflow-templates/starter.properties.json"), "").unwrap();
 
-        fs::write(workflows_dir.join("ci.yml"), "").unwrap();
-        fs::write(workflows_dir.join("test.yaml"), "").unwrap();
-        fs::write(workflows_dir.join("readme.md"), "").unwrap();
+        let processor =
+            WorkflowProcessor::new(vec![root.join(".github/workflows")], false, false, true, 10);
+
+        let discovery = processor.discover_targets().unwrap();
+        let found = discovery
+            .targets
+            .iter()
+            .map(|t| (t.kind, t.path.strip_prefix(root).unwrap().to_path_buf()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            found,
+            vec![
+                (
+                    TargetKind::Workflow,
+                    PathBuf::from(".github/workflows/ci.yml")
+                ),
+                (
+                    TargetKind::Template,
+                    PathBuf::from("workflow-templates/starter.yml")
+                ),
+            ]
+        );
+
+        // Only templates, with no workflows directory at all
+        fs::remove_dir_all(root.join(".github/workflows")).unwrap();
+        let discovery = processor.discover_targets().unwrap();
+        assert_eq!(discovery.targets.len(), 1);
+        assert_eq!(discovery.targets[0].kind, TargetKind::Template);
+    }
+
+    #[test]
+    fn test_effective_jobs() {
+        assert_eq!(effective_jobs(4), 4);
+        let auto = effective_jobs(0);
+        assert!((2..=MAX_AUTO_JOBS).contains(&auto));
+
+        assert_eq!(effective_io_workers(3), 3);
+        assert!(effective_io_workers(0) >= 1);
+    }
+
+    #[test]
+    fn test_ignore_patterns_matching() {
+        let ignore =
+            IgnorePatterns::new(vec!["*.yml".to_string(), "**/gen/*.yml".to_string()]).unwrap();
+
+        assert_eq!(ignore.matching_pattern("ci.yml"), Some("*.yml"));
+        assert_eq!(
+            ignore.matching_pattern("a/b/gen/x.yml"),
+            Some("**/gen/*.yml")
+        );
+        assert_eq!(ignore.matching_pattern("sub/ci.yml"), None);
+        assert!(IgnorePatterns::new(vec!["[".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_blocked_actions_matching() {
+        let blocked = BlockedActions::new(vec![
+            "tj-actions/changed-files".to_string(),
+            "evil-org/*".to_string(),
+        ])
+        .unwrap();
+        let matching = |action: &str| {
+            blocked
+                .matching_pattern(&ActionRef::parse(action).unwrap())
+                .map(str::to_string)
+        };
+
+        assert_eq!(
+            matching("tj-actions/changed-files@v45"),
+            Some("tj-actions/changed-files".to_string())
+        );
+        assert_eq!(
+            matching("Evil-Org/tool/sub@main"),
+            Some("evil-org/*".to_string())
+        );
+        assert_eq!(matching("actions/checkout@v4"), None);
+        assert_eq!(matching("tj-actions/verify-changed-files@v1"), None);
+        assert!(BlockedActions::new(vec!["[".to_string()]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_parallel_parsing_matches_sequential() {
+        let temp = TempDir::new().unwrap();
+        let mut paths = Vec::new();
+        for i in 0..50 {
+            let path = temp.path().join(format!("wf{:02}.yml", i));
+            let content = format!(
+                "steps:\n  - uses: actions/checkout@v{}\n  - uses: org/action-{}@main\n",
+                i % 4,
+                i
+            );
+            fs::write(&path, content).unwrap();
+            paths.push(path);
+        }
+        paths.push(temp.path().join("missing.yml"));
+
+        let summarize = |workflows: &[WorkflowFile]| {
+            workflows
+                .iter()
+                .map(|w| {
+                    let actions = w
+                        .actions
+                        .iter()
+                        .map(|u| (u.line_number, u.action.to_string()))
+                        .collect::<Vec<_>>();
+                    (w.path.clone(), actions)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let sequential = paths
+            .iter()
+            .filter_map(|p| WorkflowFile::parse(p).ok())
+            .collect::<Vec<_>>();
+
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 8);
+        let (parallel, errors, _) = processor.parse_workflows(&paths).await;
+
+        assert_eq!(parallel.len(), 50);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, ErrorCode::Parse);
+        assert_eq!(summarize(&parallel), summarize(&sequential));
+    }
+
+    #[test]
+    fn test_rewrite_identical_steps() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("ci.yml");
+        let content =
+            "steps:\n  - uses: actions/checkout@v4\n  - run: make\n  - uses: actions/checkout@v4\n";
+        fs::write(&path, content).unwrap();
+
+        let workflow = WorkflowFile::parse(&path).unwrap();
+        assert_eq!(workflow.actions.len(), 2);
+
+        let action = ActionRef::parse("actions/checkout@v4").unwrap();
+        let sha = "b4ffde65f46336ab88eb53be808477a3936bae11".to_string();
+        let pinned_map = HashMap::from([(action.to_string(), PinnedAction::new(action, sha))]);
+
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 10);
+        let mut results = Vec::new();
+        processor
+            .rewrite_workflow(&workflow, &pinned_map, &mut results)
+            .unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "steps:\n  - uses: actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11 # v4\n  - run: make\n  - uses: actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11 # v4\n"
+        );
+    }
+
+    #[test]
+    fn test_rewrite_several_uses_on_one_line() {
+        const SHA: &str = "b4ffde65f46336ab88eb53be808477a3936bae11";
+        let pin = |uses: &str| {
+            let action = ActionRef::parse(uses).unwrap();
+            (
+                action.to_string(),
+                PinnedAction::new(action, SHA.to_string()),
+            )
+        };
+        let pinned_map = HashMap::from([
+            pin("actions/checkout@v4"),
+            pin("actions/cache@v3"),
+            pin("actions/cache@v4"),
+        ]);
+
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("ci.yml");
+        let content = [
+            "jobs:",
+            "  a:",
+            "    steps: [{uses: actions/checkout@v4}, {uses: actions/cache@v3}, {uses: actions/cache@v4}]",
+            "  b:",
+            "    steps: [{uses: org/unresolved@v1}, {uses: actions/checkout@v4}] # keep me",
+            "",
+        ]
+        .join("\n");
+        fs::write(&path, content).unwrap();
+
+        let workflow = WorkflowFile::parse(&path).unwrap();
+        assert_eq!(workflow.actions.len(), 5);
+
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 10);
+        let mut results = Vec::new();
+        processor
+            .rewrite_workflow(&workflow, &pinned_map, &mut results)
+            .unwrap();
+
+        let old_refs: Vec<(usize, &str)> = results
+            .iter()
+            .map(|r| (r.line, r.old_ref.as_str()))
+            .collect();
+        assert_eq!(old_refs, vec![(3, "v4"), (3, "v3"), (3, "v4"), (5, "v4")]);
+
+        let rewritten = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = rewritten.lines().collect();
+        assert_eq!(
+            lines[2],
+            format!(
+                "    steps: [{{uses: actions/checkout@{SHA}}}, {{uses: actions/cache@{SHA}}}, {{uses: actions/cache@{SHA}}}] # v4, v3, v4"
+            )
+        );
+        assert_eq!(
+            lines[4],
+            format!(
+                "    steps: [{{uses: org/unresolved@v1}}, {{uses: actions/checkout@{SHA}}}] # , v4 # keep me"
+            )
+        );
+
+        // The shared comment reads back as each use's version
+        let reparsed = WorkflowFile::parse(&path).unwrap();
+        let comments: Vec<Option<&str>> = reparsed
+            .actions
+            .iter()
+            .map(|u| u.comment_ref.as_deref())
+            .collect();
+        assert_eq!(
+            comments,
+            vec![Some("v4"), Some("v3"), Some("v4"), None, Some("v4")]
+        );
+        assert_eq!(reparsed.actions[4].note.as_deref(), Some("keep me"));
+    }
+
+    #[test]
+    fn test_render_keeps_bom_and_whitespace() {
+        const SHA: &str = "b4ffde65f46336ab88eb53be808477a3936bae11";
+        let action = ActionRef::parse("actions/checkout@v4").unwrap();
+        let pinned_map = HashMap::from([(
+            action.to_string(),
+            PinnedAction::new(action, SHA.to_string()),
+        )]);
+
+        let render = |content: &str| {
+            let workflow = WorkflowFile::parse_str("ci.yml".to_string(), content.to_string(), &[]);
+            let mut out = Vec::new();
+            render_workflow(
+                &workflow,
+                content.as_bytes(),
+                &mut out,
+                &pinned_map,
+                &HashMap::new(),
+                &LineStyle::default(),
+            )
+            .unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let cases = [
+            (
+                "\u{feff}steps:\n  - uses: actions/checkout@v4\n",
+                format!("\u{feff}steps:\n  - uses: actions/checkout@{SHA} # v4\n"),
+            ),
+            // The mark stays in front when the first line is rewritten
+            (
+                "\u{feff}- uses: actions/checkout@v4\r\n",
+                format!("\u{feff}- uses: actions/checkout@{SHA} # v4\r\n"),
+            ),
+            // Tabs and odd spacing before the action are kept as they were;
+            // untouched lines keep their trailing whitespace
+            (
+                "steps:\t\n\t- uses:\tactions/checkout@v4   \n \t - run: make  \t\n  -  uses:  actions/checkout@v4\t# was v4\n",
+                format!(
+                    "steps:\t\n\t- uses:\tactions/checkout@{SHA} # v4\n \t - run: make  \t\n  -  uses:  actions/checkout@{SHA} # v4 # was v4\n"
+                ),
+            ),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(render(input), expected, "for {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_render_refuses_lines_read_lossily() {
+        let action = ActionRef::parse("actions/checkout@v4").unwrap();
+        let pinned_map = HashMap::from([(
+            action.to_string(),
+            PinnedAction::new(
+                action,
+                "b4ffde65f46336ab88eb53be808477a3936bae11".to_string(),
+            ),
+        )]);
+
+        let content = b"steps:\n  - uses: actions/checkout@v4 # caf\xe9\n";
+        let workflow =
+            WorkflowFile::parse_reader("ci.yml".to_string(), &content[..], &[], true).unwrap();
+        let result = render_workflow(
+            &workflow,
+            &content[..],
+            &mut Vec::new(),
+            &pinned_map,
+            &HashMap::new(),
+            &LineStyle::default(),
+        );
+        let Err(error) = result else {
+            panic!("rewrote a line that isn't valid UTF-8");
+        };
+        assert!(error.to_string().contains("line 2 isn't valid UTF-8"));
+    }
+
+    #[test]
+    fn test_render_flow_mapping_step() {
+        const SHA: &str = "b4ffde65f46336ab88eb53be808477a3936bae11";
+        let action = ActionRef::parse("actions/checkout@v4").unwrap();
+        let pinned_map = HashMap::from([(
+            action.to_string(),
+            PinnedAction::new(action, SHA.to_string()),
+        )]);
+
+        let content = "steps:\n  - { uses: actions/checkout@v4, with: { fetch-depth: 0 } }  \n  - { name: Quoted, uses: \"actions/checkout@v4\" }\n";
+        let workflow = WorkflowFile::parse_str("ci.yml".to_string(), content.to_string(), &[]);
+        let mut out = Vec::new();
+        render_workflow(
+            &workflow,
+            content.as_bytes(),
+            &mut out,
+            &pinned_map,
+            &HashMap::new(),
+            &LineStyle::default(),
+        )
+        .unwrap();
+
+        // Only the action changes; the other keys and the quotes stay put
+        let rendered = String::from_utf8(out).unwrap();
+        assert_eq!(
+            rendered,
+            format!(
+                "steps:\n  - {{ uses: actions/checkout@{SHA}, with: {{ fetch-depth: 0 }} }} # v4\n  - {{ name: Quoted, uses: \"actions/checkout@{SHA}\" }} # v4\n"
+            )
+        );
+        let yaml: serde_yaml::Value = serde_yaml::from_str(&rendered).unwrap();
+        assert_eq!(
+            yaml["steps"][0]["uses"].as_str(),
+            Some(format!("actions/checkout@{SHA}").as_str())
+        );
+        assert_eq!(yaml["steps"][0]["with"]["fetch-depth"].as_u64(), Some(0));
+        assert_eq!(yaml["steps"][1]["name"].as_str(), Some("Quoted"));
+    }
+
+    #[test]
+    fn test_render_provenance_comment() {
+        const OLD: &str = "b4ffde65f46336ab88eb53be808477a3936bae11";
+        const NEW: &str = "11bd71901bbe5b1630ceea73d27597364c9af683";
+        const PROVENANCE: &str = "pinned by pin-actions v1.0.0 via ls-remote on 2024-05-01";
+
+        let render = |content: &str, pins: &[(&str, &str)], provenance: &str| {
+            let pinned_map: HashMap<String, PinnedAction> = pins
+                .iter()
+                .map(|(action, sha)| {
+                    let action = ActionRef::parse(action).unwrap();
+                    // Re-pins name the tag, as an update would
+                    let mut pinned = PinnedAction::new(action.clone(), sha.to_string());
+                    pinned.original_ref = "v4".to_string();
+                    (action.to_string(), pinned)
+                })
+                .collect();
+            let workflow = WorkflowFile::parse_str("ci.yml".to_string(), content.to_string(), &[]);
+            let mut out = Vec::new();
+            let rendered = render_workflow(
+                &workflow,
+                content.as_bytes(),
+                &mut out,
+                &pinned_map,
+                &HashMap::new(),
+                &LineStyle {
+                    provenance: Some(provenance),
+                    ..LineStyle::default()
+                },
+            )
+            .unwrap();
+            (String::from_utf8(out).unwrap(), rendered)
+        };
+
+        // Inserted above each rewritten line at its indentation, with the
+        // file's line ending, even above a last line without one
+        let content = "steps:\r\n  - uses: actions/checkout@v4\r\n  - run: make\r\n  - uses: actions/setup-node@v4";
+        let (first, rendered) = render(
+            content,
+            &[("actions/checkout@v4", OLD), ("actions/setup-node@v4", OLD)],
+            PROVENANCE,
+        );
+        assert_eq!(
+            first,
+            format!(
+                "steps:\r\n  # {PROVENANCE}\r\n  - uses: actions/checkout@{OLD} # v4\r\n  - run: make\r\n  # {PROVENANCE}\r\n  - uses: actions/setup-node@{OLD} # v4"
+            )
+        );
+        assert_eq!(rendered.inserted, vec![2, 5]);
+        assert_eq!(rendered.lines_changed, 2);
+
+        // Pinned lines aren't rewritten again, so a re-run adds nothing
+        let (second, rendered) = render(&first, &[("actions/checkout@v4", OLD)], PROVENANCE);
+        assert_eq!(second, first);
+        assert_eq!(rendered.lines_changed, 0);
+
+        // Re-pinning refreshes the comment above instead of adding another
+        let later = "pinned by pin-actions v1.1.0 via ls-remote on 2024-06-01";
+        let (third, rendered) = render(&first, &[(&format!("actions/checkout@{OLD}"), NEW)], later);
+        assert_eq!(
+            third,
+            first.replacen(PROVENANCE, later, 1).replacen(
+                &format!("checkout@{OLD}"),
+                &format!("checkout@{NEW}"),
+                1
+            )
+        );
+        assert!(rendered.inserted.is_empty());
+        assert_eq!(rendered.lines_changed, 2);
+    }
+
+    #[test]
+    fn test_lookup_stats_percentiles() {
+        let mut metrics = Metrics::default();
+        LookupStats::default().record(&mut metrics);
+        assert_eq!(metrics.resolution_p50_ms, None);
+        assert_eq!(metrics.resolution_p95_ms, None);
+
+        // Nearest rank over 1..=20ms, recorded in any order
+        let stats = LookupStats {
+            network: 15,
+            cache_hits: 5,
+            retries: 2,
+            latencies: (1..=20).rev().map(Duration::from_millis).collect(),
+        };
+        stats.record(&mut metrics);
+        assert_eq!(metrics.network_resolutions, 15);
+        assert_eq!(metrics.cache_hits, 5);
+        assert_eq!(metrics.retries, 2);
+        assert_eq!(metrics.resolution_p50_ms, Some(10.0));
+        assert_eq!(metrics.resolution_p95_ms, Some(19.0));
+
+        let one = [Duration::from_millis(7)];
+        assert_eq!(percentile(&one, 95), Some(one[0]));
+    }
+
+    #[test]
+    fn test_render_replace_comment_policies() {
+        const OLD: &str = "b4ffde65f46336ab88eb53be808477a3936bae11";
+        const NEW: &str = "11bd71901bbe5b1630ceea73d27597364c9af683";
+        let content =
+            format!("steps:\n  - uses: actions/checkout@{OLD} # v4 (pinned 2024-01-01)\n");
+
+        let render = |tag: &str, replace_comment: ReplaceComment| {
+            let action = ActionRef::parse(&format!("actions/checkout@{OLD}")).unwrap();
+            let mut pinned = PinnedAction::new(action.clone(), NEW.to_string());
+            pinned.original_ref = tag.to_string();
+            pinned.pinned_on = Some("2024-06-01".to_string());
+            let pinned_map = HashMap::from([(action.to_string(), pinned)]);
+            let workflow = WorkflowFile::parse_str("ci.yml".to_string(), content.clone(), &[]);
+            let mut out = Vec::new();
+            render_workflow(
+                &workflow,
+                content.as_bytes(),
+                &mut out,
+                &pinned_map,
+                &HashMap::new(),
+                &LineStyle {
+                    replace_comment,
+                    ..LineStyle::default()
+                },
+            )
+            .unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let kept = format!("steps:\n  - uses: actions/checkout@{NEW} # v4 (pinned 2024-01-01)\n");
+        let fresh = |tag: &str| {
+            format!("steps:\n  - uses: actions/checkout@{NEW} # {tag} (pinned 2024-06-01)\n")
+        };
+
+        // Same tag, new SHA: only `always` touches the comment
+        assert_eq!(render("v4", ReplaceComment::Always), fresh("v4"));
+        assert_eq!(render("v4", ReplaceComment::Never), kept);
+        assert_eq!(render("v4", ReplaceComment::IfStale), kept);
+
+        // A different tag makes the comment stale
+        assert_eq!(render("v5", ReplaceComment::Always), fresh("v5"));
+        assert_eq!(render("v5", ReplaceComment::Never), kept);
+        assert_eq!(render("v5", ReplaceComment::IfStale), fresh("v5"));
+    }
+
+    #[test]
+    fn test_rewrite_multi_document_file() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("ci.yml");
+        let content = "---\nsteps:\n  - uses: actions/checkout@v4\n---\nsteps:\n  - uses: actions/checkout@v4\n";
+        fs::write(&path, content).unwrap();
+
+        let workflow = WorkflowFile::parse(&path).unwrap();
+        let action = ActionRef::parse("actions/checkout@v4").unwrap();
+        let sha = "b4ffde65f46336ab88eb53be808477a3936bae11".to_string();
+        let pinned_map = HashMap::from([(action.to_string(), PinnedAction::new(action, sha))]);
+
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 10);
+        let mut results = Vec::new();
+        processor
+            .rewrite_workflow(&workflow, &pinned_map, &mut results)
+            .unwrap();
+
+        assert_eq!(results.len(), 2);
+        let pinned = "  - uses: actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11 # v4";
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("---\nsteps:\n{}\n---\nsteps:\n{}\n", pinned, pinned)
+        );
+    }
+
+    #[test]
+    fn test_render_is_idempotent() {
+        const SHA: &str = "b4ffde65f46336ab88eb53be808477a3936bae11";
+        const DIGEST: &str =
+            "sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+        let pin = |uses: &str| {
+            let action = ActionRef::parse(uses).unwrap();
+            (
+                action.to_string(),
+                PinnedAction::new(action, SHA.to_string()),
+            )
+        };
+        let mut pinned_map =
+            HashMap::from([pin("actions/checkout@v4"), pin("actions/cache@tag:v4")]);
+        let (key, branch) = pin("actions/setup-node@main");
+        pinned_map.insert(key, branch.with_branch(true));
+        let (key, dated) = pin("actions/upload-artifact@v4");
+        pinned_map.insert(key, dated.with_pinned_on("2024-05-01".to_string()));
+        let digests = HashMap::from([
+            ("node:20".to_string(), DIGEST.to_string()),
+            ("redis:7".to_string(), DIGEST.to_string()),
+        ]);
+
+        let inputs = [
+            "steps:\n  - uses: actions/checkout@v4\n",
+            "steps:\r\n  - uses: actions/checkout@v4\r\n  - run: make\r\n",
+            "steps:\n  - uses: actions/checkout@v4",
+            "steps:\n  - uses: actions/checkout@v4 # keep me\n",
+            "steps:\n  - uses: actions/cache@tag:v4\n",
+            "steps:\n  - uses: actions/setup-node@main\n  - uses: actions/upload-artifact@v4\n",
+            "---\nsteps:\n  - uses: actions/checkout@v4\n---\nsteps:\n  - uses: actions/cache@tag:v4\n",
+            "jobs:\n  test:\n    container: node:20\n    services:\n      cache: { image: redis:7 }\n",
+            "steps:\n  - uses: actions/checkout@v4\n  - run: |\n      uses: actions/checkout@v4\n",
+            "steps: [{uses: actions/checkout@v4}, {uses: actions/setup-node@main}] # flow\n",
+            "\u{feff}steps:\r\n\t- uses:\tactions/checkout@v4  \r\n",
+            "",
+        ];
+
+        let render = |content: &str| {
+            let workflow = WorkflowFile::parse_str("ci.yml".to_string(), content.to_string(), &[]);
+            let mut out = Vec::new();
+            let rendered = render_workflow(
+                &workflow,
+                content.as_bytes(),
+                &mut out,
+                &pinned_map,
+                &digests,
+                &LineStyle::default(),
+            )
+            .unwrap();
+            (String::from_utf8(out).unwrap(), rendered)
+        };
+
+        for input in inputs {
+            let (first, _) = render(input);
+            let (second_content, second) = render(&first);
+
+            assert_eq!(second_content, first, "unstable for {:?}", input);
+            assert_eq!(second.lines_changed, 0, "unstable for {:?}", input);
+            assert!(second.actions.is_empty() && second.images.is_empty());
+            assert_eq!(
+                first.ends_with('\n'),
+                input.ends_with('\n'),
+                "trailing newline changed for {:?}",
+                input
+            );
+            assert_eq!(
+                first.matches("\r\n").count(),
+                input.matches("\r\n").count(),
+                "line endings changed for {:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_assert_idempotent_rejects_unstable_rewrite() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("ci.yml");
+        let content = "steps:\n  - uses: actions/checkout@v4\n";
+        fs::write(&path, content).unwrap();
+
+        let workflow = WorkflowFile::parse(&path).unwrap();
+        // A "pin" that still reads as a tag would be pinned again on rerun
+        let action = ActionRef::parse("actions/checkout@v4").unwrap();
+        let pinned_map = HashMap::from([(
+            action.to_string(),
+            PinnedAction::new(action, "v4".to_string()),
+        )]);
+
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 10)
+                .with_assert_idempotent(true);
+        let error = processor
+            .rewrite_workflow(&workflow, &pinned_map, &mut Vec::new())
+            .unwrap_err();
+
+        assert!(error.to_string().contains("not idempotent"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), content);
+
+        // Still caught when a provenance comment pushes the line down
+        let processor =
+            processor.with_provenance(Some(provenance_comment(ResolverKind::Git, "2024-05-01")));
+        let error = processor
+            .rewrite_workflow(&workflow, &pinned_map, &mut Vec::new())
+            .unwrap_err();
+        assert!(error.to_string().contains("not idempotent"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), content);
+    }
+
+    #[test]
+    fn test_rewrite_streams_file_and_refuses_moved_lines() {
+        const SHA: &str = "b4ffde65f46336ab88eb53be808477a3936bae11";
+
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("ci.yml");
+        // Mixed line endings are each kept as they were
+        fs::write(
+            &path,
+            "steps:\r\n  - uses: actions/checkout@v4\n  - run: make\r\n",
+        )
+        .unwrap();
+        let action = ActionRef::parse("actions/checkout@v4").unwrap();
+        let pinned_map = HashMap::from([(
+            action.to_string(),
+            PinnedAction::new(action, SHA.to_string()),
+        )]);
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 10);
+
+        let workflow = WorkflowFile::parse(&path).unwrap();
+        assert!(workflow.content.is_none());
+        processor
+            .rewrite_workflow(&workflow, &pinned_map, &mut Vec::new())
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!(
+                "steps:\r\n  - uses: actions/checkout@{} # v4\n  - run: make\r\n",
+                SHA
+            )
+        );
+
+        // A line inserted after parsing would put the pin on the wrong line
+        fs::write(&path, "steps:\n  - uses: actions/checkout@v4\n").unwrap();
+        let workflow = WorkflowFile::parse(&path).unwrap();
+        fs::write(&path, "name: CI\nsteps:\n  - uses: actions/checkout@v4\n").unwrap();
+        let error = processor
+            .rewrite_workflow(&workflow, &pinned_map, &mut Vec::new())
+            .unwrap_err();
+        assert!(error.to_string().contains("changed since it was read"));
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "name: CI\nsteps:\n  - uses: actions/checkout@v4\n"
+        );
+
+        // So would an edit that keeps every line where it was
+        fs::write(
+            &path,
+            "steps:\n  - uses: actions/checkout@v4\n  - run: make\n",
+        )
+        .unwrap();
+        let workflow = WorkflowFile::parse(&path).unwrap();
+        fs::write(
+            &path,
+            "steps:\n  - uses: actions/checkout@v4\n  - run: test\n",
+        )
+        .unwrap();
+        let error = processor
+            .rewrite_workflow(&workflow, &pinned_map, &mut Vec::new())
+            .unwrap_err();
+        assert!(error.to_string().contains("changed since it was read"));
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "steps:\n  - uses: actions/checkout@v4\n  - run: test\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_archived() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/repos/old/tool")
+            .with_body(r#"{"full_name": "old/tool", "archived": true}"#)
+            .create_async()
+            .await;
+        // Already answered by the GraphQL resolver, so never looked up
+        let checkout = server
+            .mock("GET", "/repos/actions/checkout")
+            .expect(0)
+            .create_async()
+            .await;
+
+        let temp = TempDir::new().unwrap();
+        fs::write(
+            temp.path().join("ci.yml"),
+            "steps:\n  - uses: old/tool/sub@v1\n  - uses: actions/checkout@v4\n  - uses: old/tool@v2\n",
+        )
+        .unwrap();
+
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 10)
+                .with_check_archived(Some(server.url()));
+        processor
+            .archived
+            .lock()
+            .unwrap()
+            .insert("actions/checkout".to_string(), false);
+        let results = processor.report_unpinned().await.unwrap();
+
+        let file = temp.path().join("ci.yml").to_string_lossy().to_string();
+        assert_eq!(
+            results.archived_actions,
+            vec![ArchivedAction {
+                action: "old/tool".to_string(),
+                locations: vec![format!("{}:2", file), format!("{}:4", file)],
+            }]
+        );
+        checkout.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_require_signed_leaves_unsigned_tags_unpinned() {
+        const SHA: &str = "b4ffde65f46336ab88eb53be808477a3936bae11";
+        const TAG_SHA: &str = "9c8f5b2e0d1a3f4e5b6c7d8e9f0a1b2c3d4e5f60";
+
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/repos/actions/checkout/git/ref/tags/v4")
+            .with_body(format!(
+                r#"{{"object": {{"type": "tag", "sha": "{}"}}}}"#,
+                TAG_SHA
+            ))
+            .create_async()
+            .await;
+        server
+            .mock(
+                "GET",
+                format!("/repos/actions/checkout/git/tags/{}", TAG_SHA).as_str(),
+            )
+            .with_body(format!(
+                r#"{{"object": {{"type": "commit", "sha": "{}"}}, "verification": {{"verified": true, "reason": "valid", "signature": "sig"}}}}"#,
+                SHA
+            ))
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/repos/actions/cache/git/ref/tags/v4")
+            .with_body(format!(
+                r#"{{"object": {{"type": "commit", "sha": "{}"}}}}"#,
+                SHA
+            ))
+            .create_async()
+            .await;
+
+        let temp = TempDir::new().unwrap();
+        let lockfile = temp.path().join("pin-actions.lock");
+        fs::write(
+            &lockfile,
+            format!(
+                r#"{{"actions": {{"actions/checkout@v4": "{}", "actions/cache@v4": "{}"}}}}"#,
+                SHA, SHA
+            ),
+        )
+        .unwrap();
+        let path = temp.path().join("ci.yml");
+        fs::write(
+            &path,
+            "steps:\n  - uses: actions/checkout@v4\n  - uses: actions/cache@v4\n",
+        )
+        .unwrap();
+
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 10)
+                .with_lockfile(Some(lockfile))
+                .with_offline(true)
+                .with_verify_signatures(Some(server.url()))
+                .with_require_signed(true);
+        let results = processor.process().await.unwrap();
+
+        assert_eq!(results.pinned_actions.len(), 1);
+        assert_eq!(
+            results.pinned_actions[0].signature,
+            Some(SignatureStatus::Verified)
+        );
+        assert_eq!(results.errors_detail.len(), 1);
+        assert_eq!(results.errors_detail[0].code, ErrorCode::Signature);
+        assert_eq!(results.errors_detail[0].target, "actions/cache@v4");
+        assert!(
+            fs::read_to_string(&path)
+                .unwrap()
+                .contains("uses: actions/cache@v4\n")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_require_signed_tags_pins_only_signed_tags() {
+        const SHA: &str = "b4ffde65f46336ab88eb53be808477a3936bae11";
+        const TAG_SHA: &str = "9c8f5b2e0d1a3f4e5b6c7d8e9f0a1b2c3d4e5f60";
+        const OTHER: &str = "11bd71901bbe5b1630ceea73d27597364c9af683";
+
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/repos/actions/checkout/git/ref/tags/v4")
+            .with_body(format!(
+                r#"{{"object": {{"type": "tag", "sha": "{}"}}}}"#,
+                TAG_SHA
+            ))
+            .create_async()
+            .await;
+        server
+            .mock(
+                "GET",
+                format!("/repos/actions/checkout/git/tags/{}", TAG_SHA).as_str(),
+            )
+            .with_body(format!(
+                r#"{{"object": {{"type": "commit", "sha": "{}"}}, "verification": {{"verified": true, "reason": "valid", "signature": "sig"}}}}"#,
+                SHA
+            ))
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/repos/actions/cache/git/ref/tags/v4")
+            .with_body(format!(
+                r#"{{"object": {{"type": "commit", "sha": "{}"}}}}"#,
+                SHA
+            ))
+            .create_async()
+            .await;
+        // Signed and verified, but the tag is of another commit
+        server
+            .mock("GET", "/repos/actions/setup-node/git/ref/tags/v4")
+            .with_body(format!(
+                r#"{{"object": {{"type": "tag", "sha": "{}"}}}}"#,
+                TAG_SHA
+            ))
+            .create_async()
+            .await;
+        server
+            .mock(
+                "GET",
+                format!("/repos/actions/setup-node/git/tags/{}", TAG_SHA).as_str(),
+            )
+            .with_body(format!(
+                r#"{{"object": {{"type": "commit", "sha": "{}"}}, "verification": {{"verified": true, "reason": "valid", "signature": "sig"}}}}"#,
+                OTHER
+            ))
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/repos/owner/other/git/ref/tags/v2")
+            .with_status(404)
+            .create_async()
+            .await;
+
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("ci.yml");
+        let content = "steps:\n  - uses: actions/checkout@v4\n  - uses: actions/cache@v4\n  - uses: owner/tool@branch:stable\n  - uses: owner/other@v2\n  - uses: actions/setup-node@v4\n";
+        fs::write(&path, content).unwrap();
+
+        let resolver = Arc::new(
+            FakeResolver::default()
+                .with("actions/checkout@v4", Ok(SHA))
+                .with("actions/cache@v4", Ok(SHA))
+                .with("owner/tool@branch:stable", Ok(SHA))
+                .with("owner/other@v2", Ok(SHA))
+                .with("actions/setup-node@v4", Ok(SHA)),
+        );
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], true, false, true, 10)
+                .with_resolver(resolver)
+                .with_verify_signatures(Some(server.url()))
+                .with_require_signed_tags(true);
+        let results = processor.process().await.unwrap();
+
+        assert_eq!(results.pinned_actions.len(), 1);
+        assert_eq!(results.pinned_actions[0].action, "actions/checkout");
+        let mut errors: Vec<(&str, &str)> = results
+            .errors_detail
+            .iter()
+            .inspect(|e| assert_eq!(e.code, ErrorCode::Signature))
+            .map(|e| (e.target.as_str(), e.message.as_str()))
+            .collect();
+        errors.sort();
+        assert_eq!(
+            errors,
+            vec![
+                ("actions/cache@v4", "tag is unsigned"),
+                (
+                    "actions/setup-node@v4",
+                    &format!("tag v4 points at {}, not the pinned {}", OTHER, SHA)[..],
+                ),
+                ("owner/other@v2", "not a tag"),
+                ("owner/tool@branch:stable", "a branch, not a signed tag"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires network access
+    async fn test_default_branch_annotation() {
+        let temp = TempDir::new().unwrap();
+        let sha = "b4ffde65f46336ab88eb53be808477a3936bae11";
+        let path = temp.path().join("ci.yml");
+        fs::write(
+            &path,
+            "steps:\n  - uses: actions/checkout@main\n  - uses: actions/cache@v4\n",
+        )
+        .unwrap();
+
+        // Refs come from the lockfile; only the default branch is looked up
+        let lockfile_path = temp.path().join("pin-actions.lock");
+        let mut lockfile = Lockfile::default();
+        lockfile.insert("actions/checkout@main".to_string(), sha.to_string());
+        lockfile.insert("actions/cache@v4".to_string(), sha.to_string());
+        lockfile.save(&lockfile_path).unwrap();
+
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 10)
+                .with_lockfile(Some(lockfile_path));
+        let results = processor.process().await.unwrap();
+
+        assert_eq!(results.errors, 0);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!(
+                "steps:\n  - uses: actions/checkout@{} # main (branch)\n  - uses: actions/cache@{} # v4\n",
+                sha, sha
+            )
+        );
+        let checkout = results
+            .pinned_actions
+            .iter()
+            .find(|r| r.action == "actions/checkout")
+            .unwrap();
+        assert!(checkout.on_branch);
+    }
+
+    #[tokio::test]
+    async fn test_default_branch_from_resolver() {
+        const SHA: &str = "b4ffde65f46336ab88eb53be808477a3936bae11";
+
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("ci.yml");
+        fs::write(
+            &path,
+            "steps:\n  - uses: owner/tool@master\n  - uses: owner/tool@v1\n  - uses: other/lib/sub@main\n",
+        )
+        .unwrap();
+
+        // The branches come with the lookups, so none are asked of git
+        let resolver = Arc::new(
+            FakeResolver::default()
+                .with("owner/tool@master", Ok(SHA))
+                .with("owner/tool@v1", Ok(SHA))
+                .with("other/lib/sub@main", Ok(SHA))
+                .branch("owner/tool", "master")
+                .branch("other/lib", "develop"),
+        );
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 10)
+                .with_resolver(resolver);
+        let results = processor.process().await.unwrap();
+
+        assert_eq!(results.errors, 0);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!(
+                "steps:\n  - uses: owner/tool@{SHA} # master (branch)\n  - uses: owner/tool@{SHA} # v1\n  - uses: other/lib/sub@{SHA} # main\n"
+            )
+        );
+        let on_branch: Vec<&str> = results
+            .pinned_actions
+            .iter()
+            .filter(|r| r.on_branch)
+            .map(|r| r.old_ref.as_str())
+            .collect();
+        assert_eq!(on_branch, ["master"]);
+    }
+
+    #[tokio::test]
+    async fn test_report_drift_looks_past_the_lockfile() {
+        let temp = TempDir::new().unwrap();
+        let old_sha = "b4ffde65f46336ab88eb53be808477a3936bae11";
+        let new_sha = "11bd71901bbe5b1630ceea73d27597364c9af683";
+        let path = temp.path().join("ci.yml");
+        fs::write(
+            &path,
+            format!("steps:\n  - uses: actions/checkout@{} # v4\n", old_sha),
+        )
+        .unwrap();
+
+        // The lockfile still records what was pinned
+        let lockfile_path = temp.path().join("pin-actions.lock");
+        let mut lockfile = Lockfile::default();
+        lockfile.insert("actions/checkout@v4".to_string(), old_sha.to_string());
+        lockfile.save(&lockfile_path).unwrap();
+
+        let resolver = Arc::new(FakeResolver::default().with("actions/checkout@v4", Ok(new_sha)));
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 10)
+                .with_lockfile(Some(lockfile_path.clone()))
+                .with_resolver(resolver.clone());
+        let results = processor.report_drift().await.unwrap();
+
+        assert_eq!(*resolver.asked.lock().unwrap(), vec!["actions/checkout@v4"]);
+        assert_eq!(results.drifted_actions.len(), 1);
+        assert_eq!(results.drifted_actions[0].current_sha, new_sha);
+
+        // Offline, the lockfile is all there is to go on
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 10)
+                .with_lockfile(Some(lockfile_path))
+                .with_offline(true);
+        let results = processor.report_drift().await.unwrap();
+        assert!(results.drifted_actions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_known_refs_look_past_the_lockfile() {
+        let temp = TempDir::new().unwrap();
+        let old_sha = "b4ffde65f46336ab88eb53be808477a3936bae11";
+        let new_sha = "11bd71901bbe5b1630ceea73d27597364c9af683";
+        let path = temp.path().join("ci.yml");
+        let original = "steps:\n  - uses: actions/checkout@v4\n";
+        fs::write(&path, original).unwrap();
+
+        // Both the lockfile and the store remember where the tag was
+        let lockfile_path = temp.path().join("pin-actions.lock");
+        let mut lockfile = Lockfile::default();
+        lockfile.insert("actions/checkout@v4".to_string(), old_sha.to_string());
+        lockfile.save(&lockfile_path).unwrap();
+        let known_path = temp.path().join("known.json");
+        let mut known = KnownRefs::default();
+        known.insert(
+            "actions/checkout@v4".to_string(),
+            old_sha.to_string(),
+            "2024-01-01".to_string(),
+        );
+        known.save(&known_path).unwrap();
+
+        let resolver = Arc::new(FakeResolver::default().with("actions/checkout@v4", Ok(new_sha)));
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 10)
+                .with_lockfile(Some(lockfile_path.clone()))
+                .with_known_refs(Some(known_path.clone()))
+                .with_resolver(resolver);
+        let results = processor.process().await.unwrap();
+
+        assert_eq!(results.moved_refs.len(), 1);
+        assert_eq!(results.moved_refs[0].known_sha, old_sha);
+        assert_eq!(results.moved_refs[0].resolved_sha, new_sha);
+        assert!(results.pinned_actions.is_empty());
+        assert_eq!(fs::read_to_string(&path).unwrap(), original);
+        assert_eq!(results.remaining_unpinned.len(), 1);
+        assert_eq!(
+            results.remaining_unpinned[0].detail,
+            format!(
+                "ref moved from {} (first seen 2024-01-01) to {}",
+                old_sha, new_sha
+            )
+        );
+
+        // Offline, the lockfile is all there is to go on
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 10)
+                .with_lockfile(Some(lockfile_path))
+                .with_known_refs(Some(known_path))
+                .with_offline(true);
+        let results = processor.process().await.unwrap();
+        assert!(results.moved_refs.is_empty());
+        assert_eq!(results.pinned_actions.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_and_moved_refs_count_as_unresolved() {
+        const SHA: &str = "b4ffde65f46336ab88eb53be808477a3936bae11";
+        const MOVED: &str = "11bd71901bbe5b1630ceea73d27597364c9af683";
+
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("ci.yml");
+        fs::write(
+            &path,
+            "steps:\n  - uses: actions/checkout@v4\n  - uses: actions/cache@v9\n  - uses: actions/setup-node@v4\n",
+        )
+        .unwrap();
+        let known_path = temp.path().join("known.json");
+        let mut known = KnownRefs::default();
+        known.insert(
+            "actions/checkout@v4".to_string(),
+            SHA.to_string(),
+            "2024-01-01".to_string(),
+        );
+        known.save(&known_path).unwrap();
+
+        let resolver = Arc::new(
+            FakeResolver::default()
+                .with("actions/checkout@v4", Ok(MOVED))
+                .with("actions/cache@v9", Err(ErrorCode::RefNotFound))
+                .with("actions/setup-node@v4", Ok(SHA)),
+        );
+        for dry_run in [true, false] {
+            let processor =
+                WorkflowProcessor::new(vec![temp.path().to_path_buf()], dry_run, false, true, 10)
+                    .with_known_refs(Some(known_path.clone()))
+                    .with_resolver(resolver.clone());
+            let results = processor.process().await.unwrap();
+
+            // Only setup-node was pinned; the moved and the failed ref weren't
+            assert_eq!(results.actions_pinned, 1, "dry run: {}", dry_run);
+            assert_eq!(results.pinned_actions.len(), 1);
+            assert_eq!(results.remaining_unpinned.len(), 2);
+            assert_eq!(results.actions_unresolved(), 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_comments_looks_past_the_lockfile() {
+        let temp = TempDir::new().unwrap();
+        let v3_sha = "f43a0e5ff2bd294095638e18286ca9a3d1956744";
+        let stale_sha = "b4ffde65f46336ab88eb53be808477a3936bae11";
+        let v4_sha = "11bd71901bbe5b1630ceea73d27597364c9af683";
+        let path = temp.path().join("ci.yml");
+        // The comment was edited from v3 to v4 by hand
+        fs::write(
+            &path,
+            format!("steps:\n  - uses: actions/checkout@{} # v4\n", v3_sha),
+        )
+        .unwrap();
+
+        // The lockfile has v4 where it was before the tag moved
+        let lockfile_path = temp.path().join("pin-actions.lock");
+        let mut lockfile = Lockfile::default();
+        lockfile.insert("actions/checkout@v4".to_string(), stale_sha.to_string());
+        lockfile.save(&lockfile_path).unwrap();
+
+        let resolver = Arc::new(FakeResolver::default().with("actions/checkout@v4", Ok(v4_sha)));
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 10)
+                .with_lockfile(Some(lockfile_path))
+                .with_resolver(resolver);
+        let results = processor.sync_comments().await.unwrap();
+
+        assert_eq!(results.updated.len(), 1);
+        assert_eq!(results.updated[0].sha, v4_sha);
+        assert!(
+            fs::read_to_string(&path)
+                .unwrap()
+                .contains(&format!("actions/checkout@{} # v4", v4_sha))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_status_of_a_run_leaving_uses_unpinned() {
+        const SHA: &str = "b4ffde65f46336ab88eb53be808477a3936bae11";
+
+        let temp = TempDir::new().unwrap();
+        fs::write(
+            temp.path().join("ci.yml"),
+            "steps:\n  - uses: actions/checkout@v4\n",
+        )
+        .unwrap();
+        let known_path = temp.path().join("known.json");
+        let mut known = KnownRefs::default();
+        known.insert(
+            "actions/checkout@v4".to_string(),
+            "0000000000000000000000000000000000000000".to_string(),
+            "2024-01-01".to_string(),
+        );
+        known.save(&known_path).unwrap();
+
+        // The tag moved, so the use stays on it without any error
+        let resolver = Arc::new(FakeResolver::default().with("actions/checkout@v4", Ok(SHA)));
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 10)
+                .with_known_refs(Some(known_path))
+                .with_resolver(resolver);
+        let results = processor.process().await.unwrap();
+
+        assert_eq!(results.errors, 0);
+        assert_eq!(results.lines_changed, 0);
+        assert_eq!(results.actions_unresolved(), 1);
+        assert_eq!(
+            serde_json::to_value(results.run_status()).unwrap(),
+            "unresolved"
+        );
+    }
+
+    #[test]
+    fn test_run_status() {
+        let status = |results: &ProcessResults| serde_json::to_value(results.run_status()).unwrap();
+        let remaining = |reason| RemainingAction {
+            file: "ci.yml".to_string(),
+            line: 3,
+            action: "actions/cache".to_string(),
+            reference: "v4".to_string(),
+            reason,
+            detail: String::new(),
+        };
+        let mut results = ProcessResults {
+            actions_found: 3,
+            already_pinned: 1,
+            actions_ignored: 1,
+            remaining_unpinned: vec![remaining(RemainingReason::ResolutionFailed)],
+            ..Default::default()
+        };
+        assert_eq!(status(&results), "unresolved");
+
+        // Uses left alone on purpose, or only known at run time, are fine
+        results.remaining_unpinned = vec![
+            remaining(RemainingReason::Excluded),
+            remaining(RemainingReason::DynamicRef),
+        ];
+        assert_eq!(status(&results), "ok");
+
+        results.lines_changed = 1;
+        assert_eq!(status(&results), "changes_made");
+
+        results.policy_violations.push(PolicyViolation {
+            file: "ci.yml".to_string(),
+            line: 2,
+            action: "evil/action@v1".to_string(),
+            pattern: "evil/*".to_string(),
+        });
+        assert_eq!(status(&results), "changes_made");
+        results.lines_changed = 0;
+        assert_eq!(status(&results), "unresolved");
+
+        results.errors = 1;
+        assert_eq!(status(&results), "errors");
+
+        let mut check = UnpinnedResults::default();
+        assert_eq!(serde_json::to_value(check.run_status()).unwrap(), "ok");
+        check.unpinned_actions.push(UnpinnedAction {
+            file: "ci.yml".to_string(),
+            line: 2,
+            column: 11,
+            action: "actions/checkout".to_string(),
+            reference: Some("v4".to_string()),
+        });
+        assert_eq!(
+            serde_json::to_value(check.run_status()).unwrap(),
+            "unresolved"
+        );
+    }
+
+    #[test]
+    fn test_dry_run_results_not_applied() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("ci.yml");
+        let content = "steps:\n  - uses: actions/checkout@v4\n";
+        fs::write(&path, content).unwrap();
+
+        let workflow = WorkflowFile::parse(&path).unwrap();
+        let action = ActionRef::parse("actions/checkout@v4").unwrap();
+        let sha = "b4ffde65f46336ab88eb53be808477a3936bae11".to_string();
+        let pinned_map = HashMap::from([(action.to_string(), PinnedAction::new(action, sha))]);
+
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], true, false, true, 10);
+        let mut results = Vec::new();
+        processor
+            .rewrite_workflow(&workflow, &pinned_map, &mut results)
+            .unwrap();
+
+        assert_eq!(results.len(), 1);
+        assert!(!results[0].applied);
+        assert_eq!(fs::read_to_string(&path).unwrap(), content);
 
-        let processor = WorkflowProcessor::new(workflows_dir, false, false, true, 10);
+        let processor =
+            WorkflowProcessor::new(vec![temp.path().to_path_buf()], false, false, true, 10);
+        let mut results = Vec::new();
+        processor
+            .rewrite_workflow(&workflow, &pinned_map, &mut results)
+            .unwrap();
 
-        let files = processor.find_workflow_files().unwrap();
-        assert_eq!(files.len(), 2);
+        assert_eq!(results.len(), 1);
+        assert!(results[0].applied);
     }
 }
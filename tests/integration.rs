@@ -105,6 +105,203 @@ jobs:
         .stdout(predicate::str::contains("files_processed"));
 }
 
+#[test]
+fn test_dry_run_json_output() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let workflow_content = r#"
+name: Test
+on: [push]
+jobs:
+  test:
+    runs-on: ubuntu-latest
+    steps:
+      - uses: actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11 # v4
+"#;
+
+    fs::write(workflows_dir.join("test.yml"), workflow_content).unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--dry-run")
+        .arg("--format")
+        .arg("json")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"dry_run\": true"));
+}
+
+#[test]
+fn test_lines_changed_summary() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        r#"{"actions": {"actions/checkout@v4": "b4ffde65f46336ab88eb53be808477a3936bae11"}}"#,
+    )
+    .unwrap();
+
+    let workflow_content = "steps:\n  - uses: actions/checkout@v4\n  - run: make\n  - uses: actions/checkout@v4\n  - uses: actions/cache@0c45773b623bea8c8e75f6c82b208c3cf94ea4f9 # v4\n";
+    fs::write(workflows_dir.join("test.yml"), workflow_content).unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .arg("--dry-run")
+        .arg("--format")
+        .arg("json")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"lines_changed\": 2"))
+        .stdout(predicate::str::contains("\"files_changed\": 1"));
+}
+
+#[test]
+fn test_error_codes() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    fs::write(
+        workflows_dir.join("test.yml"),
+        "steps:\n  - uses: actions/checkout@v4\n",
+    )
+    .unwrap();
+    fs::write(workflows_dir.join("binary.yml"), [0xff, 0xfe, 0x00]).unwrap();
+
+    let lockfile = temp.path().join("pin-actions.lock");
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .arg("--format")
+        .arg("json")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("\"code\": \"E_OFFLINE\""))
+        .stdout(predicate::str::contains(
+            "\"reason\": \"invalid UTF-8 at byte 0\"",
+        ))
+        .stderr(predicate::str::contains("E_OFFLINE=1"));
+
+    // Nothing listens on port 1
+    fs::remove_file(workflows_dir.join("binary.yml")).unwrap();
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--git-host")
+        .arg("127.0.0.1:1")
+        .arg("--format")
+        .arg("json")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("\"code\": \"E_NETWORK\""));
+}
+
+#[test]
+fn test_archive() {
+    let temp = TempDir::new().unwrap();
+
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        r#"{"actions": {"actions/checkout@v4": "b4ffde65f46336ab88eb53be808477a3936bae11"}}"#,
+    )
+    .unwrap();
+
+    let workflow_content = b"steps:\n  - uses: actions/checkout@v4\n";
+    let input = temp.path().join("repo.tar");
+    let mut builder = tar::Builder::new(fs::File::create(&input).unwrap());
+    let mut header = tar::Header::new_gnu();
+    header.set_size(workflow_content.len() as u64);
+    header.set_mode(0o644);
+    builder
+        .append_data(
+            &mut header,
+            "repo-main/.github/workflows/ci.yml",
+            &workflow_content[..],
+        )
+        .unwrap();
+    builder.finish().unwrap();
+    drop(builder);
+    let original = fs::read(&input).unwrap();
+
+    let output = temp.path().join("pinned.tar");
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--archive")
+        .arg(&input)
+        .arg("--out")
+        .arg(&output)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Actions pinned:   1"));
+
+    // The input archive is never modified
+    assert_eq!(fs::read(&input).unwrap(), original);
+
+    let mut archive = tar::Archive::new(fs::File::open(&output).unwrap());
+    let mut entry = archive.entries().unwrap().next().unwrap().unwrap();
+    assert_eq!(
+        entry.path().unwrap().to_str(),
+        Some("repo-main/.github/workflows/ci.yml")
+    );
+    let mut content = String::new();
+    std::io::Read::read_to_string(&mut entry, &mut content).unwrap();
+    assert_eq!(
+        content,
+        "steps:\n  - uses: actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11 # v4\n"
+    );
+}
+
+#[test]
+fn test_group_by_repository() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        r#"{"actions": {
+            "actions/checkout@v4": "b4ffde65f46336ab88eb53be808477a3936bae11",
+            "actions/cache@v4": "0c45773b623bea8c8e75f6c82b208c3cf94ea4f9"
+        }}"#,
+    )
+    .unwrap();
+
+    let workflow_content = "steps:\n  - uses: actions/checkout@v4\n  - uses: actions/cache@v4\n";
+    fs::write(workflows_dir.join("ci.yml"), workflow_content).unwrap();
+    fs::write(workflows_dir.join("release.yml"), workflow_content).unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .arg("--dry-run")
+        .arg("--group-by")
+        .arg("repository")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("actions/cache (2)"))
+        .stdout(predicate::str::contains("actions/checkout (2)"));
+}
+
 #[test]
 fn test_backup_creation() {
     let temp = TempDir::new().unwrap();
@@ -139,6 +336,62 @@ jobs:
     assert_eq!(backup_content, workflow_content);
 }
 
+#[test]
+fn test_backup_keep() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let sha = "b4ffde65f46336ab88eb53be808477a3936bae11";
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        format!(
+            r#"{{"actions": {{"actions/checkout@v4": "{sha}", "actions/cache@v4": "{sha}"}}}}"#
+        ),
+    )
+    .unwrap();
+
+    let workflow_path = workflows_dir.join("test.yml");
+    let first = "steps:\n  - uses: actions/checkout@v4\n";
+    let second =
+        format!("steps:\n  - uses: actions/checkout@{sha} # v4\n  - uses: actions/cache@v4\n");
+    fs::write(&workflow_path, first).unwrap();
+
+    let run = || {
+        Command::new(cargo_bin!("pin-actions"))
+            .arg("--workflows-dir")
+            .arg(&workflows_dir)
+            .arg("--lockfile")
+            .arg(&lockfile)
+            .args(["--offline", "--backup", "--backup-keep", "2"])
+            .assert()
+            .success();
+    };
+    run();
+    fs::write(&workflow_path, &second).unwrap();
+    run();
+
+    // Each run's original is kept, newest first
+    assert_eq!(
+        fs::read_to_string(workflows_dir.join("test.yml.bak")).unwrap(),
+        second
+    );
+    assert_eq!(
+        fs::read_to_string(workflows_dir.join("test.yml.bak.1")).unwrap(),
+        first
+    );
+
+    // A third run drops the oldest
+    fs::write(&workflow_path, first).unwrap();
+    run();
+    assert_eq!(
+        fs::read_to_string(workflows_dir.join("test.yml.bak.1")).unwrap(),
+        second
+    );
+    assert!(!workflows_dir.join("test.yml.bak.2").exists());
+}
+
 #[test]
 fn test_skip_local_actions() {
     let temp = TempDir::new().unwrap();
@@ -210,3 +463,3742 @@ fn test_verbose_output() {
         .assert()
         .success();
 }
+
+#[test]
+fn test_ignore_files() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let workflow_content =
+        "name: Test\non: [push]\njobs:\n  test:\n    steps:\n      - uses: actions/checkout@v4\n";
+    fs::write(workflows_dir.join("release-linux.yml"), workflow_content).unwrap();
+    fs::write(workflows_dir.join("release-macos.yml"), workflow_content).unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--ignore-files")
+        .arg("release-*.yml")
+        .arg("--format")
+        .arg("json")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"files_skipped\": 2"));
+
+    let content = fs::read_to_string(workflows_dir.join("release-linux.yml")).unwrap();
+    assert_eq!(content, workflow_content);
+}
+
+#[test]
+fn test_offline_mode() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        r#"{"actions": {"actions/checkout@v4": "b4ffde65f46336ab88eb53be808477a3936bae11"}}"#,
+    )
+    .unwrap();
+
+    let workflow_path = workflows_dir.join("test.yml");
+    fs::write(&workflow_path, "steps:\n  - uses: actions/checkout@v4\n").unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .assert()
+        .success();
+
+    let content = fs::read_to_string(&workflow_path).unwrap();
+    assert_eq!(
+        content,
+        "steps:\n  - uses: actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11 # v4\n"
+    );
+
+    fs::write(&workflow_path, "steps:\n  - uses: actions/setup-node@v4\n").unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("not in the lockfile"));
+
+    let content = fs::read_to_string(&workflow_path).unwrap();
+    assert_eq!(content, "steps:\n  - uses: actions/setup-node@v4\n");
+}
+
+#[test]
+fn test_fixed_resolver() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let map = temp.path().join("pins.json");
+    fs::write(
+        &map,
+        r#"{
+            "actions/checkout@v4": "b4ffde65f46336ab88eb53be808477a3936bae11",
+            "actions/cache@v4": "13aacd865c20de90d75de3b17ebe84f7a17d57d2"
+        }"#,
+    )
+    .unwrap();
+
+    let workflow_path = workflows_dir.join("test.yml");
+    fs::write(
+        &workflow_path,
+        "steps:\n  - uses: actions/checkout@v4\n  - uses: actions/cache@v4\n",
+    )
+    .unwrap();
+
+    // Nothing listens on port 1, so any lookup over the network would fail
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .args(["--resolver", "fixed", "--resolution-map"])
+        .arg(&map)
+        .args(["--git-host", "127.0.0.1:1"])
+        .assert()
+        .success();
+
+    assert_eq!(
+        fs::read_to_string(&workflow_path).unwrap(),
+        "steps:\n  - uses: actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11 # v4\n  - uses: actions/cache@13aacd865c20de90d75de3b17ebe84f7a17d57d2 # v4\n"
+    );
+
+    fs::write(&workflow_path, "steps:\n  - uses: actions/setup-node@v4\n").unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .args(["--resolver", "fixed", "--resolution-map"])
+        .arg(&map)
+        .args(["--git-host", "127.0.0.1:1", "--format", "json"])
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("\"code\": \"E_REF_NOT_FOUND\""))
+        .stdout(predicate::str::contains("actions/setup-node@v4 not in map"));
+
+    assert_eq!(
+        fs::read_to_string(&workflow_path).unwrap(),
+        "steps:\n  - uses: actions/setup-node@v4\n"
+    );
+}
+
+#[test]
+fn test_fixed_resolver_rejects_bad_map() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+    fs::write(
+        workflows_dir.join("test.yml"),
+        "steps:\n  - uses: actions/checkout@v4\n",
+    )
+    .unwrap();
+
+    let map = temp.path().join("pins.json");
+    fs::write(&map, r#"{"actions/checkout@v4": "v4"}"#).unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .args(["--resolver", "fixed", "--resolution-map"])
+        .arg(&map)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Invalid SHA 'v4' for actions/checkout@v4",
+        ));
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .args(["--resolver", "fixed"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "--resolver fixed requires --resolution-map",
+        ));
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--resolution-map")
+        .arg(&map)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "--resolution-map only applies to --resolver fixed",
+        ));
+}
+
+#[test]
+fn test_timings_refuses_graphql() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .args(["--resolver", "graphql", "--timings", "--dry-run"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--resolver graphql batches them"));
+}
+
+#[test]
+fn test_config_overrides() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let natural = "b4ffde65f46336ab88eb53be808477a3936bae11";
+    let known_good = "11bd71901bbe5b1630ceea73d27597364c9af683";
+    let map = temp.path().join("pins.json");
+    fs::write(
+        &map,
+        format!(r#"{{"actions/checkout@v4": "{natural}", "actions/cache@v4": "{natural}"}}"#),
+    )
+    .unwrap();
+    let config_path = temp.path().join("pin-actions.toml");
+    fs::write(
+        &config_path,
+        format!("[overrides]\n\"actions/checkout\" = \"{known_good}\"\n"),
+    )
+    .unwrap();
+
+    let workflow_path = workflows_dir.join("test.yml");
+    fs::write(
+        &workflow_path,
+        "steps:\n  - uses: actions/checkout@v4\n  - uses: actions/cache@v4\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    let output = cmd
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--config")
+        .arg(&config_path)
+        .args(["--resolver", "fixed", "--resolution-map"])
+        .arg(&map)
+        .args(["--format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains(&format!(
+        "actions/checkout@v4 resolves to {natural}, but the config overrides it to {known_good}"
+    )));
+
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let pinned = results["pinned_actions"].as_array().unwrap();
+    let checkout = pinned
+        .iter()
+        .find(|p| p["action"] == "actions/checkout")
+        .unwrap();
+    assert_eq!(checkout["sha"], known_good);
+    assert_eq!(checkout["source"], "override");
+    let cache = pinned
+        .iter()
+        .find(|p| p["action"] == "actions/cache")
+        .unwrap();
+    assert_eq!(cache["sha"], natural);
+    assert!(cache.get("source").is_none());
+
+    assert_eq!(
+        fs::read_to_string(&workflow_path).unwrap(),
+        format!(
+            "steps:\n  - uses: actions/checkout@{known_good} # v4\n  - uses: actions/cache@{natural} # v4\n"
+        )
+    );
+
+    // Malformed overrides fail before anything is resolved
+    fs::write(&config_path, "[overrides]\n\"actions/checkout\" = \"v4\"\n").unwrap();
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--config")
+        .arg(&config_path)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Invalid SHA 'v4' for override actions/checkout",
+        ));
+}
+
+#[test]
+fn test_blocked_actions() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let sha = "b4ffde65f46336ab88eb53be808477a3936bae11";
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        format!(r#"{{"actions": {{"actions/checkout@v4": "{sha}"}}}}"#),
+    )
+    .unwrap();
+    let config_path = temp.path().join("pin-actions.toml");
+    fs::write(&config_path, "blocked_actions = [\"evil-org/*\"]\n").unwrap();
+
+    // The blocked action isn't in the lockfile, so resolving it offline
+    // would be an error
+    let workflow_path = workflows_dir.join("test.yml");
+    let content = format!(
+        "steps:\n  - uses: actions/checkout@v4\n  - uses: evil-org/tool@v1\n  - uses: evil-org/other@{sha}\n"
+    );
+    fs::write(&workflow_path, &content).unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    let output = cmd
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--config")
+        .arg(&config_path)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .args(["--offline", "--dry-run", "--format", "json"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(results["errors"], 0);
+    assert_eq!(results["actions_found"], 1);
+    assert_eq!(results["pinned_actions"].as_array().unwrap().len(), 1);
+    assert_eq!(
+        results["policy_violations"],
+        serde_json::json!([
+            {"file": workflow_path.to_string_lossy(), "line": 3, "action": "evil-org/tool@v1", "pattern": "evil-org/*"},
+            {"file": workflow_path.to_string_lossy(), "line": 4, "action": format!("evil-org/other@{sha}"), "pattern": "evil-org/*"},
+        ])
+    );
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--config")
+        .arg(&config_path)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .args(["--offline", "--fail-on", "never"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Policy Violations"))
+        .stdout(predicate::str::contains(
+            "test.yml:3 evil-org/tool@v1 (blocked by 'evil-org/*')",
+        ));
+
+    assert_eq!(
+        fs::read_to_string(&workflow_path).unwrap(),
+        content.replace(
+            "actions/checkout@v4",
+            &format!("actions/checkout@{sha} # v4")
+        )
+    );
+
+    // check reports them too, even with nothing left unpinned
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--config")
+        .arg(&config_path)
+        .arg("check")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("Policy Violations"));
+}
+
+#[test]
+fn test_policy() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let sha = "b4ffde65f46336ab88eb53be808477a3936bae11";
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        format!(
+            r#"{{"actions": {{"docker/login-action@v3": "{sha}", "actions/setup-node@main": "{sha}"}}}}"#
+        ),
+    )
+    .unwrap();
+    let config_path = temp.path().join("pin-actions.toml");
+    fs::write(
+        &config_path,
+        "[policy]\ntrusted_owners = [\"actions\"]\nrequire_pin = \"third-party\"\nallow_branches = false\n",
+    )
+    .unwrap();
+
+    let workflow_path = workflows_dir.join("test.yml");
+    fs::write(
+        &workflow_path,
+        format!(
+            "steps:\n  - uses: actions/checkout@v4\n  - uses: docker/login-action@v3\n  - uses: actions/setup-node@main\n  - uses: docker/build-push-action@{sha}\n"
+        ),
+    )
+    .unwrap();
+
+    let check = || {
+        Command::new(cargo_bin!("pin-actions"))
+            .arg("--workflows-dir")
+            .arg(&workflows_dir)
+            .arg("--config")
+            .arg(&config_path)
+            .args(["--format", "json", "check"])
+            .output()
+            .unwrap()
+    };
+
+    let output = check();
+    assert_eq!(output.status.code(), Some(1));
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let reasons: Vec<(&str, bool)> = results["policy_checks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|check| {
+            (
+                check["reason"].as_str().unwrap(),
+                check["compliant"].as_bool().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        reasons,
+        vec![
+            ("trusted_owner", true),
+            ("unpinned", false),
+            ("branch", false),
+            ("pinned", true),
+        ]
+    );
+
+    // Pinning leaves the trusted tag alone and fixes the rest
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    let output = cmd
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--config")
+        .arg(&config_path)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .args(["--offline", "--format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(results["actions_allowed"], 1);
+    assert_eq!(results["pinned_actions"].as_array().unwrap().len(), 2);
+
+    let content = fs::read_to_string(&workflow_path).unwrap();
+    assert!(content.contains("  - uses: actions/checkout@v4\n"));
+    assert!(content.contains(&format!("  - uses: docker/login-action@{sha} # v3\n")));
+
+    let output = check();
+    assert!(output.status.success());
+}
+
+#[test]
+fn test_check_strict_sha() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+    fs::write(
+        workflows_dir.join("test.yml"),
+        "steps:\n  - uses: actions/checkout@b4ffde6\n",
+    )
+    .unwrap();
+    let config_path = temp.path().join("pin-actions.toml");
+    fs::write(
+        &config_path,
+        "[policy]\ntrusted_owners = [\"actions\"]\nrequire_pin = \"third-party\"\n",
+    )
+    .unwrap();
+
+    let check = |args: &[&str]| {
+        Command::new(cargo_bin!("pin-actions"))
+            .arg("--workflows-dir")
+            .arg(&workflows_dir)
+            .arg("check")
+            .args(args)
+            .assert()
+    };
+
+    check(&["--strict-sha"]).failure();
+    // The policy trusts the owner's short SHA, unless it must be full length
+    check(&["--config", config_path.to_str().unwrap()]).success();
+    check(&["--config", config_path.to_str().unwrap(), "--strict-sha"])
+        .failure()
+        .stdout(predicate::str::contains("actions/checkout@b4ffde6"));
+
+    Command::new(cargo_bin!("pin-actions"))
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .args(["unpin", "--strict-sha"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "--strict-sha only applies to `check`",
+        ));
+}
+#[test]
+fn test_dry_run_lists_planned_edits() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let sha = "b4ffde65f46336ab88eb53be808477a3936bae11";
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        format!(r#"{{"actions": {{"actions/checkout@v4": "{sha}"}}}}"#),
+    )
+    .unwrap();
+    let workflow_path = workflows_dir.join("test.yml");
+    fs::write(&workflow_path, "steps:\n  - uses: actions/checkout@v4\n").unwrap();
+    let planned = format!("test.yml:2 actions/checkout@v4 -> @{sha}");
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .args(["--offline", "--dry-run"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Planned edits:"))
+        .stdout(predicate::str::contains(planned.as_str()));
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Planned edits:").not())
+        .stdout(predicate::str::contains(planned.as_str()).not());
+}
+
+#[test]
+fn test_record_constraint() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let old_sha = "b4ffde65f46336ab88eb53be808477a3936bae11";
+    let new_sha = "11bd71901bbe5b1630ceea73d27597364c9af683";
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        format!(
+            r#"{{"actions": {{"actions/checkout@v4": "{old_sha}", "actions/cache@v4.1": "{old_sha}", "actions/setup-node@main": "{old_sha}"}}}}"#
+        ),
+    )
+    .unwrap();
+
+    let workflow_path = workflows_dir.join("test.yml");
+    fs::write(
+        &workflow_path,
+        "steps:\n  - uses: actions/checkout@v4\n  - uses: actions/cache@v4.1\n  - uses: actions/setup-node@main\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .args(["--offline", "--record-constraint"])
+        .assert()
+        .success();
+
+    assert_eq!(
+        fs::read_to_string(&workflow_path).unwrap(),
+        format!(
+            "steps:\n  - uses: actions/checkout@{old_sha} # v4 constraint:^4.0.0\n  - uses: actions/cache@{old_sha} # v4.1 constraint:^4.1.0\n  - uses: actions/setup-node@{old_sha} # main\n"
+        )
+    );
+
+    // Updating the pin later keeps the recorded constraint
+    fs::write(
+        &lockfile,
+        format!(r#"{{"actions": {{"actions/checkout@v4": "{new_sha}", "actions/cache@v4.1": "{old_sha}", "actions/setup-node@main": "{old_sha}"}}}}"#),
+    )
+    .unwrap();
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .args(["--offline", "update"])
+        .assert()
+        .success();
+
+    assert!(
+        fs::read_to_string(&workflow_path)
+            .unwrap()
+            .contains(&format!(
+                "actions/checkout@{new_sha} # v4 constraint:^4.0.0\n"
+            ))
+    );
+}
+
+#[test]
+fn test_no_comment() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let sha = "b4ffde65f46336ab88eb53be808477a3936bae11";
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        format!(
+            r#"{{"actions": {{"actions/checkout@v4": "{sha}", "actions/cache@v4": "{sha}"}}}}"#
+        ),
+    )
+    .unwrap();
+
+    let workflow_path = workflows_dir.join("test.yml");
+    fs::write(
+        &workflow_path,
+        "steps:\n  - uses: actions/checkout@v4 # keep me\n  - uses: actions/cache@v4\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .args(["--offline", "--no-comment"])
+        .assert()
+        .success();
+
+    // The author's comment stays, but no version comment is added
+    assert_eq!(
+        fs::read_to_string(&workflow_path).unwrap(),
+        format!(
+            "steps:\n  - uses: actions/checkout@{sha} # keep me\n  - uses: actions/cache@{sha}\n"
+        )
+    );
+
+    // Updating can't tell which ref a bare pin came from
+    fs::write(
+        &workflow_path,
+        format!("steps:\n  - uses: actions/cache@{sha}\n"),
+    )
+    .unwrap();
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .args(["--offline", "update"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1 pin(s) have no version comment"))
+        .stdout(predicate::str::contains("No version comment: 1"));
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .args(["--no-comment", "--comment-date"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+}
+
+#[test]
+fn test_skip_unversioned() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        r#"{"actions": {"actions/checkout@v4": "b4ffde65f46336ab88eb53be808477a3936bae11"}}"#,
+    )
+    .unwrap();
+
+    let workflow_path = workflows_dir.join("test.yml");
+    let workflow_content = "steps:\n  - uses: actions/checkout@v4\n  - uses: owner/repo\n";
+    fs::write(&workflow_path, workflow_content).unwrap();
+
+    // The default branch can't be looked up offline
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .arg("--dry-run")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("owner/repo"));
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .arg("--skip-unversioned")
+        .assert()
+        .success();
+
+    let content = fs::read_to_string(&workflow_path).unwrap();
+    assert_eq!(
+        content,
+        "steps:\n  - uses: actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11 # v4\n  - uses: owner/repo\n"
+    );
+}
+
+#[test]
+fn test_ref_kind_hints() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        r#"{"actions": {
+            "actions/checkout@tag:stable": "b4ffde65f46336ab88eb53be808477a3936bae11",
+            "actions/cache@branch:stable": "0c45773b623bea8c8e75f6c82b208c3cf94ea4f9"
+        }}"#,
+    )
+    .unwrap();
+
+    let workflow_path = workflows_dir.join("test.yml");
+    fs::write(
+        &workflow_path,
+        "steps:\n  - uses: actions/checkout@tag:stable\n  - uses: actions/cache@branch:stable\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .assert()
+        .success();
+
+    let content = fs::read_to_string(&workflow_path).unwrap();
+    assert_eq!(
+        content,
+        "steps:\n  - uses: actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11 # tag:stable\n  - uses: actions/cache@0c45773b623bea8c8e75f6c82b208c3cf94ea4f9 # branch:stable\n"
+    );
+}
+
+#[test]
+fn test_pin_inputs() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        r#"{"actions": {
+            "actions/checkout@v4": "b4ffde65f46336ab88eb53be808477a3936bae11",
+            "actions/setup-node@v4": "60edb5dd545a775178f52524783378180af0d1f8"
+        }}"#,
+    )
+    .unwrap();
+
+    let workflow_path = workflows_dir.join("test.yml");
+    let workflow_content = "steps:\n  - uses: actions/checkout@v4\n    with:\n      action: actions/setup-node@v4\n  - uses: actions/checkout@v4\n    with: { action: 'actions/setup-node@v4' }\n";
+    fs::write(&workflow_path, workflow_content).unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .arg("--pin-inputs")
+        .assert()
+        .success();
+
+    let content = fs::read_to_string(&workflow_path).unwrap();
+    assert_eq!(
+        content,
+        "steps:\n  - uses: actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11 # v4\n    with:\n      action: actions/setup-node@60edb5dd545a775178f52524783378180af0d1f8 # v4\n  - uses: actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11 # v4\n    with: { action: 'actions/setup-node@60edb5dd545a775178f52524783378180af0d1f8' } # v4\n"
+    );
+}
+
+#[test]
+fn test_list_subcommand() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let workflow_path = workflows_dir.join("test.yml");
+    let workflow_content = "steps:\n  - uses: actions/checkout@v4\n  - uses: actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11 # v4\n  - uses: actions/setup-node@v3\n";
+    fs::write(&workflow_path, workflow_content).unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    let output = cmd
+        .arg("list")
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--format")
+        .arg("json")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let inventory: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let inventory = inventory.as_array().unwrap();
+    assert_eq!(inventory.len(), 2);
+    assert_eq!(inventory[0]["action"], "actions/checkout");
+    assert_eq!(inventory[0]["refs"].as_array().unwrap().len(), 2);
+    assert_eq!(inventory[0]["usages"][1]["pinned"], true);
+    assert_eq!(inventory[0]["usages"][1]["line"], 3);
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    let output = cmd
+        .arg("list")
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--format")
+        .arg("csv")
+        .arg("--unpinned-only")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let csv = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "action,ref,pinned,file,line");
+    assert_eq!(lines.len(), 3);
+    assert!(lines[1].starts_with("actions/checkout,v4,false,"));
+    assert!(lines[2].starts_with("actions/setup-node,v3,false,"));
+
+    // Listing never touches the files
+    let content = fs::read_to_string(&workflow_path).unwrap();
+    assert_eq!(content, workflow_content);
+}
+
+#[test]
+fn test_no_resolve() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let workflow_path = workflows_dir.join("test.yml");
+    // Resolving this would fail, so success shows nothing was contacted
+    let workflow_content = "steps:\n  - uses: nonexistent-owner/nonexistent-action@v1\n  - uses: actions/cache@b4ffde65f46336ab88eb53be808477a3936bae11\n";
+    fs::write(&workflow_path, workflow_content).unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    let output = cmd
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--no-resolve")
+        .arg("--format")
+        .arg("json")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(results["actions_found"], 2);
+    assert_eq!(results["already_pinned"], 1);
+    assert_eq!(
+        results["unpinned_actions"][0]["action"],
+        "nonexistent-owner/nonexistent-action"
+    );
+    assert_eq!(results["unpinned_actions"][0]["reference"], "v1");
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--no-resolve")
+        .arg("--fail-on")
+        .arg("unpinned")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "nonexistent-owner/nonexistent-action@v1",
+        ));
+
+    let content = fs::read_to_string(&workflow_path).unwrap();
+    assert_eq!(content, workflow_content);
+}
+
+#[test]
+fn test_print_config() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let config_path = temp.path().join("pin-actions.toml");
+    fs::write(&config_path, "ignore_files = [\"release.yml\"]\n").unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    let output = cmd
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--config")
+        .arg(&config_path)
+        .arg("--jobs")
+        .arg("3")
+        .arg("--ignore-files")
+        .arg("deploy.yml")
+        .arg("--print-config")
+        .arg("json")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let config: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(config["jobs"], 3);
+    assert_eq!(config["dry_run"], false);
+    assert_eq!(
+        config["ignore_files"],
+        serde_json::json!(["release.yml", "deploy.yml"])
+    );
+
+    // TOML is the default
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--dry-run")
+        .arg("--print-config")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("dry_run = true"))
+        .stdout(predicate::str::contains("jobs = 10"));
+}
+
+#[test]
+fn test_repo_mode() {
+    let temp = TempDir::new().unwrap();
+    let root = temp.path();
+    fs::create_dir_all(root.join(".github/workflows")).unwrap();
+    fs::create_dir_all(root.join(".github/actions/setup")).unwrap();
+
+    let pinned =
+        "steps:\n  - uses: actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11 # v4\n";
+    fs::write(root.join(".github/workflows/ci.yml"), pinned).unwrap();
+    fs::write(root.join(".github/actions/setup/action.yml"), pinned).unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--repo")
+        .arg(root)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Composite actions:"))
+        .stdout(predicate::str::contains("Already pinned:   2"));
+}
+
+#[test]
+fn test_workflows_dir_found_from_subdirectory() {
+    let temp = TempDir::new().unwrap();
+    let repo = temp.path();
+    fs::create_dir(repo.join(".git")).unwrap();
+    fs::create_dir_all(repo.join(".github/workflows")).unwrap();
+    fs::create_dir_all(repo.join("src/app")).unwrap();
+    fs::write(
+        repo.join(".github/workflows/ci.yml"),
+        "steps:\n  - uses: actions/checkout@v4\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    let output = cmd
+        .current_dir(repo.join("src/app"))
+        .args(["--format", "json", "--fail-on", "never", "check"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["files_processed"], 1);
+    assert_eq!(
+        json["unpinned_actions"][0]["file"],
+        "../../.github/workflows/ci.yml"
+    );
+
+    // An explicit path is taken as given
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.current_dir(repo.join("src/app"))
+        .args(["--workflows-dir", ".github/workflows", "check"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Workflows directory not found"));
+}
+
+#[test]
+fn test_multiple_workflows_dirs() {
+    let temp = TempDir::new().unwrap();
+    let web_dir = temp.path().join("apps/web/.github/workflows");
+    let api_dir = temp.path().join("apps/api/.github/workflows");
+    fs::create_dir_all(&web_dir).unwrap();
+    fs::create_dir_all(&api_dir).unwrap();
+
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        r#"{"actions": {"actions/checkout@v4": "b4ffde65f46336ab88eb53be808477a3936bae11"}}"#,
+    )
+    .unwrap();
+
+    let workflow_content = "steps:\n  - uses: actions/checkout@v4\n";
+    fs::write(web_dir.join("ci.yml"), workflow_content).unwrap();
+    fs::write(api_dir.join("ci.yml"), workflow_content).unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&web_dir)
+        .arg("--workflows-dir")
+        .arg(&api_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .arg("--format")
+        .arg("json")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"files_processed\": 2"));
+
+    for dir in [&web_dir, &api_dir] {
+        let content = fs::read_to_string(dir.join("ci.yml")).unwrap();
+        assert!(content.contains("actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11 # v4"));
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_gh_cli_auth_discovery() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+    fs::write(
+        workflows_dir.join("test.yml"),
+        "steps:\n  - uses: actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11 # v4\n",
+    )
+    .unwrap();
+
+    let bin_dir = temp.path().join("bin");
+    fs::create_dir(&bin_dir).unwrap();
+    let gh = bin_dir.join("gh");
+    fs::write(&gh, "#!/bin/sh\necho gho_fake_token\n").unwrap();
+    fs::set_permissions(&gh, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let path = format!(
+        "{}:{}",
+        bin_dir.display(),
+        std::env::var("PATH").unwrap_or_default()
+    );
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.env("PATH", &path)
+        .env_remove("GITHUB_TOKEN")
+        .env_remove("GH_TOKEN")
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--resolver")
+        .arg("graphql")
+        .arg("--verbose")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Using GitHub credentials from gh CLI",
+        ))
+        .stdout(predicate::str::contains("gho_fake_token").not());
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.env("PATH", &path)
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--resolver")
+        .arg("graphql")
+        .arg("--no-auth")
+        .arg("--verbose")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Using GitHub credentials from anonymous",
+        ));
+}
+
+#[test]
+fn test_report_drift() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        r#"{"actions": {"actions/checkout@v4": "11bd71901bbe5b1630ceea73d27597364c9af683"}}"#,
+    )
+    .unwrap();
+
+    let workflow_content =
+        "steps:\n  - uses: actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11 # v4\n";
+    fs::write(workflows_dir.join("test.yml"), workflow_content).unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .arg("--report-drift")
+        .arg("--format")
+        .arg("json")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "\"current_sha\": \"11bd71901bbe5b1630ceea73d27597364c9af683\"",
+        ));
+
+    let content = fs::read_to_string(workflows_dir.join("test.yml")).unwrap();
+    assert_eq!(content, workflow_content);
+}
+
+#[test]
+fn test_outdated_update() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        r#"{"actions": {"actions/checkout@v4": "11bd71901bbe5b1630ceea73d27597364c9af683"}}"#,
+    )
+    .unwrap();
+
+    let workflow_path = workflows_dir.join("test.yml");
+    let workflow_content =
+        "steps:\n  - uses: actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11 # v4\n";
+    fs::write(&workflow_path, workflow_content).unwrap();
+
+    // Read-only without --update
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .arg("--outdated")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("b4ffde65"))
+        .stdout(predicate::str::contains("11bd7190"));
+    assert_eq!(
+        fs::read_to_string(&workflow_path).unwrap(),
+        workflow_content
+    );
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .arg("--outdated")
+        .arg("--update")
+        .assert()
+        .success();
+
+    assert_eq!(
+        fs::read_to_string(&workflow_path).unwrap(),
+        "steps:\n  - uses: actions/checkout@11bd71901bbe5b1630ceea73d27597364c9af683 # v4\n"
+    );
+}
+
+#[test]
+fn test_strict_sha() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        r#"{"actions": {"actions/checkout@b4ffde6": "b4ffde65f46336ab88eb53be808477a3936bae11"}}"#,
+    )
+    .unwrap();
+
+    let workflow_path = workflows_dir.join("test.yml");
+    let workflow_content = "steps:\n  - uses: actions/checkout@b4ffde6 # v4\n  - uses: actions/cache@1bd1e32a3bdc45362d1e726936510720a7c30a57 # v4\n";
+    fs::write(&workflow_path, workflow_content).unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--strict-sha")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "test.yml:2 actions/checkout@b4ffde6",
+        ));
+    assert_eq!(
+        fs::read_to_string(&workflow_path).unwrap(),
+        workflow_content
+    );
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .arg("--strict-sha")
+        .arg("--expand-short-shas")
+        .assert()
+        .success();
+
+    assert_eq!(
+        fs::read_to_string(&workflow_path).unwrap(),
+        "steps:\n  - uses: actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11 # v4\n  - uses: actions/cache@1bd1e32a3bdc45362d1e726936510720a7c30a57 # v4\n"
+    );
+}
+
+#[test]
+fn test_workflow_templates() {
+    let temp = TempDir::new().unwrap();
+    let root = temp.path();
+    fs::create_dir_all(root.join("workflow-templates")).unwrap();
+
+    let lockfile = root.join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        r#"{"actions": {"actions/checkout@v4": "b4ffde65f46336ab88eb53be808477a3936bae11"}}"#,
+    )
+    .unwrap();
+
+    let template_path = root.join("workflow-templates/ci.yml");
+    fs::write(&template_path, "steps:\n  - uses: actions/checkout@v4\n").unwrap();
+    let properties_path = root.join("workflow-templates/ci.properties.json");
+    let properties = r#"{"name": "CI", "description": "uses: actions/checkout@v4"}"#;
+    fs::write(&properties_path, properties).unwrap();
+
+    // No .github/workflows in an organization's .github repository
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(root.join(".github/workflows"))
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Templates:"))
+        .stdout(predicate::str::contains("not run in this repo"));
+
+    assert_eq!(
+        fs::read_to_string(&template_path).unwrap(),
+        "steps:\n  - uses: actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11 # v4\n"
+    );
+    assert_eq!(fs::read_to_string(&properties_path).unwrap(), properties);
+}
+
+#[test]
+fn test_jsonl_output() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        r#"{"actions": {"actions/checkout@v4": "b4ffde65f46336ab88eb53be808477a3936bae11"}}"#,
+    )
+    .unwrap();
+
+    let workflow_content = "steps:\n  - uses: actions/checkout@v4\n  - uses: actions/setup-node@v4\n  - uses: actions/cache@b4ffde65f46336ab88eb53be808477a3936bae11\n";
+    fs::write(workflows_dir.join("test.yml"), workflow_content).unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    let output = cmd
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .arg("--format")
+        .arg("jsonl")
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines = stdout
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+
+    let events = lines
+        .iter()
+        .map(|line| line["event"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert!(events.contains(&"pinned"));
+    assert!(events.contains(&"failed"));
+    assert!(events.contains(&"skipped"));
+
+    let summary = lines.last().unwrap();
+    assert_eq!(summary["event"], "summary");
+    assert_eq!(summary["actions_found"], 3);
+    assert_eq!(summary["already_pinned"], 1);
+    assert_eq!(summary["errors"], 1);
+}
+
+#[test]
+fn test_jsonl_only_for_pin() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+    fs::write(
+        workflows_dir.join("test.yml"),
+        "steps:\n  - uses: actions/checkout@v4\n",
+    )
+    .unwrap();
+
+    // Only pins stream an object per action
+    for args in [
+        &["check"][..],
+        &["unpin"],
+        &["audit"],
+        &["update"],
+        &["list"],
+        &["--report-drift"],
+        &["--strict-sha"],
+        &["--inventory"],
+    ] {
+        let mut cmd = Command::new(cargo_bin!("pin-actions"));
+        cmd.args(args)
+            .arg("--workflows-dir")
+            .arg(&workflows_dir)
+            .args(["--format", "jsonl"])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                "--format jsonl is only supported by `pin`",
+            ));
+    }
+}
+
+#[test]
+fn test_summary_only() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        r#"{"actions": {"actions/checkout@v4": "b4ffde65f46336ab88eb53be808477a3936bae11"}}"#,
+    )
+    .unwrap();
+    fs::write(
+        workflows_dir.join("test.yml"),
+        "steps:\n  - uses: actions/checkout@v4\n  - uses: actions/cache@v4 # pin-actions: ignore\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    let output = cmd
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .args([
+            "--offline",
+            "--dry-run",
+            "--format",
+            "json",
+            "--summary-only",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let fields = json.as_object().unwrap();
+    assert_eq!(json["schema_version"], 1);
+    assert_eq!(json["actions_pinned"], 1);
+    assert_eq!(json["actions_ignored"], 1);
+    for (field, value) in fields {
+        assert!(!value.is_array(), "{} is a per-item array", field);
+    }
+    assert!(!fields.contains_key("pinned_actions"));
+    assert!(!fields.contains_key("remaining_unpinned"));
+
+    // The text summary stays, without a line per pin
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .args(["--offline", "--dry-run", "--summary-only"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Actions pinned"))
+        .stdout(predicate::str::contains("📌").not())
+        .stdout(predicate::str::contains("Planned edits").not());
+
+    // Counters make a table in any format
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .args([
+            "--offline",
+            "--dry-run",
+            "--format",
+            "csv",
+            "--summary-only",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("field,value\n"))
+        .stdout(predicate::str::contains("actions_pinned,1\n"));
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    let output = cmd
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .args(["--format", "json", "--summary-only", "list"])
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["uses"], 2);
+    assert_eq!(json["unpinned"], 2);
+
+    // JSON lines shrink to the summary line
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    let output = cmd
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .args([
+            "--offline",
+            "--dry-run",
+            "--format",
+            "jsonl",
+            "--summary-only",
+        ])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().count(), 1);
+    let summary: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(summary["event"], "summary");
+    assert_eq!(summary["schema_version"], 1);
+}
+
+#[test]
+fn test_audit_summary_only() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        r#"{"actions": {"actions/checkout@v4": "b4ffde65f46336ab88eb53be808477a3936bae11"}}"#,
+    )
+    .unwrap();
+    fs::write(
+        workflows_dir.join("test.yml"),
+        "steps:\n  - uses: actions/checkout@11bd71901bbe5b1630ceea73d27597364c9af683 # v4\n  - uses: actions/cache@b4ffde6\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    let output = cmd
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .args(["--offline", "--format", "json", "--summary-only", "audit"])
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["schema_version"], 1);
+    assert_eq!(json["outdated"]["pins_checked"], 1);
+
+    // Neither result keeps its per-item arrays
+    for results in ["outdated", "short_shas"] {
+        for (field, value) in json[results].as_object().unwrap() {
+            assert!(
+                !value.is_array(),
+                "{}.{} is a per-item array",
+                results,
+                field
+            );
+        }
+    }
+}
+
+#[test]
+fn test_output_file() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        r#"{"actions": {"actions/checkout@v4": "b4ffde65f46336ab88eb53be808477a3936bae11"}}"#,
+    )
+    .unwrap();
+    fs::write(
+        workflows_dir.join("test.yml"),
+        "steps:\n  - uses: actions/checkout@v4\n",
+    )
+    .unwrap();
+
+    // The document lands in the file, in a directory made for it, while
+    // the text summary still prints
+    let report = temp.path().join("reports/pin.json");
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .args(["--offline", "--dry-run", "--format", "json", "--output"])
+        .arg(&report)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Files processed"))
+        .stdout(predicate::str::contains("\"actions_found\"").not());
+
+    let json: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&report).unwrap()).unwrap();
+    assert_eq!(json["status"], "changes_made");
+    assert_eq!(json["actions_pinned"], 1);
+
+    // JSON lines gather in the file too, summary last
+    let report = temp.path().join("reports/pin.jsonl");
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .args(["--offline", "--dry-run", "--format", "jsonl", "-o"])
+        .arg(&report)
+        .assert()
+        .success();
+    let content = fs::read_to_string(&report).unwrap();
+    let last: serde_json::Value = serde_json::from_str(content.lines().last().unwrap()).unwrap();
+    assert_eq!(last["event"], "summary");
+    assert!(content.lines().count() > 1);
+
+    // `-` is stdout, as without --output
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    let output = cmd
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .args(["--format", "json", "--output", "-", "check"])
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["status"], "unresolved");
+
+    // Text isn't a document to write
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .args(["--output", "report.txt", "check"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("needs a --format other than text"));
+}
+
+/// A workflows directory holding `content` as test.yml, and a lockfile
+/// resolving actions/checkout@v4, for the subcommand tests
+struct Subcommands {
+    _temp: TempDir,
+    workflows_dir: std::path::PathBuf,
+    lockfile: std::path::PathBuf,
+    workflow_path: std::path::PathBuf,
+}
+
+impl Subcommands {
+    fn new(content: &str) -> Self {
+        let temp = TempDir::new().unwrap();
+        let workflows_dir = temp.path().join("workflows");
+        fs::create_dir(&workflows_dir).unwrap();
+
+        let lockfile = temp.path().join("pin-actions.lock");
+        fs::write(
+            &lockfile,
+            r#"{"actions": {"actions/checkout@v4": "11bd71901bbe5b1630ceea73d27597364c9af683"}}"#,
+        )
+        .unwrap();
+
+        let workflow_path = workflows_dir.join("test.yml");
+        fs::write(&workflow_path, content).unwrap();
+
+        Self {
+            _temp: temp,
+            workflows_dir,
+            lockfile,
+            workflow_path,
+        }
+    }
+
+    fn run(&self, args: &[&str]) -> assert_cmd::assert::Assert {
+        let mut cmd = Command::new(cargo_bin!("pin-actions"));
+        cmd.args(args)
+            .arg("--workflows-dir")
+            .arg(&self.workflows_dir)
+            .arg("--lockfile")
+            .arg(&self.lockfile)
+            .arg("--offline");
+        cmd.assert()
+    }
+
+    fn workflow(&self) -> String {
+        fs::read_to_string(&self.workflow_path).unwrap()
+    }
+}
+
+#[test]
+fn test_check_subcommand() {
+    let unpinned = "steps:\n  - uses: actions/checkout@v4\n";
+    let fixture = Subcommands::new(unpinned);
+
+    // check fails while anything is unpinned, and never writes
+    fixture.run(&["check"]).failure().code(1);
+    fixture.run(&["check", "--fail-on", "never"]).success();
+    assert_eq!(fixture.workflow(), unpinned);
+
+    fs::write(
+        &fixture.workflow_path,
+        "steps:\n  - uses: actions/checkout@11bd71901bbe5b1630ceea73d27597364c9af683 # v4\n",
+    )
+    .unwrap();
+    fixture.run(&["check"]).success();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("check")
+        .arg("--help")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Exit codes"));
+}
+
+#[test]
+fn test_pin_subcommand() {
+    let fixture = Subcommands::new("steps:\n  - uses: actions/checkout@v4\n");
+
+    fixture.run(&["pin"]).success();
+    assert_eq!(
+        fixture.workflow(),
+        "steps:\n  - uses: actions/checkout@11bd71901bbe5b1630ceea73d27597364c9af683 # v4\n"
+    );
+}
+
+#[test]
+fn test_unpin_subcommand() {
+    let fixture = Subcommands::new(
+        "steps:\n  - uses: actions/checkout@11bd71901bbe5b1630ceea73d27597364c9af683 # v4\n",
+    );
+
+    fixture.run(&["unpin"]).success();
+    assert_eq!(
+        fixture.workflow(),
+        "steps:\n  - uses: actions/checkout@v4\n"
+    );
+}
+
+#[test]
+fn test_audit_subcommand() {
+    // A stale pin and an abbreviated one are both findings
+    let stale = "steps:\n  - uses: actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11 # v4\n  - uses: actions/cache@b4ffde6\n";
+    let fixture = Subcommands::new(stale);
+
+    fixture
+        .run(&["audit"])
+        .failure()
+        .stdout(predicate::str::contains("b4ffde65"))
+        .stdout(predicate::str::contains("actions/cache@b4ffde6"));
+    assert_eq!(fixture.workflow(), stale);
+}
+
+#[test]
+fn test_audit_parses_once() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+    fs::write(
+        workflows_dir.join("test.yml"),
+        "steps:\n  - uses: actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11 # v4\n",
+    )
+    .unwrap();
+    let map = temp.path().join("map.json");
+    fs::write(
+        &map,
+        r#"{"actions/checkout@v4": "b4ffde65f46336ab88eb53be808477a3936bae11"}"#,
+    )
+    .unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    let output = cmd
+        .arg("audit")
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .args(["--resolver", "fixed", "--resolution-map"])
+        .arg(&map)
+        .args(["--git-host", "127.0.0.1:1", "--verbose"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let all = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(all.matches("Found 1 workflow file(s)").count(), 1);
+    // A resolution map answer is counted like any other local one
+    assert!(all.contains("0 over the network, 1 cached"));
+}
+#[test]
+fn test_update_subcommand() {
+    let fixture = Subcommands::new(
+        "steps:\n  - uses: actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11 # v4\n",
+    );
+
+    fixture.run(&["update"]).success();
+    assert_eq!(
+        fixture.workflow(),
+        "steps:\n  - uses: actions/checkout@11bd71901bbe5b1630ceea73d27597364c9af683 # v4\n"
+    );
+}
+
+#[test]
+fn test_list_subcommand_text() {
+    let content = "steps:\n  - uses: actions/checkout@v4\n  - uses: actions/cache@11bd71901bbe5b1630ceea73d27597364c9af683 # v4\n";
+    let fixture = Subcommands::new(content);
+
+    let file = regex::escape(&fixture.workflow_path.display().to_string());
+    fixture
+        .run(&["list"])
+        .success()
+        .stdout(predicate::str::contains("ACTION"))
+        .stdout(predicate::str::is_match(format!("actions/checkout +v4 +no +{}:2", file)).unwrap())
+        .stdout(
+            predicate::str::is_match(format!("actions/cache +11bd71901bbe # v4 +yes +{}:3", file))
+                .unwrap(),
+        );
+    fixture
+        .run(&["list", "--unpinned-only"])
+        .success()
+        .stdout(predicate::str::contains("actions/checkout"))
+        .stdout(predicate::str::contains("actions/cache").not());
+    assert_eq!(fixture.workflow(), content);
+}
+
+#[test]
+fn test_jobs_auto() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        r#"{"actions": {"actions/checkout@v4": "b4ffde65f46336ab88eb53be808477a3936bae11"}}"#,
+    )
+    .unwrap();
+    fs::write(
+        workflows_dir.join("test.yml"),
+        "steps:\n  - uses: actions/checkout@v4\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    let output = cmd
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .arg("--dry-run")
+        .arg("--jobs")
+        .arg("auto")
+        .arg("--format")
+        .arg("json")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(results["jobs"].as_u64().unwrap() >= 1);
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--jobs")
+        .arg("many")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "expected a number of jobs or `auto`",
+        ));
+}
+
+#[test]
+fn test_io_workers_separate_from_jobs() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        r#"{"actions": {"actions/checkout@v4": "b4ffde65f46336ab88eb53be808477a3936bae11"}}"#,
+    )
+    .unwrap();
+    for name in ["a.yml", "b.yml", "c.yml"] {
+        fs::write(
+            workflows_dir.join(name),
+            "steps:\n  - uses: actions/checkout@v4\n",
+        )
+        .unwrap();
+    }
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    let output = cmd
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .arg("--dry-run")
+        .arg("--jobs")
+        .arg("3")
+        .arg("--io-workers")
+        .arg("2")
+        .arg("--format")
+        .arg("json")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(results["jobs"], 3);
+    assert_eq!(results["io_workers"], 2);
+    assert_eq!(results["actions_pinned"], 3);
+
+    for value in ["0", "many"] {
+        let mut cmd = Command::new(cargo_bin!("pin-actions"));
+        cmd.arg("--workflows-dir")
+            .arg(&workflows_dir)
+            .arg("--io-workers")
+            .arg(value)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("--io-workers"));
+    }
+}
+
+#[test]
+fn test_emit_patches() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        r#"{"actions": {"actions/checkout@v4": "b4ffde65f46336ab88eb53be808477a3936bae11"}}"#,
+    )
+    .unwrap();
+
+    let workflow_path = workflows_dir.join("test.yml");
+    let workflow_content = "name: CI\non: [push]\njobs:\n  test:\n    runs-on: ubuntu-latest\n    steps:\n      - uses: actions/checkout@v4\n";
+    fs::write(&workflow_path, workflow_content).unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    let output = cmd
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .arg("--dry-run")
+        .arg("--emit-patches")
+        .arg("--format")
+        .arg("json")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let patches = results["patches"].as_array().unwrap();
+    assert_eq!(patches.len(), 1);
+    assert_eq!(patches[0]["original"], workflow_content);
+
+    let rewritten = patches[0]["rewritten"].as_str().unwrap();
+    assert!(rewritten.contains("actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11 # v4"));
+    let parsed: serde_yaml::Value = serde_yaml::from_str(rewritten).unwrap();
+    assert_eq!(
+        parsed["jobs"]["test"]["steps"][0]["uses"],
+        "actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11"
+    );
+
+    // Dry-run still leaves the file alone
+    assert_eq!(
+        fs::read_to_string(&workflow_path).unwrap(),
+        workflow_content
+    );
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--emit-patches")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "--emit-patches requires --format json",
+        ));
+}
+
+#[test]
+fn test_pin_images() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let digest = "sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        format!(
+            r#"{{"actions": {{"actions/checkout@v4": "b4ffde65f46336ab88eb53be808477a3936bae11"}}, "images": {{"node:20": "{}"}}}}"#,
+            digest
+        ),
+    )
+    .unwrap();
+
+    let workflow_path = workflows_dir.join("test.yml");
+    let workflow_content = format!(
+        "jobs:\n  build:\n    container: node:20\n    steps:\n      - uses: actions/checkout@v4\n  test:\n    container:\n      image: redis:7@{}\n",
+        digest
+    );
+    fs::write(&workflow_path, &workflow_content).unwrap();
+
+    // Images are left alone unless asked for
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .arg("--dry-run")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Images found").not());
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    let output = cmd
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .arg("--pin-images")
+        .arg("--format")
+        .arg("json")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(results["actions_pinned"], 1);
+    assert_eq!(results["images_found"], 2);
+    assert_eq!(results["images_pinned"], 1);
+    assert_eq!(results["images_already_pinned"], 1);
+    assert_eq!(results["pinned_images"][0]["image"], "node:20");
+    assert_eq!(results["pinned_images"][0]["line"], 3);
+
+    let content = fs::read_to_string(&workflow_path).unwrap();
+    assert!(content.contains(&format!("    container: node:20@{}\n", digest)));
+    assert!(content.contains("actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11 # v4"));
+}
+
+#[test]
+fn test_ignore_directive() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        r#"{"actions": {"actions/setup-node@v4": "b4ffde65f46336ab88eb53be808477a3936bae11"}}"#,
+    )
+    .unwrap();
+
+    let workflow_path = workflows_dir.join("test.yml");
+    fs::write(
+        &workflow_path,
+        "steps:\n  - uses: actions/checkout@v4 # pin-actions: ignore\n  - uses: actions/setup-node@v4\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Ignored:          1"));
+
+    assert_eq!(
+        fs::read_to_string(&workflow_path).unwrap(),
+        "steps:\n  - uses: actions/checkout@v4 # pin-actions: ignore\n  - uses: actions/setup-node@b4ffde65f46336ab88eb53be808477a3936bae11 # v4\n"
+    );
+
+    // An ignored tag doesn't fail the unpinned check
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("check")
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_open_pr_commits_to_branch() {
+    let temp = TempDir::new().unwrap();
+    let repo = git2::Repository::init(temp.path()).unwrap();
+    let workflows_dir = temp.path().join(".github/workflows");
+    fs::create_dir_all(&workflows_dir).unwrap();
+    fs::write(
+        workflows_dir.join("ci.yml"),
+        "steps:\n  - uses: actions/checkout@v4\n",
+    )
+    .unwrap();
+
+    let mut index = repo.index().unwrap();
+    index
+        .add_path(std::path::Path::new(".github/workflows/ci.yml"))
+        .unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let signature = git2::Signature::now("test", "test@example.com").unwrap();
+    repo.commit(Some("HEAD"), &signature, &signature, "init", &tree, &[])
+        .unwrap();
+
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        r#"{"actions": {"actions/checkout@v4": "b4ffde65f46336ab88eb53be808477a3936bae11"}}"#,
+    )
+    .unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .arg("--no-auth")
+        .arg("--open-pr")
+        .assert()
+        .success();
+
+    // The branch is committed to without checking it out
+    let head = repo.head().unwrap();
+    assert!(!head.shorthand().unwrap().starts_with("pin-actions/"));
+    let branch = repo
+        .branches(Some(git2::BranchType::Local))
+        .unwrap()
+        .map(|branch| branch.unwrap().0)
+        .find(|branch| branch.name().unwrap().unwrap().starts_with("pin-actions/"))
+        .unwrap();
+    let commit = branch.get().peel_to_commit().unwrap();
+    assert_eq!(commit.parent_id(0).unwrap(), head.target().unwrap());
+    assert!(
+        commit
+            .message()
+            .unwrap()
+            .contains("`actions/checkout@v4` → `b4ffde65f46336ab88eb53be808477a3936bae11`")
+    );
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--open-pr")
+        .arg("--dry-run")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--create-pr"));
+}
+
+#[test]
+fn test_pin_service_images() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let digest = "sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        format!(
+            r#"{{"actions": {{}}, "images": {{"redis:7": "{0}", "postgres:16": "{0}", "node:20": "{0}"}}}}"#,
+            digest
+        ),
+    )
+    .unwrap();
+
+    let workflow_path = workflows_dir.join("test.yml");
+    fs::write(
+        &workflow_path,
+        format!(
+            "jobs:\n  test:\n    container: node:20\n    services:\n      redis: {{ image: redis:7, ports: [\"6379:6379\"] }}\n      postgres:\n        image: 'postgres:16'\n        ports:\n          - 5432:5432\n      mysql:\n        image: mysql:8@{}\n",
+            digest
+        ),
+    )
+    .unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    let output = cmd
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .arg("--pin-images")
+        .arg("--format")
+        .arg("json")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(results["images_pinned"], 3);
+    assert_eq!(results["images_pinned_by_kind"]["container"], 1);
+    assert_eq!(results["images_pinned_by_kind"]["service"], 2);
+    assert_eq!(results["images_found_by_kind"]["container"], 1);
+    assert_eq!(results["images_found_by_kind"]["service"], 3);
+    assert_eq!(results["images_already_pinned_by_kind"]["service"], 1);
+    assert!(results["images_already_pinned_by_kind"]["container"].is_null());
+
+    assert_eq!(
+        fs::read_to_string(&workflow_path).unwrap(),
+        format!(
+            "jobs:\n  test:\n    container: node:20@{0}\n    services:\n      redis: {{ image: redis:7@{0}, ports: [\"6379:6379\"] }}\n      postgres:\n        image: 'postgres:16@{0}'\n        ports:\n          - 5432:5432\n      mysql:\n        image: mysql:8@{0}\n",
+            digest
+        )
+    );
+}
+
+#[test]
+fn test_known_refs_detects_moved_tag() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        r#"{"actions": {"actions/checkout@v4": "b4ffde65f46336ab88eb53be808477a3936bae11"}}"#,
+    )
+    .unwrap();
+
+    let workflow_path = workflows_dir.join("test.yml");
+    let original = "steps:\n  - uses: actions/checkout@v4\n";
+    fs::write(&workflow_path, original).unwrap();
+
+    // First run records the resolution
+    let known = temp.path().join("known.json");
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .arg("--known-refs")
+        .arg(&known)
+        .arg("--dry-run")
+        .assert()
+        .success();
+    assert!(!known.exists(), "dry runs don't record");
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .arg("--known-refs")
+        .arg(&known)
+        .assert()
+        .success();
+    let store = fs::read_to_string(&known).unwrap();
+    assert!(store.contains("b4ffde65f46336ab88eb53be808477a3936bae11"));
+
+    // Now the tag resolves somewhere else than first seen
+    fs::write(&workflow_path, original).unwrap();
+    fs::write(
+        &known,
+        r#"{"actions": {"actions/checkout@v4": {"sha": "0000000000000000000000000000000000000000", "first_seen": "2024-01-01"}}}"#,
+    )
+    .unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .arg("--known-refs")
+        .arg(&known)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Tag moved"));
+    assert_eq!(fs::read_to_string(&workflow_path).unwrap(), original);
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .arg("--known-refs")
+        .arg(&known)
+        .arg("--fail-on-tag-moved")
+        .assert()
+        .failure();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .arg("--known-refs")
+        .arg(&known)
+        .arg("--fail-on-tag-moved")
+        .arg("--accept-changes")
+        .assert()
+        .success();
+    assert!(
+        fs::read_to_string(&workflow_path)
+            .unwrap()
+            .contains("actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11 # v4")
+    );
+    let store = fs::read_to_string(&known).unwrap();
+    assert!(store.contains("b4ffde65f46336ab88eb53be808477a3936bae11"));
+    assert!(!store.contains("0000000000000000000000000000000000000000"));
+}
+
+#[test]
+fn test_moved_tag_counts_as_unresolved() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        r#"{"actions": {"actions/checkout@v4": "b4ffde65f46336ab88eb53be808477a3936bae11"}}"#,
+    )
+    .unwrap();
+    let known = temp.path().join("known.json");
+    fs::write(
+        &known,
+        r#"{"actions": {"actions/checkout@v4": {"sha": "0000000000000000000000000000000000000000", "first_seen": "2024-01-01"}}}"#,
+    )
+    .unwrap();
+    fs::write(
+        workflows_dir.join("test.yml"),
+        "steps:\n  - uses: actions/checkout@v4\n",
+    )
+    .unwrap();
+
+    let run = |extra: &[&str]| {
+        let mut cmd = Command::new(cargo_bin!("pin-actions"));
+        cmd.arg("--workflows-dir")
+            .arg(&workflows_dir)
+            .arg("--lockfile")
+            .arg(&lockfile)
+            .arg("--offline")
+            .arg("--known-refs")
+            .arg(&known)
+            .args(extra)
+            .assert()
+    };
+
+    let output = run(&["--format", "json"])
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(json["status"], "unresolved");
+    assert_eq!(json["actions_pinned"], 0);
+    assert_eq!(json["remaining_unpinned"].as_array().unwrap().len(), 1);
+    assert_eq!(json["moved_refs"].as_array().unwrap().len(), 1);
+
+    // Left on the moved tag, so it fails --fail-on unpinned
+    run(&["--fail-on", "unpinned"]).failure();
+}
+#[test]
+fn test_check_health_requires_network() {
+    let temp = TempDir::new().unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(temp.path())
+        .arg("--lockfile")
+        .arg(temp.path().join("pin-actions.lock"))
+        .arg("--offline")
+        .arg("--check-health")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "--check-health needs the GitHub API",
+        ));
+}
+
+#[test]
+fn test_rerun_is_noop() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        r#"{
+            "actions": {
+                "actions/checkout@v4": "b4ffde65f46336ab88eb53be808477a3936bae11",
+                "actions/cache@tag:v4": "0c45773b623bea8c8e75f6c82b208c3cf94ea4f9"
+            },
+            "images": {
+                "node:20": "sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef"
+            }
+        }"#,
+    )
+    .unwrap();
+
+    let inputs = [
+        ("lf.yml", "steps:\n  - uses: actions/checkout@v4\n"),
+        (
+            "crlf.yml",
+            "steps:\r\n  - uses: actions/checkout@v4\r\n  - run: make\r\n",
+        ),
+        ("no-newline.yml", "steps:\n  - uses: actions/checkout@v4"),
+        (
+            "comment.yml",
+            "steps:\n  - uses: actions/checkout@v4 # keep\n",
+        ),
+        (
+            "multi-doc.yml",
+            "---\nsteps:\n  - uses: actions/checkout@v4\n---\nsteps:\n  - uses: actions/cache@tag:v4\n",
+        ),
+        (
+            "container.yml",
+            "jobs:\n  test:\n    container: node:20\n    steps:\n      - uses: actions/checkout@v4\n",
+        ),
+    ];
+    for (name, content) in inputs {
+        fs::write(workflows_dir.join(name), content).unwrap();
+    }
+
+    let run = || {
+        let mut cmd = Command::new(cargo_bin!("pin-actions"));
+        cmd.arg("--workflows-dir")
+            .arg(&workflows_dir)
+            .arg("--lockfile")
+            .arg(&lockfile)
+            .arg("--offline")
+            .arg("--pin-images")
+            .arg("--assert-idempotent")
+            .arg("--format")
+            .arg("json")
+            .assert()
+            .success()
+            .get_output()
+            .stdout
+            .clone()
+    };
+
+    let first: serde_json::Value = serde_json::from_slice(&run()).unwrap();
+    assert_eq!(first["errors"], 0);
+    assert_eq!(first["files_changed"], inputs.len());
+
+    let pinned: Vec<String> = inputs
+        .iter()
+        .map(|(name, _)| fs::read_to_string(workflows_dir.join(name)).unwrap())
+        .collect();
+    for ((name, content), pinned) in inputs.iter().zip(&pinned) {
+        assert_eq!(
+            pinned.ends_with('\n'),
+            content.ends_with('\n'),
+            "trailing newline changed in {}",
+            name
+        );
+        assert_eq!(
+            pinned.matches("\r\n").count(),
+            content.matches("\r\n").count(),
+            "line endings changed in {}",
+            name
+        );
+    }
+
+    let second: serde_json::Value = serde_json::from_slice(&run()).unwrap();
+    assert_eq!(second["errors"], 0);
+    assert_eq!(second["lines_changed"], 0);
+    assert_eq!(second["actions_pinned"], 0);
+    assert_eq!(second["images_pinned"], 0);
+    for ((name, _), pinned) in inputs.iter().zip(&pinned) {
+        assert_eq!(
+            &fs::read_to_string(workflows_dir.join(name)).unwrap(),
+            pinned,
+            "{} changed on the second run",
+            name
+        );
+    }
+}
+
+#[test]
+fn test_lock_contention() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+    fs::write(workflows_dir.join("ci.yml"), "steps:\n  - run: make\n").unwrap();
+
+    // Stand in for another run holding the lock
+    let held = fs::File::open(&workflows_dir).unwrap();
+    held.try_lock().unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lock")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("holds the lock"));
+
+    let waiting = Command::new(cargo_bin!("pin-actions"))
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lock")
+        .arg("--lock-mode")
+        .arg("wait")
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(300));
+    drop(held);
+
+    let output = waiting.wait_with_output().unwrap();
+    assert!(output.status.success());
+}
+
+#[test]
+fn test_mirror_dir_offline() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    // A pre-seeded mirror holding one tagged repository
+    let mirror = temp.path().join("mirror");
+    let repo = git2::Repository::init_bare(mirror.join("actions/checkout")).unwrap();
+    let sig = git2::Signature::now("test", "test@example.com").unwrap();
+    let tree = repo
+        .find_tree(repo.treebuilder(None).unwrap().write().unwrap())
+        .unwrap();
+    let commit = repo
+        .commit(Some("refs/heads/main"), &sig, &sig, "init", &tree, &[])
+        .unwrap();
+    repo.tag_lightweight("v4", &repo.find_object(commit, None).unwrap(), false)
+        .unwrap();
+
+    let workflow_path = workflows_dir.join("test.yml");
+    fs::write(
+        &workflow_path,
+        "steps:\n  - uses: actions/checkout@v4\n  - uses: actions/cache@v4\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--mirror-dir")
+        .arg(&mirror)
+        .arg("--offline")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("actions/cache@v4"));
+
+    assert_eq!(
+        fs::read_to_string(&workflow_path).unwrap(),
+        format!(
+            "steps:\n  - uses: actions/checkout@{} # v4\n  - uses: actions/cache@v4\n",
+            commit
+        )
+    );
+
+    // Offline needs somewhere to resolve from
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--offline")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--lockfile or --mirror-dir"));
+}
+
+#[test]
+fn test_verify_shas() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    // A tag named like a SHA, as when vendoring an upstream hash
+    let vendored = "0123456789abcdef0123456789abcdef01234567";
+    let mirror = temp.path().join("mirror");
+    let repo = git2::Repository::init_bare(mirror.join("org/vendored")).unwrap();
+    let sig = git2::Signature::now("test", "test@example.com").unwrap();
+    let tree = repo
+        .find_tree(repo.treebuilder(None).unwrap().write().unwrap())
+        .unwrap();
+    let commit = repo
+        .commit(Some("refs/heads/main"), &sig, &sig, "init", &tree, &[])
+        .unwrap();
+    repo.tag_lightweight(vendored, &repo.find_object(commit, None).unwrap(), false)
+        .unwrap();
+
+    let workflow_path = workflows_dir.join("test.yml");
+    fs::write(
+        &workflow_path,
+        format!("steps:\n  - uses: org/vendored@{vendored}\n  - uses: org/vendored@{commit}\n"),
+    )
+    .unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    let output = cmd
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--mirror-dir")
+        .arg(&mirror)
+        .args(["--offline", "--verify-shas", "--format", "json", "audit"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        results["short_shas"]["sha_collisions"],
+        serde_json::json!([{
+            "file": workflow_path.to_string_lossy(),
+            "line": 2,
+            "column": 11,
+            "action": "org/vendored",
+            "reference": vendored,
+            "kind": "tag",
+            "commit": commit.to_string(),
+        }])
+    );
+
+    // Pinning repins the tag to its commit, keeping its name
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--mirror-dir")
+        .arg(&mirror)
+        .args(["--offline", "--verify-shas"])
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(&workflow_path).unwrap(),
+        format!(
+            "steps:\n  - uses: org/vendored@{commit} # {vendored}\n  - uses: org/vendored@{commit}\n"
+        )
+    );
+}
+
+#[test]
+fn test_log_file_json() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+    fs::write(
+        workflows_dir.join("test.yml"),
+        "steps:\n  - uses: actions/checkout@v4\n  - uses: actions/cache@v4\n",
+    )
+    .unwrap();
+
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        r#"{"actions": {
+            "actions/checkout@v4": "b4ffde65f46336ab88eb53be808477a3936bae11",
+            "actions/cache@v4": "0c45773b623bea8c8e75f6c82b208c3cf94ea4f9"
+        }}"#,
+    )
+    .unwrap();
+
+    let log_file = temp.path().join("run.log");
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .arg("--offline")
+        .arg("--log-file")
+        .arg(&log_file)
+        .arg("--log-format")
+        .arg("json")
+        .assert()
+        .success();
+
+    let records: Vec<serde_json::Value> = fs::read_to_string(&log_file)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let mut resolved: Vec<(&str, &str)> = records
+        .iter()
+        .filter_map(|record| {
+            let fields = &record["fields"];
+            Some((fields["action"].as_str()?, fields["sha"].as_str()?))
+        })
+        .collect();
+    resolved.sort();
+    assert_eq!(
+        resolved,
+        [
+            (
+                "actions/cache@v4",
+                "0c45773b623bea8c8e75f6c82b208c3cf94ea4f9"
+            ),
+            (
+                "actions/checkout@v4",
+                "b4ffde65f46336ab88eb53be808477a3936bae11"
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_dry_run_preview() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+    fs::write(
+        workflows_dir.join("test.yml"),
+        "steps:\n  - uses: actions/checkout@v4\n  - run: echo hi\n",
+    )
+    .unwrap();
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        r#"{"actions": {"actions/checkout@v4": "b4ffde65f46336ab88eb53be808477a3936bae11"}}"#,
+    )
+    .unwrap();
+
+    let run = |extra: &[&str]| {
+        let mut cmd = Command::new(cargo_bin!("pin-actions"));
+        cmd.arg("--workflows-dir")
+            .arg(&workflows_dir)
+            .arg("--lockfile")
+            .arg(&lockfile)
+            .args(["--offline", "--dry-run"])
+            .args(extra)
+            .assert()
+            .success()
+    };
+
+    // The summary's planned edits are enough unless verbose
+    run(&[]).stdout(predicate::str::contains("-  - uses:").not());
+    run(&["--verbose"])
+        .stdout(predicate::str::contains("test.yml"))
+        .stdout(predicate::str::contains("-  - uses: actions/checkout@v4\n"))
+        .stdout(predicate::str::contains(
+            "+  - uses: actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11 # v4\n",
+        ))
+        .stdout(predicate::str::contains("echo hi").not());
+
+    run(&["--verbose", "--quiet"]).stdout(predicate::str::contains("-  - uses:").not());
+
+    let output = run(&["--format", "json"]).get_output().stdout.clone();
+    let results: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(
+        results["planned_changes"],
+        serde_json::json!([{
+            "file": workflows_dir.join("test.yml").to_string_lossy(),
+            "line": 2,
+            "before": "  - uses: actions/checkout@v4",
+            "after": "  - uses: actions/checkout@b4ffde65f46336ab88eb53be808477a3936bae11 # v4",
+        }])
+    );
+}
+
+#[test]
+fn test_warm_cache() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+    let workflow_path = workflows_dir.join("test.yml");
+    fs::write(&workflow_path, "steps:\n  - uses: actions/checkout@v4\n").unwrap();
+
+    // A primed entry is a cache hit, so nothing goes to the network
+    let sha = "b4ffde65f46336ab88eb53be808477a3936bae11";
+    let cache = temp.path().join("cache.json");
+    fs::write(&cache, format!(r#"{{"actions/checkout@v4": "{}"}}"#, sha)).unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--warm-cache")
+        .arg(&cache)
+        .assert()
+        .success();
+
+    assert_eq!(
+        fs::read_to_string(&workflow_path).unwrap(),
+        format!("steps:\n  - uses: actions/checkout@{} # v4\n", sha)
+    );
+    let saved: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&cache).unwrap()).unwrap();
+    assert_eq!(saved["actions/checkout@v4"], sha);
+}
+
+#[test]
+fn test_modified_files() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+    let sha = "b4ffde65f46336ab88eb53be808477a3936bae11";
+    fs::write(
+        workflows_dir.join("unpinned.yml"),
+        "steps:\n  - uses: actions/checkout@v4\n",
+    )
+    .unwrap();
+    fs::write(
+        workflows_dir.join("pinned.yml"),
+        format!("steps:\n  - uses: actions/checkout@{} # v4\n", sha),
+    )
+    .unwrap();
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        format!(r#"{{"actions": {{"actions/checkout@v4": "{}"}}}}"#, sha),
+    )
+    .unwrap();
+    let unpinned = workflows_dir.join("unpinned.yml");
+
+    let run = |dry_run: bool| {
+        let mut cmd = Command::new(cargo_bin!("pin-actions"));
+        cmd.arg("--workflows-dir")
+            .arg(&workflows_dir)
+            .arg("--lockfile")
+            .arg(&lockfile)
+            .args(["--offline", "--format", "json"]);
+        if dry_run {
+            cmd.arg("--dry-run");
+        }
+        let output = cmd.assert().success().get_output().stdout.clone();
+        serde_json::from_slice::<serde_json::Value>(&output).unwrap()["modified_files"].clone()
+    };
+
+    let expected = serde_json::json!([unpinned.to_string_lossy()]);
+    assert_eq!(run(true), expected);
+    assert_eq!(run(false), expected);
+    // Nothing left to change on a second run
+    assert_eq!(run(false), serde_json::json!([]));
+}
+
+#[test]
+fn test_commit_flag() {
+    let temp = TempDir::new().unwrap();
+    let root = temp.path().join("repo");
+    let workflows_dir = root.join(".github/workflows");
+    fs::create_dir_all(&workflows_dir).unwrap();
+    fs::write(
+        workflows_dir.join("ci.yml"),
+        "steps:\n  - uses: actions/checkout@v4\n",
+    )
+    .unwrap();
+    fs::write(
+        workflows_dir.join("docs.yml"),
+        "steps:\n  - run: make docs\n",
+    )
+    .unwrap();
+
+    let repo = git2::Repository::init(&root).unwrap();
+    let mut config = repo.config().unwrap();
+    config.set_str("user.name", "test").unwrap();
+    config.set_str("user.email", "test@example.com").unwrap();
+    let mut index = repo.index().unwrap();
+    index
+        .add_all(["."], git2::IndexAddOption::DEFAULT, None)
+        .unwrap();
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let sig = git2::Signature::now("test", "test@example.com").unwrap();
+    let initial = repo
+        .commit(Some("HEAD"), &sig, &sig, "init", &tree, &[])
+        .unwrap();
+
+    let sha = "b4ffde65f46336ab88eb53be808477a3936bae11";
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        format!(r#"{{"actions": {{"actions/checkout@v4": "{}"}}}}"#, sha),
+    )
+    .unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .args(["--offline", "--commit", "ci: pin actions"])
+        .assert()
+        .success();
+
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    assert_eq!(head.parent_id(0).unwrap(), initial);
+    assert_eq!(head.summary(), Some("ci: pin actions"));
+    assert!(head.message().unwrap().contains(sha));
+
+    // Only the pinned workflow is in the commit
+    let diff = repo
+        .diff_tree_to_tree(
+            Some(&head.parent(0).unwrap().tree().unwrap()),
+            Some(&head.tree().unwrap()),
+            None,
+        )
+        .unwrap();
+    let changed: Vec<_> = diff
+        .deltas()
+        .map(|delta| delta.new_file().path().unwrap().to_path_buf())
+        .collect();
+    assert_eq!(changed, [std::path::Path::new(".github/workflows/ci.yml")]);
+}
+
+#[test]
+fn test_pin_links() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+    fs::write(
+        workflows_dir.join("test.yml"),
+        "steps:\n  - uses: github/codeql-action/analyze@v3\n",
+    )
+    .unwrap();
+    let sha = "b4ffde65f46336ab88eb53be808477a3936bae11";
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        format!(
+            r#"{{"actions": {{"github/codeql-action/analyze@v3": "{}"}}}}"#,
+            sha
+        ),
+    )
+    .unwrap();
+
+    let run = |extra: &[&str]| {
+        let mut cmd = Command::new(cargo_bin!("pin-actions"));
+        cmd.arg("--workflows-dir")
+            .arg(&workflows_dir)
+            .arg("--lockfile")
+            .arg(&lockfile)
+            .args(["--offline", "--dry-run"])
+            .args(extra)
+            .assert()
+            .success()
+    };
+
+    let output = run(&["--format", "json"]).get_output().stdout.clone();
+    let results: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(
+        results["pinned_actions"][0]["link"],
+        format!("https://github.com/github/codeql-action/commit/{}", sha)
+    );
+
+    run(&["--print-links", "--git-host", "ghes.example.com"]).stdout(predicate::str::contains(
+        format!(
+            "https://ghes.example.com/github/codeql-action/commit/{}",
+            sha
+        ),
+    ));
+}
+
+#[test]
+fn test_changed_only() {
+    let temp = TempDir::new().unwrap();
+    let root = temp.path().join("repo");
+    let workflows_dir = root.join(".github/workflows");
+    fs::create_dir_all(&workflows_dir).unwrap();
+    for name in ["ci.yml", "release.yml"] {
+        fs::write(
+            workflows_dir.join(name),
+            "steps:\n  - uses: actions/checkout@v4\n",
+        )
+        .unwrap();
+    }
+
+    let repo = git2::Repository::init(&root).unwrap();
+    let mut index = repo.index().unwrap();
+    index
+        .add_all(["."], git2::IndexAddOption::DEFAULT, None)
+        .unwrap();
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let sig = git2::Signature::now("test", "test@example.com").unwrap();
+    let base = repo
+        .commit(Some("HEAD"), &sig, &sig, "init", &tree, &[])
+        .unwrap();
+    repo.reference("refs/remotes/origin/main", base, false, "test")
+        .unwrap();
+
+    // Only ci.yml differs from origin/main
+    fs::write(
+        workflows_dir.join("ci.yml"),
+        "steps:\n  - uses: actions/checkout@v4\n  - run: make\n",
+    )
+    .unwrap();
+
+    let sha = "b4ffde65f46336ab88eb53be808477a3936bae11";
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        format!(r#"{{"actions": {{"actions/checkout@v4": "{}"}}}}"#, sha),
+    )
+    .unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    let output = cmd
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .args(["--offline", "--changed-only", "--format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(results["files_processed"], 1);
+    assert_eq!(results["files_unchanged"], 1);
+    assert!(
+        fs::read_to_string(workflows_dir.join("ci.yml"))
+            .unwrap()
+            .contains(sha)
+    );
+    assert!(
+        !fs::read_to_string(workflows_dir.join("release.yml"))
+            .unwrap()
+            .contains(sha)
+    );
+
+    // Outside a git repository the flag fails clearly
+    let outside = temp.path().join("plain/.github/workflows");
+    fs::create_dir_all(&outside).unwrap();
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    cmd.arg("--workflows-dir")
+        .arg(&outside)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .args(["--offline", "--changed-only"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("needs a git repository"));
+}
+
+#[test]
+fn test_multi_document_workflow() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+    let workflow_path = workflows_dir.join("test.yml");
+    fs::write(
+        &workflow_path,
+        "---\nname: First\njobs:\n  a:\n    steps:\n      - uses: actions/checkout@v4\n---\nname: Second\njobs:\n  b:\n    steps:\n      - uses: actions/setup-node@v4\n",
+    )
+    .unwrap();
+
+    let checkout = "b4ffde65f46336ab88eb53be808477a3936bae11";
+    let setup_node = "60edb5dd545a775178f52524783378180af0d1f8";
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        format!(
+            r#"{{"actions": {{"actions/checkout@v4": "{}", "actions/setup-node@v4": "{}"}}}}"#,
+            checkout, setup_node
+        ),
+    )
+    .unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    let output = cmd
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .args(["--offline", "--format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(results["actions_pinned"], 2);
+
+    // Each document is pinned on its own line, with the separator intact
+    let content = fs::read_to_string(&workflow_path).unwrap();
+    let lines: Vec<&str> = content.lines().collect();
+    assert_eq!(
+        lines[5],
+        format!("      - uses: actions/checkout@{} # v4", checkout)
+    );
+    assert_eq!(lines[6], "---");
+    assert_eq!(
+        lines[11],
+        format!("      - uses: actions/setup-node@{} # v4", setup_node)
+    );
+
+    let documents: Vec<serde_yaml::Value> = serde_yaml::Deserializer::from_str(&content)
+        .map(|document| serde::Deserialize::deserialize(document).unwrap())
+        .collect();
+    assert_eq!(documents.len(), 2);
+    assert_eq!(
+        documents[1]["jobs"]["b"]["steps"][0]["uses"],
+        format!("actions/setup-node@{}", setup_node).as_str()
+    );
+}
+
+#[test]
+fn test_only_unresolvable() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let mirror = temp.path().join("mirror");
+    let repo = git2::Repository::init_bare(mirror.join("actions/checkout")).unwrap();
+    let sig = git2::Signature::now("test", "test@example.com").unwrap();
+    let tree = repo
+        .find_tree(repo.treebuilder(None).unwrap().write().unwrap())
+        .unwrap();
+    let commit = repo
+        .commit(Some("refs/heads/main"), &sig, &sig, "init", &tree, &[])
+        .unwrap();
+    let object = repo.find_object(commit, None).unwrap();
+    for tag in ["v4", "v4.1.0", "v3"] {
+        repo.tag_lightweight(tag, &object, false).unwrap();
+    }
+
+    // A typo next to a ref that resolves
+    let workflow_content =
+        "steps:\n  - uses: actions/checkout@v4\n  - uses: actions/checkout@v4.1.9\n";
+    let workflow_path = workflows_dir.join("test.yml");
+    fs::write(&workflow_path, workflow_content).unwrap();
+
+    let run = |extra: &[&str]| {
+        let mut cmd = Command::new(cargo_bin!("pin-actions"));
+        cmd.arg("--workflows-dir")
+            .arg(&workflows_dir)
+            .arg("--mirror-dir")
+            .arg(&mirror)
+            .args(["--offline", "--only-unresolvable", "--candidate-refs", "2"])
+            .args(extra)
+            .assert()
+            .failure()
+    };
+
+    let output = run(&["--format", "json"]).get_output().stdout.clone();
+    let results: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(results["actions_checked"], 2);
+    assert_eq!(
+        results["unresolvable"],
+        serde_json::json!([{
+            "action": "actions/checkout@v4.1.9",
+            "code": "E_REF_NOT_FOUND",
+            "source": mirror.join("actions/checkout").to_string_lossy(),
+            "message": results["unresolvable"][0]["message"],
+            "files": [workflow_path.to_string_lossy()],
+            "available_refs": ["v4.1.0", "v4"],
+        }])
+    );
+
+    run(&[])
+        .stdout(predicate::str::contains("actions/checkout@v4.1.9"))
+        .stdout(predicate::str::contains("v4.1.0, v4"))
+        .stdout(predicate::str::contains("actions/checkout@v4 ").not());
+
+    // Nothing is written
+    assert_eq!(
+        fs::read_to_string(&workflow_path).unwrap(),
+        workflow_content
+    );
+}
+
+#[test]
+fn test_annotate() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    // v4 and v4.1.7 mark the first commit, main has moved past it, and a
+    // third commit is on no branch at all
+    let mirror = temp.path().join("mirror");
+    let repo = git2::Repository::init_bare(mirror.join("actions/checkout")).unwrap();
+    let sig = git2::Signature::now("test", "test@example.com").unwrap();
+    let tree = repo
+        .find_tree(repo.treebuilder(None).unwrap().write().unwrap())
+        .unwrap();
+    let tagged = repo
+        .commit(Some("refs/heads/main"), &sig, &sig, "init", &tree, &[])
+        .unwrap();
+    let object = repo.find_object(tagged, None).unwrap();
+    for tag in ["v4", "v4.1.7"] {
+        repo.tag_lightweight(tag, &object, false).unwrap();
+    }
+    let parent = repo.find_commit(tagged).unwrap();
+    let on_main = repo
+        .commit(
+            Some("refs/heads/main"),
+            &sig,
+            &sig,
+            "next",
+            &tree,
+            &[&parent],
+        )
+        .unwrap();
+    let orphan = repo.commit(None, &sig, &sig, "orphan", &tree, &[]).unwrap();
+
+    let workflow_path = workflows_dir.join("test.yml");
+    fs::write(
+        &workflow_path,
+        format!(
+            "steps:\n  - uses: actions/checkout@{tagged}\n  - uses: actions/checkout@{tagged} # v4\n  - uses: actions/checkout@{on_main}\n  - uses: actions/checkout@{orphan}\n  - uses: actions/checkout@v4\n"
+        ),
+    )
+    .unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    let output = cmd
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--mirror-dir")
+        .arg(&mirror)
+        .args(["--offline", "--annotate", "--format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(results["actions_pinned"], 1);
+    assert_eq!(results["actions_annotated"], 2);
+    assert_eq!(results["unidentifiable"][0]["line"], 5);
+    assert_eq!(results["unidentifiable"][0]["sha"], orphan.to_string());
+
+    assert_eq!(
+        fs::read_to_string(&workflow_path).unwrap(),
+        format!(
+            "steps:\n  - uses: actions/checkout@{tagged} # v4.1.7\n  - uses: actions/checkout@{tagged} # v4\n  - uses: actions/checkout@{on_main} # main (branch)\n  - uses: actions/checkout@{orphan}\n  - uses: actions/checkout@{tagged} # v4\n"
+        )
+    );
+}
+
+#[test]
+fn test_rerun_keeps_comments_stable() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let checkout = "b4ffde65f46336ab88eb53be808477a3936bae11";
+    let cache = "1bd1e32a3bdc45362d1e726936510720a7c30a57";
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        format!(
+            r#"{{"actions": {{"actions/checkout@v4": "{}", "actions/cache@1bd1e32": "{}"}}}}"#,
+            checkout, cache
+        ),
+    )
+    .unwrap();
+
+    // An author's note on an unpinned line and on a short SHA pin whose
+    // version comment carries a date
+    let workflow_path = workflows_dir.join("test.yml");
+    fs::write(
+        &workflow_path,
+        "steps:\n  - uses: actions/checkout@v4 # needed for the build\n  - uses: actions/cache@1bd1e32 # v4 (pinned 2024-05-01) # restore deps\n",
+    )
+    .unwrap();
+
+    let run = |extra: &[&str]| {
+        let mut cmd = Command::new(cargo_bin!("pin-actions"));
+        cmd.arg("--workflows-dir")
+            .arg(&workflows_dir)
+            .arg("--lockfile")
+            .arg(&lockfile)
+            .arg("--offline")
+            .args(extra)
+            .assert()
+            .success();
+        fs::read_to_string(&workflow_path).unwrap()
+    };
+
+    let expanded = run(&["--strict-sha", "--expand-short-shas"]);
+    assert_eq!(run(&["--strict-sha", "--expand-short-shas"]), expanded);
+    let pinned = run(&[]);
+    assert_eq!(run(&[]), pinned);
+
+    assert_eq!(
+        pinned,
+        format!(
+            "steps:\n  - uses: actions/checkout@{} # v4 # needed for the build\n  - uses: actions/cache@{} # v4 # restore deps\n",
+            checkout, cache
+        )
+    );
+}
+
+#[test]
+fn test_sync_comments() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let mirror = temp.path().join("mirror");
+    let repo = git2::Repository::init_bare(mirror.join("actions/checkout")).unwrap();
+    let sig = git2::Signature::now("test", "test@example.com").unwrap();
+    let tree = repo
+        .find_tree(repo.treebuilder(None).unwrap().write().unwrap())
+        .unwrap();
+    let v3 = repo
+        .commit(Some("refs/heads/main"), &sig, &sig, "v3", &tree, &[])
+        .unwrap();
+    let parent = repo.find_commit(v3).unwrap();
+    let v4 = repo
+        .commit(Some("refs/heads/main"), &sig, &sig, "v4", &tree, &[&parent])
+        .unwrap();
+    for (tag, commit) in [("v3", v3), ("v4", v4)] {
+        let object = repo.find_object(commit, None).unwrap();
+        repo.tag_lightweight(tag, &object, false).unwrap();
+    }
+
+    // The first comment was bumped by hand; the last names no tag
+    let workflow_path = workflows_dir.join("test.yml");
+    fs::write(
+        &workflow_path,
+        format!(
+            "steps:\n  - uses: actions/checkout@{v3} # v4\n  - uses: actions/checkout@{v3} # v3\n  - uses: actions/checkout@{v3} # v9\n"
+        ),
+    )
+    .unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    let output = cmd
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--mirror-dir")
+        .arg(&mirror)
+        .args(["--offline", "--sync-comments", "--assert-idempotent"])
+        .args(["--format", "json"])
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(results["errors"], 0);
+    assert_eq!(results["drifted_actions"][0]["line"], 2);
+    assert_eq!(results["updated"].as_array().unwrap().len(), 1);
+    assert_eq!(results["unresolved_comments"][0]["line"], 4);
+    assert_eq!(results["unresolved_comments"][0]["comment_ref"], "v9");
+
+    assert_eq!(
+        fs::read_to_string(&workflow_path).unwrap(),
+        format!(
+            "steps:\n  - uses: actions/checkout@{v4} # v4\n  - uses: actions/checkout@{v3} # v3\n  - uses: actions/checkout@{v3} # v9\n"
+        )
+    );
+}
+
+#[test]
+fn test_pin_only_named_job() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let checkout = "b4ffde65f46336ab88eb53be808477a3936bae11";
+    let node = "1e60f620b9541d16bece96c5465dc8ee9832be0b";
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        format!(
+            r#"{{"actions": {{"actions/checkout@v4": "{}", "actions/setup-node@v4": "{}"}}}}"#,
+            checkout, node
+        ),
+    )
+    .unwrap();
+
+    let workflow = "jobs:\n  build:\n    name: Build\n    steps:\n      - uses: actions/checkout@v4\n      - name: Setup\n        uses: actions/setup-node@v4\n  test:\n    steps:\n      - uses: actions/checkout@v4\n      - uses: actions/setup-node@v4\n";
+    let workflow_path = workflows_dir.join("ci.yml");
+
+    let run = |filters: &[&str]| {
+        fs::write(&workflow_path, workflow).unwrap();
+        let mut cmd = Command::new(cargo_bin!("pin-actions"));
+        let output = cmd
+            .arg("--workflows-dir")
+            .arg(&workflows_dir)
+            .arg("--lockfile")
+            .arg(&lockfile)
+            .args(["--offline", "--format", "json"])
+            .args(filters)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        (results, fs::read_to_string(&workflow_path).unwrap())
+    };
+
+    // The job's name selects it as well as its key
+    for job in ["build", "Build"] {
+        let (results, pinned) = run(&["--job", job]);
+        assert_eq!(results["actions_pinned"], 2);
+        assert_eq!(
+            pinned,
+            format!(
+                "jobs:\n  build:\n    name: Build\n    steps:\n      - uses: actions/checkout@{checkout} # v4\n      - name: Setup\n        uses: actions/setup-node@{node} # v4\n  test:\n    steps:\n      - uses: actions/checkout@v4\n      - uses: actions/setup-node@v4\n"
+            )
+        );
+    }
+
+    let (results, pinned) = run(&["--job", "build", "--step", "Setup"]);
+    assert_eq!(results["actions_pinned"], 1);
+    assert!(pinned.contains(&format!("uses: actions/setup-node@{node} # v4")));
+    assert_eq!(pinned.matches("actions/checkout@v4").count(), 2);
+}
+
+#[test]
+fn test_inventory() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let checkout = "b4ffde65f46336ab88eb53be808477a3936bae11";
+    let node = "1e60f620b9541d16bece96c5465dc8ee9832be0b";
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        format!(
+            r#"{{"actions": {{"actions/checkout@v4": "{}"}}}}"#,
+            checkout
+        ),
+    )
+    .unwrap();
+
+    let workflow = format!(
+        "steps:\n  - uses: actions/checkout@v4\n  - uses: actions/setup-node@{node} # v4\n"
+    );
+    let workflow_path = workflows_dir.join("ci.yml");
+    fs::write(&workflow_path, &workflow).unwrap();
+
+    let inventory = |format: &str| {
+        let mut cmd = Command::new(cargo_bin!("pin-actions"));
+        let output = cmd
+            .arg("--workflows-dir")
+            .arg(&workflows_dir)
+            .arg("--lockfile")
+            .arg(&lockfile)
+            .args(["--offline", "--inventory", "--format", format])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let results: serde_json::Value = serde_json::from_str(&inventory("json")).unwrap();
+    assert_eq!(results["errors"], 0);
+    let actions = results["actions"].as_array().unwrap();
+    assert_eq!(actions[0]["action"], "actions/checkout");
+    assert_eq!(actions[0]["usages"][0]["pinned"], false);
+    assert_eq!(actions[0]["usages"][0]["reference"], "v4");
+    assert_eq!(actions[0]["usages"][0]["sha"], checkout);
+    assert_eq!(actions[1]["action"], "actions/setup-node");
+    assert_eq!(actions[1]["usages"][0]["pinned"], true);
+    assert_eq!(actions[1]["usages"][0]["sha"], node);
+
+    let csv = inventory("csv");
+    let mut lines = csv.lines();
+    assert_eq!(lines.next(), Some("action,ref,pinned,sha,file,line"));
+    assert!(
+        lines
+            .next()
+            .unwrap()
+            .starts_with(&format!("actions/checkout,v4,false,{},", checkout))
+    );
+
+    // Nothing is written
+    assert_eq!(fs::read_to_string(&workflow_path).unwrap(), workflow);
+}
+
+#[test]
+fn test_untouched_file_is_not_written() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let checkout = "b4ffde65f46336ab88eb53be808477a3936bae11";
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        format!(
+            r#"{{"actions": {{"actions/checkout@v4": "{}"}}}}"#,
+            checkout
+        ),
+    )
+    .unwrap();
+
+    // One file already fully pinned, one still needing a pin
+    let pinned_path = workflows_dir.join("pinned.yml");
+    fs::write(
+        &pinned_path,
+        format!("steps:\n  - uses: actions/checkout@{checkout} # v4\n"),
+    )
+    .unwrap();
+    fs::write(
+        workflows_dir.join("unpinned.yml"),
+        "steps:\n  - uses: actions/checkout@v4\n",
+    )
+    .unwrap();
+    let long_ago = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+    fs::File::options()
+        .write(true)
+        .open(&pinned_path)
+        .unwrap()
+        .set_modified(long_ago)
+        .unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    let output = cmd
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .args(["--offline", "--backup", "--format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(results["files_changed"], 1);
+    assert_eq!(results["files_untouched"], 1);
+
+    assert_eq!(
+        fs::metadata(&pinned_path).unwrap().modified().unwrap(),
+        long_ago
+    );
+    assert!(!workflows_dir.join("pinned.yml.bak").exists());
+    assert!(workflows_dir.join("unpinned.yml.bak").exists());
+}
+
+#[test]
+fn test_invalid_utf8_workflow() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let sha = "b4ffde65f46336ab88eb53be808477a3936bae11";
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        format!(r#"{{"actions": {{"actions/checkout@v4": "{sha}"}}}}"#),
+    )
+    .unwrap();
+
+    // A stray latin-1 "é" in the workflow's name
+    let workflow_path = workflows_dir.join("latin1.yml");
+    let content = b"name: Caf\xe9 CI\nsteps:\n  - uses: actions/checkout@v4\n".to_vec();
+    fs::write(&workflow_path, &content).unwrap();
+
+    let run = |extra: &[&str]| {
+        let mut cmd = Command::new(cargo_bin!("pin-actions"));
+        let output = cmd
+            .arg("--workflows-dir")
+            .arg(&workflows_dir)
+            .arg("--lockfile")
+            .arg(&lockfile)
+            .args(["--offline", "--format", "json"])
+            .args(extra)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap()
+    };
+
+    // Skipped by default, naming the file and the offending byte
+    let results = run(&[]);
+    assert_eq!(results["errors"], 0);
+    assert_eq!(results["files_skipped"], 1);
+    assert_eq!(
+        results["skipped_files"][0]["file"],
+        workflow_path.to_string_lossy().as_ref()
+    );
+    assert_eq!(
+        results["skipped_files"][0]["reason"],
+        "invalid UTF-8 at byte 9"
+    );
+    assert_eq!(fs::read(&workflow_path).unwrap(), content);
+
+    // Patches show the invalid byte as a replacement character
+    let results = run(&["--lossy-utf8", "--dry-run", "--emit-patches"]);
+    let patches = results["patches"].as_array().unwrap();
+    assert_eq!(patches.len(), 1);
+    assert_eq!(
+        patches[0]["original"],
+        "name: Caf\u{fffd} CI\nsteps:\n  - uses: actions/checkout@v4\n"
+    );
+    assert_eq!(
+        patches[0]["rewritten"],
+        format!("name: Caf\u{fffd} CI\nsteps:\n  - uses: actions/checkout@{sha} # v4\n")
+    );
+    assert_eq!(fs::read(&workflow_path).unwrap(), content);
+
+    // Read lossily, the valid lines are pinned and the invalid byte kept
+    let results = run(&["--lossy-utf8"]);
+    assert_eq!(results["actions_pinned"], 1);
+    let mut expected = b"name: Caf\xe9 CI\nsteps:\n  - uses: actions/checkout@".to_vec();
+    expected.extend(format!("{sha} # v4\n").bytes());
+    assert_eq!(fs::read(&workflow_path).unwrap(), expected);
+}
+
+/// Check a document against reviewdog's DiagnosticResult schema, from its
+/// proto/rdf/jsonschema/DiagnosticResult.jsonschema
+fn assert_valid_rdjson(document: &serde_json::Value) {
+    let schema: serde_json::Value = serde_json::from_str(include_str!(
+        "fixtures/reviewdog/DiagnosticResult.jsonschema"
+    ))
+    .unwrap();
+    let validator = jsonschema::draft4::new(&schema).unwrap();
+    let errors: Vec<String> = validator
+        .iter_errors(document)
+        .map(|e| format!("{} at {}", e, e.instance_path()))
+        .collect();
+    assert!(errors.is_empty(), "not valid rdjson: {:?}", errors);
+}
+
+#[test]
+fn test_rdjson_output() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let sha = "b4ffde65f46336ab88eb53be808477a3936bae11";
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        format!(r#"{{"actions": {{"actions/checkout@v4": "{sha}"}}}}"#),
+    )
+    .unwrap();
+    let workflow_path = workflows_dir.join("test.yml");
+    let content = "steps:\n  - uses: actions/checkout@v4\n";
+    fs::write(&workflow_path, content).unwrap();
+
+    let run = |args: &[&str]| {
+        let mut cmd = Command::new(cargo_bin!("pin-actions"));
+        cmd.arg("--workflows-dir")
+            .arg(&workflows_dir)
+            .arg("--lockfile")
+            .arg(&lockfile)
+            .args(["--offline", "--format", "rdjson"])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    // A dry run suggests the pinned line
+    let output = run(&["--dry-run"]);
+    assert!(output.status.success());
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_valid_rdjson(&results);
+    let diagnostic = &results["diagnostics"][0];
+    assert_eq!(
+        diagnostic["location"]["path"],
+        workflow_path.to_string_lossy().as_ref()
+    );
+    assert_eq!(
+        diagnostic["location"]["range"]["start"],
+        serde_json::json!({"line": 2, "column": 11})
+    );
+    assert_eq!(diagnostic["severity"], "WARNING");
+    assert_eq!(
+        diagnostic["suggestions"][0]["text"],
+        format!("  - uses: actions/checkout@{sha} # v4")
+    );
+    assert_eq!(fs::read_to_string(&workflow_path).unwrap(), content);
+
+    // `check` reports the same use without a fix
+    let output = run(&["check"]);
+    assert_eq!(output.status.code(), Some(1));
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_valid_rdjson(&results);
+    assert_eq!(results["diagnostics"][0]["code"]["value"], "unpinned");
+    assert!(results["diagnostics"][0].get("suggestions").is_none());
+
+    // Pinning for real has nothing left to suggest
+    let output = run(&[]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--format rdjson"));
+}
+
+#[test]
+fn test_metrics_in_results() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        r#"{"actions": {"actions/checkout@v4": "b4ffde65f46336ab88eb53be808477a3936bae11"}}"#,
+    )
+    .unwrap();
+    fs::write(
+        workflows_dir.join("test.yml"),
+        "steps:\n  - uses: actions/checkout@v4\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::new(cargo_bin!("pin-actions"));
+    let output = cmd
+        .arg("--workflows-dir")
+        .arg(&workflows_dir)
+        .arg("--lockfile")
+        .arg(&lockfile)
+        .args(["--offline", "--dry-run", "--format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    // The lockfile answered, so nothing went over the network or was timed
+    let results: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let metrics = &results["metrics"];
+    assert_eq!(metrics["cache_hits"], 1);
+    assert_eq!(metrics["network_resolutions"], 0);
+    assert_eq!(metrics["retries"], 0);
+    assert!(metrics.get("resolution_p50_ms").is_none());
+    for phase in ["discovery_ms", "parse_ms", "resolve_ms", "rewrite_ms"] {
+        assert!(metrics["phases"][phase].as_f64().unwrap() >= 0.0);
+    }
+    let total = metrics["total_ms"].as_f64().unwrap();
+    assert!(total >= metrics["phases"]["parse_ms"].as_f64().unwrap());
+}
+
+#[test]
+fn test_verbose_timing_for_other_commands() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let sha = "b4ffde65f46336ab88eb53be808477a3936bae11";
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        format!(r#"{{"actions": {{"actions/checkout@v4": "{sha}"}}}}"#),
+    )
+    .unwrap();
+    fs::write(
+        workflows_dir.join("test.yml"),
+        format!("steps:\n  - uses: actions/checkout@v4\n  - uses: actions/cache@{sha} # v4\n"),
+    )
+    .unwrap();
+
+    for command in [
+        &["check"][..],
+        &["update", "--dry-run"],
+        &["unpin", "--dry-run"],
+    ] {
+        let mut cmd = Command::new(cargo_bin!("pin-actions"));
+        let output = cmd
+            .arg("--workflows-dir")
+            .arg(&workflows_dir)
+            .arg("--lockfile")
+            .arg(&lockfile)
+            .args(["--offline", "--verbose"])
+            .args(command)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("Timing"), "{:?}: {}", command, stdout);
+        assert!(stdout.contains("Parse:"), "{:?}: {}", command, stdout);
+    }
+}
+
+#[test]
+fn test_fail_on_unpinned_after_pinning_everything() {
+    let temp = TempDir::new().unwrap();
+    let workflows_dir = temp.path().join("workflows");
+    fs::create_dir(&workflows_dir).unwrap();
+
+    let sha = "b4ffde65f46336ab88eb53be808477a3936bae11";
+    let lockfile = temp.path().join("pin-actions.lock");
+    fs::write(
+        &lockfile,
+        format!(r#"{{"actions": {{"actions/checkout@v4": "{sha}"}}}}"#),
+    )
+    .unwrap();
+    let workflow_path = workflows_dir.join("test.yml");
+    fs::write(
+        &workflow_path,
+        "steps:\n  - uses: actions/checkout@v4\n  - uses: actions/cache@v4 # pin-actions: ignore\n",
+    )
+    .unwrap();
+
+    // Everything this run pinned, or was told to leave alone, isn't unpinned
+    let run = || {
+        let mut cmd = Command::new(cargo_bin!("pin-actions"));
+        cmd.arg("--workflows-dir")
+            .arg(&workflows_dir)
+            .arg("--lockfile")
+            .arg(&lockfile)
+            .args(["--offline", "--fail-on", "unpinned"])
+            .assert()
+    };
+    run().success();
+    assert!(
+        fs::read_to_string(&workflow_path)
+            .unwrap()
+            .contains(&format!("actions/checkout@{sha} # v4"))
+    );
+
+    // A use left on its ref still fails the run
+    fs::write(
+        &workflow_path,
+        "steps:\n  - uses: actions/checkout@v4\n  - uses: actions/setup-node@v4\n",
+    )
+    .unwrap();
+    run().failure();
+}